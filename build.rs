@@ -1,4 +1,6 @@
+#[cfg(windows)]
 use std::path::Path;
+#[cfg(windows)]
 use windows::Win32::Graphics::Direct3D::Fxc::D3DCompile;
 
 #[cfg(windows)]
@@ -9,56 +11,41 @@ fn main() {
 
     println!("cargo:rerun-if-changed=resources/shader.hlsl");
     let hlsl_file = std::fs::read("resources/shader.hlsl").unwrap();
-    let mut vs_blob = None;
-    let mut ps_blob = None;
-    let vs_blob = unsafe {
-        D3DCompile(
-            hlsl_file.as_ptr() as _,
-            hlsl_file.len(),
-            windows::s!("shader.hlsl"),
-            None,
-            None,
-            windows::s!("vs_main"),
-            windows::s!("vs_5_0"),
-            0,
-            0,
-            &mut vs_blob,
-            None,
-        ).unwrap();
-        vs_blob.unwrap()
-    };
-    let ps_blob = unsafe {
+    compile_shader(&hlsl_file, windows::s!("vs_main"), windows::s!("vs_5_0"), "shader.vs_blob");
+    compile_shader(&hlsl_file, windows::s!("ps_main"), windows::s!("ps_5_0"), "shader.ps_blob");
+    compile_shader(&hlsl_file, windows::s!("ps_filtered"), windows::s!("ps_5_0"), "shader.ps_filtered_blob");
+    compile_shader(&hlsl_file, windows::s!("ps_shape"), windows::s!("ps_5_0"), "shader.ps_shape_blob");
+    compile_shader(&hlsl_file, windows::s!("ps_pixelate"), windows::s!("ps_5_0"), "shader.ps_pixelate_blob");
+}
+
+#[cfg(windows)]
+fn compile_shader(hlsl_file: &[u8], entry: windows::core::PCSTR, target: windows::core::PCSTR, out: &str) {
+    let mut blob = None;
+    let blob = unsafe {
         D3DCompile(
             hlsl_file.as_ptr() as _,
             hlsl_file.len(),
             windows::s!("shader.hlsl"),
             None,
             None,
-            windows::s!("ps_main"),
-            windows::s!("ps_5_0"),
+            entry,
+            target,
             0,
             0,
-            &mut ps_blob,
+            &mut blob,
             None,
         ).unwrap();
-        ps_blob.unwrap()
+        blob.unwrap()
     };
 
-    let vs_blob = unsafe {
-        std::slice::from_raw_parts(
-            vs_blob.GetBufferPointer() as *const u8,
-            vs_blob.GetBufferSize(),
-        )
-    };
-    let ps_blob = unsafe {
+    let blob = unsafe {
         std::slice::from_raw_parts(
-            ps_blob.GetBufferPointer() as *const u8,
-            ps_blob.GetBufferSize(),
+            blob.GetBufferPointer() as *const u8,
+            blob.GetBufferSize(),
         )
     };
-    std::fs::write(Path::new(&std::env::var("OUT_DIR").unwrap()).join("shader.vs_blob"), vs_blob).unwrap();
-    std::fs::write(Path::new(&std::env::var("OUT_DIR").unwrap()).join("shader.ps_blob"), ps_blob).unwrap();
+    std::fs::write(Path::new(&std::env::var("OUT_DIR").unwrap()).join(out), blob).unwrap();
 }
 
 #[cfg(unix)]
-fn main() {}
\ No newline at end of file
+fn main() {}
@@ -8,9 +8,37 @@ struct VSOutput {
     float2 uv: TEXCOORD0;
 };
 
-cbuffer cbPerObject
+cbuffer cbPerObject : register(b0)
 {
     float4x4 transform;
+    //x: 1 if the alpha of the texture should be ignored, y: block size in texels for ps_pixelate
+    float4 object_params;
+    //xy: offset, zw: size of the used texture region
+    float4 uv_rect;
+    //premultiplied color that is multiplied with the texture
+    float4 tint;
+};
+
+#define LUT_SIZE 256
+#define MAX_TAPS 6
+
+cbuffer cbFilter : register(b1)
+{
+    //x: kernel radius
+    float4 filter_params;
+    float4 filter_lut[LUT_SIZE / 4];
+};
+
+cbuffer cbShape : register(b2)
+{
+    //x, y, width, height in pixels
+    float4 shape_rect;
+    //x: corner radius, y: border width (0 for filled shapes), z: blur radius, w: 1 if textured quads should be clipped to the shape
+    float4 shape_params;
+    //premultiplied
+    float4 shape_color;
+    //xy: center, z: radius of a circle that is cut out of the shape (0 for none)
+    float4 shape_hole;
 };
 
 Texture2D tex: register(t0);
@@ -20,10 +48,97 @@ SamplerState samp: register(s0);
 VSOutput vs_main(VSInput input) {
     VSOutput output;
     output.position = mul(float4(input.position, 1.0f), transform);
-    output.uv= input.uv;
+    output.uv= uv_rect.xy + input.uv * uv_rect.zw;
     return output;
 }
 
+//Signed distance in pixels to the edge of the rounded rectangle described by cbShape
+float shape_distance(float2 pos) {
+    float2 half_size = shape_rect.zw * 0.5f;
+    float2 center = shape_rect.xy + half_size;
+    float radius = min(shape_params.x, min(half_size.x, half_size.y));
+    float2 q = abs(pos - center) - half_size + radius;
+    return length(max(q, 0.0f)) + min(max(q.x, q.y), 0.0f) - radius;
+}
+
+float shape_coverage(float2 pos) {
+    float d = shape_distance(pos);
+    float coverage = shape_params.z > 0.0f
+        ? 1.0f - smoothstep(-shape_params.z, shape_params.z, d)
+        : saturate(0.5f - d);
+    if (shape_params.y > 0.0f)
+        coverage *= saturate(0.5f + d + shape_params.y);
+    if (shape_hole.z > 0.0f) {
+        float h = length(pos - shape_hole.xy) - shape_hole.z;
+        coverage *= shape_params.z > 0.0f
+            ? smoothstep(-shape_params.z, shape_params.z, h)
+            : saturate(0.5f + h);
+    }
+    return coverage;
+}
+
+float clip_coverage(float2 pos) {
+    return shape_params.w > 0.0f ? shape_coverage(pos) : 1.0f;
+}
+
+float4 ps_shape(VSOutput vs): SV_TARGET {
+    return shape_color * shape_coverage(vs.position.xy);
+}
+
+//The texture is expected to be premultiplied, so forcing the alpha to one turns it opaque without touching the color
+float4 apply_alpha(float4 color) {
+    return float4(color.rgb, lerp(color.a, 1.0f, object_params.x));
+}
+
 float4 ps_main(VSOutput vs): SV_TARGET {
-    return tex.Sample(samp, vs.uv);
-}
\ No newline at end of file
+    return apply_alpha(tex.Sample(samp, vs.uv)) * tint * clip_coverage(vs.position.xy);
+}
+
+//Every block of the texture gets the color at its center
+float4 ps_pixelate(VSOutput vs): SV_TARGET {
+    float2 size;
+    tex.GetDimensions(size.x, size.y);
+    float2 block = object_params.y / size;
+    return apply_alpha(tex.SampleLevel(samp, (floor(vs.uv / block) + 0.5f) * block, 0)) * tint;
+}
+
+float kernel_weight(float x) {
+    float t = abs(x) / filter_params.x * (LUT_SIZE - 1);
+    if (t >= LUT_SIZE - 1)
+        return 0.0f;
+    uint i = (uint)t;
+    uint j = i + 1;
+    return lerp(filter_lut[i / 4][i % 4], filter_lut[j / 4][j % 4], frac(t));
+}
+
+//Picks the mip level that is closest to the output resolution and resamples it using the kernel from the lookup table
+float4 ps_filtered(VSOutput vs): SV_TARGET {
+    float2 base_size;
+    float levels;
+    tex.GetDimensions(0, base_size.x, base_size.y, levels);
+    float2 texel_pos = vs.uv * base_size;
+    float footprint = max(length(ddx(texel_pos)), length(ddy(texel_pos)));
+    float scale = max(footprint, 1.0f);
+    float lod = min(floor(log2(scale)), levels - 1.0f);
+
+    float2 size;
+    tex.GetDimensions((uint)lod, size.x, size.y, levels);
+    float stretch = max(scale / exp2(lod), 1.0f);
+    int taps = min((int)ceil(filter_params.x * stretch), MAX_TAPS);
+
+    float2 pos = vs.uv * size - 0.5f;
+    float2 center = floor(pos);
+    float4 color = 0.0f;
+    float weight_sum = 0.0f;
+    [loop]
+    for (int y = 1 - taps; y <= taps; y++) {
+        float wy = kernel_weight((pos.y - center.y - y) / stretch);
+        [loop]
+        for (int x = 1 - taps; x <= taps; x++) {
+            float w = wy * kernel_weight((pos.x - center.x - x) / stretch);
+            color += w * tex.SampleLevel(samp, (center + float2(x, y) + 0.5f) / size, lod);
+            weight_sum += w;
+        }
+    }
+    return apply_alpha(saturate(color / weight_sum)) * tint * clip_coverage(vs.position.xy);
+}
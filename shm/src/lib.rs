@@ -0,0 +1,518 @@
+//! Shared memory frame export of display_peek.
+//!
+//! The export is a file that both sides map into memory. All values are little endian and every offset is a
+//! multiple of eight, so the sequence numbers can be accessed atomically.
+//!
+//! File header, [`HEADER_SIZE`] bytes:
+//!
+//! | Offset | Type     | Content                                              |
+//! |--------|----------|------------------------------------------------------|
+//! | 0      | [u8; 8]  | Magic `DPEEKSHM`                                     |
+//! | 8      | u32      | Layout version, currently [`VERSION`]                |
+//! | 12     | u32      | Size of this header                                  |
+//! | 16     | u32      | Number of slots in the ring                          |
+//! | 20     | u32      | Size of a slot including its header                  |
+//! | 24     | u32      | Largest frame width that fits into a slot            |
+//! | 28     | u32      | Largest frame height that fits into a slot           |
+//! | 32     | u64      | Sequence number of the newest complete frame, 0 if none |
+//! | 40     |          | Reserved                                             |
+//!
+//! Frame `n` (starting at 1) is written to slot `(n - 1) % slots`, which starts at `header size + slot * slot size`.
+//! Slot header, [`SLOT_HEADER_SIZE`] bytes, followed by `stride * height` bytes of pixels:
+//!
+//! | Offset | Type | Content                                                        |
+//! |--------|------|----------------------------------------------------------------|
+//! | 0      | u64  | Sequence number of the frame, 0 while the slot is being written |
+//! | 8      | u64  | Capture time in microseconds since the unix epoch              |
+//! | 16     | u32  | Width                                                          |
+//! | 20     | u32  | Height                                                         |
+//! | 24     | u32  | Bytes per row                                                  |
+//! | 28     | u32  | [`PixelFormat`]                                                |
+//! | 32     | u32  | 1 if the pointer is on the monitor, otherwise 0                |
+//! | 36     | i32  | Pointer hotspot x in frame coordinates                         |
+//! | 40     | i32  | Pointer hotspot y in frame coordinates                         |
+//! | 44     | u32  | [`CursorKind`]                                                 |
+//! | 48     | u32  | Hotspot x inside the cursor shape                              |
+//! | 52     | u32  | Hotspot y inside the cursor shape                              |
+//! | 56     |      | Reserved                                                       |
+//!
+//! The writer zeroes the slot sequence, writes the slot, stores the sequence and finally publishes it in the file
+//! header. A reader copies the slot and only keeps the copy if the slot sequence was the same before and after.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use memmap2::{Mmap, MmapMut};
+
+pub const MAGIC: [u8; 8] = *b"DPEEKSHM";
+pub const VERSION: u32 = 1;
+pub const HEADER_SIZE: usize = 64;
+pub const SLOT_HEADER_SIZE: usize = 64;
+
+const LATEST_OFFSET: usize = 32;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u32)]
+pub enum PixelFormat {
+    /// Straight alpha rgba with eight bits per channel
+    Rgba8 = 1
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u32)]
+pub enum CursorKind {
+    Unknown = 0,
+    Color = 1,
+    Monochrome = 2,
+    MaskedColor = 3
+}
+
+impl CursorKind {
+    fn from_u32(value: u32) -> Self {
+        match value {
+            1 => CursorKind::Color,
+            2 => CursorKind::Monochrome,
+            3 => CursorKind::MaskedColor,
+            _ => CursorKind::Unknown
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CursorInfo {
+    /// Hotspot position in frame coordinates
+    pub position: (i32, i32),
+    pub kind: CursorKind,
+    /// Hotspot offset inside the cursor shape
+    pub hotspot: (u32, u32)
+}
+
+/// Everything in a slot header except the sequence number
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FrameInfo {
+    pub timestamp_micros: u64,
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+    pub format: PixelFormat,
+    /// `None` if the pointer is not on the monitor
+    pub cursor: Option<CursorInfo>
+}
+
+/// A frame copied out of the export
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Frame {
+    pub sequence: u64,
+    pub info: FrameInfo,
+    pub pixels: Vec<u8>
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("four bytes"))
+}
+
+fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// # Safety
+/// `offset` has to be in bounds of the mapping and eight byte aligned
+unsafe fn atomic_at<'a>(base: *const u8, offset: usize) -> &'a AtomicU64 {
+    &*(base.add(offset) as *const AtomicU64)
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Layout {
+    slots: u32,
+    slot_size: usize,
+    max_width: u32,
+    max_height: u32
+}
+
+impl Layout {
+
+    fn new(slots: u32, max_width: u32, max_height: u32) -> Self {
+        let pixels = 4 * max_width as usize * max_height as usize;
+        Self {
+            slots: slots.max(2),
+            slot_size: (SLOT_HEADER_SIZE + pixels).next_multiple_of(8),
+            max_width,
+            max_height,
+        }
+    }
+
+    fn file_size(&self) -> usize {
+        HEADER_SIZE + self.slots as usize * self.slot_size
+    }
+
+    fn slot_offset(&self, sequence: u64) -> usize {
+        HEADER_SIZE + ((sequence - 1) % self.slots as u64) as usize * self.slot_size
+    }
+
+    fn read(header: &[u8]) -> Result<Self> {
+        if header.len() < HEADER_SIZE || header[..8] != MAGIC {
+            return Err(invalid("Not a display_peek frame export"));
+        }
+        if u32_at(header, 8) != VERSION {
+            return Err(invalid(format!("Unsupported layout version {}", u32_at(header, 8))));
+        }
+        if u32_at(header, 12) as usize != HEADER_SIZE {
+            return Err(invalid("Unexpected header size"));
+        }
+        let layout = Self {
+            slots: u32_at(header, 16),
+            slot_size: u32_at(header, 20) as usize,
+            max_width: u32_at(header, 24),
+            max_height: u32_at(header, 28),
+        };
+        if layout.slots == 0 || !layout.slot_size.is_multiple_of(8) || layout.slot_size < SLOT_HEADER_SIZE + 4 * layout.max_width as usize * layout.max_height as usize {
+            return Err(invalid("Inconsistent slot layout"));
+        }
+        Ok(layout)
+    }
+
+    fn write(&self, header: &mut [u8]) {
+        header[..8].copy_from_slice(&MAGIC);
+        put_u32(header, 8, VERSION);
+        put_u32(header, 12, HEADER_SIZE as u32);
+        put_u32(header, 16, self.slots);
+        put_u32(header, 20, self.slot_size as u32);
+        put_u32(header, 24, self.max_width);
+        put_u32(header, 28, self.max_height);
+    }
+
+}
+
+/// Fills the ring buffer. There must only be one writer per file
+pub struct ExportWriter {
+    map: MmapMut,
+    layout: Layout,
+    sequence: u64
+}
+
+impl ExportWriter {
+
+    /// Creates or replaces the export file with room for `slots` frames of up to `max_width` x `max_height` pixels.
+    ///
+    /// Readers may still have the old file mapped, so it is never truncated: An export with the same layout is reused
+    /// and continues its sequence numbers, any other one is replaced by a new file
+    pub fn create(path: impl AsRef<Path>, slots: u32, max_width: u32, max_height: u32) -> Result<Self> {
+        let path = path.as_ref();
+        let layout = Layout::new(slots, max_width, max_height);
+        if layout.slot_size > u32::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "The maximum frame size is too large"));
+        }
+        if let Some(writer) = Self::reuse(path, layout)? {
+            return Ok(writer);
+        }
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(format!(".{}.tmp", std::process::id()));
+        let map = Self::create_new(Path::new(&temp), layout)
+            .and_then(|map| std::fs::rename(&temp, path).map(|_| map))
+            .inspect_err(|_| {
+                let _ = std::fs::remove_file(&temp);
+            })?;
+        Ok(Self {
+            map,
+            layout,
+            sequence: 0,
+        })
+    }
+
+    /// Opens the export at `path` if it exists and has the same layout
+    fn reuse(path: &Path, layout: Layout) -> Result<Option<Self>> {
+        let file = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err)
+        };
+        if file.metadata()?.len() != layout.file_size() as u64 {
+            return Ok(None);
+        }
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        if Layout::read(&map).ok() != Some(layout) {
+            return Ok(None);
+        }
+        let sequence = unsafe { atomic_at(map.as_ptr(), LATEST_OFFSET) }.load(Ordering::Acquire);
+        Ok(Some(Self { map, layout, sequence }))
+    }
+
+    fn create_new(path: &Path, layout: Layout) -> Result<MmapMut> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(layout.file_size() as u64)?;
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        layout.write(&mut map[..HEADER_SIZE]);
+        map.flush()?;
+        Ok(map)
+    }
+
+    pub fn max_size(&self) -> (u32, u32) {
+        (self.layout.max_width, self.layout.max_height)
+    }
+
+    /// Writes the next frame and returns its sequence number. `pixels` holds `info.stride * info.height` bytes
+    pub fn write(&mut self, info: &FrameInfo, pixels: &[u8]) -> Result<u64> {
+        let size = info.stride as usize * info.height as usize;
+        if info.width > self.layout.max_width || info.height > self.layout.max_height || info.stride < 4 * info.width ||
+            SLOT_HEADER_SIZE + size > self.layout.slot_size {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("A {}x{} frame does not fit into the export", info.width, info.height)));
+        }
+        if pixels.len() < size {
+            return Err(Error::new(ErrorKind::InvalidInput, "Not enough pixel data"));
+        }
+        let sequence = self.sequence + 1;
+        let offset = self.layout.slot_offset(sequence);
+        unsafe { atomic_at(self.map.as_ptr(), offset) }.store(0, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        let mut header = [0u8; SLOT_HEADER_SIZE];
+        header[8..16].copy_from_slice(&info.timestamp_micros.to_le_bytes());
+        put_u32(&mut header, 16, info.width);
+        put_u32(&mut header, 20, info.height);
+        put_u32(&mut header, 24, info.stride);
+        put_u32(&mut header, 28, info.format as u32);
+        if let Some(cursor) = info.cursor {
+            put_u32(&mut header, 32, 1);
+            put_u32(&mut header, 36, cursor.position.0 as u32);
+            put_u32(&mut header, 40, cursor.position.1 as u32);
+            put_u32(&mut header, 44, cursor.kind as u32);
+            put_u32(&mut header, 48, cursor.hotspot.0);
+            put_u32(&mut header, 52, cursor.hotspot.1);
+        }
+        //The sequence number is only ever touched atomically
+        self.map[offset + 8..offset + SLOT_HEADER_SIZE].copy_from_slice(&header[8..]);
+        self.map[offset + SLOT_HEADER_SIZE..offset + SLOT_HEADER_SIZE + size].copy_from_slice(&pixels[..size]);
+
+        let base = self.map.as_ptr();
+        unsafe { atomic_at(base, offset) }.store(sequence, Ordering::Release);
+        unsafe { atomic_at(base, LATEST_OFFSET) }.store(sequence, Ordering::Release);
+        self.sequence = sequence;
+        Ok(sequence)
+    }
+
+}
+
+/// Reads frames from an export. Any number of readers can use the same file
+pub struct ExportReader {
+    map: Mmap,
+    layout: Layout
+}
+
+impl ExportReader {
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        let map = unsafe { Mmap::map(&file)? };
+        let layout = Layout::read(&map)?;
+        if map.len() < layout.file_size() {
+            return Err(invalid("The export file is truncated"));
+        }
+        Ok(Self { map, layout })
+    }
+
+    /// Sequence number of the newest complete frame, 0 if there is none yet
+    pub fn latest_sequence(&self) -> u64 {
+        unsafe { atomic_at(self.map.as_ptr(), LATEST_OFFSET) }.load(Ordering::Acquire)
+    }
+
+    /// Copies the newest frame if it is newer than `after`. Returns `None` if there is no such frame or
+    /// the writer overwrote it during the copy
+    pub fn read_newer(&self, after: u64) -> Result<Option<Frame>> {
+        let sequence = self.latest_sequence();
+        if sequence == 0 || sequence <= after {
+            return Ok(None);
+        }
+        let offset = self.layout.slot_offset(sequence);
+        let slot_sequence = unsafe { atomic_at(self.map.as_ptr(), offset) };
+        if slot_sequence.load(Ordering::Acquire) != sequence {
+            return Ok(None);
+        }
+        let slot = &self.map[offset..offset + self.layout.slot_size];
+        let (width, height, stride) = (u32_at(slot, 16), u32_at(slot, 20), u32_at(slot, 24));
+        let size = stride as usize * height as usize;
+        if SLOT_HEADER_SIZE + size > slot.len() {
+            return Ok(None);
+        }
+        let info = FrameInfo {
+            timestamp_micros: u64::from_le_bytes(slot[8..16].try_into().expect("eight bytes")),
+            width,
+            height,
+            stride,
+            format: PixelFormat::Rgba8,
+            cursor: (u32_at(slot, 32) != 0).then(|| CursorInfo {
+                position: (u32_at(slot, 36) as i32, u32_at(slot, 40) as i32),
+                kind: CursorKind::from_u32(u32_at(slot, 44)),
+                hotspot: (u32_at(slot, 48), u32_at(slot, 52)),
+            }),
+        };
+        let format = u32_at(slot, 28);
+        let pixels = slot[SLOT_HEADER_SIZE..SLOT_HEADER_SIZE + size].to_vec();
+        fence(Ordering::Acquire);
+        if slot_sequence.load(Ordering::Relaxed) != sequence {
+            return Ok(None);
+        }
+        if format != PixelFormat::Rgba8 as u32 {
+            return Err(invalid(format!("Unknown pixel format {}", format)));
+        }
+        Ok(Some(Frame { sequence, info, pixels }))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+    use super::*;
+
+    /// Set for the child process of `frames_cross_processes`; The path of the export it writes
+    const CHILD_ENV: &str = "DISPLAY_PEEK_SHM_WRITER";
+    const FRAMES: u64 = 300;
+    const WIDTH: u32 = 64;
+    const HEIGHT: u32 = 32;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("display_peek_shm_{}_{}.shm", name, std::process::id()))
+    }
+
+    /// Every byte of the pixels and every field of the header derives from the sequence number, so a torn copy shows
+    fn frame(sequence: u64) -> (FrameInfo, Vec<u8>) {
+        let width = WIDTH - (sequence % 8) as u32;
+        let info = FrameInfo {
+            timestamp_micros: sequence * 1000,
+            width,
+            height: HEIGHT,
+            stride: 4 * width,
+            format: PixelFormat::Rgba8,
+            cursor: (!sequence.is_multiple_of(3)).then_some(CursorInfo {
+                position: (sequence as i32, -(sequence as i32)),
+                kind: CursorKind::from_u32(sequence as u32 % 4),
+                hotspot: (sequence as u32 % 32, 7),
+            }),
+        };
+        (info, vec![sequence as u8; (info.stride * info.height) as usize])
+    }
+
+    fn check(frame: &Frame) {
+        let (info, pixels) = self::frame(frame.sequence);
+        assert_eq!(frame.info, info, "frame {}", frame.sequence);
+        assert!(frame.pixels == pixels, "frame {} is torn", frame.sequence);
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let path = temp_path("round_trip");
+        let mut writer = ExportWriter::create(&path, 2, WIDTH, HEIGHT).unwrap();
+        let reader = ExportReader::open(&path).unwrap();
+        assert_eq!(reader.read_newer(0).unwrap(), None);
+        for sequence in 1..=5 {
+            let (info, pixels) = frame(sequence);
+            assert_eq!(writer.write(&info, &pixels).unwrap(), sequence);
+            let read = reader.read_newer(sequence - 1).unwrap().unwrap();
+            assert_eq!(read.sequence, sequence);
+            check(&read);
+            assert_eq!(reader.read_newer(sequence).unwrap(), None);
+        }
+        let (mut info, pixels) = frame(1);
+        info.width = WIDTH + 1;
+        assert!(writer.write(&info, &pixels).is_err(), "too wide");
+        assert!(writer.write(&frame(1).0, &pixels[1..]).is_err(), "not enough pixels");
+        drop((writer, reader));
+
+        std::fs::write(&path, [0u8; HEADER_SIZE]).unwrap();
+        assert!(ExportReader::open(&path).is_err(), "not an export");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn exports_are_replaced_while_mapped() {
+        let path = temp_path("replace");
+        let _ = std::fs::remove_file(&path);
+        let mut writer = ExportWriter::create(&path, 2, WIDTH, HEIGHT).unwrap();
+        let (info, pixels) = frame(1);
+        writer.write(&info, &pixels).unwrap();
+        let reader = ExportReader::open(&path).unwrap();
+        drop(writer);
+
+        //The same layout keeps the file and its sequence numbers
+        let mut writer = ExportWriter::create(&path, 2, WIDTH, HEIGHT).unwrap();
+        let (info, pixels) = frame(2);
+        assert_eq!(writer.write(&info, &pixels).unwrap(), 2);
+        check(&reader.read_newer(1).unwrap().unwrap());
+        drop(writer);
+
+        //Another layout gets a new file while the old one stays readable
+        let mut writer = ExportWriter::create(&path, 3, WIDTH, HEIGHT).unwrap();
+        let (info, pixels) = frame(1);
+        assert_eq!(writer.write(&info, &pixels).unwrap(), 1);
+        check(&reader.read_newer(0).unwrap().unwrap());
+        assert_eq!(reader.read_newer(0).unwrap().unwrap().sequence, 2);
+        let replaced = ExportReader::open(&path).unwrap();
+        check(&replaced.read_newer(0).unwrap().unwrap());
+        assert_eq!(replaced.read_newer(0).unwrap().unwrap().sequence, 1);
+        drop((writer, reader, replaced));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Runs only as the child of `frames_cross_processes`
+    #[test]
+    fn writer_process() {
+        let Some(path) = std::env::var_os(CHILD_ENV) else { return };
+        let mut writer = ExportWriter::create(path, 3, WIDTH, HEIGHT).unwrap();
+        for sequence in 1..=FRAMES {
+            let (info, pixels) = frame(sequence);
+            writer.write(&info, &pixels).unwrap();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn frames_cross_processes() {
+        let path = temp_path("cross_process");
+        let _ = std::fs::remove_file(&path);
+        let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["tests::writer_process", "--exact", "--test-threads=1", "--quiet"])
+            .env(CHILD_ENV, &path)
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(20);
+        //The file only becomes readable once the child wrote the header
+        let reader = loop {
+            match ExportReader::open(&path) {
+                Ok(reader) => break reader,
+                Err(_) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(1)),
+                Err(err) => panic!("The writer did not create the export: {}", err)
+            }
+        };
+        let mut last = 0;
+        let mut seen = 0;
+        while last < FRAMES {
+            assert!(Instant::now() < deadline, "Only got to frame {} of {}", last, FRAMES);
+            match reader.read_newer(last).unwrap() {
+                Some(frame) => {
+                    assert!(frame.sequence > last);
+                    check(&frame);
+                    last = frame.sequence;
+                    seen += 1;
+                }
+                None => std::thread::yield_now()
+            }
+        }
+        assert!(child.wait().unwrap().success(), "The writer process failed");
+        assert!(seen > 1, "The reader should see frames while the writer is running");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,379 @@
+use std::f32::consts::PI;
+use std::time::Instant;
+use glam::{Vec2, vec2};
+use serde::Deserialize;
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Easing {
+    Linear,
+    #[default]
+    Quad,
+    Cubic,
+    Back,
+    Spring
+}
+
+impl Easing {
+
+    /// Maps the linear progress `t` in `[0, 1]` onto the curve. Every curve starts at 0 and ends at 1
+    /// but `Back` and `Spring` may overshoot in between.
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::Quad => t * t,
+            Easing::Cubic => t * t * t,
+            Easing::Back => {
+                const C1: f32 = 1.70158;
+                const C3: f32 = C1 + 1.0;
+                1.0 + C3 * (t - 1.0).powi(3) + C1 * (t - 1.0).powi(2)
+            },
+            Easing::Spring => match t < 1.0 {
+                true => 1.0 - (-6.0 * t).exp() * (3.0 * PI * t).cos(),
+                false => 1.0
+            }
+        }
+    }
+
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnimationKind {
+    #[default]
+    Zoom,
+    Fade,
+    Slide
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+    #[default]
+    Bottom
+}
+
+impl Edge {
+    fn direction(self) -> Vec2 {
+        match self {
+            Edge::Left => vec2(-1.0, 0.0),
+            Edge::Right => vec2(1.0, 0.0),
+            Edge::Top => vec2(0.0, -1.0),
+            Edge::Bottom => vec2(0.0, 1.0)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AnimationConfig {
+    /// Length of the animation in seconds
+    pub duration: f32,
+    pub easing: Easing,
+    pub kind: AnimationKind,
+    /// The edge the overlay slides in from (or out to). Only used by `AnimationKind::Slide`
+    pub edge: Edge
+}
+
+impl Default for AnimationConfig {
+    fn default() -> Self {
+        Self {
+            duration: 1.0 / 3.0,
+            easing: Easing::default(),
+            kind: AnimationKind::default(),
+            edge: Edge::default(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AnimationSettings {
+    pub open: AnimationConfig,
+    pub close: AnimationConfig
+}
+
+impl Default for AnimationSettings {
+    fn default() -> Self {
+        Self {
+            open: AnimationConfig::default(),
+            close: AnimationConfig {
+                duration: 0.2,
+                kind: AnimationKind::Fade,
+                ..AnimationConfig::default()
+            },
+        }
+    }
+}
+
+/// How the overlay should be drawn at a given point of the animation
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AnimationFrame {
+    /// Scale around the center of the overlay
+    pub scale: f32,
+    pub opacity: f32,
+    /// Translation as fraction of the overlay size
+    pub offset: Vec2
+}
+
+impl AnimationFrame {
+    pub const IDENTITY: Self = Self {
+        scale: 1.0,
+        opacity: 1.0,
+        offset: Vec2::ZERO,
+    };
+
+    fn lerp(self, other: Self, t: f32) -> Self {
+        Self {
+            scale: self.scale + (other.scale - self.scale) * t,
+            opacity: self.opacity + (other.opacity - self.opacity) * t,
+            offset: self.offset.lerp(other.offset, t),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Direction {
+    Opening(AnimationConfig),
+    Closing(AnimationConfig),
+    Idle
+}
+
+/// Drives the open and close animation of the overlay.
+///
+/// The progress is linear in time and goes from 0 (hidden) to 1 (fully visible).
+/// The close animation plays the curve of its config backwards. Open and close can use different kinds,
+/// so reversing an animation midway blends from the frame that was shown into the new animation
+/// until it finishes instead of jumping to the other curve.
+/// All methods take the current time as parameter instead of querying the clock themselves.
+#[derive(Debug, Clone)]
+pub struct Animator {
+    progress: f32,
+    direction: Direction,
+    last_update: Option<Instant>,
+    /// The frame that was shown when a running animation was reversed and the progress at that point
+    blend: Option<(AnimationFrame, f32)>
+}
+
+impl Default for Animator {
+    fn default() -> Self {
+        Self {
+            progress: 0.0,
+            direction: Direction::Idle,
+            last_update: None,
+            blend: None,
+        }
+    }
+}
+
+impl Animator {
+
+    pub fn open(&mut self, now: Instant, config: AnimationConfig) {
+        self.start(now, Direction::Opening(config));
+    }
+
+    pub fn close(&mut self, now: Instant, config: AnimationConfig) {
+        self.start(now, Direction::Closing(config));
+    }
+
+    fn start(&mut self, now: Instant, direction: Direction) {
+        if direction != self.direction {
+            self.blend = self.is_running().then(|| (self.frame(), self.progress));
+        }
+        self.direction = direction;
+        self.last_update = Some(now);
+    }
+
+    /// Advances the animation to `now`. Returns `true` if a close animation finished during this update
+    pub fn update(&mut self, now: Instant) -> bool {
+        let elapsed = self.last_update
+            .replace(now)
+            .map(|last| now.saturating_duration_since(last).as_secs_f32())
+            .unwrap_or(0.0);
+        match self.direction {
+            Direction::Opening(config) => {
+                self.progress = advance(self.progress, elapsed, config.duration);
+                if self.progress >= 1.0 {
+                    self.direction = Direction::Idle;
+                    self.blend = None;
+                }
+                false
+            }
+            Direction::Closing(config) => {
+                self.progress = 1.0 - advance(1.0 - self.progress, elapsed, config.duration);
+                if self.progress <= 0.0 {
+                    self.direction = Direction::Idle;
+                    self.blend = None;
+                    return true;
+                }
+                false
+            }
+            Direction::Idle => false
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.direction != Direction::Idle
+    }
+
+    /// `false` once the overlay is completely hidden
+    pub fn is_visible(&self) -> bool {
+        self.progress > 0.0 || matches!(self.direction, Direction::Opening(_))
+    }
+
+    pub fn frame(&self) -> AnimationFrame {
+        let config = match self.direction {
+            Direction::Opening(config) | Direction::Closing(config) => config,
+            Direction::Idle => return match self.progress > 0.0 {
+                true => AnimationFrame::IDENTITY,
+                false => AnimationFrame { opacity: 0.0, ..AnimationFrame::IDENTITY }
+            }
+        };
+        let value = config.easing.apply(self.progress);
+        let frame = match config.kind {
+            AnimationKind::Zoom => AnimationFrame {
+                scale: value.max(0.0),
+                ..AnimationFrame::IDENTITY
+            },
+            AnimationKind::Fade => AnimationFrame {
+                opacity: value.clamp(0.0, 1.0),
+                ..AnimationFrame::IDENTITY
+            },
+            AnimationKind::Slide => AnimationFrame {
+                offset: config.edge.direction() * (1.0 - value),
+                ..AnimationFrame::IDENTITY
+            }
+        };
+        match self.blend {
+            Some((from, start)) => {
+                // How far the progress moved from the reversal towards the end of the new animation
+                let (covered, remaining) = match self.direction {
+                    Direction::Closing(_) => (start - self.progress, start),
+                    _ => (self.progress - start, 1.0 - start)
+                };
+                let t = match remaining > 0.0 {
+                    true => (covered / remaining).clamp(0.0, 1.0),
+                    false => 1.0
+                };
+                from.lerp(frame, t)
+            }
+            None => frame
+        }
+    }
+
+}
+
+fn advance(progress: f32, elapsed: f32, duration: f32) -> f32 {
+    match duration > 0.0 {
+        true => (progress + elapsed / duration).min(1.0),
+        false => 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    fn secs(seconds: f32) -> Duration {
+        Duration::from_secs_f32(seconds)
+    }
+
+    fn assert_close(a: AnimationFrame, b: AnimationFrame, tolerance: f32) {
+        assert!((a.scale - b.scale).abs() <= tolerance &&
+                    (a.opacity - b.opacity).abs() <= tolerance &&
+                    a.offset.distance(b.offset) <= tolerance, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn open_and_close_run_to_completion() {
+        let settings = AnimationSettings::default();
+        let start = Instant::now();
+        let mut animator = Animator::default();
+        assert!(!animator.is_visible());
+
+        animator.open(start, settings.open);
+        assert!(animator.is_visible());
+        assert!(!animator.update(start + secs(settings.open.duration * 0.5)));
+        assert!(animator.is_running());
+        assert!(!animator.update(start + secs(settings.open.duration)));
+        assert!(!animator.is_running());
+        assert_eq!(animator.frame(), AnimationFrame::IDENTITY);
+
+        let closing = start + secs(1.0);
+        animator.close(closing, settings.close);
+        assert!(!animator.update(closing + secs(settings.close.duration * 0.5)));
+        assert!(animator.update(closing + secs(settings.close.duration)));
+        assert!(!animator.is_running());
+        assert!(!animator.is_visible());
+        assert_eq!(animator.frame().opacity, 0.0);
+    }
+
+    #[test]
+    fn progress_is_linear_in_time() {
+        let config = AnimationConfig { duration: 1.0, easing: Easing::Linear, ..AnimationConfig::default() };
+        let start = Instant::now();
+        let mut animator = Animator::default();
+        animator.open(start, config);
+        for step in 1..4 {
+            animator.update(start + secs(0.25 * step as f32));
+            assert!((animator.frame().scale - 0.25 * step as f32).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn zero_duration_finishes_immediately() {
+        let config = AnimationConfig { duration: 0.0, ..AnimationConfig::default() };
+        let start = Instant::now();
+        let mut animator = Animator::default();
+        animator.open(start, config);
+        animator.update(start);
+        assert_eq!(animator.frame(), AnimationFrame::IDENTITY);
+        animator.close(start, config);
+        assert!(animator.update(start));
+    }
+
+    #[test]
+    fn reversing_midway_does_not_jump() {
+        // The default open animation zooms while the close animation fades
+        let settings = AnimationSettings::default();
+        let start = Instant::now();
+        let mut animator = Animator::default();
+        animator.open(start, settings.open);
+        let reversal = start + secs(settings.open.duration * 0.5);
+        animator.update(reversal);
+        let shown = animator.frame();
+
+        animator.close(reversal, settings.close);
+        animator.update(reversal);
+        assert_close(animator.frame(), shown, 1e-6);
+
+        let mut previous = shown;
+        let mut now = reversal;
+        while animator.is_running() {
+            now += Duration::from_millis(5);
+            animator.update(now);
+            let frame = animator.frame();
+            assert_close(frame, previous, 0.1);
+            previous = frame;
+        }
+        assert!(!animator.is_visible());
+
+        // And back again while closing
+        animator.open(now, settings.open);
+        let reversal = now + secs(settings.open.duration * 0.3);
+        animator.update(reversal);
+        animator.close(reversal, settings.close);
+        let before = animator.frame();
+        animator.open(reversal, settings.open);
+        animator.update(reversal);
+        assert_close(animator.frame(), before, 1e-6);
+        animator.update(reversal + secs(settings.open.duration));
+        assert_eq!(animator.frame(), AnimationFrame::IDENTITY);
+    }
+
+}
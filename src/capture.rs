@@ -0,0 +1,96 @@
+//! Captures the monitor the overlay showed last on a timer of its own, so recordings, the web viewer, VNC and the frame export
+//! keep going while the overlay is hidden
+
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::time::Duration;
+use anyhow::Result;
+use tao::event_loop::EventLoopProxy;
+use crate::{redaction, snapshot, CustomEvent};
+use crate::config::Config;
+use crate::directx::{AcquisitionResults, CachedFrame, Direct3D, Display, FrameReader, QuadRenderer};
+use crate::frame_source::FrameSource;
+use crate::image_io::Image;
+use crate::remote::RemoteSource;
+
+/// Sends `CustomEvent::CaptureTick` at a fixed rate. The next tick is only sent once the last one was handled,
+/// so a busy event loop does not pile them up. Dropping it stops the timer
+struct CaptureTimer {
+    _stop: Sender<()>,
+    pending: Arc<AtomicBool>
+}
+
+impl CaptureTimer {
+    fn start(proxy: EventLoopProxy<CustomEvent>, fps: u32) -> Self {
+        let interval = Duration::from_secs(1) / fps.max(1);
+        let (stop, stopped) = channel::<()>();
+        let pending = Arc::new(AtomicBool::new(false));
+        let sent = pending.clone();
+        std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if !sent.swap(true, Ordering::AcqRel) && proxy.send_event(CustomEvent::CaptureTick).is_err() {
+                    break;
+                }
+            }
+            log::trace!("Stopping capture timer");
+        });
+        Self {
+            _stop: stop,
+            pending,
+        }
+    }
+}
+
+/// A duplication of its own, independent of the one the overlay shows
+pub struct Capture {
+    name: String,
+    source: FrameSource,
+    frame: CachedFrame,
+    reader: RefCell<FrameReader>,
+    timer: CaptureTimer
+}
+
+impl Capture {
+
+    /// Captures `display`, or the remote monitor shown in its place, `fps` times per second
+    pub fn start(d3d: &Direct3D, display: Display, remote: Option<&RemoteSource>, fps: u32, proxy: EventLoopProxy<CustomEvent>) -> Result<Self> {
+        let name = display.name()?;
+        let source = FrameSource::new(&d3d.device, display, remote)?;
+        log::debug!("Capturing {} at {} fps", name, fps);
+        Ok(Self {
+            name,
+            source,
+            frame: CachedFrame::new(),
+            reader: RefCell::new(FrameReader::default()),
+            timer: CaptureTimer::start(proxy, fps),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn source(&self) -> &FrameSource {
+        &self.source
+    }
+
+    /// Takes the next frame from the source and redacts it like the overlay does. Called for every tick
+    pub fn acquire(&mut self, d3d: &Direct3D, renderer: &QuadRenderer, config: &Config) -> Result<AcquisitionResults> {
+        self.timer.pending.store(false, Ordering::Release);
+        let result = self.source.try_acquire_next_frame()?;
+        if let Some(frame) = self.source.get_frame().filter(|_| result.frame_update) {
+            self.frame.update(&d3d.device, &d3d.context, frame);
+            let areas = redaction::frame_areas(config.redactions(&self.name), self.source.get_display_mode());
+            self.frame.redact(d3d, renderer, &areas)?;
+        }
+        Ok(result)
+    }
+
+    /// Reads the last acquired frame back the way the monitor shows it
+    pub fn read(&self, d3d: &Direct3D, with_cursor: bool) -> Result<Image> {
+        snapshot::capture(&mut self.reader.borrow_mut(), d3d, &self.frame, &self.source, with_cursor)
+    }
+
+}
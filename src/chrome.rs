@@ -0,0 +1,123 @@
+#[cfg(windows)]
+use glam::{Mat4, Quat, vec3};
+use serde::Deserialize;
+use crate::config::Color;
+use crate::directx::DisplayMode;
+#[cfg(windows)]
+use crate::directx::{Direct3D, QuadRenderer, Rect, Shape, TextRenderer};
+
+/// Decoration that is drawn around the mirrored monitor
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ChromeConfig {
+    pub border_width: f32,
+    pub border_color: Color,
+    pub corner_radius: f32,
+    /// Size of the drop shadow in pixels; zero disables the shadow
+    pub shadow_size: f32,
+    pub shadow_color: Color,
+    /// Show the name and resolution of the mirrored monitor
+    pub caption: bool,
+    pub caption_color: Color,
+    pub caption_background: Color,
+    /// Size of a font pixel in window pixels
+    pub caption_scale: f32
+}
+
+impl Default for ChromeConfig {
+    fn default() -> Self {
+        Self {
+            border_width: 0.0,
+            border_color: Color::rgba(255, 255, 255, 255),
+            corner_radius: 0.0,
+            shadow_size: 0.0,
+            shadow_color: Color::rgba(0, 0, 0, 160),
+            caption: false,
+            caption_color: Color::rgba(255, 255, 255, 255),
+            caption_background: Color::rgba(0, 0, 0, 160),
+            caption_scale: 2.0,
+        }
+    }
+}
+
+impl ChromeConfig {
+
+    /// Space that has to be kept free between the edge of the window and the mirrored content
+    pub fn margin(&self) -> f32 {
+        self.border_width.max(0.0) + self.shadow_size.max(0.0)
+    }
+
+    /// Shape that the mirrored content should be clipped to
+    #[cfg(windows)]
+    pub fn clip(&self, content: Rect) -> Option<Shape> {
+        (self.corner_radius > 0.0).then(|| Shape {
+            rect: content,
+            corner_radius: self.corner_radius,
+            color: [1.0; 4],
+            ..Default::default()
+        })
+    }
+
+    /// Draws everything that belongs behind the mirrored content
+    #[cfg(windows)]
+    pub fn draw_background(&self, d3d: &Direct3D, quad_renderer: &QuadRenderer, screenspace: Mat4, content: Rect) {
+        if self.shadow_size > 0.0 {
+            let offset = 0.25 * self.shadow_size;
+            quad_renderer.draw_shape(d3d, screenspace, &Shape {
+                rect: content.expand(self.border_width.max(0.0)).offset(0.0, offset),
+                corner_radius: self.corner_radius + self.border_width.max(0.0),
+                blur: 0.75 * self.shadow_size,
+                color: self.shadow_color.premultiplied(),
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Draws everything that belongs on top of the mirrored content
+    #[cfg(windows)]
+    pub fn draw_foreground(&self, d3d: &Direct3D, quad_renderer: &QuadRenderer, text_renderer: &TextRenderer, screenspace: Mat4, content: Rect, caption: &str) {
+        if self.border_width > 0.0 {
+            quad_renderer.draw_shape(d3d, screenspace, &Shape {
+                rect: content.expand(self.border_width),
+                corner_radius: self.corner_radius + self.border_width,
+                border_width: self.border_width,
+                color: self.border_color.premultiplied(),
+                ..Default::default()
+            });
+        }
+        if self.caption && !caption.is_empty() {
+            let scale = self.caption_scale.max(1.0).round();
+            let padding = 2.0 * scale;
+            let (width, height) = TextRenderer::measure(caption, scale);
+            let inset = self.corner_radius.max(padding);
+            let background = Rect::new(
+                (content.x + inset).round(),
+                (content.y + inset).round(),
+                width + 2.0 * padding,
+                height + padding);
+            quad_renderer.draw_shape(d3d, screenspace, &Shape {
+                rect: background,
+                corner_radius: padding,
+                color: self.caption_background.premultiplied(),
+                ..Default::default()
+            });
+            let transform = screenspace * Mat4::from_scale_rotation_translation(
+                vec3(scale, scale, 1.0),
+                Quat::IDENTITY,
+                vec3(background.x + padding, background.y + padding, 0.0));
+            text_renderer.draw(d3d, quad_renderer, transform, self.caption_color.premultiplied(), caption);
+        }
+    }
+
+}
+
+/// Describes the mirrored monitor, e.g. `\\.\DISPLAY2 1920x1080 60Hz`
+/// `position` is the index of the monitor and the number of monitors while cycling through them
+pub fn caption_text(name: &str, mode: DisplayMode, position: Option<(usize, usize)>) -> String {
+    let (width, height) = mode.get_flipped_size();
+    let refresh = mode.refresh_num / mode.refresh_den.max(1);
+    let position = position
+        .map(|(index, count)| format!("[{}/{}] ", index + 1, count))
+        .unwrap_or_default();
+    format!("{}{} {}x{} {}Hz{}", position, name, width, height, refresh, if mode.hdr { " HDR" } else { "" })
+}
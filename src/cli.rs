@@ -0,0 +1,282 @@
+//! Command line interface. Everything except `run` and `ctl` prints to the terminal and exits.
+
+use std::path::PathBuf;
+#[cfg(windows)]
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, bail, Context, Result};
+use crate::config::Config;
+#[cfg(windows)]
+use crate::directx::{create_device, read_texture, AdapterFactory, DesktopDuplication, Display};
+#[cfg(windows)]
+use crate::image_io;
+use crate::ipc::{self, Command};
+#[cfg(windows)]
+use crate::snapshot::compose;
+#[cfg(windows)]
+use crate::utils::{attach_console, com_initialized};
+
+pub const USAGE: &str = "\
+Usage: display_peek [--config <path>] [<command>]
+
+Commands:
+  run [--show|--hide|--pin|--reload|--quit|--peek <monitor>|--profile <name>|--start-recording|--stop-recording]
+                           Start the overlay or pass the flag to the running instance (default)
+  ctl <command>            Control the running instance, see `display_peek ctl help`
+  list-displays            Print all adapters and their displays
+  check-config             Parse the config file and report errors
+  print-default-config     Print the default config file
+  config-path              Print the path of the config file
+  screenshot [--monitor <name>] [--out <file.png>]
+                           Capture a monitor to a png file";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Subcommand {
+    Run(Option<Command>),
+    Ctl(Vec<String>),
+    ListDisplays,
+    CheckConfig,
+    PrintDefaultConfig,
+    ConfigPath,
+    Screenshot { monitor: Option<String>, out: Option<PathBuf> },
+    Help
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cli {
+    pub config: Option<PathBuf>,
+    pub subcommand: Subcommand
+}
+
+impl Cli {
+
+    /// Global options like `--config` only count in front of the command, everything after it belongs to the command
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut config = None;
+        let mut args = args.into_iter().peekable();
+        while args.next_if(|arg| arg == "--config").is_some() {
+            config = Some(PathBuf::from(args
+                .next()
+                .ok_or_else(|| anyhow!("--config needs a path"))?));
+        }
+        let rest: Vec<String> = args.collect();
+        let subcommand = match rest.first().map(String::as_str) {
+            None => Subcommand::Run(None),
+            Some(flag) if flag.starts_with("--") && flag != "--help" => Subcommand::Run(Command::from_flags(&rest)?),
+            Some("run") => Subcommand::Run(Command::from_flags(&rest[1..])?),
+            Some("ctl") => Subcommand::Ctl(rest[1..].to_vec()),
+            Some(name) => {
+                let options = parse_options(&rest[1..])?;
+                let option = |name: &str| options
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, value)| value.clone());
+                let subcommand = match name {
+                    "list-displays" => Subcommand::ListDisplays,
+                    "check-config" => Subcommand::CheckConfig,
+                    "print-default-config" => Subcommand::PrintDefaultConfig,
+                    "config-path" => Subcommand::ConfigPath,
+                    "screenshot" => Subcommand::Screenshot {
+                        monitor: option("--monitor"),
+                        out: option("--out").map(PathBuf::from),
+                    },
+                    "help" | "--help" => Subcommand::Help,
+                    other => bail!("Unknown command: {}\n\n{}", other, USAGE)
+                };
+                let allowed: &[&str] = match subcommand {
+                    Subcommand::Screenshot { .. } => &["--monitor", "--out"],
+                    _ => &[]
+                };
+                if let Some((name, _)) = options.iter().find(|(n, _)| !allowed.contains(&n.as_str())) {
+                    bail!("Unknown option {} for {}", name, rest[0]);
+                }
+                subcommand
+            }
+        };
+        Ok(Self { config, subcommand })
+    }
+
+}
+
+/// Splits `--name value` pairs
+fn parse_options(args: &[String]) -> Result<Vec<(String, String)>> {
+    args.chunks(2)
+        .map(|pair| match pair {
+            [name, value] if name.starts_with("--") => Ok((name.clone(), value.clone())),
+            [name] if name.starts_with("--") => bail!("{} needs a value", name),
+            _ => bail!("Unexpected argument: {}", pair[0])
+        })
+        .collect()
+}
+
+/// Runs everything except `Run`, which is handled by `main`
+pub fn execute(subcommand: Subcommand) -> Result<()> {
+    match subcommand {
+        Subcommand::Run(_) => unreachable!("The overlay is started by main"),
+        Subcommand::Ctl(args) => if !ipc::run_client(&args)? {
+            std::process::exit(1);
+        },
+        Subcommand::ListDisplays => list_displays()?,
+        Subcommand::CheckConfig => check_config()?,
+        Subcommand::PrintDefaultConfig => print!("{}", Config::DEFAULT),
+        Subcommand::ConfigPath => println!("{}", Config::path().display()),
+        Subcommand::Screenshot { monitor, out } => screenshot(monitor.as_deref(), out)?,
+        Subcommand::Help => println!("{}", USAGE)
+    }
+    Ok(())
+}
+
+pub fn exit_with_error(err: anyhow::Error) -> ! {
+    #[cfg(windows)]
+    attach_console();
+    eprintln!("Error: {:#}", err);
+    std::process::exit(1)
+}
+
+#[cfg(windows)]
+fn list_displays() -> Result<()> {
+    for adapter in AdapterFactory::new()? {
+        println!("{}", adapter.name().unwrap_or_else(|err| format!("<{}>", err)));
+        for display in adapter.iter_displays() {
+            let name = display.name()?;
+            match display.get_current_display_mode() {
+                Ok(mode) => println!("  {} {}x{} {}Hz {:?}{}", name, mode.width, mode.height,
+                                     mode.refresh_num / mode.refresh_den.max(1), mode.orientation,
+                                     if mode.hdr { " HDR" } else { "" }),
+                Err(err) => println!("  {} ({})", name, err)
+            }
+            if let Ok(rect) = display.desktop_rect() {
+                println!("    desktop {},{} to {},{}", rect.left, rect.top, rect.right, rect.bottom);
+            }
+            if let Ok(modes) = display.get_display_modes() {
+                let mut names: Vec<String> = modes
+                    .iter()
+                    .map(|mode| format!("{}x{}@{}{}", mode.width, mode.height,
+                                        mode.refresh_num / mode.refresh_den.max(1), if mode.hdr { " HDR" } else { "" }))
+                    .collect();
+                names.dedup();
+                println!("    modes {}", names.join(", "));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_config() -> Result<()> {
+    let path = Config::path();
+    if !path.exists() {
+        println!("{} does not exist; the default config will be written on the first start", path.display());
+        return Ok(());
+    }
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("Can not read {}", path.display()))?;
+    let config = Config::parse(&text)
+        .with_context(|| format!("{} is invalid", path.display()))?;
+    println!("{} is valid ({} monitors)", path.display(), config.monitors.len());
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn list_displays() -> Result<()> {
+    bail!("list-displays needs DXGI and is only available on Windows")
+}
+
+/// Captures the next frame of `monitor`, or of the first display if it is `None`, and applies its redactions
+#[cfg(windows)]
+fn screenshot(monitor: Option<&str>, out: Option<PathBuf>) -> Result<()> {
+    com_initialized();
+    let adapter = AdapterFactory::new()?
+        .get_adapter_by_idx(0)
+        .context("Can not get default graphics adapter")?;
+    let display: Display = match monitor {
+        None => adapter.get_display_by_idx(0),
+        Some(monitor) => adapter.get_display_by_name(monitor)
+    }.ok_or_else(|| anyhow!("Can not find monitor {}", monitor.unwrap_or_default()))?;
+    //Failing is better than saving something that should have been hidden
+    let config = Config::read().context("Can not load the redactions from the config")?;
+    let redactions = config.redactions(&display.name()?).to_vec();
+    let (device, context) = create_device(&adapter)?;
+    let mut dupl = DesktopDuplication::new(&device, display)?;
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let image = loop {
+        let result = dupl.try_acquire_next_frame()?;
+        if let Some(frame) = dupl.get_frame().filter(|_| result.success && result.frame_update) {
+            break compose(read_texture(&device, &context, frame)?, dupl.get_display_mode().orientation, &redactions, None)?;
+        }
+        if Instant::now() > deadline {
+            bail!("The display did not produce a frame");
+        }
+        std::thread::sleep(Duration::from_millis(16));
+    };
+    let out = out.unwrap_or_else(|| image_io::output_path("screenshot", "png"));
+    image.save_png(&out)?;
+    println!("Saved {}", out.display());
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn screenshot(_monitor: Option<&str>, _out: Option<PathBuf>) -> Result<()> {
+    bail!("screenshot needs desktop duplication and is only available on Windows")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Cli> {
+        Cli::parse(args.split_whitespace().map(String::from))
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn arguments_parse() {
+        let cases = [
+            ("", None, Subcommand::Run(None)),
+            ("run", None, Subcommand::Run(None)),
+            ("--quit", None, Subcommand::Run(Some(Command::Quit))),
+            ("run --peek DISPLAY2", None, Subcommand::Run(Some(Command::Peek { monitor: "DISPLAY2".into() }))),
+            ("--config a.toml", Some("a.toml"), Subcommand::Run(None)),
+            ("--config a.toml --show", Some("a.toml"), Subcommand::Run(Some(Command::Show))),
+            ("--config a.toml --config b.toml run", Some("b.toml"), Subcommand::Run(None)),
+            ("--config a.toml ctl status", Some("a.toml"), Subcommand::Ctl(strings(&["status"]))),
+            ("ctl peek --config", None, Subcommand::Ctl(strings(&["peek", "--config"]))),
+            ("ctl profile --config x", None, Subcommand::Ctl(strings(&["profile", "--config", "x"]))),
+            ("list-displays", None, Subcommand::ListDisplays),
+            ("check-config", None, Subcommand::CheckConfig),
+            ("print-default-config", None, Subcommand::PrintDefaultConfig),
+            ("--config c.toml config-path", Some("c.toml"), Subcommand::ConfigPath),
+            ("screenshot", None, Subcommand::Screenshot { monitor: None, out: None }),
+            ("screenshot --out a.png --monitor DISPLAY1", None, Subcommand::Screenshot {
+                monitor: Some("DISPLAY1".into()),
+                out: Some(PathBuf::from("a.png"))
+            }),
+            ("help", None, Subcommand::Help),
+            ("--help", None, Subcommand::Help),
+        ];
+        for (args, config, subcommand) in cases {
+            let cli = parse(args).unwrap_or_else(|err| panic!("{:?} failed: {:#}", args, err));
+            assert_eq!(cli, Cli { config: config.map(PathBuf::from), subcommand }, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn bad_arguments_are_refused() {
+        let cases = [
+            "--config",
+            "--bogus",
+            "run --show --hide",
+            "run --config a.toml",
+            "list-displays --config a.toml",
+            "screenshot --monitor",
+            "screenshot --size 5",
+            "screenshot DISPLAY1",
+            "check-config --out a.png",
+            "frobnicate",
+        ];
+        for args in cases {
+            assert!(parse(args).is_err(), "{:?} must be refused", args);
+        }
+    }
+}
@@ -1,50 +1,226 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use directories_next::BaseDirs;
+#[cfg(windows)]
 use notify::{RecommendedWatcher, Watcher, RecursiveMode};
 use serde::Deserialize;
+#[cfg(windows)]
 use tao::dpi::{LogicalPosition, LogicalSize};
-use tao::event_loop::EventLoop;
-use anyhow::Result;
+#[cfg(not(windows))]
+use self::dpi::{LogicalPosition, LogicalSize};
+#[cfg(windows)]
+use tao::event_loop::EventLoopProxy;
+use anyhow::{ensure, Result};
+#[cfg(windows)]
 use error_tools::log::LogResultExt;
+#[cfg(windows)]
 use crate::CustomEvent;
+use crate::scaling::ScalingFilter;
+use crate::animation::AnimationSettings;
+use crate::chrome::ChromeConfig;
+use crate::osd::OsdConfig;
+use crate::cursor_effects::CursorEffectsConfig;
+use crate::dwell::DwellConfig;
+use crate::zones::ZoneConfig;
+use crate::keys::Key;
+use crate::trigger::TriggerMode;
+use crate::hotkeys::HotkeyConfig;
+use crate::snapshot::SnapshotConfig;
+use crate::recording::RecordingConfig;
+use crate::web::WebConfig;
+use crate::vnc::VncConfig;
+use crate::remote::{RemoteConfig, RemoteSource};
+use crate::frame_export::ExportConfig;
+use crate::redaction::Redaction;
+
+/// Stand-ins for the tao types, which are only built on Windows, with the same layout in the config
+#[cfg(not(windows))]
+mod dpi {
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+    pub struct LogicalPosition<P> {
+        pub x: P,
+        pub y: P
+    }
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+    pub struct LogicalSize<P> {
+        pub width: P,
+        pub height: P
+    }
+}
 
 #[derive(Debug, Clone, Copy, Deserialize)]
 pub struct OverlayConfig {
     pub position: LogicalPosition<f64>,
-    pub size: LogicalSize<f64>
+    pub size: LogicalSize<f64>,
+    #[serde(default)]
+    pub filter: ScalingFilter,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub force_opaque: bool
+}
+
+fn default_opacity() -> f32 {
+    1.0
 }
 
 #[derive(Debug, Clone, Copy, Default, Deserialize)]
 pub struct OverlayOverride {
     pub position: Option<LogicalPosition<f64>>,
-    pub size: Option<LogicalSize<f64>>
+    pub size: Option<LogicalSize<f64>>,
+    pub filter: Option<ScalingFilter>,
+    pub opacity: Option<f32>,
+    pub force_opaque: Option<bool>
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct MonitorConfig {
     pub name: String,
-    pub overlay: Option<OverlayOverride>
+    pub overlay: Option<OverlayOverride>,
+    /// Show this monitor only while the pointer is in one of these zones instead of whenever it is on the monitor
+    #[serde(default)]
+    pub zones: Vec<ZoneConfig>,
+    #[serde(default)]
+    pub trigger: TriggerMode,
+    /// Key used by the toggle, hold and pin modes
+    pub key: Option<Key>,
+    /// Show the frames of a remote sender instead of this monitor
+    pub remote: Option<RemoteSource>,
+    /// Parts of this monitor that never show up in the overlay or any capture
+    #[serde(default)]
+    pub redact: Vec<Redaction>
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub overlay: OverlayConfig,
+    #[serde(default)]
+    pub animation: AnimationSettings,
+    #[serde(default)]
+    pub chrome: ChromeConfig,
+    #[serde(default)]
+    pub osd: OsdConfig,
+    #[serde(default)]
+    pub cursor: CursorEffectsConfig,
+    #[serde(default)]
+    pub dwell: DwellConfig,
+    #[serde(default)]
+    pub hotkeys: HotkeyConfig,
+    #[serde(default)]
+    pub snapshot: SnapshotConfig,
+    #[serde(default)]
+    pub recording: RecordingConfig,
+    #[serde(default)]
+    pub web: WebConfig,
+    #[serde(default)]
+    pub vnc: VncConfig,
+    #[serde(default)]
+    pub remote: RemoteConfig,
+    #[serde(default)]
+    pub export: ExportConfig,
     pub monitors: Vec<MonitorConfig>
 }
 
+/// A color written as `"#RRGGBB"` or `"#RRGGBBAA"` in the config
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8
+}
+
+impl Color {
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+
+    /// Converts the color into normalized rgba with the color channels multiplied by alpha
+    pub fn premultiplied(self) -> [f32; 4] {
+        let a = self.a as f32 / 255.0;
+        [
+            self.r as f32 / 255.0 * a,
+            self.g as f32 / 255.0 * a,
+            self.b as f32 / 255.0 * a,
+            a
+        ]
+    }
+
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        let hex = value
+            .strip_prefix('#')
+            .filter(|hex| hex.is_ascii() && (hex.len() == 6 || hex.len() == 8))
+            .ok_or_else(|| format!("invalid color \"{}\", expected #RRGGBB or #RRGGBBAA", value))?;
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16)
+            .map_err(|e| format!("invalid color \"{}\": {}", value, e));
+        Ok(Self {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+            a: match hex.len() {
+                8 => channel(6)?,
+                _ => u8::MAX
+            },
+        })
+    }
+}
+
+static PATH_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+#[cfg(windows)]
 #[must_use]
 pub struct ConfigWatcher(RecommendedWatcher);
 
 impl Config {
 
+    pub const DEFAULT: &'static str = include_str!("../resources/default_config.toml");
+
     pub fn path() -> PathBuf {
+        if let Some(path) = PATH_OVERRIDE.get() {
+            return path.clone();
+        }
         let dirs = BaseDirs::new().expect("can not get directories");
         let config_dir = dirs.config_dir();
         config_dir.join("DisplayPeek.toml")
     }
 
-    pub fn create_watcher(event_loop: &EventLoop<CustomEvent>) -> Result<ConfigWatcher> {
-        let proxy = event_loop.create_proxy();
+    /// Overrides the file in the config directory that `path` returns. Only the first call has an effect
+    pub fn set_path(path: PathBuf) {
+        PATH_OVERRIDE
+            .set(path)
+            .unwrap_or_else(|_| log::warn!("The config path was already set"));
+    }
+
+    /// The file of a profile: `DisplayPeek.work.toml` next to the main config is the profile `work`.
+    /// `None` and the profile `default` are the main config
+    pub fn profile_path(profile: Option<&str>) -> Result<PathBuf> {
+        let path = Self::path();
+        let Some(name) = profile.filter(|name| *name != "default") else {
+            return Ok(path);
+        };
+        ensure!(!name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+            "Invalid profile name \"{}\"", name);
+        let stem = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        Ok(path.with_file_name(format!("{}.{}.toml", stem, name)))
+    }
+
+    /// Sends `CustomEvent::ConfigChange` whenever the file at `path` is modified
+    #[cfg(windows)]
+    pub fn create_watcher(proxy: &EventLoopProxy<CustomEvent>, path: &Path) -> Result<ConfigWatcher> {
+        let proxy = proxy.clone();
         let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
             match res {
                 Ok(event) => {
@@ -56,19 +232,84 @@ impl Config {
                 Err(e) => log::warn!("watch error: {:?}", e),
             };
         })?;
-        watcher.watch(&Self::path(), RecursiveMode::NonRecursive)?;
+        watcher.watch(path, RecursiveMode::NonRecursive)?;
         Ok(ConfigWatcher(watcher))
     }
 
     pub fn load() -> Result<Config> {
         if !Self::path().exists(){
             log::info!("Writing default config");
-            std::fs::write(Self::path(), include_bytes!("../resources/default_config.toml"))?;
+            std::fs::write(Self::path(), Self::DEFAULT)?;
+        }
+        Self::parse(&std::fs::read_to_string(Self::path())?)
+    }
+
+    /// Like `load` for the main config. Profiles are never created
+    pub fn load_profile(profile: Option<&str>) -> Result<Config> {
+        let path = Self::profile_path(profile)?;
+        if path == Self::path() {
+            return Self::load();
+        }
+        Self::parse_file(&path)
+    }
+
+    /// Like `load`, but falls back to the default config instead of writing it if the file does not exist
+    pub fn read() -> Result<Config> {
+        match Self::path().exists() {
+            true => Self::parse(&std::fs::read_to_string(Self::path())?),
+            false => Self::parse(Self::DEFAULT)
+        }
+    }
+
+    /// Like `read` for a profile; Only the main config falls back to the default one
+    pub fn read_profile(profile: Option<&str>) -> Result<Config> {
+        let path = Self::profile_path(profile)?;
+        if path == Self::path() {
+            return Self::read();
         }
-        let config: Config = toml::from_str(&std::fs::read_to_string(Self::path())?)?;
+        Self::parse_file(&path)
+    }
+
+    fn parse_file(path: &Path) -> Result<Config> {
+        ensure!(path.exists(), "There is no profile at {}", path.display());
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Config> {
+        let config: Config = toml::from_str(text)?;
         Ok(config)
     }
 
+    /// Whether the monitor is shown as soon as the pointer is on it
+    pub fn shows_on_hover(&self, monitor_name: &str) -> bool {
+        self.monitors
+            .iter()
+            .any(|m| m.name == monitor_name && m.zones.is_empty() && m.trigger.uses_hover())
+    }
+
+    pub fn trigger_mode(&self, monitor_name: &str) -> TriggerMode {
+        self.monitors
+            .iter()
+            .find(|m| m.name == monitor_name)
+            .map(|m| m.trigger)
+            .unwrap_or_default()
+    }
+
+    pub fn remote_source(&self, monitor_name: &str) -> Option<&RemoteSource> {
+        self.monitors
+            .iter()
+            .find(|m| m.name == monitor_name)
+            .and_then(|m| m.remote.as_ref())
+    }
+
+    pub fn redactions(&self, monitor_name: &str) -> &[Redaction] {
+        self.monitors
+            .iter()
+            .find(|m| m.name == monitor_name)
+            .map(|m| m.redact.as_slice())
+            .unwrap_or_default()
+    }
+
     pub fn get_overlay_config(&self, monitor_name: &str) -> Option<OverlayConfig> {
         self.monitors
             .iter()
@@ -87,6 +328,110 @@ impl OverlayConfig {
             size: overlay_override
                 .and_then(|x|x.size)
                 .unwrap_or(self.size),
+            filter: overlay_override
+                .and_then(|x|x.filter)
+                .unwrap_or(self.filter),
+            opacity: overlay_override
+                .and_then(|x|x.opacity)
+                .unwrap_or(self.opacity),
+            force_opaque: overlay_override
+                .and_then(|x|x.force_opaque)
+                .unwrap_or(self.force_opaque),
+        }
+    }
+
+    /// Returns the opacity of the overlay and whether the alpha channel of the source should be ignored.
+    /// When `translucent` is `false` the overlay is always fully opaque.
+    pub fn alpha_mode(&self, translucent: bool) -> (f32, bool) {
+        match translucent {
+            true => (self.opacity.clamp(0.0, 1.0), self.force_opaque),
+            false => (1.0, true)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlay(opacity: f32, force_opaque: bool) -> OverlayConfig {
+        OverlayConfig {
+            opacity,
+            force_opaque,
+            ..Config::parse(Config::DEFAULT).unwrap().overlay
+        }
+    }
+
+    #[test]
+    fn default_config_parses() {
+        let config = Config::parse(Config::DEFAULT).unwrap();
+        assert_eq!(config.overlay.alpha_mode(true), (1.0, false));
+    }
+
+    #[test]
+    fn profiles_live_next_to_the_config() {
+        let path = Config::path();
+        assert_eq!(Config::profile_path(None).unwrap(), path);
+        assert_eq!(Config::profile_path(Some("default")).unwrap(), path);
+        let work = Config::profile_path(Some("work-2")).unwrap();
+        assert_eq!(work.parent(), path.parent());
+        assert!(work.to_string_lossy().ends_with(".work-2.toml"));
+        for name in ["", "../evil", "a b", "x.toml", r"C:\x"] {
+            assert!(Config::profile_path(Some(name)).is_err(), "{:?} must be refused", name);
+        }
+        assert!(Config::read_profile(Some(&format!("missing{}", std::process::id()))).is_err());
+    }
+
+    #[test]
+    fn alpha_mode_clamps_the_opacity() {
+        assert_eq!(overlay(0.5, false).alpha_mode(true), (0.5, false));
+        assert_eq!(overlay(1.5, false).alpha_mode(true), (1.0, false));
+        assert_eq!(overlay(-0.5, true).alpha_mode(true), (0.0, true));
+    }
+
+    /// What ends up on screen for a premultiplied `source` pixel over `background`: `apply_alpha` in the shader
+    /// followed by the premultiplied blend DirectComposition does with the overlay opacity
+    fn composite(overlay: &OverlayConfig, translucent: bool, source: [f32; 4], background: [f32; 3]) -> [f32; 3] {
+        let (opacity, force_opaque) = overlay.alpha_mode(translucent);
+        let alpha = if force_opaque { 1.0 } else { source[3] };
+        std::array::from_fn(|i| source[i] * opacity + background[i] * (1.0 - alpha * opacity))
+    }
+
+    fn assert_close(actual: [f32; 3], expected: [f32; 3]) {
+        assert!(actual.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-6), "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn alpha_mode_blends_with_the_background() {
+        let background = [0.0, 0.0, 1.0];
+        let opaque = [1.0, 0.0, 0.0, 1.0];
+        //Half transparent red, premultiplied
+        let translucent = [0.5, 0.0, 0.0, 0.5];
+
+        assert_close(composite(&overlay(1.0, false), true, opaque, background), [1.0, 0.0, 0.0]);
+        assert_close(composite(&overlay(1.0, false), true, translucent, background), [0.5, 0.0, 0.5]);
+        assert_close(composite(&overlay(1.0, true), true, translucent, background), [0.5, 0.0, 0.0]);
+        assert_close(composite(&overlay(0.5, false), true, opaque, background), [0.5, 0.0, 0.5]);
+        assert_close(composite(&overlay(0.5, false), true, translucent, background), [0.25, 0.0, 0.75]);
+        assert_close(composite(&overlay(0.5, true), true, translucent, background), [0.25, 0.0, 0.5]);
+        assert_close(composite(&overlay(0.0, false), true, opaque, background), background);
+        assert_close(composite(&overlay(1.5, false), true, opaque, background), [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn opaque_overlay_hides_the_background() {
+        let background = [0.0, 0.0, 1.0];
+        for overlay in [overlay(0.5, false), overlay(0.0, false), overlay(1.0, false)] {
+            assert_close(composite(&overlay, false, [0.5, 0.0, 0.0, 0.5], background), [0.5, 0.0, 0.0]);
+            assert_close(composite(&overlay, false, [0.0, 0.0, 0.0, 0.0], background), [0.0, 0.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn opaque_overlay_ignores_the_config() {
+        for (opacity, force_opaque) in [(0.5, false), (1.5, false), (-0.5, true), (1.0, false)] {
+            assert_eq!(overlay(opacity, force_opaque).alpha_mode(false), (1.0, true));
+        }
+    }
+
+}
@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+#[cfg(windows)]
+use glam::Mat4;
+use glam::Vec2;
+use serde::Deserialize;
+#[cfg(windows)]
+use crate::animation::Easing;
+use crate::config::Color;
+#[cfg(windows)]
+use crate::directx::{Circle, Direct3D, QuadRenderer, Shape};
+
+/// Effects that make the pointer easier to find in the scaled-down mirror.
+/// All sizes are in window pixels so they are independent of the overlay scale.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CursorEffectsConfig {
+    /// Radius of a colored circle behind the pointer; zero disables the halo
+    pub halo_radius: f32,
+    pub halo_color: Color,
+    /// Radius of the area around the pointer that is not dimmed; zero disables the spotlight
+    pub spotlight_radius: f32,
+    pub spotlight_color: Color,
+    /// The pointer is enlarged if it would be drawn smaller than this
+    pub min_size: f32,
+    /// Show expanding rings when a mouse button is pressed
+    pub ripples: bool,
+    pub ripple_radius: f32,
+    pub ripple_width: f32,
+    pub ripple_color: Color,
+    /// Duration of a ripple in seconds
+    pub ripple_duration: f32
+}
+
+impl Default for CursorEffectsConfig {
+    fn default() -> Self {
+        Self {
+            halo_radius: 0.0,
+            halo_color: Color::rgba(255, 255, 0, 96),
+            spotlight_radius: 0.0,
+            spotlight_color: Color::rgba(0, 0, 0, 128),
+            min_size: 0.0,
+            ripples: false,
+            ripple_radius: 30.0,
+            ripple_width: 3.0,
+            ripple_color: Color::rgba(255, 64, 64, 255),
+            ripple_duration: 0.5,
+        }
+    }
+}
+
+impl CursorEffectsConfig {
+
+    /// Factor the pointer has to be enlarged by to reach `min_size` when drawn with `scale`
+    pub fn pointer_scale(&self, height: u32, scale: f32) -> f32 {
+        (self.min_size / (height as f32 * scale).max(1.0)).max(1.0)
+    }
+
+    pub fn ripple_duration(&self) -> Duration {
+        Duration::from_secs_f32(self.ripple_duration.max(0.0))
+    }
+
+    /// Draws the spotlight and the halo. `pointer` is the hotspot in window pixels and `clip` the shape of the content.
+    /// This resets the clip shape
+    #[cfg(windows)]
+    pub fn draw_background(&self, d3d: &Direct3D, quad_renderer: &QuadRenderer, windowspace: Mat4, clip: Shape, pointer: Vec2) {
+        if self.spotlight_radius > 0.0 {
+            quad_renderer.draw_shape(d3d, windowspace, &Shape {
+                color: self.spotlight_color.premultiplied(),
+                blur: 0.0,
+                border_width: 0.0,
+                hole: Some(Circle::new(pointer.x, pointer.y, self.spotlight_radius)),
+                ..clip
+            });
+        }
+        if self.halo_radius > 0.0 {
+            let halo = Circle::new(pointer.x, pointer.y, self.halo_radius);
+            quad_renderer.draw_shape(d3d, windowspace, &Shape {
+                rect: halo.bounds(),
+                corner_radius: halo.radius,
+                blur: 0.25 * halo.radius,
+                color: self.halo_color.premultiplied(),
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Draws all active ripples. `to_window` maps positions of the mirrored output into window pixels.
+    /// This resets the clip shape
+    #[cfg(windows)]
+    pub fn draw_ripples(&self, d3d: &Direct3D, quad_renderer: &QuadRenderer, windowspace: Mat4, ripples: &Ripples, now: Instant, to_window: impl Fn(Vec2) -> Vec2) {
+        let duration = self.ripple_duration().as_secs_f32().max(f32::EPSILON);
+        for (start, position) in ripples.iter() {
+            let t = (now.saturating_duration_since(*start).as_secs_f32() / duration).min(1.0);
+            let center = to_window(*position);
+            let radius = self.ripple_radius * (1.0 - Easing::Quad.apply(1.0 - t));
+            let color = self.ripple_color.premultiplied().map(|c| c * (1.0 - t));
+            let ring = Circle::new(center.x, center.y, radius + 0.5 * self.ripple_width);
+            quad_renderer.draw_shape(d3d, windowspace, &Shape {
+                rect: ring.bounds(),
+                corner_radius: ring.radius,
+                border_width: self.ripple_width.min(ring.radius),
+                color,
+                ..Default::default()
+            });
+        }
+    }
+
+}
+
+/// Clicks that are still visible as ripples
+#[derive(Debug, Default, Clone)]
+pub struct Ripples {
+    active: VecDeque<(Instant, Vec2)>
+}
+
+impl Ripples {
+
+    /// Starts a new ripple at `position` in desktop coordinates of the mirrored output
+    pub fn push(&mut self, now: Instant, position: Vec2) {
+        self.active.push_back((now, position));
+    }
+
+    /// Removes all finished ripples
+    pub fn update(&mut self, now: Instant, duration: Duration) {
+        while self.active
+            .front()
+            .is_some_and(|(start, _)| now.saturating_duration_since(*start) >= duration) {
+            self.active.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.active.clear();
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.active.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(Instant, Vec2)> {
+        self.active.iter()
+    }
+
+}
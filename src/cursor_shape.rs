@@ -0,0 +1,414 @@
+use std::io::Write;
+use std::path::Path;
+use anyhow::{ensure, Result};
+use crate::directx::{CursorData, CursorType};
+
+/// A single pixel of a decoded pointer shape
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ShapePixel {
+    /// Alpha blended on top of the screen; The color is premultiplied
+    Blend([u8; 4]),
+    /// The screen color is xor-ed with this rgb value
+    Xor([u8; 3])
+}
+
+impl ShapePixel {
+    pub const TRANSPARENT: Self = ShapePixel::Xor([0, 0, 0]);
+
+    /// Applies the pixel to the premultiplied rgba `dst`
+    pub fn apply(self, dst: [u8; 4]) -> [u8; 4] {
+        match self {
+            ShapePixel::Blend([r, g, b, a]) => {
+                let inv = 255 - a as u32;
+                let blend = |s: u8, d: u8| (s as u32 + (d as u32 * inv + 127) / 255).min(255) as u8;
+                [blend(r, dst[0]), blend(g, dst[1]), blend(b, dst[2]), blend(a, dst[3])]
+            }
+            ShapePixel::Xor([r, g, b]) => [dst[0] ^ r, dst[1] ^ g, dst[2] ^ b, dst[3]]
+        }
+    }
+}
+
+/// A pointer shape converted into an independent representation with exact AND/XOR semantics
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CursorShape {
+    pub cursor_type: CursorType,
+    pub width: u32,
+    pub height: u32,
+    pub hotspot: (u32, u32),
+    pub pixels: Vec<ShapePixel>
+}
+
+impl CursorShape {
+
+    pub fn decode(data: &CursorData) -> Result<Self> {
+        ensure!(data.width > 0 && data.height > 0, "Pointer shape is empty");
+        let height = data.height as usize;
+        let rows = match data.cursor_type {
+            CursorType::Monochrome => 2 * height,
+            _ => height
+        };
+        let min_pitch = match data.cursor_type {
+            CursorType::Monochrome => (data.width as usize).div_ceil(8),
+            _ => 4 * data.width as usize
+        };
+        let pitch = data.pitch as usize;
+        ensure!(pitch >= min_pitch, "Pointer shape pitch {} is too small for {}x{} {:?}",
+            pitch, data.width, data.height, data.cursor_type);
+        let required = pitch
+            .checked_mul(rows - 1)
+            .and_then(|n| n.checked_add(min_pitch));
+        ensure!(required.is_some_and(|n| data.data.len() >= n), "Pointer shape buffer is too small: {} bytes for {} rows with a pitch of {}",
+            data.data.len(), rows, pitch);
+        let row = |y: usize| &data.data[y * pitch..y * pitch + min_pitch];
+
+        //Every row fits into the buffer, so this can not overflow
+        let mut pixels = Vec::with_capacity(data.width as usize * height);
+        for y in 0..height {
+            match data.cursor_type {
+                CursorType::Monochrome => {
+                    let and_row = row(y);
+                    let xor_row = row(y + height);
+                    let bit = |row: &[u8], x: usize| row[x / 8] & (0x80 >> (x % 8)) != 0;
+                    pixels.extend((0..data.width as usize).map(|x| match (bit(and_row, x), bit(xor_row, x)) {
+                        (false, false) => ShapePixel::Blend([0, 0, 0, 255]),
+                        (false, true) => ShapePixel::Blend([255, 255, 255, 255]),
+                        (true, false) => ShapePixel::TRANSPARENT,
+                        (true, true) => ShapePixel::Xor([255, 255, 255])
+                    }));
+                }
+                CursorType::Color => pixels.extend(row(y)
+                    .chunks_exact(4)
+                    .map(|bgra| ShapePixel::Blend([bgra[2], bgra[1], bgra[0], bgra[3]]))),
+                CursorType::MaskedColor => pixels.extend(row(y)
+                    .chunks_exact(4)
+                    .map(|bgra| match bgra[3] {
+                        0 => ShapePixel::Blend([bgra[2], bgra[1], bgra[0], 255]),
+                        _ => ShapePixel::Xor([bgra[2], bgra[1], bgra[0]])
+                    }))
+            }
+        }
+
+        Ok(Self {
+            cursor_type: data.cursor_type,
+            width: data.width,
+            height: data.height,
+            hotspot: data.hotspot,
+            pixels,
+        })
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> ShapePixel {
+        self.pixels[y as usize * self.width as usize + x as usize]
+    }
+
+    /// Whether any pixel inverts the screen
+    pub fn has_xor(&self) -> bool {
+        self.pixels
+            .iter()
+            .any(|p| matches!(p, ShapePixel::Xor(c) if *c != [0, 0, 0]))
+    }
+
+    /// Draws the shape onto a premultiplied rgba image with the hotspot at `x`/`y`
+    pub fn composite_at_hotspot(&self, image: &mut [u8], image_width: u32, image_height: u32, x: i32, y: i32) {
+        self.composite_at(image, image_width, image_height, x as i64 - self.hotspot.0 as i64, y as i64 - self.hotspot.1 as i64);
+    }
+
+    /// Draws the shape onto a premultiplied rgba image with the top-left corner of the shape at `x`/`y`
+    pub fn composite(&self, image: &mut [u8], image_width: u32, image_height: u32, x: i32, y: i32) {
+        self.composite_at(image, image_width, image_height, x as i64, y as i64);
+    }
+
+    fn composite_at(&self, image: &mut [u8], image_width: u32, image_height: u32, x: i64, y: i64) {
+        debug_assert_eq!(image.len(), image_width as usize * image_height as usize * 4);
+        for sy in 0..self.height {
+            let dy = y + sy as i64;
+            if dy < 0 || dy >= image_height as i64 {
+                continue;
+            }
+            for sx in 0..self.width {
+                let dx = x + sx as i64;
+                if dx < 0 || dx >= image_width as i64 {
+                    continue;
+                }
+                let idx = 4 * (dy as usize * image_width as usize + dx as usize);
+                let dst = [image[idx], image[idx + 1], image[idx + 2], image[idx + 3]];
+                image[idx..idx + 4].copy_from_slice(&self.pixel(sx, sy).apply(dst));
+            }
+        }
+    }
+
+    /// Premultiplied rgba buffer of all alpha blended pixels. Xor pixels are transparent
+    pub fn color_layer(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|p| match p {
+                ShapePixel::Blend(c) => *c,
+                ShapePixel::Xor(_) => [0, 0, 0, 0]
+            })
+            .collect()
+    }
+
+    /// Rgba buffer with the xor value of every pixel. Alpha blended pixels are black and the alpha is always zero
+    pub fn xor_layer(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|p| match p {
+                ShapePixel::Blend(_) => [0, 0, 0, 0],
+                ShapePixel::Xor([r, g, b]) => [*r, *g, *b, 0]
+            })
+            .collect()
+    }
+
+    /// Straight alpha rgba preview of the shape. Xor pixels are drawn on top of black
+    pub fn preview(&self) -> Vec<u8> {
+        let mut image = vec![0u8; self.pixels.len() * 4];
+        for (pixel, dst) in self.pixels.iter().zip(image.chunks_exact_mut(4)) {
+            let [r, g, b, a] = match pixel {
+                ShapePixel::Blend(c) => *c,
+                ShapePixel::Xor([0, 0, 0]) => [0, 0, 0, 0],
+                ShapePixel::Xor([r, g, b]) => [*r, *g, *b, 255]
+            };
+            let unmultiply = |c: u8| match a {
+                0 => 0,
+                _ => ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8
+            };
+            dst.copy_from_slice(&[unmultiply(r), unmultiply(g), unmultiply(b), a]);
+        }
+        image
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<()> {
+        crate::image_io::save_png(path, self.width, self.height, &self.preview())
+    }
+
+    /// Encodes the shape as windows `.cur` file.
+    /// Shapes with xor pixels use the classic AND/XOR masks, all other shapes use a 32 bit alpha channel
+    pub fn to_cur(&self) -> Result<Vec<u8>> {
+        ensure!(self.width <= 256 && self.height <= 256, "Cursors can not be larger than 256x256");
+        let has_xor = self.has_xor();
+        let mask_pitch = (self.width.div_ceil(32) * 4) as usize;
+        let color_size = (self.width * self.height * 4) as usize;
+        let mask_size = mask_pitch * self.height as usize;
+        let image_size = 40 + color_size + mask_size;
+
+        let mut out = Vec::with_capacity(6 + 16 + image_size);
+        //ICONDIR
+        out.write_all(&0u16.to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?;
+        //ICONDIRENTRY
+        out.write_all(&[(self.width % 256) as u8, (self.height % 256) as u8, 0, 0])?;
+        out.write_all(&u16::try_from(self.hotspot.0)?.to_le_bytes())?;
+        out.write_all(&u16::try_from(self.hotspot.1)?.to_le_bytes())?;
+        out.write_all(&(image_size as u32).to_le_bytes())?;
+        out.write_all(&(6u32 + 16).to_le_bytes())?;
+        //BITMAPINFOHEADER
+        out.write_all(&40u32.to_le_bytes())?;
+        out.write_all(&(self.width as i32).to_le_bytes())?;
+        out.write_all(&(2 * self.height as i32).to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&32u16.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(&((color_size + mask_size) as u32).to_le_bytes())?;
+        out.write_all(&[0u8; 16])?;
+        //XOR bitmap, bottom up
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let bgra = match (self.pixel(x, y), has_xor) {
+                    (ShapePixel::Blend([r, g, b, _]), true) => [b, g, r, 0],
+                    (ShapePixel::Blend([r, g, b, a]), false) => [b, g, r, a],
+                    (ShapePixel::Xor([r, g, b]), _) => [b, g, r, 0]
+                };
+                out.write_all(&bgra)?;
+            }
+        }
+        //AND mask, bottom up
+        for y in (0..self.height).rev() {
+            let mut row = vec![0u8; mask_pitch];
+            for x in 0..self.width {
+                let transparent = match self.pixel(x, y) {
+                    ShapePixel::Blend([_, _, _, a]) => a == 0,
+                    ShapePixel::Xor(_) => true
+                };
+                if transparent {
+                    row[(x / 8) as usize] |= 0x80 >> (x % 8);
+                }
+            }
+            out.write_all(&row)?;
+        }
+        Ok(out)
+    }
+
+    pub fn save_cur(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, self.to_cur()?)?;
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// xorshift64, so the property test is reproducible without extra dependencies
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        /// Mostly small values with the occasional extreme one
+        fn size(&mut self) -> u32 {
+            match self.next() % 8 {
+                0 => 0,
+                1 => u32::MAX - (self.next() % 4) as u32,
+                2 => (self.next() as u32) | 0x8000_0000,
+                _ => (self.next() % 70) as u32
+            }
+        }
+    }
+
+    fn random_cursor(rng: &mut Rng) -> CursorData {
+        let cursor_type = match rng.next() % 3 {
+            0 => CursorType::Color,
+            1 => CursorType::Monochrome,
+            _ => CursorType::MaskedColor
+        };
+        let width = rng.size();
+        let height = rng.size();
+        let pitch = match rng.next() % 3 {
+            0 => rng.size(),
+            1 => 4 * width.min(70),
+            _ => width.min(70).div_ceil(8) + (rng.next() % 4) as u32
+        };
+        let len = (rng.next() % 40000) as usize;
+        CursorData {
+            cursor_type,
+            width,
+            height,
+            pitch,
+            hotspot: (rng.size(), rng.size()),
+            data: (0..len).map(|_| rng.next() as u8).collect(),
+        }
+    }
+
+    #[test]
+    fn random_shapes_never_panic() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let mut decoded = 0;
+        for _ in 0..5000 {
+            let data = random_cursor(&mut rng);
+            let Ok(shape) = CursorShape::decode(&data) else {
+                continue;
+            };
+            decoded += 1;
+            assert_eq!(shape.pixels.len(), shape.width as usize * shape.height as usize);
+            assert_eq!(shape.preview().len(), 4 * shape.pixels.len());
+            let mut image = vec![0u8; 32 * 32 * 4];
+            for (x, y) in [(0, 0), (-5, 31), (i32::MIN, i32::MAX), (i32::MAX, i32::MIN)] {
+                shape.composite(&mut image, 32, 32, x, y);
+                shape.composite_at_hotspot(&mut image, 32, 32, x, y);
+            }
+            let hotspot_fits = shape.hotspot.0 <= u16::MAX as u32 && shape.hotspot.1 <= u16::MAX as u32;
+            match shape.width <= 256 && shape.height <= 256 && hotspot_fits {
+                true => assert!(shape.to_cur().is_ok()),
+                false => assert!(shape.to_cur().is_err())
+            }
+        }
+        assert!(decoded > 50, "only {} of the random shapes were valid", decoded);
+    }
+
+    fn cursor(cursor_type: CursorType, width: u32, height: u32, pitch: u32, hotspot: (u32, u32), data: &[u8]) -> CursorData {
+        CursorData { cursor_type, width, height, pitch, hotspot, data: data.to_vec() }
+    }
+
+    const BLACK: ShapePixel = ShapePixel::Blend([0, 0, 0, 255]);
+    const WHITE: ShapePixel = ShapePixel::Blend([255, 255, 255, 255]);
+
+    #[test]
+    fn monochrome_follows_the_and_xor_table() {
+        //The last two bytes of every row are padding and must be ignored
+        let data = cursor(CursorType::Monochrome, 10, 1, 4, (3, 0), &[
+            0b0011_0000, 0x00, 0xFF, 0xFF,
+            0b0101_0000, 0x00, 0xFF, 0xFF
+        ]);
+        let shape = CursorShape::decode(&data).unwrap();
+        assert_eq!((shape.width, shape.height, shape.hotspot), (10, 1, (3, 0)));
+        assert_eq!(&shape.pixels[..4], &[BLACK, WHITE, ShapePixel::TRANSPARENT, ShapePixel::Xor([255, 255, 255])]);
+        assert!(shape.pixels[4..].iter().all(|p| *p == BLACK));
+        assert!(shape.has_xor());
+    }
+
+    #[test]
+    fn monochrome_rejects_a_missing_xor_mask() {
+        let data = cursor(CursorType::Monochrome, 8, 2, 1, (0, 0), &[0, 0, 0]);
+        assert!(CursorShape::decode(&data).is_err());
+    }
+
+    #[test]
+    fn color_converts_bgra_and_skips_the_padding() {
+        let data = cursor(CursorType::Color, 2, 2, 12, (1, 1), &[
+            1, 2, 3, 255,  4, 5, 6, 128,  9, 9, 9, 9,
+            7, 8, 9, 0,  10, 11, 12, 255,  9, 9, 9, 9
+        ]);
+        let shape = CursorShape::decode(&data).unwrap();
+        assert_eq!(shape.pixel(0, 0), ShapePixel::Blend([3, 2, 1, 255]));
+        assert_eq!(shape.pixel(1, 0), ShapePixel::Blend([6, 5, 4, 128]));
+        assert_eq!(shape.pixel(0, 1), ShapePixel::Blend([9, 8, 7, 0]));
+        assert_eq!(shape.pixel(1, 1), ShapePixel::Blend([12, 11, 10, 255]));
+        assert!(!shape.has_xor());
+        //The last row does not need its padding
+        assert!(CursorShape::decode(&CursorData { data: data.data[..20].to_vec(), ..data.clone() }).is_ok());
+        assert!(CursorShape::decode(&CursorData { data: data.data[..19].to_vec(), ..data }).is_err());
+    }
+
+    #[test]
+    fn masked_color_uses_the_alpha_as_mask() {
+        let data = cursor(CursorType::MaskedColor, 3, 1, 12, (0, 0), &[
+            1, 2, 3, 0,  4, 5, 6, 0xFF,  0, 0, 0, 0xFF
+        ]);
+        let shape = CursorShape::decode(&data).unwrap();
+        assert_eq!(shape.pixels, vec![
+            ShapePixel::Blend([3, 2, 1, 255]),
+            ShapePixel::Xor([6, 5, 4]),
+            ShapePixel::TRANSPARENT
+        ]);
+        assert!(shape.has_xor());
+        assert_eq!(shape.color_layer(), vec![3, 2, 1, 255,  0, 0, 0, 0,  0, 0, 0, 0]);
+        assert_eq!(shape.xor_layer(), vec![0, 0, 0, 0,  6, 5, 4, 0,  0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn composite_places_the_hotspot() {
+        let data = cursor(CursorType::MaskedColor, 2, 2, 8, (1, 1), &[
+            0, 0, 255, 0,  0, 0, 0, 0xFF,
+            0, 0, 0, 0xFF,  255, 255, 255, 0xFF
+        ]);
+        let shape = CursorShape::decode(&data).unwrap();
+        let mut image = [10u8, 20, 30, 255].repeat(16);
+        shape.composite_at_hotspot(&mut image, 4, 4, 2, 2);
+        let px = |x: usize, y: usize| &image[4 * (y * 4 + x)..4 * (y * 4 + x) + 4];
+        assert_eq!(px(1, 1), &[255, 0, 0, 255]);
+        assert_eq!(px(2, 1), &[10, 20, 30, 255]);
+        assert_eq!(px(1, 2), &[10, 20, 30, 255]);
+        assert_eq!(px(2, 2), &[245, 235, 225, 255]);
+        assert_eq!(px(3, 3), &[10, 20, 30, 255]);
+        assert_eq!(px(0, 0), &[10, 20, 30, 255]);
+    }
+
+    #[test]
+    fn cur_files_keep_the_hotspot() {
+        let data = cursor(CursorType::Color, 1, 1, 4, (300, 7), &[0, 0, 0, 0xFF]);
+        let cur = CursorShape::decode(&data).unwrap().to_cur().unwrap();
+        assert_eq!(&cur[10..14], &[44, 1, 7, 0]);
+
+        for hotspot in [(65536, 0), (0, u32::MAX)] {
+            let data = cursor(CursorType::Color, 1, 1, 4, hotspot, &[0, 0, 0, 0xFF]);
+            assert!(CursorShape::decode(&data).unwrap().to_cur().is_err());
+        }
+    }
+}
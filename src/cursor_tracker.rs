@@ -1,18 +1,70 @@
 use std::cell::{RefCell};
+use std::collections::VecDeque;
 use std::mem::size_of;
 use std::ops::DerefMut;
 use anyhow::{Context, ensure, Result};
+use glam::Vec2;
 use tao::event_loop::{EventLoop, EventLoopProxy};
 use windows::Win32::Foundation::{HINSTANCE, LPARAM, LRESULT, POINT, RECT, TRUE, WPARAM};
 use windows::Win32::Graphics::Gdi::{GetMonitorInfoW, HMONITOR, MONITOR_DEFAULTTONEAREST, MonitorFromPoint, MONITORINFO};
-use windows::Win32::UI::WindowsAndMessaging::{CallNextHookEx, GetCursorPos, HHOOK, MSLLHOOKSTRUCT, SetWindowsHookExW, UnhookWindowsHookEx, WH_MOUSE_LL, WM_MOUSEMOVE};
+use windows::Win32::UI::WindowsAndMessaging::{CallNextHookEx, GetCursorPos, HHOOK, KBDLLHOOKSTRUCT, MSLLHOOKSTRUCT, SetWindowsHookExW, UnhookWindowsHookEx, WH_KEYBOARD_LL, WH_MOUSE_LL, WM_KEYDOWN, WM_SYSKEYDOWN, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MBUTTONDOWN, WM_MBUTTONUP, WM_MOUSEHWHEEL, WM_MOUSEMOVE, WM_MOUSEWHEEL, WM_RBUTTONDOWN, WM_RBUTTONUP, WM_XBUTTONDOWN, WM_XBUTTONUP};
 use windows::Win32::Graphics::Gdi::HMONITOR as WinHMonitor;
 use crate::CustomEvent;
+use crate::input::{InputEvent, InputEventKind, MouseButton};
+use crate::keys::Key;
+use crate::zones::ZoneShape;
 
 struct CursorTrackerContext {
     current_monitor: HMONITOR,
     current_monitor_info: MONITORINFO,
-    event_loop_proxy: EventLoopProxy<CustomEvent>
+    event_loop_proxy: EventLoopProxy<CustomEvent>,
+    pending: VecDeque<InputEvent>,
+    notified: bool,
+    zones: Vec<(HMONITOR, ZoneShape)>,
+    active_zone: Option<usize>,
+    /// Only these keys are reported so the stream never contains regular typing
+    watched_keys: Vec<Key>,
+    pressed_keys: Vec<Key>,
+    /// Only installed while there are watched keys, so key strokes are not routed through this process for nothing
+    keyboard_hook: Option<HHOOK>
+}
+
+impl CursorTrackerContext {
+
+    /// Queues the event and notifies the event loop if this is the first event since the last `take_events`.
+    /// Consecutive moves are merged as only the most recent position matters
+    fn push(&mut self, event: InputEvent) {
+        match (self.pending.back_mut(), event.kind) {
+            (Some(last @ InputEvent { kind: InputEventKind::Move { .. }, .. }), InputEventKind::Move { .. }) => *last = event,
+            _ => self.pending.push_back(event)
+        }
+        if !self.notified {
+            self.notified = true;
+            if let Err(e) = self.event_loop_proxy.send_event(CustomEvent::InputAvailable) {
+                log::warn!("Cannot send event: {}", e);
+            }
+        }
+    }
+
+}
+
+fn button_from_message(msg: u32, event: &MSLLHOOKSTRUCT) -> Option<(MouseButton, bool)> {
+    //The high word of mouseData is XBUTTON1 (1) or XBUTTON2 (2)
+    let x_button = || match event.mouseData >> 16 {
+        1 => MouseButton::X1,
+        _ => MouseButton::X2
+    };
+    Some(match msg {
+        WM_LBUTTONDOWN => (MouseButton::Left, true),
+        WM_LBUTTONUP => (MouseButton::Left, false),
+        WM_RBUTTONDOWN => (MouseButton::Right, true),
+        WM_RBUTTONUP => (MouseButton::Right, false),
+        WM_MBUTTONDOWN => (MouseButton::Middle, true),
+        WM_MBUTTONUP => (MouseButton::Middle, false),
+        WM_XBUTTONDOWN => (x_button(), true),
+        WM_XBUTTONUP => (x_button(), false),
+        _ => return None
+    })
 }
 
 thread_local! {static CONTEXT: RefCell<Option<CursorTrackerContext>> = RefCell::new(None)}
@@ -37,26 +89,66 @@ fn get_monitor_info(monitor: HMONITOR) -> Option<MONITORINFO> {
 }
 
 unsafe extern "system" fn ll_mouse_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
-    if wparam.0 as u32 == WM_MOUSEMOVE {
-        let event = (lparam.0 as *const MSLLHOOKSTRUCT).read();
-        CONTEXT.with(|ctx| {
-           if let Some(ctx) = ctx.borrow_mut().deref_mut() {
+    let msg = wparam.0 as u32;
+    let event = (lparam.0 as *const MSLLHOOKSTRUCT).read();
+    CONTEXT.with(|ctx| {
+        if let Some(ctx) = ctx.borrow_mut().deref_mut() {
+            let time = event.time;
+            let position = event.pt;
+            if msg == WM_MOUSEMOVE {
                 if !contains(ctx.current_monitor_info.rcMonitor, event.pt) {
                     let monitor = MonitorFromPoint(event.pt, MONITOR_DEFAULTTONEAREST);
                     if monitor != ctx.current_monitor {
                         if let Some(info) = get_monitor_info(monitor) {
+                            ctx.push(InputEvent { time, kind: InputEventKind::MonitorLeave(ctx.current_monitor) });
+                            ctx.push(InputEvent { time, kind: InputEventKind::MonitorEnter(monitor) });
                             ctx.current_monitor_info = info;
                             ctx.current_monitor = monitor;
-                            //let monitor = WinHMonitor(monitor);
-                            if let Err(e) = ctx.event_loop_proxy.send_event(CustomEvent::CursorMonitorSwitch(monitor)){
-                                log::warn!("Cannot send event: {}", e);
-                            }
                         }
                     }
                 }
-           }
-        });
-    }
+                let origin = ctx.current_monitor_info.rcMonitor;
+                let local = POINT { x: position.x - origin.left, y: position.y - origin.top };
+                ctx.push(InputEvent { time, kind: InputEventKind::Move { position, local } });
+                let zone = ctx.zones
+                    .iter()
+                    .position(|(host, shape)| *host == ctx.current_monitor && shape.contains(origin, position));
+                if zone != ctx.active_zone {
+                    if let Some(old) = ctx.active_zone {
+                        ctx.push(InputEvent { time, kind: InputEventKind::ZoneLeave(old as u32) });
+                    }
+                    if let Some(new) = zone {
+                        ctx.push(InputEvent { time, kind: InputEventKind::ZoneEnter(new as u32) });
+                    }
+                    ctx.active_zone = zone;
+                }
+            } else if msg == WM_MOUSEWHEEL || msg == WM_MOUSEHWHEEL {
+                let delta = (event.mouseData >> 16) as u16 as i16 as i32;
+                ctx.push(InputEvent { time, kind: InputEventKind::Wheel { delta, horizontal: msg == WM_MOUSEHWHEEL, position } });
+            } else if let Some((button, pressed)) = button_from_message(msg, &event) {
+                ctx.push(InputEvent { time, kind: InputEventKind::Button { button, pressed, position } });
+            }
+        }
+    });
+    CallNextHookEx(HHOOK::default(), code, wparam, lparam)
+}
+
+unsafe extern "system" fn ll_keyboard_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    let event = (lparam.0 as *const KBDLLHOOKSTRUCT).read();
+    let key = Key(event.vkCode as u16);
+    let pressed = matches!(wparam.0 as u32, WM_KEYDOWN | WM_SYSKEYDOWN);
+    CONTEXT.with(|ctx| {
+        if let Some(ctx) = ctx.borrow_mut().deref_mut() {
+            //Auto-repeat sends more key downs, only changes are reported
+            if ctx.watched_keys.contains(&key) && ctx.pressed_keys.contains(&key) != pressed {
+                match pressed {
+                    true => ctx.pressed_keys.push(key),
+                    false => ctx.pressed_keys.retain(|k| *k != key)
+                }
+                ctx.push(InputEvent { time: event.time, kind: InputEventKind::Key { key, pressed } });
+            }
+        }
+    });
     CallNextHookEx(HHOOK::default(), code, wparam, lparam)
 }
 
@@ -65,12 +157,20 @@ pub struct CursorTrackerHandle(HHOOK);
 
 impl Drop for CursorTrackerHandle {
     fn drop(&mut self) {
-        CONTEXT.with(|ctx| ctx.replace(None));
+        let keyboard_hook = CONTEXT.with(|ctx| ctx.replace(None).and_then(|ctx| ctx.keyboard_hook));
         let result = unsafe { UnhookWindowsHookEx(self.0) == TRUE };
         log::trace!("Removing mouse hook (successful: {})", result);
+        if let Some(hook) = keyboard_hook {
+            remove_keyboard_hook(hook);
+        }
     }
 }
 
+fn remove_keyboard_hook(hook: HHOOK) {
+    let result = unsafe { UnhookWindowsHookEx(hook) == TRUE };
+    log::trace!("Removing keyboard hook (successful: {})", result);
+}
+
 fn get_current_monitor_sys() -> Option<HMONITOR> {
     unsafe {
         let mut pt = POINT::default();
@@ -82,6 +182,60 @@ fn get_current_monitor_sys() -> Option<HMONITOR> {
 
 }
 
+/// Removes all queued input events. Calling this acknowledges the last `InputAvailable` event
+pub fn take_events() -> Vec<InputEvent> {
+    CONTEXT.with(|ctx| ctx
+        .borrow_mut()
+        .as_mut()
+        .map(|ctx| {
+            ctx.notified = false;
+            ctx.pending.drain(..).collect()
+        })
+        .unwrap_or_default())
+}
+
+/// Replaces the trigger zones that are checked on every move. Each zone is given as the monitor it is placed on and its shape
+pub fn set_zones(zones: Vec<(HMONITOR, ZoneShape)>) {
+    CONTEXT.with(|ctx| if let Some(ctx) = ctx.borrow_mut().as_mut() {
+        ctx.zones = zones;
+        ctx.active_zone = None;
+    });
+}
+
+/// Replaces the keys whose presses and releases are reported.
+/// The keyboard hook is installed when the first key is watched and removed again when there are none left
+pub fn set_watched_keys(keys: Vec<Key>) -> Result<()> {
+    CONTEXT.with(|ctx| {
+        let mut ctx = ctx.borrow_mut();
+        let Some(ctx) = ctx.as_mut() else {
+            return Ok(());
+        };
+        match (keys.is_empty(), ctx.keyboard_hook) {
+            (false, None) => {
+                log::trace!("Installing keyboard hook");
+                ctx.keyboard_hook = Some(unsafe { SetWindowsHookExW(WH_KEYBOARD_LL, Some(ll_keyboard_proc), HINSTANCE::default(), 0)? });
+            },
+            (true, Some(hook)) => {
+                remove_keyboard_hook(hook);
+                ctx.keyboard_hook = None;
+            },
+            _ => {}
+        }
+        ctx.pressed_keys.retain(|k| keys.contains(k));
+        ctx.watched_keys = keys;
+        Ok(())
+    })
+}
+
+/// Converts `position` from virtual desktop coordinates into coordinates relative to the output at `desktop`.
+/// Both the hook and DXGI use physical pixels, so an offset is all that is needed.
+/// Returns `None` if the position is outside of the output
+pub fn output_relative(desktop: RECT, position: POINT) -> Option<Vec2> {
+    let inside = position.x >= desktop.left && position.x < desktop.right &&
+        position.y >= desktop.top && position.y < desktop.bottom;
+    inside.then(|| Vec2::new((position.x - desktop.left) as f32, (position.y - desktop.top) as f32))
+}
+
 pub fn get_current_monitor() -> Option<WinHMonitor> {
     get_current_monitor_sys()
 }
@@ -98,13 +252,64 @@ pub fn set_hook(event_loop: &EventLoop<CustomEvent>) -> Result<CursorTrackerHand
                 current_monitor: monitor,
                 current_monitor_info: info,
                 event_loop_proxy: event_loop.create_proxy(),
+                pending: VecDeque::new(),
+                notified: false,
+                zones: Vec::new(),
+                active_zone: None,
+                watched_keys: Vec::new(),
+                pressed_keys: Vec::new(),
+                keyboard_hook: None,
             });
             true
         } else {
             false
         }
     }), "It seems like there is already a hook in place for this thread");
-    let hook = unsafe { SetWindowsHookExW(WH_MOUSE_LL, Some(ll_mouse_proc), HINSTANCE::default(), 0)? };
+    let mouse_hook = unsafe { SetWindowsHookExW(WH_MOUSE_LL, Some(ll_mouse_proc), HINSTANCE::default(), 0)? };
+
+    Ok(CursorTrackerHandle(mouse_hook))
+}
 
-    Ok(CursorTrackerHandle(hook))
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(left: i32, top: i32, right: i32, bottom: i32) -> RECT {
+        RECT { left, top, right, bottom }
+    }
+
+    //A 150% 4k output left of and above a 100% 1080p primary output. The desktop rects are in physical pixels
+    const PRIMARY: RECT = RECT { left: 0, top: 0, right: 1920, bottom: 1080 };
+    const SECONDARY: RECT = RECT { left: -3840, top: -400, right: 0, bottom: 1760 };
+
+    #[test]
+    fn negative_origins_are_offset() {
+        assert_eq!(output_relative(SECONDARY, POINT { x: -3840, y: -400 }), Some(Vec2::new(0.0, 0.0)));
+        assert_eq!(output_relative(SECONDARY, POINT { x: -1, y: 1759 }), Some(Vec2::new(3839.0, 2159.0)));
+        assert_eq!(output_relative(SECONDARY, POINT { x: -1920, y: 0 }), Some(Vec2::new(1920.0, 400.0)));
+    }
+
+    #[test]
+    fn edges_belong_to_exactly_one_output() {
+        for point in [POINT { x: 0, y: 0 }, POINT { x: -1, y: 0 }, POINT { x: 0, y: 1079 }, POINT { x: -1, y: 1080 }] {
+            let hits = [PRIMARY, SECONDARY]
+                .into_iter()
+                .filter(|output| output_relative(*output, point).is_some())
+                .count();
+            assert_eq!(hits, 1, "{:?}", point);
+        }
+        assert_eq!(output_relative(PRIMARY, POINT { x: 0, y: 0 }), Some(Vec2::ZERO));
+        assert_eq!(output_relative(SECONDARY, POINT { x: 0, y: 0 }), None);
+    }
+
+    #[test]
+    fn mixed_dpi_does_not_scale() {
+        //The same physical offset maps to the same output position on both outputs regardless of their scale factor
+        let primary = output_relative(PRIMARY, POINT { x: 100, y: 200 }).unwrap();
+        let secondary = output_relative(SECONDARY, POINT { x: -3840 + 100, y: -400 + 200 }).unwrap();
+        assert_eq!(primary, secondary);
+        assert_eq!(output_relative(PRIMARY, POINT { x: 1920, y: 500 }), None);
+        assert_eq!(output_relative(PRIMARY, POINT { x: 500, y: -1 }), None);
+        assert_eq!(output_relative(rect(-10, -10, -10, 0), POINT { x: -10, y: -5 }), None);
+    }
+}
@@ -45,6 +45,13 @@ impl Adapter {
     pub fn get_display_by_handle(&self, handle: HMONITOR) -> Option<Display> {
         self.iter_displays().find(|d|d.hmonitor().ok() == Some(handle))
     }
+
+    /// Accepts the full DXGI name like `\\.\DISPLAY1` or just `DISPLAY1`
+    pub fn get_display_by_name(&self, name: &str) -> Option<Display> {
+        self.iter_displays().find(|d| d
+            .name()
+            .is_ok_and(|n| n == name || n.trim_start_matches(r"\\.\") == name))
+    }
 }
 
 #[repr(C)]
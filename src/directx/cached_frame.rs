@@ -0,0 +1,186 @@
+use anyhow::Result;
+use error_tools::log::LogResultExt;
+use glam::Mat4;
+use windows::Win32::Graphics::Direct3D::D3D_SRV_DIMENSION_TEXTURE2D;
+use windows::Win32::Graphics::Direct3D11::*;
+use windows::Win32::Graphics::Dxgi::Common::DXGI_SAMPLE_DESC;
+use crate::directx::{Direct3D, QuadRenderer, Rect, Shape};
+use crate::image_io::Area;
+use crate::redaction::{Redaction, RedactionStyle};
+use crate::utils::{make_blend_state, make_resource, retrieve};
+
+/// Views of the cache texture that are needed to draw into its top level
+struct RedactionViews {
+    target: ID3D11RenderTargetView,
+    /// One view per mip level
+    levels: Vec<ID3D11ShaderResourceView>,
+    blend_state: ID3D11BlendState
+}
+
+/// Copy of the last duplicated frame including a full mip chain for downscaling
+pub struct CachedFrame {
+    resource: Option<(ID3D11Texture2D, ID3D11ShaderResourceView)>,
+    redaction_views: Option<RedactionViews>,
+    valid: bool
+}
+
+impl CachedFrame {
+
+    pub fn new() -> Self {
+        Self {
+            resource: None,
+            redaction_views: None,
+            valid: false,
+        }
+    }
+
+    pub fn get_view(&self) -> Option<&ID3D11ShaderResourceView> {
+        match self.valid {
+            true => self.resource.as_ref().map(|r| &r.1),
+            false => None
+        }
+    }
+
+    pub fn get_texture(&self) -> Option<&ID3D11Texture2D> {
+        match self.valid {
+            true => self.resource.as_ref().map(|r| &r.0),
+            false => None
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.valid = false;
+    }
+
+    pub fn update(&mut self, device: &ID3D11Device, context: &ID3D11DeviceContext4, frame: &ID3D11Texture2D) {
+        let frame_desc = retrieve(frame, ID3D11Texture2D::GetDesc);
+        self.valid = true;
+        let recreate = match &self.resource {
+            None => true,
+            Some((cache, _)) => {
+                let cache_desc = retrieve(cache, ID3D11Texture2D::GetDesc);
+                frame_desc.Width != cache_desc.Width ||
+                    frame_desc.Height != cache_desc.Height ||
+                    frame_desc.Format != cache_desc.Format
+            }
+        };
+        if recreate {
+            log::trace!("Creating new cache texture {}x{}", frame_desc.Width, frame_desc.Height);
+            let tex = make_resource(|ptr| unsafe {
+                device.CreateTexture2D(&D3D11_TEXTURE2D_DESC {
+                    MipLevels: 0,
+                    ArraySize: 1,
+                    SampleDesc: DXGI_SAMPLE_DESC {
+                        Count: 1,
+                        Quality: 0,
+                    },
+                    Usage: D3D11_USAGE_DEFAULT,
+                    BindFlags: D3D11_BIND_SHADER_RESOURCE | D3D11_BIND_RENDER_TARGET,
+                    CPUAccessFlags: Default::default(),
+                    MiscFlags: D3D11_RESOURCE_MISC_GENERATE_MIPS,
+                    ..frame_desc
+                }, None, ptr)
+            }).log_ok("Failed to create new texture");
+            let srv = tex.as_ref().and_then(|tex|
+                make_resource(|ptr| unsafe {
+                    device.CreateShaderResourceView(tex, None, ptr)
+                }).log_ok("Failed to create new shader resource view"));
+            self.resource = tex.zip(srv);
+            self.redaction_views = None;
+        }
+        if let Some((cache, srv)) = &self.resource {
+            unsafe {
+                //The mip counts differ so CopyResource can not be used here
+                context.CopySubresourceRegion(cache, 0, 0, 0, 0, frame, 0, None);
+                context.GenerateMips(srv);
+            }
+        }
+    }
+
+    fn create_redaction_views(device: &ID3D11Device, texture: &ID3D11Texture2D) -> Result<RedactionViews> {
+        let desc = retrieve(texture, ID3D11Texture2D::GetDesc);
+        let target = make_resource(|ptr| unsafe {
+            device.CreateRenderTargetView(texture, None, ptr)
+        })?;
+        let levels = (0..desc.MipLevels)
+            .map(|level| make_resource(|ptr| unsafe {
+                device.CreateShaderResourceView(texture, Some(&D3D11_SHADER_RESOURCE_VIEW_DESC {
+                    Format: desc.Format,
+                    ViewDimension: D3D_SRV_DIMENSION_TEXTURE2D,
+                    Anonymous: D3D11_SHADER_RESOURCE_VIEW_DESC_0 {
+                        Texture2D: D3D11_TEX2D_SRV {
+                            MostDetailedMip: level,
+                            MipLevels: 1,
+                        },
+                    },
+                }), ptr)
+            }))
+            .collect::<Result<_>>()?;
+        let blend_state = make_blend_state(device, D3D11_BLEND_ONE, D3D11_BLEND_ZERO)?;
+        Ok(RedactionViews { target, levels, blend_state })
+    }
+
+    /// Covers the `areas` (in frame pixels) of the top level and regenerates the other levels from it.
+    /// Blurring and pixelation sample the smaller levels, so they never read the pixels they overwrite
+    pub fn redact(&mut self, d3d: &Direct3D, renderer: &QuadRenderer, areas: &[(Area, Redaction)]) -> Result<()> {
+        let Some((texture, srv)) = self.resource.as_ref().filter(|_| self.valid && !areas.is_empty()) else {
+            return Ok(());
+        };
+        if self.redaction_views.is_none() {
+            self.redaction_views = Some(Self::create_redaction_views(&d3d.device, texture)?);
+        }
+        let views = self.redaction_views.as_ref().expect("The views were just created");
+        let desc = retrieve(texture, ID3D11Texture2D::GetDesc);
+        let (width, height) = (desc.Width as f32, desc.Height as f32);
+        //Level zero can not be sampled while it is the render target
+        let level_for = |size: u32| ((size.max(2) as f32).log2().floor() as usize).clamp(1, views.levels.len().max(2) - 1);
+        let framespace = Mat4::orthographic_rh(0.0, width, height, 0.0, -1.0, 1.0);
+        unsafe {
+            d3d.context.OMSetRenderTargets(Some(&[views.target.clone()]), None);
+            d3d.context.RSSetViewports(Some(&[D3D11_VIEWPORT {
+                Width: width,
+                Height: height,
+                MaxDepth: 1.0,
+                ..Default::default()
+            }]));
+            d3d.context.OMSetBlendState(&views.blend_state, None, u32::MAX);
+        }
+        renderer.bind(d3d);
+        renderer.set_clip(d3d, None);
+        for (area, redaction) in areas {
+            let rect = Rect::new(area.x as f32, area.y as f32, area.width as f32, area.height as f32);
+            let uv = Rect::new(rect.x / width, rect.y / height, rect.width / width, rect.height / height);
+            let transform = framespace * rect.transform();
+            let style = match redaction.style {
+                //A texture without smaller levels has nothing to sample, so the area is filled rather than left visible
+                RedactionStyle::Blur | RedactionStyle::Pixelate if views.levels.len() < 2 => RedactionStyle::Fill,
+                style => style
+            };
+            match style {
+                RedactionStyle::Fill => {
+                    let color = redaction.color;
+                    renderer.draw_shape(d3d, framespace, &Shape {
+                        rect,
+                        color: [color.r as f32 / 255.0, color.g as f32 / 255.0, color.b as f32 / 255.0, 1.0],
+                        ..Default::default()
+                    });
+                }
+                RedactionStyle::Blur => {
+                    let level = level_for(redaction.size);
+                    renderer.draw_region(d3d, transform, &views.levels[level], uv, [1.0; 4]);
+                }
+                RedactionStyle::Pixelate => {
+                    let level = level_for(redaction.size);
+                    let block = redaction.size.max(2) as f32 / (1u32 << level) as f32;
+                    renderer.draw_pixelated(d3d, transform, &views.levels[level], uv, block);
+                }
+            }
+        }
+        unsafe {
+            d3d.context.OMSetRenderTargets(None, None);
+            d3d.context.GenerateMips(srv);
+        }
+        Ok(())
+    }
+
+}
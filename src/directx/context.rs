@@ -12,12 +12,34 @@ use windows::Win32::Graphics::Dxgi::Common::*;
 use crate::directx::Adapter;
 use crate::utils::make_resource;
 
+/// Creates a device without any window, e.g. for capturing from the command line
+pub fn create_device(adapter: &Adapter) -> Result<(ID3D11Device, ID3D11DeviceContext4)> {
+    let mut d3d_device = None;
+    let mut d3d_ctx = None;
+    unsafe {
+        D3D11CreateDevice(
+            adapter.as_raw_ref(),
+            D3D_DRIVER_TYPE_UNKNOWN,
+            None,
+            D3D11_CREATE_DEVICE_FLAG(0),
+            Some(&[D3D_FEATURE_LEVEL_11_1]),
+            D3D11_SDK_VERSION,
+            Some(&mut d3d_device),
+            None,
+            Some(&mut d3d_ctx),
+        )?;
+    }
+    Ok((d3d_device.some()?, d3d_ctx.some()?.cast::<ID3D11DeviceContext4>()?))
+}
+
 pub struct Direct3D {
     pub device: ID3D11Device,
     pub context: ID3D11DeviceContext4,
     pub swap_chain: IDXGISwapChain1,
     render_target: Option<ID3D11RenderTargetView>,
-    _comp_device: IDCompositionDevice,
+    opacity: f32,
+    comp_device: IDCompositionDevice,
+    comp_effect: IDCompositionEffectGroup,
     _comp_target: IDCompositionTarget,
     _comp_visual: IDCompositionVisual,
 }
@@ -25,23 +47,7 @@ pub struct Direct3D {
 impl Direct3D {
 
     pub fn new(adapter: &Adapter, window: &Window) -> Result<Self> {
-        let mut d3d_device = None;
-        let mut d3d_ctx = None;
-        unsafe {
-            D3D11CreateDevice(
-                adapter.as_raw_ref(),
-                D3D_DRIVER_TYPE_UNKNOWN,
-                None,
-                D3D11_CREATE_DEVICE_FLAG(0),
-                Some(&[D3D_FEATURE_LEVEL_11_1]),
-                D3D11_SDK_VERSION,
-                Some(&mut d3d_device),
-                None,
-                Some(&mut d3d_ctx),
-            )?;
-        }
-        let d3d_device = d3d_device.some()?;
-        let d3d_ctx = d3d_ctx.some()?.cast::<ID3D11DeviceContext4>()?;
+        let (d3d_device, d3d_ctx) = create_device(adapter)?;
 
         let dxgi_device: IDXGIDevice = d3d_device.cast()?;
 
@@ -71,14 +77,16 @@ impl Direct3D {
         };
 
 
-       let (comp_device, comp_target, comp_visual) = unsafe {
+       let (comp_device, comp_effect, comp_target, comp_visual) = unsafe {
            let device: IDCompositionDevice = DCompositionCreateDevice(&dxgi_device)?;
            let target: IDCompositionTarget = device.CreateTargetForHwnd(HWND(window.hwnd() as _), TRUE)?;
            let visual: IDCompositionVisual = device.CreateVisual()?;
+           let effect: IDCompositionEffectGroup = device.CreateEffectGroup()?;
            visual.SetContent(&swap_chain)?;
+           visual.SetEffect(&effect)?;
            target.SetRoot(&visual)?;
            device.Commit()?;
-           (device, target, visual)
+           (device, effect, target, visual)
        };
 
         let rtv = make_resource(|ptr| unsafe {
@@ -91,7 +99,9 @@ impl Direct3D {
             context: d3d_ctx,
             swap_chain,
             render_target: Some(rtv),
-            _comp_device: comp_device,
+            opacity: 1.0,
+            comp_device,
+            comp_effect,
             _comp_target: comp_target,
             _comp_visual: comp_visual,
         })
@@ -113,4 +123,16 @@ impl Direct3D {
         }
     }
 
+    /// Sets the opacity of the whole overlay. This is applied by DirectComposition after presenting
+    pub fn set_opacity(&mut self, opacity: f32) -> Result<()> {
+        if self.opacity != opacity {
+            unsafe {
+                self.comp_effect.SetOpacity2(opacity)?;
+                self.comp_device.Commit()?;
+            }
+            self.opacity = opacity;
+        }
+        Ok(())
+    }
+
 }
\ No newline at end of file
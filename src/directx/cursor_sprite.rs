@@ -3,29 +3,35 @@ use std::mem::size_of;
 use anyhow::Result;
 use windows::Win32::Graphics::Direct3D11::{D3D11_BIND_RENDER_TARGET, D3D11_BIND_SHADER_RESOURCE, D3D11_CPU_ACCESS_FLAG, D3D11_RESOURCE_MISC_GENERATE_MIPS, D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT, ID3D11Device, ID3D11DeviceContext4, ID3D11ShaderResourceView, ID3D11Texture2D};
 use windows::Win32::Graphics::Dxgi::Common::{DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_SAMPLE_DESC};
+use crate::cursor_shape::CursorShape;
 use crate::directx::{CursorData, CursorType};
-use crate::utils::{make_resource, U8Iter};
+use crate::utils::make_resource;
 
 pub struct CursorSprite {
     pub valid: bool,
     pub cursor_type: CursorType,
     pub width: u32,
     pub height: u32,
-    pub norm: (ID3D11Texture2D, ID3D11ShaderResourceView),
-    pub mask: (ID3D11Texture2D, ID3D11ShaderResourceView),
+    pub hotspot: (u32, u32),
+    /// Whether the shape has pixels that invert the screen
+    pub has_xor: bool,
+    pub color: (ID3D11Texture2D, ID3D11ShaderResourceView),
+    pub xor: (ID3D11Texture2D, ID3D11ShaderResourceView),
 }
 
 impl CursorSprite {
 
-    pub fn norm_srv(&self) -> &ID3D11ShaderResourceView {
+    /// The alpha blended pixels of the shape, premultiplied
+    pub fn color_srv(&self) -> &ID3D11ShaderResourceView {
         debug_assert!(self.valid);
-        &self.norm.1
+        &self.color.1
     }
 
-    pub fn mask_srv(&self) -> &ID3D11ShaderResourceView {
+    /// The xor value of the pixels that invert the screen
+    pub fn xor_srv(&self) -> &ID3D11ShaderResourceView {
         debug_assert!(self.valid);
-        debug_assert!(matches!(self.cursor_type, CursorType::MaskedColor | CursorType::Monochrome));
-        &self.mask.1
+        debug_assert!(self.has_xor);
+        &self.xor.1
     }
 
     pub fn new(device: &ID3D11Device, width: u32, height: u32) -> Result<Self> {
@@ -35,54 +41,31 @@ impl CursorSprite {
             cursor_type: CursorType::Color,
             width,
             height,
-            norm: make_texture(device, width, height)?,
-            mask: make_texture(device, width, height)?,
+            hotspot: (0, 0),
+            has_xor: false,
+            color: make_texture(device, width, height)?,
+            xor: make_texture(device, width, height)?,
         })
     }
 
     pub fn update(&mut self, device: &ID3D11Device, context: &ID3D11DeviceContext4, data: &CursorData) -> Result<()> {
-        self.resize(device, data.width, data.height)?;
-        self.update_content(context, data.cursor_type, data.data.as_slice());
+        let shape = CursorShape::decode(data)?;
+        self.resize(device, shape.width, shape.height)?;
+        self.update_content(context, &shape);
         Ok(())
     }
 
-    fn update_content(&mut self, context: &ID3D11DeviceContext4, cursor_type: CursorType, data: &[u8]) {
-        self.cursor_type = cursor_type;
-        match cursor_type {
-            CursorType::Monochrome => {
-                assert_eq!((2 * self.height * self.width / u8::BITS) as usize, data.len());
-                let (and_mask, xor_mask) = data.split_at((self.height * self.width / u8::BITS) as usize);
-                assert_eq!(and_mask.len(), xor_mask.len());
-                let and_buffer: Vec<u32> = and_mask
-                    .iter()
-                    .flat_map(|mask|U8Iter::new(*mask))
-                    .map(|b | if b {0xFFFFFFFF} else {0xFF000000})
-                    .collect();
-
-                let xor_buffer: Vec<u32> = xor_mask
-                    .iter()
-                    .flat_map(|mask|U8Iter::new(*mask))
-                    .map(|b | if b {0x00FFFFFF} else {0x00000000})
-                    .collect();
-
-                self.update_textures(context, Some(and_buffer.as_ptr() as _), Some(xor_buffer.as_ptr() as _));
-            },
-            CursorType::Color => {
-                assert_eq!((self.height * self.width) as usize * size_of::<u32>(), data.len());
-                let buffer: Vec<u32> = bgra_to_rgba(data)
-                    .collect();
-                self.update_textures(context, Some(buffer.as_ptr() as _), None)
-            },
-            CursorType::MaskedColor => {
-                assert_eq!((self.height * self.width) as usize * size_of::<u32>(), data.len());
-                let (color_buffer, xor_buffer): (Vec<u32>, Vec<u32>) = bgra_to_rgba(data)
-                    .map(|c| match (c & 0xFF000000) != 0 {
-                        true => (c & 0x00FFFFFF, c & 0x00FFFFFF),
-                        false => (c | 0xFF000000, 0xFF000000)
-                    })
-                    .unzip();
-
-                self.update_textures(context, Some(color_buffer.as_ptr() as _), Some(xor_buffer.as_ptr() as _));
+    /// Uploads the layers of the decoded shape. The xor layer is only needed if the shape inverts parts of the screen
+    fn update_content(&mut self, context: &ID3D11DeviceContext4, shape: &CursorShape) {
+        self.cursor_type = shape.cursor_type;
+        self.hotspot = shape.hotspot;
+        self.has_xor = shape.has_xor();
+        let color = shape.color_layer();
+        match self.has_xor {
+            false => self.update_textures(context, Some(color.as_ptr() as _), None),
+            true => {
+                let xor = shape.xor_layer();
+                self.update_textures(context, Some(color.as_ptr() as _), Some(xor.as_ptr() as _));
             }
         }
         self.valid = true;
@@ -95,17 +78,17 @@ impl CursorSprite {
         Ok(())
     }
 
-    fn update_textures(&self, context: &ID3D11DeviceContext4, norm: Option<*const c_void>, mask: Option<*const c_void>){
+    fn update_textures(&self, context: &ID3D11DeviceContext4, color: Option<*const c_void>, xor: Option<*const c_void>){
         unsafe {
             let row_pitch = size_of::<u32>() as u32 * self.width;
             let depth_pitch = row_pitch * self.height;
-            if let Some(buf) = norm {
-                let (tex, srv) = &self.norm;
+            if let Some(buf) = color {
+                let (tex, srv) = &self.color;
                 context.UpdateSubresource(tex, 0, None, buf, row_pitch,depth_pitch);
                 context.GenerateMips(srv);
             }
-            if let Some(buf) = mask {
-                let (tex, srv) = &self.mask;
+            if let Some(buf) = xor {
+                let (tex, srv) = &self.xor;
                 context.UpdateSubresource(tex, 0, None, buf, row_pitch,depth_pitch);
                 context.GenerateMips(srv);
             }
@@ -114,12 +97,6 @@ impl CursorSprite {
 
 }
 
-fn bgra_to_rgba(bytes: &[u8]) -> impl Iterator<Item=u32> + '_ {
-    bytes
-        .chunks_exact(size_of::<u32>())
-        .map(|b| u32::from_le_bytes([b[2], b[1], b[0], b[3]]))
-}
-
 fn make_texture(device: &ID3D11Device, width: u32, height: u32) -> Result<(ID3D11Texture2D, ID3D11ShaderResourceView)> {
     let tex = make_resource(|ptr| unsafe {
         device.CreateTexture2D(&D3D11_TEXTURE2D_DESC {
@@ -6,8 +6,10 @@ use windows::Win32::Graphics::Dxgi::Common::*;
 use windows::Win32::System::StationsAndDesktops::*;
 use anyhow::{Context, Result};
 use error_tools::SomeOptionExt;
+use std::time::Duration;
+use windows::Win32::System::Performance::{QueryPerformanceCounter, QueryPerformanceFrequency};
 use windows::Win32::System::SystemServices::GENERIC_READ;
-use crate::directx::{Display, DisplayMode};
+use crate::directx::{CursorData, CursorType, Display, DisplayMode, MoveRect};
 
 #[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
 pub struct AcquisitionResults {
@@ -24,6 +26,9 @@ pub struct DesktopDuplication {
     frame: Option<ID3D11Texture2D>,
     cursor_pos: Option<POINT>,
     cursor_data: Option<CursorData>,
+    move_rects: Vec<MoveRect>,
+    last_present_time: i64,
+    timeouts: u64,
 }
 
 impl DesktopDuplication {
@@ -38,6 +43,9 @@ impl DesktopDuplication {
             frame: None,
             cursor_pos: None,
             cursor_data: None,
+            move_rects: Vec::new(),
+            last_present_time: 0,
+            timeouts: 0,
         })
     }
 
@@ -64,7 +72,10 @@ impl DesktopDuplication {
                     self.reacquire_dup()?;
                     Err(e.into())
                 }
-                DXGI_ERROR_WAIT_TIMEOUT => Ok(result),
+                DXGI_ERROR_WAIT_TIMEOUT => {
+                    self.timeouts += 1;
+                    Ok(result)
+                },
                 _ => Err(e.into())
             }
         }
@@ -75,6 +86,31 @@ impl DesktopDuplication {
         }
         result.success = true;
         result.frame_update = frame_info.AccumulatedFrames != 0 || frame_info.TotalMetadataBufferSize != 0 || frame_info.LastPresentTime != 0;
+        if frame_info.LastPresentTime != 0 {
+            self.last_present_time = frame_info.LastPresentTime;
+        }
+
+        self.move_rects.clear();
+        if frame_info.TotalMetadataBufferSize != 0 {
+            //The metadata buffer holds the move and the dirty rects, so it is always large enough for the move rects
+            let size = std::mem::size_of::<DXGI_OUTDUPL_MOVE_RECT>();
+            let mut rects = vec![DXGI_OUTDUPL_MOVE_RECT::default(); (frame_info.TotalMetadataBufferSize as usize).div_ceil(size)];
+            let mut used_size = 0;
+            unsafe {
+                dupl.GetFrameMoveRects(
+                    (rects.len() * size) as u32,
+                    rects.as_mut_ptr(),
+                    &mut used_size)?;
+            }
+            rects.truncate(used_size as usize / size);
+            self.move_rects.extend(rects.iter().map(|rect| MoveRect {
+                source: (rect.SourcePoint.x.max(0) as u32, rect.SourcePoint.y.max(0) as u32),
+                destination: (rect.DestinationRect.left.max(0) as u32, rect.DestinationRect.top.max(0) as u32),
+                size: (
+                    (rect.DestinationRect.right - rect.DestinationRect.left).max(0) as u32,
+                    (rect.DestinationRect.bottom - rect.DestinationRect.top).max(0) as u32),
+            }));
+        }
 
 
         if frame_info.PointerShapeBufferSize != 0 {
@@ -82,6 +118,8 @@ impl DesktopDuplication {
                 cursor_type: CursorType::Color,
                 width: 0,
                 height: 0,
+                pitch: 0,
+                hotspot: (0, 0),
                 data: vec![0u8; frame_info.PointerShapeBufferSize as usize],
             });
             cursor_data.data.resize(frame_info.PointerShapeBufferSize as usize, 0u8);
@@ -100,6 +138,9 @@ impl DesktopDuplication {
                 CursorType::Monochrome => info.Height / 2,
                 _ => info.Height
             };
+            cursor_data.pitch = info.Pitch;
+            cursor_data.hotspot = (info.HotSpot.x.max(0) as u32, info.HotSpot.y.max(0) as u32);
+            cursor_data.data.truncate(used_size as usize);
             result.cursor_updated = true;
         }
 
@@ -118,14 +159,49 @@ impl DesktopDuplication {
         self.frame.as_ref()
     }
 
+    /// Position of the pointer hotspot in desktop coordinates of the output
     pub fn get_cursor_pos(&self) -> Option<POINT> {
-        self.cursor_pos
+        //DXGI reports the top-left corner of the pointer shape
+        let (hx, hy) = self.cursor_data
+            .as_ref()
+            .map(|data| data.hotspot)
+            .unwrap_or_default();
+        self.cursor_pos.map(|pos| POINT {
+            x: pos.x + hx as i32,
+            y: pos.y + hy as i32,
+        })
     }
 
     pub fn get_cursor_data(&self) -> Option<&CursorData> {
         self.cursor_data.as_ref()
     }
 
+    /// Areas the desktop moved in the most recently acquired frame. They have to be applied before the dirty areas
+    pub fn get_move_rects(&self) -> &[MoveRect] {
+        &self.move_rects
+    }
+
+    /// Number of times `try_acquire_next_frame` did not find a new frame
+    pub fn get_timeout_count(&self) -> u64 {
+        self.timeouts
+    }
+
+    /// Time since the desktop presented the most recent frame
+    pub fn get_frame_age(&self) -> Option<Duration> {
+        if self.last_present_time == 0 {
+            return None;
+        }
+        let mut now = 0;
+        let mut frequency = 0;
+        unsafe {
+            if !QueryPerformanceCounter(&mut now).as_bool() || !QueryPerformanceFrequency(&mut frequency).as_bool() {
+                return None;
+            }
+        }
+        let ticks = now.saturating_sub(self.last_present_time).max(0) as u64;
+        Some(Duration::from_secs_f64(ticks as f64 / frequency.max(1) as f64))
+    }
+
     pub fn get_display_mode(&self) -> DisplayMode {
         self.display_mode
     }
@@ -169,13 +245,6 @@ impl DesktopDuplication {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum CursorType {
-    Color,
-    Monochrome,
-    MaskedColor
-}
-
 impl From<u32> for CursorType {
     fn from(value: u32) -> Self {
         match DXGI_OUTDUPL_POINTER_SHAPE_TYPE(value as i32) {
@@ -186,11 +255,3 @@ impl From<u32> for CursorType {
         }
     }
 }
-
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub struct CursorData {
-    pub cursor_type: CursorType,
-    pub width: u32,
-    pub height: u32,
-    pub data: Vec<u8>
-}
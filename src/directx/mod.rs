@@ -1,13 +1,39 @@
+mod types;
+#[cfg(windows)]
 mod adapter;
+#[cfg(windows)]
 mod output;
+#[cfg(windows)]
 mod duplication;
+#[cfg(windows)]
 mod context;
+#[cfg(windows)]
 mod quad_renderer;
+#[cfg(windows)]
 mod cursor_sprite;
+#[cfg(windows)]
+mod cached_frame;
+#[cfg(windows)]
+mod text_renderer;
+#[cfg(windows)]
+mod readback;
 
+pub use types::*;
+#[cfg(windows)]
 pub use adapter::*;
+#[cfg(windows)]
 pub use output::*;
+#[cfg(windows)]
 pub use duplication::*;
+#[cfg(windows)]
 pub use context::*;
+#[cfg(windows)]
 pub use quad_renderer::*;
-pub use cursor_sprite::*;
\ No newline at end of file
+#[cfg(windows)]
+pub use cursor_sprite::*;
+#[cfg(windows)]
+pub use cached_frame::*;
+#[cfg(windows)]
+pub use text_renderer::*;
+#[cfg(windows)]
+pub use readback::*;
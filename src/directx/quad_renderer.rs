@@ -1,10 +1,11 @@
 use std::mem::size_of;
 use windows::Win32::Graphics::Direct3D11::{D3D11_APPEND_ALIGNED_ELEMENT, D3D11_BIND_CONSTANT_BUFFER, D3D11_BIND_INDEX_BUFFER, D3D11_BIND_VERTEX_BUFFER, D3D11_BUFFER_DESC, D3D11_FILTER_MIN_MAG_MIP_LINEAR, D3D11_INPUT_ELEMENT_DESC, D3D11_INPUT_PER_VERTEX_DATA, D3D11_SAMPLER_DESC, D3D11_SUBRESOURCE_DATA, D3D11_TEXTURE_ADDRESS_CLAMP, D3D11_USAGE_DEFAULT, ID3D11Buffer, ID3D11InputLayout, ID3D11PixelShader, ID3D11SamplerState, ID3D11ShaderResourceView, ID3D11VertexShader};
 use anyhow::Result;
-use glam::Mat4;
+use glam::{Mat4, Quat, vec3};
 use windows::Win32::Graphics::Direct3D::D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST;
 use windows::Win32::Graphics::Dxgi::Common::{DXGI_FORMAT_R32_UINT, DXGI_FORMAT_R32G32_FLOAT, DXGI_FORMAT_R32G32B32_FLOAT};
 use crate::directx::Direct3D;
+use crate::scaling::{LUT_SIZE, ScalingFilter};
 use crate::utils::make_resource;
 
 #[repr(C)]
@@ -28,14 +29,140 @@ const VERTICES: [Vertex; 4] = [
 
 const INDICES: [u32; 6] = [0, 2, 1, 3, 1, 2];
 
+#[repr(C)]
+struct ObjectConstants {
+    transform: Mat4,
+    params: [f32; 4],
+    uv_rect: [f32; 4],
+    tint: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ShapeConstants {
+    rect: [f32; 4],
+    params: [f32; 4],
+    color: [f32; 4],
+    hole: [f32; 4],
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32
+}
+
+impl Rect {
+
+    pub const UNIT: Self = Self::new(0.0, 0.0, 1.0, 1.0);
+
+    pub const fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    pub fn expand(self, amount: f32) -> Self {
+        Self::new(self.x - amount, self.y - amount, self.width + 2.0 * amount, self.height + 2.0 * amount)
+    }
+
+    pub fn offset(self, x: f32, y: f32) -> Self {
+        Self::new(self.x + x, self.y + y, self.width, self.height)
+    }
+
+    /// Transform that maps the unit quad onto this rect
+    pub fn transform(self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(
+            vec3(self.width, self.height, 1.0),
+            Quat::IDENTITY,
+            vec3(self.x, self.y, 0.0))
+    }
+
+    fn to_array(self) -> [f32; 4] {
+        [self.x, self.y, self.width, self.height]
+    }
+
+}
+
+/// A rounded rectangle in window pixels
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Shape {
+    pub rect: Rect,
+    pub corner_radius: f32,
+    /// Only draw an outline of this width. Zero draws a filled shape
+    pub border_width: f32,
+    /// Softens the edge by this amount of pixels
+    pub blur: f32,
+    /// Premultiplied rgba
+    pub color: [f32; 4],
+    /// Circle that is left empty; Its edge is softened by `blur` as well
+    pub hole: Option<Circle>
+}
+
+/// A circle in window pixels
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Circle {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32
+}
+
+impl Circle {
+
+    pub const fn new(x: f32, y: f32, radius: f32) -> Self {
+        Self { x, y, radius }
+    }
+
+    /// Square that contains the circle
+    pub fn bounds(self) -> Rect {
+        Rect::new(self.x - self.radius, self.y - self.radius, 2.0 * self.radius, 2.0 * self.radius)
+    }
+
+}
+
+impl Shape {
+    fn constants(&self, clip: bool) -> ShapeConstants {
+        ShapeConstants {
+            rect: self.rect.to_array(),
+            params: [self.corner_radius, self.border_width, self.blur, if clip { 1.0 } else { 0.0 }],
+            color: self.color,
+            hole: self.hole
+                .map(|c| [c.x, c.y, c.radius, 0.0])
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[repr(C)]
+struct FilterConstants {
+    params: [f32; 4],
+    lut: [f32; LUT_SIZE],
+}
+
+impl From<ScalingFilter> for FilterConstants {
+    fn from(filter: ScalingFilter) -> Self {
+        Self {
+            params: [filter.radius(), 0.0, 0.0, 0.0],
+            lut: filter.lookup_table(),
+        }
+    }
+}
+
 pub struct QuadRenderer {
     vertex_buffer: ID3D11Buffer,
     index_buffer: ID3D11Buffer,
     vertex_shader: ID3D11VertexShader,
     pixel_shader: ID3D11PixelShader,
+    filtered_pixel_shader: ID3D11PixelShader,
+    shape_pixel_shader: ID3D11PixelShader,
+    pixelate_pixel_shader: ID3D11PixelShader,
     input_layout: ID3D11InputLayout,
     sampler: ID3D11SamplerState,
-    constant_buffer: ID3D11Buffer
+    constant_buffer: ID3D11Buffer,
+    filter_buffer: ID3D11Buffer,
+    shape_buffer: ID3D11Buffer,
+    filter: ScalingFilter,
+    force_opaque: bool
 }
 
 impl QuadRenderer {
@@ -75,12 +202,24 @@ impl QuadRenderer {
 
         let vs_blob = include_bytes!(concat!(env!("OUT_DIR"), "/shader.vs_blob"));
         let ps_blob = include_bytes!(concat!(env!("OUT_DIR"), "/shader.ps_blob"));
+        let ps_filtered_blob = include_bytes!(concat!(env!("OUT_DIR"), "/shader.ps_filtered_blob"));
+        let ps_shape_blob = include_bytes!(concat!(env!("OUT_DIR"), "/shader.ps_shape_blob"));
+        let ps_pixelate_blob = include_bytes!(concat!(env!("OUT_DIR"), "/shader.ps_pixelate_blob"));
         let vs = make_resource(|ptr| unsafe {
             d3d.device.CreateVertexShader(vs_blob, None, ptr)
         })?;
         let ps = make_resource(|ptr| unsafe {
             d3d.device.CreatePixelShader(ps_blob, None,ptr)
         })?;
+        let ps_filtered = make_resource(|ptr| unsafe {
+            d3d.device.CreatePixelShader(ps_filtered_blob, None,ptr)
+        })?;
+        let ps_shape = make_resource(|ptr| unsafe {
+            d3d.device.CreatePixelShader(ps_shape_blob, None,ptr)
+        })?;
+        let ps_pixelate = make_resource(|ptr| unsafe {
+            d3d.device.CreatePixelShader(ps_pixelate_blob, None,ptr)
+        })?;
         let descs = [
             D3D11_INPUT_ELEMENT_DESC {
                 SemanticName: windows::s!("POSITION"),
@@ -107,7 +246,7 @@ impl QuadRenderer {
 
         let constant_buffer = make_resource(|ptr| unsafe {
             d3d.device.CreateBuffer(&D3D11_BUFFER_DESC {
-                ByteWidth: size_of::<Mat4>() as _,
+                ByteWidth: size_of::<ObjectConstants>() as _,
                 Usage: D3D11_USAGE_DEFAULT,
                 BindFlags: D3D11_BIND_CONSTANT_BUFFER,
                 ..Default::default()
@@ -117,6 +256,39 @@ impl QuadRenderer {
             )
         })?;
 
+        let filter = ScalingFilter::default();
+        let filter_constants = FilterConstants::from(filter);
+        let filter_buffer = make_resource(|ptr| unsafe {
+            d3d.device.CreateBuffer(&D3D11_BUFFER_DESC {
+                ByteWidth: size_of::<FilterConstants>() as _,
+                Usage: D3D11_USAGE_DEFAULT,
+                BindFlags: D3D11_BIND_CONSTANT_BUFFER,
+                ..Default::default()
+            },
+            Some(&D3D11_SUBRESOURCE_DATA {
+                pSysMem: &filter_constants as *const FilterConstants as _,
+                ..Default::default()
+            }),
+            ptr
+            )
+        })?;
+
+        let shape_constants = ShapeConstants::default();
+        let shape_buffer = make_resource(|ptr| unsafe {
+            d3d.device.CreateBuffer(&D3D11_BUFFER_DESC {
+                ByteWidth: size_of::<ShapeConstants>() as _,
+                Usage: D3D11_USAGE_DEFAULT,
+                BindFlags: D3D11_BIND_CONSTANT_BUFFER,
+                ..Default::default()
+            },
+            Some(&D3D11_SUBRESOURCE_DATA {
+                pSysMem: &shape_constants as *const ShapeConstants as _,
+                ..Default::default()
+            }),
+            ptr
+            )
+        })?;
+
         let sampler = make_resource(|ptr| unsafe {
             d3d.device.CreateSamplerState(&D3D11_SAMPLER_DESC {
                 Filter: D3D11_FILTER_MIN_MAG_MIP_LINEAR,
@@ -136,9 +308,16 @@ impl QuadRenderer {
             index_buffer,
             vertex_shader: vs,
             pixel_shader: ps,
+            filtered_pixel_shader: ps_filtered,
+            shape_pixel_shader: ps_shape,
+            pixelate_pixel_shader: ps_pixelate,
             input_layout,
             sampler,
             constant_buffer,
+            filter_buffer,
+            shape_buffer,
+            filter,
+            force_opaque: false
         })
     }
 
@@ -157,14 +336,42 @@ impl QuadRenderer {
             d3d.context.VSSetShader(&self.vertex_shader, None);
             d3d.context.VSSetConstantBuffers(0, Some(&[self.constant_buffer.clone()]));
             d3d.context.PSSetShader(&self.pixel_shader, None);
+            d3d.context.PSSetConstantBuffers(0, Some(&[self.constant_buffer.clone(), self.filter_buffer.clone(), self.shape_buffer.clone()]));
             d3d.context.PSSetSamplers(0, Some(&[self.sampler.clone()]));
         }
     }
 
     pub fn draw(&self, d3d: &Direct3D, transform: Mat4, texture: &ID3D11ShaderResourceView) {
+        self.draw_with(d3d, transform, texture, false, Rect::UNIT, [1.0; 4]);
+    }
+
+    /// Draws the `uv` region of the texture multiplied by the premultiplied `tint`
+    pub fn draw_region(&self, d3d: &Direct3D, transform: Mat4, texture: &ID3D11ShaderResourceView, uv: Rect, tint: [f32; 4]) {
+        self.draw_with(d3d, transform, texture, false, uv, tint);
+    }
+
+    /// Draws the `uv` region of the texture as blocks of `block` texels of a single color
+    pub fn draw_pixelated(&self, d3d: &Direct3D, transform: Mat4, texture: &ID3D11ShaderResourceView, uv: Rect, block: f32) {
+        unsafe {
+            d3d.context.PSSetShader(&self.pixelate_pixel_shader, None);
+            self.draw_with_params(d3d, transform, texture, [0.0, block, 0.0, 0.0], uv, [1.0; 4]);
+            d3d.context.PSSetShader(&self.pixel_shader, None);
+        }
+    }
+
+    fn draw_with(&self, d3d: &Direct3D, transform: Mat4, texture: &ID3D11ShaderResourceView, force_opaque: bool, uv: Rect, tint: [f32; 4]) {
+        self.draw_with_params(d3d, transform, texture, [if force_opaque { 1.0 } else { 0.0 }, 0.0, 0.0, 0.0], uv, tint);
+    }
+
+    fn draw_with_params(&self, d3d: &Direct3D, transform: Mat4, texture: &ID3D11ShaderResourceView, params: [f32; 4], uv: Rect, tint: [f32; 4]) {
         unsafe {
-            let transposed = transform.transpose();
-            let ptr = transposed.as_ref().as_ptr() as _;
+            let constants = ObjectConstants {
+                transform: transform.transpose(),
+                params,
+                uv_rect: uv.to_array(),
+                tint,
+            };
+            let ptr = &constants as *const ObjectConstants as _;
             d3d.context.UpdateSubresource(&self.constant_buffer, 0, None, ptr, 0, 0);
             d3d.context.PSSetShaderResources(0, Some(&[texture.clone()]));
             d3d.context.DrawIndexed(INDICES.len() as _, 0, 0);
@@ -172,4 +379,61 @@ impl QuadRenderer {
 
     }
 
+    pub fn set_filter(&mut self, d3d: &Direct3D, filter: ScalingFilter) {
+        if self.filter != filter {
+            log::trace!("Switching scaling filter to {:?}", filter);
+            self.filter = filter;
+            let filter_constants = FilterConstants::from(filter);
+            unsafe {
+                d3d.context.UpdateSubresource(&self.filter_buffer, 0, None, &filter_constants as *const FilterConstants as _, 0, 0);
+            }
+        }
+    }
+
+    /// Replaces the alpha channel of every texture drawn with `draw_scaled` with one
+    pub fn set_force_opaque(&mut self, force_opaque: bool) {
+        self.force_opaque = force_opaque;
+    }
+
+    /// Like `draw` but uses the configured scaling filter instead of plain trilinear sampling
+    pub fn draw_scaled(&self, d3d: &Direct3D, transform: Mat4, texture: &ID3D11ShaderResourceView) {
+        match self.filter {
+            ScalingFilter::Linear => self.draw_with(d3d, transform, texture, self.force_opaque, Rect::UNIT, [1.0; 4]),
+            _ => unsafe {
+                d3d.context.PSSetShader(&self.filtered_pixel_shader, None);
+                self.draw_with(d3d, transform, texture, self.force_opaque, Rect::UNIT, [1.0; 4]);
+                d3d.context.PSSetShader(&self.pixel_shader, None);
+            }
+        }
+    }
+
+    /// Clips all following textured draws to the given shape (in window pixels)
+    pub fn set_clip(&self, d3d: &Direct3D, clip: Option<Shape>) {
+        let constants = clip
+            .map(|shape| shape.constants(true))
+            .unwrap_or_default();
+        unsafe {
+            d3d.context.UpdateSubresource(&self.shape_buffer, 0, None, &constants as *const ShapeConstants as _, 0, 0);
+        }
+    }
+
+    /// Draws a rounded rectangle. `screenspace` has to map window pixels to clip space. This resets the clip shape
+    pub fn draw_shape(&self, d3d: &Direct3D, screenspace: Mat4, shape: &Shape) {
+        let constants = shape.constants(false);
+        let transform = screenspace * shape.rect.expand(shape.blur).transform();
+        unsafe {
+            d3d.context.UpdateSubresource(&self.shape_buffer, 0, None, &constants as *const ShapeConstants as _, 0, 0);
+            d3d.context.PSSetShader(&self.shape_pixel_shader, None);
+            let constants = ObjectConstants {
+                transform: transform.transpose(),
+                params: [0.0; 4],
+                uv_rect: Rect::UNIT.to_array(),
+                tint: [1.0; 4],
+            };
+            d3d.context.UpdateSubresource(&self.constant_buffer, 0, None, &constants as *const ObjectConstants as _, 0, 0);
+            d3d.context.DrawIndexed(INDICES.len() as _, 0, 0);
+            d3d.context.PSSetShader(&self.pixel_shader, None);
+        }
+    }
+
 }
\ No newline at end of file
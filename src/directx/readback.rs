@@ -0,0 +1,61 @@
+use anyhow::{bail, Result};
+use windows::Win32::Graphics::Direct3D11::*;
+use windows::Win32::Graphics::Dxgi::Common::*;
+use crate::image_io::{Image, PixelFormat};
+use crate::utils::{make_resource, retrieve};
+
+/// Copies the top mip level of `texture` into system memory
+pub fn read_texture(device: &ID3D11Device, context: &ID3D11DeviceContext4, texture: &ID3D11Texture2D) -> Result<Image> {
+    FrameReader::default().read(device, context, texture)
+}
+
+/// Reads textures back through a staging texture that is kept as long as the size and format stay the same
+#[derive(Default)]
+pub struct FrameReader {
+    staging: Option<(ID3D11Texture2D, D3D11_TEXTURE2D_DESC)>
+}
+
+impl FrameReader {
+
+    pub fn read(&mut self, device: &ID3D11Device, context: &ID3D11DeviceContext4, texture: &ID3D11Texture2D) -> Result<Image> {
+        let desc = retrieve(texture, ID3D11Texture2D::GetDesc);
+        let format = match desc.Format {
+            DXGI_FORMAT_B8G8R8A8_UNORM | DXGI_FORMAT_B8G8R8A8_UNORM_SRGB => PixelFormat::Bgra8,
+            DXGI_FORMAT_R10G10B10A2_UNORM => PixelFormat::Rgb10a2,
+            DXGI_FORMAT_R16G16B16A16_FLOAT => PixelFormat::Rgba16f,
+            other => bail!("Can not read back textures with format {:?}", other)
+        };
+        let reusable = self.staging.as_ref().is_some_and(|(_, staging)|
+            staging.Width == desc.Width && staging.Height == desc.Height && staging.Format == desc.Format);
+        if !reusable {
+            let staging = make_resource(|ptr| unsafe {
+                device.CreateTexture2D(&D3D11_TEXTURE2D_DESC {
+                    MipLevels: 1,
+                    ArraySize: 1,
+                    SampleDesc: DXGI_SAMPLE_DESC {
+                        Count: 1,
+                        Quality: 0,
+                    },
+                    Usage: D3D11_USAGE_STAGING,
+                    BindFlags: Default::default(),
+                    CPUAccessFlags: D3D11_CPU_ACCESS_READ,
+                    MiscFlags: Default::default(),
+                    ..desc
+                }, None, ptr)
+            })?;
+            self.staging = Some((staging, desc));
+        }
+        let (staging, _) = self.staging.as_ref().expect("The staging texture was just created");
+        unsafe {
+            context.CopySubresourceRegion(staging, 0, 0, 0, 0, texture, 0, None);
+            let mut mapped = D3D11_MAPPED_SUBRESOURCE::default();
+            context.Map(staging, 0, D3D11_MAP_READ, 0, Some(&mut mapped))?;
+            let pitch = mapped.RowPitch as usize;
+            let data = std::slice::from_raw_parts(mapped.pData as *const u8, pitch * desc.Height as usize);
+            let image = Image::from_rows(format, desc.Width, desc.Height, pitch, data);
+            context.Unmap(staging, 0);
+            image
+        }
+    }
+
+}
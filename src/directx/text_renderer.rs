@@ -0,0 +1,76 @@
+use std::mem::size_of;
+use anyhow::Result;
+use glam::Mat4;
+use windows::Win32::Graphics::Direct3D11::{D3D11_BIND_SHADER_RESOURCE, D3D11_CPU_ACCESS_FLAG, D3D11_RESOURCE_MISC_FLAG, D3D11_SUBRESOURCE_DATA, D3D11_TEXTURE2D_DESC, D3D11_USAGE_IMMUTABLE, ID3D11ShaderResourceView};
+use windows::Win32::Graphics::Dxgi::Common::{DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_SAMPLE_DESC};
+use crate::directx::{Direct3D, QuadRenderer, Rect};
+use crate::font;
+use crate::utils::make_resource;
+
+/// Draws text using the built-in bitmap font
+pub struct TextRenderer {
+    atlas: ID3D11ShaderResourceView,
+    atlas_width: u32
+}
+
+impl TextRenderer {
+
+    pub fn new(d3d: &Direct3D) -> Result<Self> {
+        let (width, height, pixels) = font::create_atlas();
+        let tex = make_resource(|ptr| unsafe {
+            d3d.device.CreateTexture2D(&D3D11_TEXTURE2D_DESC {
+                Width: width,
+                Height: height,
+                MipLevels: 1,
+                ArraySize: 1,
+                Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Usage: D3D11_USAGE_IMMUTABLE,
+                BindFlags: D3D11_BIND_SHADER_RESOURCE,
+                CPUAccessFlags: D3D11_CPU_ACCESS_FLAG(0),
+                MiscFlags: D3D11_RESOURCE_MISC_FLAG(0),
+            }, Some(&D3D11_SUBRESOURCE_DATA {
+                pSysMem: pixels.as_ptr() as _,
+                SysMemPitch: width * size_of::<u32>() as u32,
+                SysMemSlicePitch: 0,
+            }), ptr)
+        })?;
+        let atlas = make_resource(|ptr| unsafe {
+            d3d.device.CreateShaderResourceView(&tex, None, ptr)
+        })?;
+        Ok(Self {
+            atlas,
+            atlas_width: width,
+        })
+    }
+
+    /// Size of the text in window pixels
+    pub fn measure(text: &str, scale: f32) -> (f32, f32) {
+        let (width, height) = font::text_size(text);
+        (width as f32 * scale, height as f32 * scale)
+    }
+
+    /// Draws `text` with its top-left corner at the origin. `transform` maps font pixels to clip space and `color` is premultiplied.
+    pub fn draw(&self, d3d: &Direct3D, quad_renderer: &QuadRenderer, transform: Mat4, color: [f32; 4], text: &str) {
+        let cell_width = font::ADVANCE as f32 / self.atlas_width as f32;
+        for (row, line) in text.lines().enumerate() {
+            for (column, c) in line.chars().enumerate() {
+                if c == ' ' {
+                    continue;
+                }
+                let glyph = font::glyph_index(c);
+                let rect = Rect::new(
+                    (column as u32 * font::ADVANCE) as f32,
+                    (row as u32 * font::LINE_HEIGHT) as f32,
+                    font::ADVANCE as f32,
+                    font::GLYPH_HEIGHT as f32);
+                let uv = Rect::new(glyph as f32 * cell_width, 0.0, cell_width, 1.0);
+                quad_renderer.draw_region(d3d, transform * rect.transform(), &self.atlas, uv, color);
+            }
+        }
+    }
+
+}
@@ -0,0 +1,99 @@
+use glam::{Mat4, Quat, vec3};
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Default)]
+pub enum DisplayOrientation {
+    #[default]
+    Landscape,
+    Portrait,
+    FlippedLandscape,
+    FlippedPortrait,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct DisplayMode {
+    pub width: u32,
+    pub height: u32,
+    pub orientation: DisplayOrientation,
+    pub refresh_num: u32,
+    pub refresh_den: u32,
+    pub hdr: bool,
+}
+
+impl DisplayMode {
+
+    pub fn get_flipped_size(self) -> (u32, u32) {
+        match self.orientation {
+            DisplayOrientation::Landscape | DisplayOrientation::FlippedLandscape => (self.width, self.height),
+            DisplayOrientation::FlippedPortrait | DisplayOrientation::Portrait => (self.height, self.width)
+        }
+    }
+
+    pub fn get_frame_transform(self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(
+            vec3(self.width as f32, self.height as f32, 0.0),
+            match self.orientation {
+                DisplayOrientation::Landscape => Quat::from_rotation_z(0f32.to_radians()),
+                DisplayOrientation::Portrait => Quat::from_rotation_z(90f32.to_radians()),
+                DisplayOrientation::FlippedLandscape => Quat::from_rotation_z(180f32.to_radians()),
+                DisplayOrientation::FlippedPortrait => Quat::from_rotation_z(270f32.to_radians()),
+            },
+            match self.orientation {
+                DisplayOrientation::Landscape => vec3(0.0, 0.0, 0.0),
+                DisplayOrientation::Portrait => vec3(self.height as f32, 0.0, 0.0),
+                DisplayOrientation::FlippedLandscape => vec3(self.width as f32, self.height as f32, 0.0),
+                DisplayOrientation::FlippedPortrait => vec3(0.0, self.width as f32, 0.0),
+            },
+        )
+    }
+
+}
+
+/// A block of pixels that was copied from `source` to `destination` in the unrotated frame
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MoveRect {
+    pub source: (u32, u32),
+    pub destination: (u32, u32),
+    pub size: (u32, u32)
+}
+
+impl MoveRect {
+
+    /// Moves the rect along with a `width` x `height` frame that is rotated clockwise by `quarter_turns` * 90 degrees
+    pub fn rotated(self, width: u32, height: u32, quarter_turns: u32) -> Self {
+        let (w, h) = self.size;
+        let turn = |(x, y): (u32, u32)| match quarter_turns % 4 {
+            0 => (x, y),
+            1 => (height.saturating_sub(y + h), x),
+            2 => (width.saturating_sub(x + w), height.saturating_sub(y + h)),
+            _ => (y, width.saturating_sub(x + w))
+        };
+        Self {
+            source: turn(self.source),
+            destination: turn(self.destination),
+            size: if quarter_turns.is_multiple_of(2) { (w, h) } else { (h, w) },
+        }
+    }
+
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CursorType {
+    Color,
+    Monochrome,
+    MaskedColor
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CursorData {
+    pub cursor_type: CursorType,
+    pub width: u32,
+    /// Height of the shape. For monochrome cursors this is half the height of the buffer
+    pub height: u32,
+    /// Bytes per row in `data`; Can be larger than the width requires
+    pub pitch: u32,
+    /// Offset of the point that is located at the pointer position from the top-left of the shape
+    pub hotspot: (u32, u32),
+    pub data: Vec<u8>
+}
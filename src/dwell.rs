@@ -0,0 +1,277 @@
+use std::time::{Duration, Instant};
+use glam::Vec2;
+use serde::Deserialize;
+
+/// Delays that keep the overlay from flashing while the pointer crosses monitors. All times are in seconds
+#[derive(Debug, Default, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DwellConfig {
+    /// Time the pointer has to stay on a configured monitor before the overlay shows it
+    pub enter_delay: f32,
+    /// Time the pointer has to stay away before the overlay is hidden
+    pub leave_delay: f32,
+    /// Once shown, the overlay is not hidden or moved to another monitor before this much time has passed
+    pub min_visible: f32,
+    /// Pointer speed in pixels per second above which the pointer is only passing through.
+    /// Moving this fast restarts the enter and leave delays; zero disables the rule
+    pub pass_through_speed: f32
+}
+
+impl DwellConfig {
+    fn delays(&self) -> Delays {
+        Delays::from_secs(self.enter_delay, self.leave_delay)
+    }
+
+    fn min_visible(&self) -> Duration {
+        Duration::from_secs_f32(self.min_visible.max(0.0))
+    }
+}
+
+/// Delays that override the global ones for a single trigger
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Delays {
+    pub enter: Duration,
+    pub leave: Duration
+}
+
+impl Delays {
+    pub fn from_secs(enter: f32, leave: f32) -> Self {
+        Self {
+            enter: Duration::from_secs_f32(enter.max(0.0)),
+            leave: Duration::from_secs_f32(leave.max(0.0)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Switch<M> {
+    Show(M),
+    Hide
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Candidate<M> {
+    monitor: M,
+    /// Whether the overlay is shown for this candidate
+    triggers: bool,
+    delays: Option<Delays>,
+    since: Instant
+}
+
+/// Decides which monitor the overlay shows based on timestamped pointer samples.
+/// `M` identifies a monitor and the filter never queries the system itself.
+#[derive(Debug, Clone)]
+pub struct DwellFilter<M> {
+    config: DwellConfig,
+    /// `None` until the first switch
+    current: Option<Candidate<M>>,
+    /// The trigger under the pointer if it differs from `current`
+    pending: Option<Candidate<M>>,
+    last_move: Option<(Instant, Vec2)>
+}
+
+impl<M: Copy + PartialEq> DwellFilter<M> {
+
+    pub fn new(config: DwellConfig) -> Self {
+        Self {
+            config,
+            current: None,
+            pending: None,
+            last_move: None,
+        }
+    }
+
+    pub fn set_config(&mut self, config: DwellConfig) {
+        self.config = config;
+    }
+
+    /// Forgets the current state. The next sample is treated like the first one
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config);
+    }
+
+    /// The pointer moved onto `monitor`. `triggers` tells whether the overlay is shown for it
+    pub fn enter(&mut self, now: Instant, monitor: M, triggers: bool) {
+        self.set_pending(Candidate { monitor, triggers, delays: None, since: now });
+    }
+
+    /// The pointer moved into a trigger zone that shows `monitor` with its own delays
+    pub fn enter_zone(&mut self, now: Instant, monitor: M, delays: Delays) {
+        self.set_pending(Candidate { monitor, triggers: true, delays: Some(delays), since: now });
+    }
+
+    fn set_pending(&mut self, candidate: Candidate<M>) {
+        match self.current.as_mut() {
+            Some(current) if current.monitor == candidate.monitor && current.triggers == candidate.triggers => {
+                current.delays = candidate.delays;
+                self.pending = None;
+            }
+            _ => self.pending = Some(candidate)
+        }
+    }
+
+    /// The pointer moved to `position` in virtual desktop coordinates
+    pub fn moved(&mut self, now: Instant, position: Vec2) {
+        if let (Some((last_time, last_position)), Some(pending)) = (self.last_move, self.pending.as_mut()) {
+            let elapsed = now.saturating_duration_since(last_time).as_secs_f32();
+            let fast = self.config.pass_through_speed > 0.0 &&
+                elapsed > 0.0 &&
+                position.distance(last_position) / elapsed > self.config.pass_through_speed;
+            if fast {
+                pending.since = now;
+            }
+        }
+        self.last_move = Some((now, position));
+    }
+
+    /// Point in time at which `update` will switch if nothing else happens
+    pub fn deadline(&self) -> Option<Instant> {
+        let pending = self.pending.as_ref()?;
+        let shown = self.current
+            .as_ref()
+            .filter(|current| current.triggers);
+        let earliest = shown.map(|current| current.since + self.config.min_visible());
+        let deadline = match (pending.triggers, shown) {
+            (true, _) => pending.since + pending.delays.unwrap_or(self.config.delays()).enter,
+            (false, Some(current)) => pending.since + current.delays.unwrap_or(self.config.delays()).leave,
+            (false, None) => pending.since
+        };
+        Some(earliest.map_or(deadline, |earliest| deadline.max(earliest)))
+    }
+
+    pub fn update(&mut self, now: Instant) -> Option<Switch<M>> {
+        if self.deadline()? > now {
+            return None;
+        }
+        let pending = self.pending.take()?;
+        self.current = Some(Candidate { since: now, ..pending });
+        Some(match pending.triggers {
+            true => Switch::Show(pending.monitor),
+            false => Switch::Hide
+        })
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Copy, Clone)]
+    enum Step {
+        Enter(u32, bool),
+        Zone(u32, Delays),
+        Move(f32, f32)
+    }
+
+    /// Feeds the trace into a filter, polling every millisecond, and returns the switches with their time in milliseconds
+    fn run(config: DwellConfig, trace: &[(u64, Step)]) -> Vec<(u64, Switch<u32>)> {
+        let start = Instant::now();
+        let mut filter = DwellFilter::new(config);
+        let mut switches = Vec::new();
+        let end = trace.last().map_or(0, |(t, _)| *t) + 2000;
+        for ms in 0..=end {
+            let now = start + Duration::from_millis(ms);
+            for (_, step) in trace.iter().filter(|(t, _)| *t == ms) {
+                match *step {
+                    Step::Enter(monitor, triggers) => filter.enter(now, monitor, triggers),
+                    Step::Zone(monitor, delays) => filter.enter_zone(now, monitor, delays),
+                    Step::Move(x, y) => filter.moved(now, Vec2::new(x, y))
+                }
+            }
+            if let Some(switch) = filter.update(now) {
+                switches.push((ms, switch));
+            }
+        }
+        switches
+    }
+
+    fn config(enter_delay: f32, leave_delay: f32, min_visible: f32, pass_through_speed: f32) -> DwellConfig {
+        DwellConfig { enter_delay, leave_delay, min_visible, pass_through_speed }
+    }
+
+    #[test]
+    fn without_delays_every_crossing_switches() {
+        let trace = [(0, Step::Enter(1, true)), (10, Step::Enter(2, false)), (20, Step::Enter(1, true))];
+        assert_eq!(run(DwellConfig::default(), &trace), vec![
+            (0, Switch::Show(1)),
+            (10, Switch::Hide),
+            (20, Switch::Show(1))
+        ]);
+    }
+
+    #[test]
+    fn brief_visits_are_ignored() {
+        let trace = [(0, Step::Enter(2, false)), (100, Step::Enter(1, true)), (200, Step::Enter(2, false)), (500, Step::Enter(1, true))];
+        assert_eq!(run(config(0.25, 0.0, 0.0, 0.0), &trace), vec![
+            (0, Switch::Hide),
+            (750, Switch::Show(1))
+        ]);
+    }
+
+    #[test]
+    fn returning_cancels_the_pending_switch() {
+        let trace = [(0, Step::Enter(1, true)), (1000, Step::Enter(2, false)), (1100, Step::Enter(1, true))];
+        assert_eq!(run(config(0.0, 0.3, 0.0, 0.0), &trace), vec![
+            (0, Switch::Show(1))
+        ]);
+    }
+
+    #[test]
+    fn hiding_waits_for_the_leave_delay_and_min_visible() {
+        let trace = [(0, Step::Enter(1, true)), (100, Step::Enter(2, false))];
+        assert_eq!(run(config(0.0, 0.25, 0.0, 0.0), &trace), vec![(0, Switch::Show(1)), (350, Switch::Hide)]);
+        assert_eq!(run(config(0.0, 0.25, 1.0, 0.0), &trace), vec![(0, Switch::Show(1)), (1000, Switch::Hide)]);
+    }
+
+    #[test]
+    fn switching_monitors_waits_for_min_visible() {
+        let trace = [(0, Step::Enter(1, true)), (100, Step::Enter(2, true)), (1500, Step::Enter(1, true))];
+        assert_eq!(run(config(0.0, 0.0, 1.0, 0.0), &trace), vec![
+            (0, Switch::Show(1)),
+            (1000, Switch::Show(2)),
+            (2000, Switch::Show(1))
+        ]);
+        assert_eq!(run(config(0.0625, 0.0, 0.0, 0.0), &trace), vec![
+            (63, Switch::Show(1)),
+            (163, Switch::Show(2)),
+            (1563, Switch::Show(1))
+        ]);
+        //Returning before the overlay moved keeps it where it is
+        let trace = [(0, Step::Enter(1, true)), (100, Step::Enter(2, true)), (500, Step::Enter(1, true))];
+        assert_eq!(run(config(0.0, 0.0, 1.0, 0.0), &trace), vec![(0, Switch::Show(1))]);
+    }
+
+    #[test]
+    fn fast_movement_restarts_the_delay() {
+        //1000 px/s while the pass through speed is 500 px/s
+        let trace = [
+            (0, Step::Move(0.0, 0.0)),
+            (0, Step::Enter(1, true)),
+            (100, Step::Move(100.0, 0.0)),
+            (200, Step::Move(200.0, 0.0)),
+            (300, Step::Move(210.0, 0.0))
+        ];
+        assert_eq!(run(config(0.125, 0.0, 0.0, 500.0), &trace), vec![(325, Switch::Show(1))]);
+        assert_eq!(run(config(0.125, 0.0, 0.0, 0.0), &trace), vec![(125, Switch::Show(1))]);
+    }
+
+    #[test]
+    fn zones_override_the_delays() {
+        let trace = [(0, Step::Zone(3, Delays::from_secs(0.0625, 0.5))), (100, Step::Enter(3, false))];
+        assert_eq!(run(config(1.0, 0.0, 0.0, 0.0), &trace), vec![(63, Switch::Show(3)), (600, Switch::Hide)]);
+    }
+
+    #[test]
+    fn reset_forgets_the_current_monitor() {
+        let now = Instant::now();
+        let mut filter = DwellFilter::new(DwellConfig::default());
+        filter.enter(now, 1, true);
+        assert_eq!(filter.update(now), Some(Switch::Show(1)));
+        filter.enter(now, 1, true);
+        assert_eq!(filter.update(now), None);
+        filter.reset();
+        filter.enter(now, 1, true);
+        assert_eq!(filter.update(now), Some(Switch::Show(1)));
+    }
+}
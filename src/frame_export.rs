@@ -0,0 +1,140 @@
+//! Publishes the mirrored monitor through the shared memory ring buffer of `display_peek_shm`, so other local
+//! applications can read it without capturing the screen a second time.
+
+use std::path::PathBuf;
+use std::time::Instant;
+#[cfg(windows)]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+use display_peek_shm::ExportWriter;
+#[cfg(windows)]
+use display_peek_shm::{CursorInfo, CursorKind, FrameInfo, PixelFormat};
+use serde::Deserialize;
+#[cfg(windows)]
+use crate::directx::CursorType;
+#[cfg(windows)]
+use crate::frame_source::FrameSource;
+#[cfg(windows)]
+use crate::image_io::Image;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ExportConfig {
+    pub enabled: bool,
+    /// The mapped file; Defaults to `display_peek_frames.shm` in the temp directory
+    pub path: Option<PathBuf>,
+    /// Number of frames in the ring
+    pub slots: u32,
+    /// Every slot has room for a frame of this size. Larger frames are not exported
+    pub max_width: u32,
+    pub max_height: u32,
+    pub fps: u32,
+    /// Draw the pointer into the frames. Its position is part of every frame either way
+    pub cursor: bool
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            slots: 3,
+            max_width: 3840,
+            max_height: 2160,
+            fps: 30,
+            cursor: false,
+        }
+    }
+}
+
+impl ExportConfig {
+    pub fn path(&self) -> PathBuf {
+        self.path
+            .clone()
+            .unwrap_or_else(|| std::env::temp_dir().join("display_peek_frames.shm"))
+    }
+}
+
+#[cfg(windows)]
+fn cursor_info(source: &FrameSource) -> Option<CursorInfo> {
+    let position = source.get_cursor_pos()?;
+    let data = source.get_cursor_data();
+    Some(CursorInfo {
+        position: (position.x, position.y),
+        kind: match data.map(|d| d.cursor_type) {
+            None => CursorKind::Unknown,
+            Some(CursorType::Color) => CursorKind::Color,
+            Some(CursorType::Monochrome) => CursorKind::Monochrome,
+            Some(CursorType::MaskedColor) => CursorKind::MaskedColor
+        },
+        hotspot: data.map(|d| d.hotspot).unwrap_or_default(),
+    })
+}
+
+pub struct FrameExport {
+    config: ExportConfig,
+    writer: ExportWriter,
+    next_frame: Instant,
+    changed: bool,
+    /// Only the first frame that is too large gets logged
+    warned: bool
+}
+
+impl FrameExport {
+
+    pub fn start(config: ExportConfig) -> Result<Self> {
+        let path = config.path();
+        let writer = ExportWriter::create(&path, config.slots, config.max_width, config.max_height)
+            .with_context(|| format!("Can not create {}", path.display()))?;
+        log::info!("Exporting frames to {}", path.display());
+        Ok(Self {
+            config,
+            writer,
+            next_frame: Instant::now(),
+            changed: true,
+            warned: false,
+        })
+    }
+
+    pub fn config(&self) -> &ExportConfig {
+        &self.config
+    }
+
+    pub fn frame_changed(&mut self) {
+        self.changed = true;
+    }
+
+    /// Writes a new frame if the frame changed and the frame rate allows it
+    #[cfg(windows)]
+    pub fn update(&mut self, now: Instant, source: &FrameSource, capture: impl FnOnce() -> Result<Image>) -> Result<()> {
+        if !self.changed || now < self.next_frame {
+            return Ok(());
+        }
+        self.changed = false;
+        self.next_frame = now + Duration::from_secs(1) / self.config.fps.max(1);
+        let image = capture()?;
+        let (max_width, max_height) = self.writer.max_size();
+        if image.width > max_width || image.height > max_height {
+            if !self.warned {
+                log::warn!("Not exporting {}x{} frames as they exceed the maximum size of {}x{}",
+                    image.width, image.height, max_width, max_height);
+                self.warned = true;
+            }
+            return Ok(());
+        }
+        let info = FrameInfo {
+            timestamp_micros: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64,
+            width: image.width,
+            height: image.height,
+            stride: 4 * image.width,
+            format: PixelFormat::Rgba8,
+            cursor: cursor_info(source),
+        };
+        self.writer.write(&info, &image.rgba)?;
+        Ok(())
+    }
+
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+use anyhow::Result;
+use windows::Win32::Foundation::POINT;
+use windows::Win32::Graphics::Direct3D11::{ID3D11Device, ID3D11Texture2D};
+use crate::directx::{AcquisitionResults, CursorData, DesktopDuplication, Display, DisplayMode, MoveRect};
+use crate::remote::{RemoteFrames, RemoteSource};
+
+/// Where the frames of the peeked monitor come from
+pub enum FrameSource {
+    Local(DesktopDuplication),
+    /// A monitor of another machine shown in place of the local `output`
+    Remote(RemoteFrames)
+}
+
+impl FrameSource {
+
+    pub fn new(device: &ID3D11Device, output: Display, remote: Option<&RemoteSource>) -> Result<Self> {
+        Ok(match remote {
+            None => FrameSource::Local(DesktopDuplication::new(device, output)?),
+            Some(source) => FrameSource::Remote(RemoteFrames::new(device, output, source.clone()))
+        })
+    }
+
+    pub fn remote_source(&self) -> Option<&RemoteSource> {
+        match self {
+            FrameSource::Local(_) => None,
+            FrameSource::Remote(remote) => Some(remote.source())
+        }
+    }
+
+    pub fn is_local(&self) -> bool {
+        matches!(self, FrameSource::Local(_))
+    }
+
+    pub fn try_acquire_next_frame(&mut self) -> Result<AcquisitionResults> {
+        match self {
+            FrameSource::Local(dupl) => dupl.try_acquire_next_frame(),
+            FrameSource::Remote(remote) => remote.try_acquire_next_frame()
+        }
+    }
+
+    pub fn get_frame(&self) -> Option<&ID3D11Texture2D> {
+        match self {
+            FrameSource::Local(dupl) => dupl.get_frame(),
+            FrameSource::Remote(remote) => remote.get_frame()
+        }
+    }
+
+    pub fn get_cursor_pos(&self) -> Option<POINT> {
+        match self {
+            FrameSource::Local(dupl) => dupl.get_cursor_pos(),
+            FrameSource::Remote(remote) => remote.get_cursor_pos()
+        }
+    }
+
+    pub fn get_cursor_data(&self) -> Option<&CursorData> {
+        match self {
+            FrameSource::Local(dupl) => dupl.get_cursor_data(),
+            FrameSource::Remote(remote) => remote.get_cursor_data()
+        }
+    }
+
+    /// Remote frames only report the tiles that changed
+    pub fn get_move_rects(&self) -> &[MoveRect] {
+        match self {
+            FrameSource::Local(dupl) => dupl.get_move_rects(),
+            FrameSource::Remote(_) => &[]
+        }
+    }
+
+    pub fn get_timeout_count(&self) -> u64 {
+        match self {
+            FrameSource::Local(dupl) => dupl.get_timeout_count(),
+            FrameSource::Remote(remote) => remote.get_timeout_count()
+        }
+    }
+
+    pub fn get_frame_age(&self) -> Option<Duration> {
+        match self {
+            FrameSource::Local(dupl) => dupl.get_frame_age(),
+            FrameSource::Remote(remote) => remote.get_frame_age()
+        }
+    }
+
+    pub fn get_display_mode(&self) -> DisplayMode {
+        match self {
+            FrameSource::Local(dupl) => dupl.get_display_mode(),
+            FrameSource::Remote(remote) => remote.get_display_mode()
+        }
+    }
+
+    pub fn get_current_output(&self) -> &Display {
+        match self {
+            FrameSource::Local(dupl) => dupl.get_current_output(),
+            FrameSource::Remote(remote) => remote.get_current_output()
+        }
+    }
+
+}
@@ -0,0 +1,80 @@
+//! The parts of the app that work without the overlay, for platforms where the overlay is not available.
+//! The hotkeys and the control socket work like on Windows; actions that need the overlay are refused.
+
+use std::sync::mpsc::{channel, Sender};
+use anyhow::{anyhow, bail, Result};
+use crate::config::Config;
+use crate::hotkeys::{HotkeyAction, HotkeyBindings, HotkeyThreadHandle, start_hotkey_thread};
+use crate::ipc::{Command, Response, start_ipc_server, Status};
+
+#[derive(Debug, Clone)]
+enum HeadlessEvent {
+    Hotkey(HotkeyAction),
+    Command(Command, Sender<Response>)
+}
+
+fn start_hotkeys(sender: &Sender<HeadlessEvent>, config: &Config) -> Option<HotkeyThreadHandle> {
+    let sender = sender.clone();
+    start_hotkey_thread(HotkeyBindings::new(&config.hotkeys), move |action| sender
+        .send(HeadlessEvent::Hotkey(action))
+        .is_ok())
+}
+
+/// Passes the commands that work without the overlay on to the event loop
+fn forward(sender: &Sender<HeadlessEvent>, command: Command, reply: Sender<Response>) -> Result<()> {
+    match command {
+        Command::Reload | Command::SwitchProfile { .. } | Command::Status | Command::Quit => sender
+            .send(HeadlessEvent::Command(command, reply))
+            .map_err(|_| anyhow!("The app is shutting down")),
+        other => bail!("{:?} needs the overlay, which is only available on Windows", other)
+    }
+}
+
+/// `command` is carried out first, like a control client would
+pub fn run(command: Option<Command>) -> Result<()> {
+    log::info!("The overlay is only available on Windows; running without it");
+    let (sender, events) = channel();
+    let _ipc_server = start_ipc_server({
+        let sender = sender.clone();
+        move |command, reply| forward(&sender, command, reply)
+    })?;
+    if let Some(command) = command {
+        forward(&sender, command, channel().0)
+            .unwrap_or_else(|err| log::warn!("Can not carry out the command: {:#}", err));
+    }
+    let mut profile: Option<String> = None;
+    let mut hotkeys = start_hotkeys(&sender, &Config::read()?);
+    for event in events {
+        match event {
+            HeadlessEvent::Hotkey(HotkeyAction::ReloadConfig) | HeadlessEvent::Command(Command::Reload, _) => match Config::read_profile(profile.as_deref()) {
+                Ok(config) => {
+                    log::debug!("Reloading config");
+                    drop(hotkeys.take());
+                    hotkeys = start_hotkeys(&sender, &config);
+                }
+                Err(err) => log::warn!("Can not reload the config: {:#}", err)
+            },
+            HeadlessEvent::Command(Command::SwitchProfile { name }, reply) => {
+                let response = match Config::read_profile(Some(&name)) {
+                    Ok(config) => {
+                        log::info!("Switching to profile {}", name);
+                        profile = Some(name).filter(|name| name != "default");
+                        drop(hotkeys.take());
+                        hotkeys = start_hotkeys(&sender, &config);
+                        Response::success()
+                    }
+                    Err(err) => Response::failure(format!("Can not load profile {}: {:#}", name, err))
+                };
+                let _ = reply.send(response);
+            }
+            HeadlessEvent::Hotkey(action) => log::warn!("{:?} needs the overlay, which is only available on Windows", action),
+            HeadlessEvent::Command(Command::Status, reply) => {
+                let status = Status { profile: profile.clone(), ..Default::default() };
+                let _ = reply.send(Response { status: Some(status), ..Response::success() });
+            }
+            HeadlessEvent::Command(Command::Quit, _) => break,
+            HeadlessEvent::Command(command, _) => log::warn!("Unexpected command {:?}", command)
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,117 @@
+//! System wide hotkeys. The bindings are resolved from the config without touching the system;
+//! only `start_hotkey_thread` registers them with the OS (`RegisterHotKey` on Windows, `XGrabKey` on X11).
+
+#[cfg(windows)]
+mod win32;
+#[cfg(unix)]
+mod x11;
+
+use std::collections::BTreeMap;
+use serde::Deserialize;
+use crate::keys::Accelerator;
+
+#[cfg(windows)]
+pub use win32::{HotkeyThreadHandle, start_hotkey_thread};
+#[cfg(unix)]
+pub use x11::{HotkeyThreadHandle, start_hotkey_thread};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HotkeyAction {
+    /// Shows the monitor under the pointer or hides the overlay
+    Toggle,
+    /// Keeps the current monitor visible after the pointer left
+    Pin,
+    /// Walks through the configured monitors regardless of the pointer
+    NextMonitor,
+    PreviousMonitor,
+    /// Stops reacting to the pointer and keys until pressed again
+    Pause,
+    ReloadConfig,
+    /// Saves or copies what the overlay shows
+    Snapshot,
+    /// Starts or stops recording the peeked monitor
+    Record,
+    /// Shows or hides the on-screen display
+    ToggleOsd
+}
+
+/// Maps the `[hotkeys]` config table, e.g. `toggle = "Ctrl+Alt+P"`
+pub type HotkeyConfig = BTreeMap<HotkeyAction, Accelerator>;
+
+/// Accelerators in registration order. The index doubles as the hotkey id
+#[derive(Debug, Default, Clone)]
+pub struct HotkeyBindings(Vec<(Accelerator, HotkeyAction)>);
+
+impl HotkeyBindings {
+
+    /// Drops every binding whose accelerator is already used by another action
+    pub fn new(config: &HotkeyConfig) -> Self {
+        let mut bindings: Vec<(Accelerator, HotkeyAction)> = Vec::new();
+        for (&action, &accelerator) in config {
+            match bindings.iter().find(|(a, _)| *a == accelerator) {
+                Some((_, other)) => log::warn!("{} is bound to both {:?} and {:?}; ignoring {:?}", accelerator, other, action, action),
+                None => bindings.push((accelerator, action))
+            }
+        }
+        Self(bindings)
+    }
+
+    pub fn dispatch(&self, id: usize) -> Option<HotkeyAction> {
+        self.0.get(id).map(|(_, action)| *action)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Hotkeys {
+        hotkeys: HotkeyConfig
+    }
+
+    fn bindings(text: &str) -> HotkeyBindings {
+        let Hotkeys { hotkeys } = toml::from_str(text).unwrap();
+        HotkeyBindings::new(&hotkeys)
+    }
+
+    #[test]
+    fn the_config_table_maps_actions() {
+        let bindings = bindings(r#"
+            [hotkeys]
+            toggle = "Ctrl+Alt+P"
+            reload-config = "Ctrl+Alt+R"
+            toggle-osd = "Ctrl+Alt+D"
+        "#);
+        let actions: Vec<HotkeyAction> = (0..3).filter_map(|id| bindings.dispatch(id)).collect();
+        assert_eq!(actions, [HotkeyAction::Toggle, HotkeyAction::ReloadConfig, HotkeyAction::ToggleOsd]);
+        assert_eq!(bindings.dispatch(3), None);
+        assert_eq!(bindings.0[1].0.to_string(), "Ctrl+Alt+R");
+    }
+
+    #[test]
+    fn conflicting_bindings_are_dropped() {
+        let bindings = bindings(r#"
+            [hotkeys]
+            pin = "Ctrl+Alt+P"
+            toggle = "alt+ctrl+p"
+            pause = "Ctrl+Alt+Shift+P"
+        "#);
+        assert_eq!(bindings.0.len(), 2);
+        assert_eq!(bindings.dispatch(0), Some(HotkeyAction::Toggle));
+        assert_eq!(bindings.dispatch(1), Some(HotkeyAction::Pause));
+    }
+
+    #[test]
+    fn invalid_tables_are_rejected() {
+        assert!(toml::from_str::<Hotkeys>("[hotkeys]\nfly = \"Ctrl+F\"").is_err());
+        assert!(toml::from_str::<Hotkeys>("[hotkeys]\ntoggle = \"Ctrl+Nope\"").is_err());
+        assert!(bindings("[hotkeys]").is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+use std::thread::JoinHandle;
+use error_tools::log::LogResultExt;
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::System::Threading::GetCurrentThreadId;
+use windows::Win32::UI::Input::KeyboardAndMouse::*;
+use windows::Win32::UI::WindowsAndMessaging::{GetMessageW, MSG, PeekMessageW, PM_NOREMOVE, PostThreadMessageW, WM_HOTKEY, WM_QUIT};
+use crate::hotkeys::{HotkeyAction, HotkeyBindings};
+use crate::keys::Accelerator;
+
+/// Unregisters the hotkeys when dropped
+pub struct HotkeyThreadHandle {
+    thread_id: u32,
+    thread: Option<JoinHandle<()>>
+}
+
+impl Drop for HotkeyThreadHandle {
+    fn drop(&mut self) {
+        unsafe { PostThreadMessageW(self.thread_id, WM_QUIT, WPARAM(0), LPARAM(0)) };
+        if let Some(thread) = self.thread.take() {
+            thread.join()
+                .unwrap_or_else(|_| log::warn!("Hotkey thread panicked"));
+        }
+    }
+}
+
+fn register(id: usize, accelerator: Accelerator) -> bool {
+    let mut modifiers = MOD_NOREPEAT;
+    for (set, modifier) in [
+        (accelerator.modifiers.ctrl, MOD_CONTROL),
+        (accelerator.modifiers.alt, MOD_ALT),
+        (accelerator.modifiers.shift, MOD_SHIFT),
+        (accelerator.modifiers.win, MOD_WIN)] {
+        if set {
+            modifiers |= modifier;
+        }
+    }
+    unsafe { RegisterHotKey(HWND(0), id as i32, modifiers, accelerator.key.0 as u32) }.as_bool()
+}
+
+/// Registers the hotkeys on a separate thread, as `WM_HOTKEY` is posted to the thread that registered them.
+/// `on_hotkey` is called on that thread for every press and stops the thread by returning `false`
+pub fn start_hotkey_thread(bindings: HotkeyBindings, on_hotkey: impl Fn(HotkeyAction) -> bool + Send + 'static) -> Option<HotkeyThreadHandle> {
+    if bindings.is_empty() {
+        return None;
+    }
+    let (tx, rx) = std::sync::mpsc::channel();
+    let thread = std::thread::spawn(move || {
+        let mut msg = MSG::default();
+        // Makes sure the thread has a message queue before anyone posts to it
+        unsafe { PeekMessageW(&mut msg, HWND(0), 0, 0, PM_NOREMOVE) };
+        tx.send(unsafe { GetCurrentThreadId() })
+            .log_ok("Can not send hotkey thread id");
+        for (id, (accelerator, action)) in bindings.0.iter().enumerate() {
+            match register(id, *accelerator) {
+                true => log::debug!("Registered {} for {:?}", accelerator, action),
+                false => log::warn!("Can not register {} for {:?}, it is probably used by another application", accelerator, action)
+            }
+        }
+        while unsafe { GetMessageW(&mut msg, HWND(0), 0, 0) }.0 > 0 {
+            if msg.message != WM_HOTKEY {
+                continue;
+            }
+            if let Some(action) = bindings.dispatch(msg.wParam.0) {
+                log::trace!("Hotkey {:?}", action);
+                if !on_hotkey(action) {
+                    break;
+                }
+            }
+        }
+        for id in 0..bindings.0.len() {
+            unsafe { UnregisterHotKey(HWND(0), id as i32) };
+        }
+        log::trace!("Stopping hotkey thread");
+    });
+    let thread_id = rx.recv()
+        .log_ok("Hotkey thread died during startup")?;
+    Some(HotkeyThreadHandle {
+        thread_id,
+        thread: Some(thread),
+    })
+}
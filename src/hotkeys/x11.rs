@@ -0,0 +1,262 @@
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use anyhow::{Context, Result};
+use error_tools::log::LogResultExt;
+use x11rb::connection::Connection;
+use x11rb::protocol::Event;
+use x11rb::protocol::xproto::{AtomEnum, ClientMessageEvent, ConnectionExt, CreateWindowAux, EventMask, GrabMode, Keycode, ModMask, Window, WindowClass};
+use x11rb::rust_connection::RustConnection;
+use crate::hotkeys::{HotkeyAction, HotkeyBindings};
+use crate::keys::{Accelerator, Key};
+
+/// Keysyms of the Windows virtual key codes that are not plain letters, digits or function keys
+const NAMED_KEYSYMS: &[(u16, u32)] = &[
+    (0x20, 0x0020), // Space
+    (0x09, 0xFF09), // Tab
+    (0x0D, 0xFF0D), // Return
+    (0x1B, 0xFF1B), // Escape
+    (0x08, 0xFF08), // BackSpace
+    (0x2D, 0xFF63), // Insert
+    (0x2E, 0xFFFF), // Delete
+    (0x24, 0xFF50), // Home
+    (0x23, 0xFF57), // End
+    (0x21, 0xFF55), // Prior
+    (0x22, 0xFF56), // Next
+    (0x25, 0xFF51), // Left
+    (0x27, 0xFF53), // Right
+    (0x26, 0xFF52), // Up
+    (0x28, 0xFF54), // Down
+    (0x13, 0xFF13), // Pause
+    (0x2C, 0xFF61), // Print
+    (0x91, 0xFF14), // Scroll_Lock
+    (0x14, 0xFFE5), // Caps_Lock
+    (0x90, 0xFF7F), // Num_Lock
+    (0x5D, 0xFF67), // Menu
+    (0xA0, 0xFFE1), // Shift_L
+    (0xA1, 0xFFE2), // Shift_R
+    (0xA2, 0xFFE3), // Control_L
+    (0xA3, 0xFFE4), // Control_R
+    (0xA4, 0xFFE9), // Alt_L
+    (0xA5, 0xFFEA), // Alt_R
+    (0x5B, 0xFFEB), // Super_L
+    (0x5C, 0xFFEC), // Super_R
+];
+
+/// Translates the Windows virtual key code the config uses into an X11 keysym
+fn keysym(key: Key) -> Option<u32> {
+    match key.0 {
+        0x30..=0x39 => Some(key.0 as u32),
+        0x41..=0x5A => Some(key.0 as u32 + 0x20),
+        0x70..=0x87 => Some(0xFFBE + key.0 as u32 - 0x70),
+        vk => NAMED_KEYSYMS
+            .iter()
+            .find(|(code, _)| *code == vk)
+            .map(|(_, sym)| *sym)
+    }
+}
+
+/// The modifiers of `accelerator` as X11 modifier mask. Alt and Super are assumed to be on their usual Mod1 and Mod4
+fn modifier_mask(accelerator: Accelerator) -> ModMask {
+    [
+        (accelerator.modifiers.ctrl, ModMask::CONTROL),
+        (accelerator.modifiers.alt, ModMask::M1),
+        (accelerator.modifiers.shift, ModMask::SHIFT),
+        (accelerator.modifiers.win, ModMask::M4)
+    ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .fold(ModMask::from(0u16), |mask, (_, modifier)| mask | modifier)
+}
+
+/// Caps Lock and Num Lock must not keep a hotkey from working, so every key is grabbed with all combinations of them
+fn lock_masks() -> [ModMask; 4] {
+    [ModMask::from(0u16), ModMask::LOCK, ModMask::M2, ModMask::LOCK | ModMask::M2]
+}
+
+fn relevant_modifiers(state: u16) -> u16 {
+    state & u16::from(ModMask::CONTROL | ModMask::M1 | ModMask::SHIFT | ModMask::M4)
+}
+
+/// A grabbed key combination and the index of its binding
+#[derive(Debug, Copy, Clone)]
+struct Grab {
+    keycode: Keycode,
+    modifiers: u16,
+    id: usize
+}
+
+fn find_keycode(conn: &RustConnection, keysym: u32) -> Result<Option<Keycode>> {
+    let setup = conn.setup();
+    let (min, max) = (setup.min_keycode, setup.max_keycode);
+    let mapping = conn
+        .get_keyboard_mapping(min, max - min + 1)?
+        .reply()?;
+    let per_keycode = (mapping.keysyms_per_keycode as usize).max(1);
+    Ok(mapping.keysyms
+        .chunks(per_keycode)
+        .position(|syms| syms.contains(&keysym))
+        .map(|i| min + i as Keycode))
+}
+
+fn grab(conn: &RustConnection, root: Window, accelerator: Accelerator) -> Result<Grab> {
+    let keysym = keysym(accelerator.key)
+        .with_context(|| format!("{} has no X11 equivalent", accelerator.key))?;
+    let keycode = find_keycode(conn, keysym)?
+        .with_context(|| format!("{} is not on the keyboard", accelerator.key))?;
+    let modifiers = modifier_mask(accelerator);
+    for lock in lock_masks() {
+        conn.grab_key(false, root, modifiers | lock, keycode, GrabMode::ASYNC, GrabMode::ASYNC)?
+            .check()
+            .context("It is probably used by another application")?;
+    }
+    Ok(Grab { keycode, modifiers: u16::from(modifiers), id: 0 })
+}
+
+/// Ungrabs the keys when dropped
+pub struct HotkeyThreadHandle {
+    conn: Arc<RustConnection>,
+    /// Receives the message that stops the thread
+    window: Window,
+    thread: Option<JoinHandle<()>>
+}
+
+impl Drop for HotkeyThreadHandle {
+    fn drop(&mut self) {
+        let stop = ClientMessageEvent::new(32, self.window, AtomEnum::NONE, [0u32; 5]);
+        self.conn.send_event(false, self.window, EventMask::NO_EVENT, stop)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(self.conn.flush()?))
+            .log_ok("Can not stop the hotkey thread");
+        if let Some(thread) = self.thread.take() {
+            thread.join()
+                .unwrap_or_else(|_| log::warn!("Hotkey thread panicked"));
+        }
+    }
+}
+
+fn run(conn: &RustConnection, window: Window, grabs: &[Grab], bindings: &HotkeyBindings, on_hotkey: impl Fn(HotkeyAction) -> bool) -> Result<()> {
+    // Auto-repeat sends release and press pairs with the same timestamp while the key is held down
+    let mut held: Option<Keycode> = None;
+    let mut next: Option<Event> = None;
+    loop {
+        let event = match next.take() {
+            Some(event) => event,
+            None => conn.wait_for_event()?
+        };
+        match event {
+            Event::ClientMessage(msg) if msg.window == window => return Ok(()),
+            Event::KeyPress(press) if held != Some(press.detail) => {
+                let modifiers = relevant_modifiers(u16::from(press.state));
+                let grab = grabs
+                    .iter()
+                    .find(|grab| grab.keycode == press.detail && grab.modifiers == modifiers);
+                if let Some(action) = grab.and_then(|grab| bindings.dispatch(grab.id)) {
+                    held = Some(press.detail);
+                    log::trace!("Hotkey {:?}", action);
+                    if !on_hotkey(action) {
+                        return Ok(());
+                    }
+                }
+            }
+            Event::KeyRelease(release) if held == Some(release.detail) => {
+                next = conn.poll_for_event()?;
+                let repeated = matches!(&next, Some(Event::KeyPress(press)) if press.detail == release.detail && press.time == release.time);
+                match repeated {
+                    true => next = None,
+                    false => held = None
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Grabs the keys on the root window of the default screen and waits for them on a separate thread.
+/// `on_hotkey` is called on that thread for every press and stops the thread by returning `false`
+pub fn start_hotkey_thread(bindings: HotkeyBindings, on_hotkey: impl Fn(HotkeyAction) -> bool + Send + 'static) -> Option<HotkeyThreadHandle> {
+    if bindings.is_empty() {
+        return None;
+    }
+    let (conn, screen) = RustConnection::connect(None)
+        .log_ok("Can not connect to the X server")?;
+    let conn = Arc::new(conn);
+    let root = conn.setup().roots[screen].root;
+    let grabs: Vec<Grab> = bindings.0
+        .iter()
+        .enumerate()
+        .filter_map(|(id, (accelerator, action))| match grab(&conn, root, *accelerator) {
+            Ok(grab) => {
+                log::debug!("Registered {} for {:?}", accelerator, action);
+                Some(Grab { id, ..grab })
+            },
+            Err(err) => {
+                log::warn!("Can not register {} for {:?}: {:#}", accelerator, action, err);
+                None
+            }
+        })
+        .collect();
+    let window = conn.generate_id()
+        .map_err(anyhow::Error::from)
+        .and_then(|window| {
+            conn.create_window(0, window, root, 0, 0, 1, 1, 0, WindowClass::INPUT_ONLY, 0, &CreateWindowAux::new())?;
+            conn.flush()?;
+            Ok(window)
+        })
+        .log_ok("Can not create the hotkey window")?;
+    let thread = std::thread::spawn({
+        let conn = conn.clone();
+        move || {
+            run(&conn, window, &grabs, &bindings, on_hotkey)
+                .log_ok("Hotkey thread failed");
+            for grab in &grabs {
+                for lock in lock_masks() {
+                    let _ = conn.ungrab_key(grab.keycode, root, ModMask::from(grab.modifiers) | lock);
+                }
+            }
+            let _ = conn.destroy_window(window);
+            let _ = conn.flush();
+            log::trace!("Stopping hotkey thread");
+        }
+    });
+    Some(HotkeyThreadHandle {
+        conn,
+        window,
+        thread: Some(thread),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_map_to_keysyms() {
+        let sym = |name: &str| keysym(name.parse().unwrap());
+        assert_eq!(sym("P"), Some('p' as u32));
+        assert_eq!(sym("7"), Some('7' as u32));
+        assert_eq!(sym("F1"), Some(0xFFBE));
+        assert_eq!(sym("F24"), Some(0xFFD5));
+        assert_eq!(sym("Pause"), Some(0xFF13));
+        assert_eq!(sym("RightCtrl"), Some(0xFFE4));
+        assert_eq!(sym("0xB3"), None);
+    }
+
+    #[test]
+    fn every_named_key_has_a_keysym() {
+        for name in ["Space", "Tab", "Enter", "Escape", "Backspace", "Insert", "Delete", "Home", "End", "PageUp", "PageDown",
+            "Left", "Right", "Up", "Down", "PrintScreen", "ScrollLock", "CapsLock", "NumLock", "Apps",
+            "LeftShift", "RightShift", "LeftAlt", "RightAlt", "LeftWin", "RightWin"] {
+            assert!(keysym(name.parse().unwrap()).is_some(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn modifiers_map_to_the_mask() {
+        let mask = |s: &str| u16::from(modifier_mask(s.parse().unwrap()));
+        assert_eq!(mask("P"), 0);
+        assert_eq!(mask("Ctrl+Alt+P"), u16::from(ModMask::CONTROL | ModMask::M1));
+        assert_eq!(mask("Shift+Win+F8"), u16::from(ModMask::SHIFT | ModMask::M4));
+        let locked = u16::from(ModMask::CONTROL | ModMask::LOCK | ModMask::M2);
+        assert_eq!(relevant_modifiers(locked), u16::from(ModMask::CONTROL));
+    }
+}
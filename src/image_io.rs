@@ -0,0 +1,255 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{ensure, Result};
+use directories_next::UserDirs;
+
+/// Encodes a straight alpha rgba image as png
+pub fn encode_png(writer: impl Write, width: u32, height: u32, rgba: &[u8]) -> Result<()> {
+    ensure!(rgba.len() == (width * height * 4) as usize, "Image buffer does not match its size");
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    writer.finish()?;
+    Ok(())
+}
+
+pub fn save_png(path: impl AsRef<Path>, width: u32, height: u32, rgba: &[u8]) -> Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    encode_png(file, width, height, rgba)
+}
+
+/// Memory layout of captured pixels
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PixelFormat {
+    Bgra8,
+    /// Ten bits per color channel
+    Rgb10a2,
+    /// Linear scRGB as half floats
+    Rgba16f
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Bgra8 | PixelFormat::Rgb10a2 => 4,
+            PixelFormat::Rgba16f => 8
+        }
+    }
+
+    /// Converts one pixel to opaque 8 bit srgb
+    fn to_rgba8(self, pixel: &[u8]) -> [u8; 4] {
+        match self {
+            PixelFormat::Bgra8 => [pixel[2], pixel[1], pixel[0], u8::MAX],
+            PixelFormat::Rgb10a2 => {
+                let v = u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                let channel = |shift: u32| ((v >> shift & 0x3FF) >> 2) as u8;
+                [channel(0), channel(10), channel(20), u8::MAX]
+            }
+            PixelFormat::Rgba16f => {
+                let channel = |i: usize| {
+                    let linear = f16_to_f32(u16::from_le_bytes([pixel[2 * i], pixel[2 * i + 1]])).clamp(0.0, 1.0);
+                    let srgb = match linear <= 0.0031308 {
+                        true => linear * 12.92,
+                        false => 1.055 * linear.powf(1.0 / 2.4) - 0.055
+                    };
+                    (srgb * 255.0).round() as u8
+                };
+                [channel(0), channel(1), channel(2), u8::MAX]
+            }
+        }
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (bits >> 10 & 0x1F) as i32;
+    let mantissa = (bits & 0x3FF) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1F if mantissa == 0.0 => f32::INFINITY,
+        0x1F => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15)
+    }
+}
+
+/// A rectangle in pixel coordinates
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Area {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32
+}
+
+impl Area {
+
+    pub fn of(image: &Image) -> Self {
+        Self { x: 0, y: 0, width: image.width, height: image.height }
+    }
+
+    pub fn intersect(self, other: Self) -> Option<Self> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        (right > x && bottom > y).then(|| Self { x, y, width: right - x, height: bottom - y })
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.intersect(other) == Some(other)
+    }
+
+    /// Moves the area along with a `width` x `height` image that is rotated clockwise by `quarter_turns` * 90 degrees
+    pub fn rotated(self, width: u32, height: u32, quarter_turns: u32) -> Self {
+        let (x, y, w, h) = (self.x, self.y, self.width, self.height);
+        match quarter_turns % 4 {
+            0 => self,
+            1 => Self { x: height.saturating_sub(y + h), y: x, width: h, height: w },
+            2 => Self { x: width.saturating_sub(x + w), y: height.saturating_sub(y + h), width: w, height: h },
+            _ => Self { x: y, y: width.saturating_sub(x + w), width: h, height: w }
+        }
+    }
+
+}
+
+/// A straight alpha rgba image
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>
+}
+
+impl Image {
+
+    /// Converts mapped texture memory where rows start every `pitch` bytes
+    pub fn from_rows(format: PixelFormat, width: u32, height: u32, pitch: usize, data: &[u8]) -> Result<Self> {
+        let row_size = width as usize * format.bytes_per_pixel();
+        ensure!(pitch >= row_size, "Row pitch is smaller than a row");
+        ensure!(height == 0 || data.len() >= pitch * (height as usize - 1) + row_size, "Pixel data is too short");
+        let rgba = (0..height as usize)
+            .flat_map(|y| data[y * pitch..y * pitch + row_size].chunks_exact(format.bytes_per_pixel()))
+            .flat_map(|pixel| format.to_rgba8(pixel))
+            .collect();
+        Ok(Self { width, height, rgba })
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<()> {
+        save_png(path, self.width, self.height, &self.rgba)
+    }
+
+    /// The pixels of `area` in row `y`
+    pub fn row(&self, area: Area, y: u32) -> &[u8] {
+        let start = 4 * (y as usize * self.width as usize + area.x as usize);
+        &self.rgba[start..start + 4 * area.width as usize]
+    }
+
+    /// The `tile` sized tiles of `within` that differ from `other`, merged into horizontal runs
+    pub fn changed_areas(&self, other: &Image, within: Area, tile: u32) -> Vec<Area> {
+        let mut areas: Vec<Area> = Vec::new();
+        for ty in (within.y..within.y + within.height).step_by(tile as usize) {
+            let height = tile.min(within.y + within.height - ty);
+            for tx in (within.x..within.x + within.width).step_by(tile as usize) {
+                let current = Area { x: tx, y: ty, width: tile.min(within.x + within.width - tx), height };
+                if (ty..ty + height).all(|y| self.row(current, y) == other.row(current, y)) {
+                    continue;
+                }
+                match areas.last_mut() {
+                    Some(last) if last.y == ty && last.x + last.width == tx => last.width += current.width,
+                    _ => areas.push(current)
+                }
+            }
+        }
+        areas
+    }
+
+    /// Encodes the image as baseline jpeg, dropping the alpha channel
+    pub fn to_jpeg(&self, quality: u8) -> Result<Vec<u8>> {
+        let (width, height) = (u16::try_from(self.width)?, u16::try_from(self.height)?);
+        let mut jpeg = Vec::new();
+        jpeg_encoder::Encoder::new(&mut jpeg, quality)
+            .encode(&self.rgba, width, height, jpeg_encoder::ColorType::Rgba)?;
+        Ok(jpeg)
+    }
+
+    /// Rotates the image clockwise by `quarter_turns` * 90 degrees
+    pub fn rotated(self, quarter_turns: u32) -> Self {
+        let turns = quarter_turns % 4;
+        if turns == 0 {
+            return self;
+        }
+        let (w, h) = (self.width as usize, self.height as usize);
+        let (width, height) = if turns == 2 { (w, h) } else { (h, w) };
+        let source = |x: usize, y: usize| match turns {
+            1 => (y, h - 1 - x),
+            2 => (w - 1 - x, h - 1 - y),
+            _ => (w - 1 - y, x)
+        };
+        let rgba = (0..height)
+            .flat_map(|y| (0..width).map(move |x| source(x, y)))
+            .flat_map(|(x, y)| {
+                let i = 4 * (y * w + x);
+                [self.rgba[i], self.rgba[i + 1], self.rgba[i + 2], self.rgba[i + 3]]
+            })
+            .collect();
+        Self { width: width as u32, height: height as u32, rgba }
+    }
+
+    /// Encodes the image as a device independent bitmap like the clipboard expects it for `CF_DIB`
+    pub fn to_dib(&self) -> Vec<u8> {
+        let image_size = self.width * self.height * 4;
+        let mut dib = Vec::with_capacity(40 + image_size as usize);
+        for value in [40, self.width, self.height] {
+            dib.extend_from_slice(&value.to_le_bytes());
+        }
+        dib.extend_from_slice(&1u16.to_le_bytes());
+        dib.extend_from_slice(&32u16.to_le_bytes());
+        for value in [0, image_size, 0, 0, 0, 0] {
+            dib.extend_from_slice(&value.to_le_bytes());
+        }
+        //Rows are stored bottom-up in bgra order
+        for row in self.rgba.chunks_exact(4 * self.width as usize).rev() {
+            dib.extend(row.chunks_exact(4).flat_map(|p| [p[2], p[1], p[0], p[3]]));
+        }
+        dib
+    }
+
+}
+
+/// A file in the users picture directory named after `prefix` and the current time
+pub fn output_path(prefix: &str, extension: &str) -> PathBuf {
+    let dir = UserDirs::new()
+        .and_then(|dirs| dirs.picture_dir().map(Path::to_path_buf))
+        .unwrap_or_else(std::env::temp_dir);
+    dir.join(format!("{}_{}.{}", prefix, timestamp(SystemTime::now()), extension))
+}
+
+/// Formats the time as `YYYY-MM-DD_hh-mm-ss.mmm` (UTC)
+pub fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+    format!("{:04}-{:02}-{:02}_{:02}-{:02}-{:02}.{:03}",
+            year, month, day,
+            secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60,
+            since_epoch.subsec_millis())
+}
+
+//http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,244 @@
+//! Typed stream of the mouse input seen by the low-level hook.
+//! Events can be written to a text file, one event per line, and fed back into the event loop later.
+
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context, Result};
+use tao::event_loop::EventLoop;
+use windows::Win32::Foundation::POINT;
+use windows::Win32::Graphics::Gdi::HMONITOR;
+use crate::CustomEvent;
+use crate::keys::Key;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    X1,
+    X2
+}
+
+impl MouseButton {
+    const ALL: [Self; 5] = [Self::Left, Self::Right, Self::Middle, Self::X1, Self::X2];
+
+    fn name(self) -> &'static str {
+        match self {
+            MouseButton::Left => "left",
+            MouseButton::Right => "right",
+            MouseButton::Middle => "middle",
+            MouseButton::X1 => "x1",
+            MouseButton::X2 => "x2"
+        }
+    }
+}
+
+/// All positions are in virtual desktop coordinates
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum InputEventKind {
+    /// `local` is relative to the top-left corner of the monitor the pointer is on
+    Move { position: POINT, local: POINT },
+    Button { button: MouseButton, pressed: bool, position: POINT },
+    /// One notch of the wheel is a delta of 120
+    Wheel { delta: i32, horizontal: bool, position: POINT },
+    /// The monitor handles are only meaningful within the session that recorded them
+    MonitorEnter(HMONITOR),
+    MonitorLeave(HMONITOR),
+    /// Only keys passed to the cursor tracker are reported
+    Key { key: Key, pressed: bool },
+    /// Index into the trigger zones passed to the cursor tracker
+    ZoneEnter(u32),
+    ZoneLeave(u32)
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct InputEvent {
+    /// Milliseconds on the `GetTickCount` clock
+    pub time: u32,
+    pub kind: InputEventKind
+}
+
+impl Display for InputEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ", self.time)?;
+        match self.kind {
+            InputEventKind::Move { position, local } =>
+                write!(f, "move {} {} {} {}", position.x, position.y, local.x, local.y),
+            InputEventKind::Button { button, pressed, position } =>
+                write!(f, "button {} {} {} {}", button.name(), if pressed { "down" } else { "up" }, position.x, position.y),
+            InputEventKind::Wheel { delta, horizontal, position } =>
+                write!(f, "wheel {} {} {} {}", delta, if horizontal { "horizontal" } else { "vertical" }, position.x, position.y),
+            InputEventKind::MonitorEnter(monitor) => write!(f, "enter {}", monitor.0),
+            InputEventKind::MonitorLeave(monitor) => write!(f, "leave {}", monitor.0),
+            InputEventKind::Key { key, pressed } => write!(f, "key {} {}", key, if pressed { "down" } else { "up" }),
+            InputEventKind::ZoneEnter(zone) => write!(f, "zone-enter {}", zone),
+            InputEventKind::ZoneLeave(zone) => write!(f, "zone-leave {}", zone)
+        }
+    }
+}
+
+impl FromStr for InputEvent {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut tokens = s.split_whitespace();
+        let mut next = || tokens.next().ok_or_else(|| anyhow!("Unexpected end of line"));
+        let time = next()?.parse()?;
+        let kind = match next()? {
+            "move" => InputEventKind::Move {
+                position: POINT { x: next()?.parse()?, y: next()?.parse()? },
+                local: POINT { x: next()?.parse()?, y: next()?.parse()? },
+            },
+            "button" => {
+                let name = next()?;
+                let button = MouseButton::ALL
+                    .into_iter()
+                    .find(|b| b.name() == name)
+                    .ok_or_else(|| anyhow!("Unknown mouse button: {}", name))?;
+                let pressed = parse_pressed(next()?)?;
+                InputEventKind::Button { button, pressed, position: POINT { x: next()?.parse()?, y: next()?.parse()? } }
+            },
+            "wheel" => {
+                let delta = next()?.parse()?;
+                let horizontal = match next()? {
+                    "horizontal" => true,
+                    "vertical" => false,
+                    other => bail!("Expected \"horizontal\" or \"vertical\", found {}", other)
+                };
+                InputEventKind::Wheel { delta, horizontal, position: POINT { x: next()?.parse()?, y: next()?.parse()? } }
+            },
+            "enter" => InputEventKind::MonitorEnter(HMONITOR(next()?.parse()?)),
+            "leave" => InputEventKind::MonitorLeave(HMONITOR(next()?.parse()?)),
+            "key" => {
+                let key = next()?.parse()?;
+                InputEventKind::Key { key, pressed: parse_pressed(next()?)? }
+            },
+            "zone-enter" => InputEventKind::ZoneEnter(next()?.parse()?),
+            "zone-leave" => InputEventKind::ZoneLeave(next()?.parse()?),
+            other => bail!("Unknown input event: {}", other)
+        };
+        Ok(Self { time, kind })
+    }
+}
+
+fn parse_pressed(token: &str) -> Result<bool> {
+    match token {
+        "down" => Ok(true),
+        "up" => Ok(false),
+        other => bail!("Expected \"down\" or \"up\", found {}", other)
+    }
+}
+
+/// Appends every recorded event to a file
+pub struct InputRecorder(LineWriter<File>);
+
+impl InputRecorder {
+
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path.as_ref())
+            .with_context(|| format!("Can not open {}", path.as_ref().display()))?;
+        log::info!("Recording input to {}", path.as_ref().display());
+        Ok(Self(LineWriter::new(file)))
+    }
+
+    pub fn record(&mut self, event: &InputEvent) -> Result<()> {
+        writeln!(self.0, "{}", event)?;
+        Ok(())
+    }
+
+}
+
+/// Parses a recording. Empty lines and lines starting with `#` are ignored
+pub fn read_recording(path: impl AsRef<Path>) -> Result<Vec<InputEvent>> {
+    std::fs::read_to_string(path.as_ref())
+        .with_context(|| format!("Can not read {}", path.as_ref().display()))?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(i, line)| line
+            .parse()
+            .with_context(|| format!("Invalid event in line {}", i + 1)))
+        .collect()
+}
+
+/// Sends the events to the event loop with the same timing as they were recorded
+pub fn start_replay(event_loop: &EventLoop<CustomEvent>, events: Vec<InputEvent>) {
+    let proxy = event_loop.create_proxy();
+    std::thread::spawn(move || {
+        log::debug!("Replaying {} input events", events.len());
+        let mut last_time = events.first().map(|e| e.time).unwrap_or_default();
+        for event in events {
+            std::thread::sleep(Duration::from_millis(event.time.wrapping_sub(last_time) as u64));
+            last_time = event.time;
+            if proxy.send_event(CustomEvent::Input(event)).is_err() {
+                break;
+            }
+        }
+        log::trace!("Stopping input replay");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events() -> Vec<InputEvent> {
+        let kinds = [
+            InputEventKind::Move { position: POINT { x: -1920, y: 40 }, local: POINT { x: 0, y: 40 } },
+            InputEventKind::Button { button: MouseButton::Left, pressed: true, position: POINT { x: 5, y: -7 } },
+            InputEventKind::Button { button: MouseButton::X2, pressed: false, position: POINT { x: 5, y: -7 } },
+            InputEventKind::Wheel { delta: -240, horizontal: false, position: POINT { x: 1, y: 2 } },
+            InputEventKind::Wheel { delta: 120, horizontal: true, position: POINT { x: 3, y: 4 } },
+            InputEventKind::MonitorEnter(HMONITOR(65537)),
+            InputEventKind::MonitorLeave(HMONITOR(-12)),
+            InputEventKind::Key { key: "F8".parse().unwrap(), pressed: true },
+            InputEventKind::Key { key: Key(0xB3), pressed: false },
+            InputEventKind::ZoneEnter(0),
+            InputEventKind::ZoneLeave(3),
+        ];
+        kinds
+            .into_iter()
+            .enumerate()
+            .map(|(i, kind)| InputEvent { time: u32::MAX - 50 + 16 * i as u32, kind })
+            .collect()
+    }
+
+    #[test]
+    fn every_event_survives_a_text_round_trip() {
+        for event in events() {
+            let line = event.to_string();
+            assert_eq!(line.parse::<InputEvent>().unwrap(), event, "{}", line);
+        }
+    }
+
+    #[test]
+    fn recordings_can_be_replayed() {
+        let path = std::env::temp_dir().join(format!("display-peek-input-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut recorder = InputRecorder::create(&path).unwrap();
+        for event in events() {
+            recorder.record(&event).unwrap();
+        }
+        drop(recorder);
+        let mut contents = std::fs::read_to_string(&path).unwrap();
+        contents.insert_str(0, "# comment\n\n");
+        std::fs::write(&path, contents).unwrap();
+        let replayed = read_recording(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replayed.unwrap(), events());
+    }
+
+    #[test]
+    fn invalid_lines_are_rejected() {
+        for line in ["", "12", "12 move 1 2 3", "12 button left sideways 1 2", "12 wheel 1 diagonal 1 2", "12 teleport", "x move 1 2 3 4"] {
+            assert!(line.parse::<InputEvent>().is_err(), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Makes sure only one instance runs. Later launches hand their command to the running instance and exit.
+
+#[cfg(unix)]
+use std::fs::{File, OpenOptions, TryLockError};
+#[cfg(unix)]
+use std::path::Path;
+use std::time::Duration;
+use anyhow::{bail, Result};
+#[cfg(unix)]
+use anyhow::Context;
+#[cfg(windows)]
+use windows::core::HSTRING;
+#[cfg(windows)]
+use windows::Win32::Foundation::{CloseHandle, ERROR_ALREADY_EXISTS, GetLastError, HANDLE};
+#[cfg(windows)]
+use windows::Win32::System::Threading::CreateMutexW;
+use crate::ipc::{self, Command};
+
+const HANDOFF_ATTEMPTS: u32 = 20;
+const HANDOFF_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Held by the running instance. The OS releases the mutex when the process exits or crashes,
+/// so a dead instance never leaves a stale lock behind
+#[cfg(windows)]
+pub struct InstanceLock(HANDLE);
+
+#[cfg(windows)]
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+#[cfg(windows)]
+fn try_lock_named(name: &str) -> Result<Option<InstanceLock>> {
+    let handle = unsafe { CreateMutexW(None, true, &HSTRING::from(name)) }?;
+    match unsafe { GetLastError() } == ERROR_ALREADY_EXISTS {
+        true => {
+            unsafe { CloseHandle(handle) };
+            Ok(None)
+        }
+        false => Ok(Some(InstanceLock(handle)))
+    }
+}
+
+/// Returns `None` if another instance holds the lock
+#[cfg(windows)]
+fn try_lock() -> Result<Option<InstanceLock>> {
+    try_lock_named("Local\\DisplayPeek")
+}
+
+/// Held by the running instance. The lock is an advisory lock on a file next to the control socket;
+/// the OS releases it when the process exits or crashes, so a file left behind does not block later launches
+#[cfg(unix)]
+pub struct InstanceLock(File);
+
+#[cfg(unix)]
+fn try_lock_at(path: &Path) -> Result<Option<InstanceLock>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Can not open the lock file {}", path.display()))?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(InstanceLock(file))),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(err)) => Err(err).with_context(|| format!("Can not lock {}", path.display()))
+    }
+}
+
+/// Returns `None` if another instance holds the lock
+#[cfg(unix)]
+fn try_lock() -> Result<Option<InstanceLock>> {
+    try_lock_at(&ipc::socket_path().with_extension("lock"))
+}
+
+/// Becomes the running instance or hands `command` to the one that is already running.
+/// Returns `None` if this process should exit, otherwise the lock and the command this instance has to carry out itself
+pub fn acquire(command: Option<Command>) -> Result<Option<(InstanceLock, Option<Command>)>> {
+    for _ in 0..HANDOFF_ATTEMPTS {
+        if let Some(lock) = try_lock()? {
+            return Ok(match command {
+                Some(Command::Quit) => None,
+                command => Some((lock, command))
+            });
+        }
+        // The other instance might still be starting up or shutting down
+        match ipc::send(command.as_ref().unwrap_or(&Command::Status)) {
+            Ok(response) => {
+                if let Some(error) = response.error {
+                    log::warn!("The running instance refused the command: {}", error);
+                }
+                log::info!("Display Peek is already running");
+                return Ok(None);
+            }
+            Err(err) => log::debug!("Can not reach the running instance: {}", err)
+        }
+        std::thread::sleep(HANDOFF_RETRY_DELAY);
+    }
+    bail!("Another instance is running but does not respond")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+    use super::*;
+
+    const CHILD_ENV: &str = "DISPLAY_PEEK_LOCK_HOLDER";
+
+    /// Takes the lock of a test instance. Every test uses a name of its own, never the one of the real app
+    #[cfg(unix)]
+    fn lock(name: &str) -> Option<InstanceLock> {
+        try_lock_at(&std::env::temp_dir().join(format!("{}.lock", name))).unwrap()
+    }
+
+    #[cfg(windows)]
+    fn lock(name: &str) -> Option<InstanceLock> {
+        try_lock_named(&format!("Local\\{}", name)).unwrap()
+    }
+
+    fn unique_name(test: &str) -> String {
+        format!("DisplayPeek-{}-{}", test, std::process::id())
+    }
+
+    #[test]
+    fn released_locks_do_not_block() {
+        let name = unique_name("released");
+        let held = lock(&name);
+        assert!(held.is_some());
+        assert!(lock(&name).is_none(), "a held lock must block");
+        drop(held);
+        assert!(lock(&name).is_some(), "releasing the lock must unblock it");
+    }
+
+    /// Runs only as the child of `locks_of_killed_instances_do_not_block` and holds the lock until it is killed
+    #[test]
+    fn lock_holder() {
+        let Ok(name) = std::env::var(CHILD_ENV) else { return };
+        let _lock = lock(&name).expect("the lock is taken");
+        println!("locked");
+        std::thread::sleep(Duration::from_secs(60));
+    }
+
+    #[test]
+    fn locks_of_killed_instances_do_not_block() {
+        let name = unique_name("killed");
+        let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["instance::tests::lock_holder", "--exact", "--test-threads=1", "--quiet", "--nocapture"])
+            .env(CHILD_ENV, &name)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let locked = BufReader::new(child.stdout.take().unwrap())
+            .lines()
+            .map_while(|line| line.ok())
+            .any(|line| line == "locked");
+        assert!(locked, "The child did not take the lock");
+        assert!(lock(&name).is_none(), "the lock of a running instance must block");
+
+        // A crash leaves the lock file behind, but the lock dies with the process
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(lock(&name).is_some(), "the lock of a killed instance must be released");
+        #[cfg(unix)]
+        std::fs::remove_file(std::env::temp_dir().join(format!("{}.lock", name))).unwrap();
+    }
+}
@@ -0,0 +1,496 @@
+//! Control server on a named pipe (a Unix socket elsewhere). Clients write one JSON command per line
+//! and get one JSON response per line, e.g. `{"command":"peek","monitor":"\\\\.\\DISPLAY2"}` is answered with `{"ok":true}`.
+
+#[cfg(windows)]
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(windows)]
+use std::os::windows::io::FromRawHandle;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::time::Duration;
+#[cfg(windows)]
+use anyhow::anyhow;
+use anyhow::{bail, Context, Result};
+#[cfg(unix)]
+use directories_next::BaseDirs;
+use serde::{Deserialize, Serialize};
+#[cfg(windows)]
+use tao::event_loop::{EventLoop, EventLoopProxy};
+#[cfg(windows)]
+use windows::core::HSTRING;
+#[cfg(windows)]
+use windows::Win32::Foundation::{CloseHandle, ERROR_PIPE_CONNECTED, GetLastError};
+#[cfg(windows)]
+use windows::Win32::Storage::FileSystem::PIPE_ACCESS_DUPLEX;
+#[cfg(windows)]
+use windows::Win32::System::Pipes::*;
+#[cfg(windows)]
+use crate::CustomEvent;
+
+#[cfg(windows)]
+const PIPE_NAME: &str = r"\\.\pipe\DisplayPeek";
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Command {
+    /// Shows the monitor under the pointer, or the first configured one
+    Show,
+    Hide,
+    /// Pins the shown monitor or releases the pin
+    Pin,
+    /// Shows the monitor with the given DXGI name until it is hidden
+    Peek { monitor: String },
+    /// Replaces the config with the profile of the given name, `default` goes back to the main config
+    SwitchProfile { name: String },
+    Reload,
+    StartRecording,
+    StopRecording,
+    Status,
+    Quit
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    /// Monitor the overlay is showing or fading out
+    pub monitor: Option<String>,
+    /// `None` while the main config is used
+    #[serde(default)]
+    pub profile: Option<String>,
+    pub visible: bool,
+    pub paused: bool,
+    pub recording: bool,
+    pub capture_fps: f32,
+    pub present_fps: f32
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>
+}
+
+impl Response {
+
+    pub fn success() -> Self {
+        Self { ok: true, ..Default::default() }
+    }
+
+    pub fn failure(error: impl ToString) -> Self {
+        Self { ok: false, error: Some(error.to_string()), status: None }
+    }
+
+}
+
+impl Command {
+
+    /// Parses the arguments of `display_peek ctl`
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        Ok(match args.as_slice() {
+            ["show"] => Command::Show,
+            ["hide"] => Command::Hide,
+            ["pin"] => Command::Pin,
+            ["peek", monitor] => Command::Peek { monitor: monitor.to_string() },
+            ["profile", name] => Command::SwitchProfile { name: name.to_string() },
+            ["reload"] => Command::Reload,
+            ["record", "start"] => Command::StartRecording,
+            ["record", "stop"] => Command::StopRecording,
+            ["status"] => Command::Status,
+            ["quit"] => Command::Quit,
+            _ => bail!("Usage: display_peek ctl show|hide|pin|reload|status|quit|peek <monitor>|profile <name>|record start|stop")
+        })
+    }
+
+    /// Parses launch flags like `--peek DISPLAY2` or `--quit` that are handed to the running instance
+    pub fn from_flags(args: &[String]) -> Result<Option<Self>> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        Ok(match args.as_slice() {
+            [] => None,
+            ["--show"] => Some(Command::Show),
+            ["--hide"] => Some(Command::Hide),
+            ["--pin"] => Some(Command::Pin),
+            ["--peek", monitor] => Some(Command::Peek { monitor: monitor.to_string() }),
+            ["--profile", name] => Some(Command::SwitchProfile { name: name.to_string() }),
+            ["--reload"] => Some(Command::Reload),
+            ["--start-recording"] => Some(Command::StartRecording),
+            ["--stop-recording"] => Some(Command::StopRecording),
+            ["--quit"] => Some(Command::Quit),
+            _ => bail!("Unknown arguments: {}", args.join(" "))
+        })
+    }
+
+    /// The event that carries out the command. Commands that need an answer get a channel for it
+    #[cfg(windows)]
+    pub fn into_event(self, reply: Sender<Response>) -> CustomEvent {
+        match self {
+            Command::Show => CustomEvent::ShowOverlay,
+            Command::Hide => CustomEvent::DismissOverlay,
+            Command::Pin => CustomEvent::PinOverlay,
+            Command::Peek { monitor } => CustomEvent::PeekMonitor(monitor, reply),
+            Command::SwitchProfile { name } => CustomEvent::SwitchProfile(name, reply),
+            Command::Reload => CustomEvent::ReloadConfig,
+            Command::StartRecording => CustomEvent::StartRecording,
+            Command::StopRecording => CustomEvent::StopRecording,
+            Command::Status => CustomEvent::QueryStatus(reply),
+            Command::Quit => CustomEvent::QuitButton
+        }
+    }
+
+    fn expects_reply(&self) -> bool {
+        matches!(self, Command::Peek { .. } | Command::SwitchProfile { .. } | Command::Status)
+    }
+
+}
+
+/// Turns one line of the protocol into the response line.
+/// `send` hands the command to the app together with the channel that `Peek`, `SwitchProfile` and `Status` are answered on
+fn handle_line(line: &str, send: &impl Fn(Command, Sender<Response>) -> Result<()>) -> Response {
+    let result = serde_json::from_str::<Command>(line)
+        .context("Invalid command")
+        .and_then(|command| {
+            let (tx, rx) = channel();
+            let expects_reply = command.expects_reply();
+            send(command, tx)?;
+            match expects_reply {
+                true => rx
+                    .recv_timeout(REPLY_TIMEOUT)
+                    .context("The app did not answer"),
+                false => Ok(Response::success())
+            }
+        });
+    result.unwrap_or_else(|err| Response::failure(format!("{:#}", err)))
+}
+
+fn serve_client(reader: impl Read, mut writer: impl Write, send: &impl Fn(Command, Sender<Response>) -> Result<()>) -> Result<()> {
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = handle_line(&line, send);
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Writes one command and reads the response line
+fn request(mut stream: impl Read + Write, command: &Command) -> Result<Response> {
+    serde_json::to_writer(&mut stream, command)?;
+    stream.write_all(b"\n")?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    serde_json::from_str(&line)
+        .context("Invalid response")
+}
+
+/// Stops the server when dropped
+pub struct IpcServerHandle {
+    stop: Arc<AtomicBool>,
+    #[cfg(unix)]
+    path: PathBuf
+}
+
+impl Drop for IpcServerHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        // Wakes the server thread up if it is waiting for a client
+        #[cfg(windows)]
+        let _ = OpenOptions::new().read(true).write(true).open(PIPE_NAME);
+        #[cfg(unix)]
+        {
+            let _ = UnixStream::connect(&self.path);
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Serves one client at a time
+#[cfg(windows)]
+pub fn start_ipc_server(event_loop: &EventLoop<CustomEvent>) -> IpcServerHandle {
+    let proxy = event_loop.create_proxy();
+    let send = move |command: Command, reply| proxy
+        .send_event(command.into_event(reply))
+        .map_err(|_| anyhow!("The event loop is gone"));
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    std::thread::spawn(move || {
+        let name = HSTRING::from(PIPE_NAME);
+        while !stopped.load(Ordering::Acquire) {
+            let pipe = unsafe {
+                CreateNamedPipeW(
+                    &name,
+                    PIPE_ACCESS_DUPLEX,
+                    PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                    PIPE_UNLIMITED_INSTANCES,
+                    4096,
+                    4096,
+                    0,
+                    None)
+            };
+            if pipe.is_invalid() {
+                log::error!("Can not create control pipe: {:?}", unsafe { GetLastError() });
+                break;
+            }
+            let connected = unsafe { ConnectNamedPipe(pipe, None) }.as_bool() ||
+                unsafe { GetLastError() } == ERROR_PIPE_CONNECTED;
+            if !connected || stopped.load(Ordering::Acquire) {
+                unsafe { CloseHandle(pipe) };
+                continue;
+            }
+            log::trace!("Control client connected");
+            let pipe = unsafe { File::from_raw_handle(pipe.0 as _) };
+            let result = pipe
+                .try_clone()
+                .map_err(anyhow::Error::from)
+                .and_then(|writer| serve_client(pipe, writer, &send));
+            if let Err(err) = result {
+                log::debug!("Control client disconnected: {}", err);
+            }
+        }
+        log::trace!("Stopping control server");
+    });
+    IpcServerHandle { stop }
+}
+
+/// Sends one command to the running instance
+#[cfg(windows)]
+pub fn send(command: &Command) -> Result<Response> {
+    let pipe = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(PIPE_NAME)
+        .context("Can not connect to Display Peek. Is it running?")?;
+    request(pipe, command)
+}
+
+/// The control socket lives in the runtime directory, or the temp directory if there is none
+#[cfg(unix)]
+pub fn socket_path() -> PathBuf {
+    BaseDirs::new()
+        .and_then(|dirs| dirs.runtime_dir().map(Path::to_path_buf))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("DisplayPeek.sock")
+}
+
+/// Serves one client at a time. A socket left behind by a crashed instance is replaced
+#[cfg(unix)]
+fn serve_socket(path: PathBuf, send: impl Fn(Command, Sender<Response>) -> Result<()> + Send + 'static) -> Result<IpcServerHandle> {
+    if UnixStream::connect(&path).is_ok() {
+        bail!("Another instance is listening on {}", path.display());
+    }
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("Can not create control socket {}", path.display()))?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            if stopped.load(Ordering::Acquire) {
+                break;
+            }
+            log::trace!("Control client connected");
+            let result = stream
+                .and_then(|stream| Ok((stream.try_clone()?, stream)))
+                .map_err(anyhow::Error::from)
+                .and_then(|(reader, writer)| serve_client(reader, writer, &send));
+            if let Err(err) = result {
+                log::debug!("Control client disconnected: {}", err);
+            }
+        }
+        log::trace!("Stopping control server");
+    });
+    Ok(IpcServerHandle { stop, path })
+}
+
+#[cfg(unix)]
+pub fn start_ipc_server(send: impl Fn(Command, Sender<Response>) -> Result<()> + Send + 'static) -> Result<IpcServerHandle> {
+    serve_socket(socket_path(), send)
+}
+
+#[cfg(unix)]
+fn send_to(path: &Path, command: &Command) -> Result<Response> {
+    let stream = UnixStream::connect(path)
+        .context("Can not connect to Display Peek. Is it running?")?;
+    request(stream, command)
+}
+
+/// Sends one command to the running instance
+#[cfg(unix)]
+pub fn send(command: &Command) -> Result<Response> {
+    send_to(&socket_path(), command)
+}
+
+#[cfg(not(any(windows, unix)))]
+pub fn send(_command: &Command) -> Result<Response> {
+    bail!("The control pipe is not available on this platform")
+}
+
+/// Implements `display_peek ctl ...`: sends one command to the running instance and prints the response
+pub fn run_client(args: &[String]) -> Result<bool> {
+    let response = send(&Command::from_args(args)?)?;
+    println!("{}", serde_json::to_string(&response)?);
+    Ok(response.ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use super::*;
+
+    /// Records the commands and answers `Status` like the event loop would
+    fn app(received: &Mutex<Vec<Command>>) -> impl Fn(Command, Sender<Response>) -> Result<()> + '_ {
+        move |command, reply| {
+            if command == Command::Status {
+                let status = Status { monitor: Some("DISPLAY1".into()), visible: true, ..Default::default() };
+                reply.send(Response { status: Some(status), ..Response::success() })?;
+            }
+            received.lock().unwrap().push(command);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn commands_are_forwarded() {
+        let received = Mutex::new(Vec::new());
+        assert_eq!(handle_line(r#"{"command":"show"}"#, &app(&received)), Response::success());
+        assert!(!handle_line(r#"{"command":"record-start"}"#, &app(&received)).ok);
+        assert_eq!(handle_line(r#"{"command":"start-recording"}"#, &app(&received)), Response::success());
+        //Peek expects an answer, which this app never sends
+        assert!(!handle_line(r#"{"command":"peek","monitor":"\\\\.\\DISPLAY2"}"#, &app(&received)).ok);
+        assert_eq!(received.into_inner().unwrap(), vec![
+            Command::Show,
+            Command::StartRecording,
+            Command::Peek { monitor: r"\\.\DISPLAY2".into() }
+        ]);
+    }
+
+    #[test]
+    fn profile_switches_are_answered() {
+        let app = |command: Command, reply: Sender<Response>| -> Result<()> {
+            let Command::SwitchProfile { name } = command else {
+                bail!("Unexpected command {:?}", command);
+            };
+            reply.send(match name.as_str() {
+                "work" => Response::success(),
+                other => Response::failure(format!("There is no profile {}", other))
+            })?;
+            Ok(())
+        };
+        assert_eq!(handle_line(r#"{"command":"switch-profile","name":"work"}"#, &app), Response::success());
+        assert_eq!(handle_line(r#"{"command":"switch-profile","name":"home"}"#, &app), Response::failure("There is no profile home"));
+        assert!(!handle_line(r#"{"command":"switch-profile"}"#, &app).ok);
+        //Without an answer the client gets an error instead of a premature success
+        let silent = |_: Command, _: Sender<Response>| -> Result<()> { Ok(()) };
+        assert!(!handle_line(r#"{"command":"switch-profile","name":"work"}"#, &silent).ok);
+        assert_eq!(
+            serde_json::to_string(&Command::SwitchProfile { name: "work".into() }).unwrap(),
+            r#"{"command":"switch-profile","name":"work"}"#);
+    }
+
+    #[test]
+    fn status_is_answered() {
+        let received = Mutex::new(Vec::new());
+        let response = handle_line(r#"{"command":"status"}"#, &app(&received));
+        assert!(response.ok);
+        let status = response.status.unwrap();
+        assert_eq!(status.monitor.as_deref(), Some("DISPLAY1"));
+        assert!(status.visible);
+    }
+
+    #[test]
+    fn errors_become_failures() {
+        let fails = |_: Command, _: Sender<Response>| -> Result<()> { bail!("The event loop is gone") };
+        assert_eq!(handle_line(r#"{"command":"hide"}"#, &fails), Response::failure("The event loop is gone"));
+        let response = handle_line("not json", &fails);
+        assert!(!response.ok && response.error.unwrap().starts_with("Invalid command"));
+        let response = handle_line(r#"{"command":"profile","name":"work"}"#, &fails);
+        assert!(!response.ok && response.error.unwrap().starts_with("Invalid command"));
+    }
+
+    #[test]
+    fn responses_serialize_compactly() {
+        assert_eq!(serde_json::to_string(&Response::success()).unwrap(), r#"{"ok":true}"#);
+        assert_eq!(serde_json::to_string(&Response::failure("nope")).unwrap(), r#"{"ok":false,"error":"nope"}"#);
+        assert_eq!(serde_json::to_string(&Command::Peek { monitor: "X".into() }).unwrap(), r#"{"command":"peek","monitor":"X"}"#);
+    }
+
+    #[test]
+    fn arguments_parse() {
+        let args = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<_>>();
+        assert_eq!(Command::from_args(&args("peek DISPLAY2")).unwrap(), Command::Peek { monitor: "DISPLAY2".into() });
+        assert_eq!(Command::from_args(&args("record stop")).unwrap(), Command::StopRecording);
+        assert_eq!(Command::from_args(&args("profile work")).unwrap(), Command::SwitchProfile { name: "work".into() });
+        assert!(Command::from_args(&args("profile")).is_err());
+        assert!(Command::from_args(&args("profile a b")).is_err());
+        assert!(Command::from_args(&args("help")).is_err());
+        assert_eq!(Command::from_flags(&args("")).unwrap(), None);
+        assert_eq!(Command::from_flags(&args("--quit")).unwrap(), Some(Command::Quit));
+        assert!(Command::from_flags(&args("--peek")).is_err());
+        assert_eq!(Command::from_flags(&args("--profile work")).unwrap(), Some(Command::SwitchProfile { name: "work".into() }));
+    }
+
+    #[test]
+    fn clients_are_served_one_line_at_a_time() {
+        let received = Mutex::new(Vec::new());
+        let input = "{\"command\":\"pin\"}\n\n{\"command\":\"status\"}\n";
+        let mut output = Vec::new();
+        serve_client(input.as_bytes(), &mut output, &app(&received)).unwrap();
+        let lines: Vec<Response> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], Response::success());
+        assert!(lines[1].status.is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_round_trip() {
+        let path = std::env::temp_dir().join(format!("DisplayPeek-test-{}.sock", std::process::id()));
+        let (tx, rx) = channel();
+        let server = serve_socket(path.clone(), move |command, reply| {
+            tx.send(command.clone())?;
+            if command == Command::Status {
+                reply.send(Response { status: Some(Status::default()), ..Response::success() })?;
+            }
+            Ok(())
+        }).unwrap();
+        assert!(serve_socket(path.clone(), |_, _| Ok(())).is_err(), "a second server must not steal the socket");
+        assert_eq!(send_to(&path, &Command::Reload).unwrap(), Response::success());
+        assert!(!send_to(&path, &Command::SwitchProfile { name: "work".into() }).unwrap().ok, "profile switches wait for an answer");
+        assert_eq!(send_to(&path, &Command::Status).unwrap().status, Some(Status::default()));
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![
+            Command::Reload,
+            Command::SwitchProfile { name: "work".into() },
+            Command::Status
+        ]);
+        drop(server);
+        assert!(!path.exists());
+        assert!(send_to(&path, &Command::Status).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stale_sockets_are_replaced() {
+        let path = std::env::temp_dir().join(format!("DisplayPeek-stale-{}.sock", std::process::id()));
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        let server = serve_socket(path.clone(), |_, _| Ok(())).unwrap();
+        assert_eq!(send_to(&path, &Command::Show).unwrap(), Response::success());
+        drop(server);
+    }
+}
@@ -0,0 +1,204 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use anyhow::{anyhow, Error};
+use serde::Deserialize;
+
+/// A virtual key written by name in the config, e.g. `"F8"`, `"Pause"` or `"RightCtrl"`, or as code like `"0xB3"`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Key(pub u16);
+
+/// Windows virtual key codes, which the config uses on every platform
+const NAMED_KEYS: &[(&str, u16)] = &[
+    ("Space", 0x20),
+    ("Tab", 0x09),
+    ("Enter", 0x0D),
+    ("Escape", 0x1B),
+    ("Backspace", 0x08),
+    ("Insert", 0x2D),
+    ("Delete", 0x2E),
+    ("Home", 0x24),
+    ("End", 0x23),
+    ("PageUp", 0x21),
+    ("PageDown", 0x22),
+    ("Left", 0x25),
+    ("Right", 0x27),
+    ("Up", 0x26),
+    ("Down", 0x28),
+    ("Pause", 0x13),
+    ("PrintScreen", 0x2C),
+    ("ScrollLock", 0x91),
+    ("CapsLock", 0x14),
+    ("NumLock", 0x90),
+    ("Apps", 0x5D),
+    ("LeftShift", 0xA0),
+    ("RightShift", 0xA1),
+    ("LeftCtrl", 0xA2),
+    ("RightCtrl", 0xA3),
+    ("LeftAlt", 0xA4),
+    ("RightAlt", 0xA5),
+    ("LeftWin", 0x5B),
+    ("RightWin", 0x5C),
+];
+
+const VK_F1: u16 = 0x70;
+const VK_F24: u16 = 0x87;
+
+impl FromStr for Key {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let named = NAMED_KEYS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|(_, vk)| *vk);
+        let single = match s.as_bytes() {
+            [c] if c.is_ascii_alphanumeric() => Some(c.to_ascii_uppercase() as u16),
+            _ => None
+        };
+        let function = s
+            .strip_prefix(['F', 'f'])
+            .and_then(|n| n.parse::<u16>().ok())
+            .filter(|n| (1..=24).contains(n))
+            .map(|n| VK_F1 + n - 1);
+        let code = s
+            .strip_prefix("0x")
+            .and_then(|n| u16::from_str_radix(n, 16).ok());
+        named
+            .or(single)
+            .or(function)
+            .or(code)
+            .map(Key)
+            .ok_or_else(|| anyhow!("Unknown key: {}", s))
+    }
+}
+
+impl TryFrom<String> for Key {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match NAMED_KEYS.iter().find(|(_, vk)| *vk == self.0) {
+            Some((name, _)) => write!(f, "{}", name),
+            None if (VK_F1..=VK_F24).contains(&self.0) => write!(f, "F{}", self.0 - VK_F1 + 1),
+            None if matches!(self.0, 0x30..=0x39 | 0x41..=0x5A) => write!(f, "{}", self.0 as u8 as char),
+            None => write!(f, "0x{:02X}", self.0)
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub win: bool
+}
+
+/// A key combination written like `"Ctrl+Alt+P"` in the config
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Accelerator {
+    pub modifiers: Modifiers,
+    pub key: Key
+}
+
+impl FromStr for Accelerator {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modifiers = Modifiers::default();
+        let mut parts = s.split('+').map(str::trim).peekable();
+        while let Some(part) = parts.next() {
+            if parts.peek().is_none() {
+                return Ok(Self { modifiers, key: part.parse()? });
+            }
+            let modifier = match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => &mut modifiers.ctrl,
+                "alt" => &mut modifiers.alt,
+                "shift" => &mut modifiers.shift,
+                "win" | "super" => &mut modifiers.win,
+                _ => return Err(anyhow!("Unknown modifier {} in {}", part, s))
+            };
+            if std::mem::replace(modifier, true) {
+                return Err(anyhow!("Modifier {} is repeated in {}", part, s));
+            }
+        }
+        Err(anyhow!("Empty accelerator"))
+    }
+}
+
+impl TryFrom<String> for Accelerator {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for Accelerator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let modifiers = [
+            (self.modifiers.ctrl, "Ctrl"),
+            (self.modifiers.alt, "Alt"),
+            (self.modifiers.shift, "Shift"),
+            (self.modifiers.win, "Win")
+        ];
+        for (_, name) in modifiers.iter().filter(|(set, _)| *set) {
+            write!(f, "{}+", name)?;
+        }
+        write!(f, "{}", self.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_parse_by_name_letter_function_and_code() {
+        assert_eq!("Pause".parse::<Key>().unwrap(), Key(0x13));
+        assert_eq!("rightctrl".parse::<Key>().unwrap(), Key(0xA3));
+        assert_eq!("p".parse::<Key>().unwrap(), Key(0x50));
+        assert_eq!("7".parse::<Key>().unwrap(), Key(0x37));
+        assert_eq!("F1".parse::<Key>().unwrap(), Key(0x70));
+        assert_eq!("f24".parse::<Key>().unwrap(), Key(0x87));
+        assert_eq!("0xB3".parse::<Key>().unwrap(), Key(0xB3));
+        for invalid in ["", "F0", "F25", "Hyper", "0xZZ", "pp", "+"] {
+            assert!(invalid.parse::<Key>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn keys_display_round_trips() {
+        for code in 0..=0xFF {
+            let key = Key(code);
+            assert_eq!(key.to_string().parse::<Key>().unwrap(), key, "{}", key);
+        }
+        assert_eq!(Key(0x75).to_string(), "F6");
+        assert_eq!(Key(0xB3).to_string(), "0xB3");
+    }
+
+    #[test]
+    fn accelerators_parse() {
+        let accelerator: Accelerator = "Ctrl + alt+Shift+Win+F8".parse().unwrap();
+        assert_eq!(accelerator.modifiers, Modifiers { ctrl: true, alt: true, shift: true, win: true });
+        assert_eq!(accelerator.key, Key(0x77));
+        assert_eq!(accelerator.to_string(), "Ctrl+Alt+Shift+Win+F8");
+        let accelerator: Accelerator = "Super+Control+P".parse().unwrap();
+        assert_eq!(accelerator.to_string(), "Ctrl+Win+P");
+        assert_eq!("Pause".parse::<Accelerator>().unwrap().modifiers, Modifiers::default());
+    }
+
+    #[test]
+    fn invalid_accelerators_are_rejected() {
+        for invalid in ["", "Ctrl+", "Ctrl+Ctrl+P", "Meta+P", "Ctrl+Alt", "Ctrl+Unknown"] {
+            assert!(invalid.parse::<Accelerator>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+#[cfg(windows)]
+use glam::{Mat4, Quat, vec3};
+use serde::Deserialize;
+use crate::chrome::caption_text;
+use crate::config::Color;
+use crate::directx::{CursorType, DisplayMode};
+#[cfg(windows)]
+use crate::directx::{Direct3D, QuadRenderer, Rect, Shape, TextRenderer};
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Corner {
+    TopLeft,
+    #[default]
+    TopRight,
+    BottomLeft,
+    BottomRight
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct OsdConfig {
+    /// Whether the on-screen display is visible right after start
+    pub enabled: bool,
+    pub corner: Corner,
+    /// Size of a font pixel in window pixels
+    pub scale: f32,
+    pub color: Color,
+    pub background: Color
+}
+
+impl Default for OsdConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            corner: Corner::default(),
+            scale: 2.0,
+            color: Color::rgba(0, 255, 0, 255),
+            background: Color::rgba(0, 0, 0, 192),
+        }
+    }
+}
+
+/// Counts events over the last second
+#[derive(Debug, Default, Clone)]
+pub struct FpsCounter {
+    events: VecDeque<Instant>
+}
+
+impl FpsCounter {
+    const WINDOW: Duration = Duration::from_secs(1);
+
+    pub fn record(&mut self, now: Instant) {
+        self.events.push_back(now);
+        self.trim(now);
+    }
+
+    pub fn fps(&mut self, now: Instant) -> f32 {
+        self.trim(now);
+        self.events.len() as f32 / Self::WINDOW.as_secs_f32()
+    }
+
+    fn trim(&mut self, now: Instant) {
+        while self.events
+            .front()
+            .is_some_and(|t| now.saturating_duration_since(*t) > Self::WINDOW) {
+            self.events.pop_front();
+        }
+    }
+}
+
+/// Everything that is shown by the on-screen display
+#[derive(Debug, Default, Clone)]
+pub struct Diagnostics {
+    pub monitor: String,
+    pub display_mode: Option<DisplayMode>,
+    pub captured: FpsCounter,
+    pub presented: FpsCounter,
+    pub frame_age: Option<Duration>,
+    pub timeouts: u64,
+    pub cursor_type: Option<CursorType>
+}
+
+impl Diagnostics {
+
+    pub fn text(&mut self, now: Instant) -> String {
+        let mut text = String::new();
+        let _ = match self.display_mode {
+            Some(mode) => writeln!(text, "{}", caption_text(&self.monitor, mode, None)),
+            None => writeln!(text, "{}", self.monitor)
+        };
+        let _ = writeln!(text, "capture  {:5.1} fps", self.captured.fps(now));
+        let _ = writeln!(text, "present  {:5.1} fps", self.presented.fps(now));
+        let _ = match self.frame_age {
+            Some(age) => writeln!(text, "age      {:5.1} ms", age.as_secs_f32() * 1000.0),
+            None => writeln!(text, "age          - ms")
+        };
+        let _ = writeln!(text, "timeouts {:5}", self.timeouts);
+        let _ = match self.cursor_type {
+            Some(cursor_type) => write!(text, "cursor   {:?}", cursor_type),
+            None => write!(text, "cursor   -")
+        };
+        text
+    }
+
+}
+
+#[cfg(windows)]
+impl OsdConfig {
+
+    /// Draws the text into the configured corner of `content`
+    pub fn draw(&self, d3d: &Direct3D, quad_renderer: &QuadRenderer, text_renderer: &TextRenderer, windowspace: Mat4, content: Rect, text: &str) {
+        let scale = self.scale.max(1.0).round();
+        let padding = 2.0 * scale;
+        let (width, height) = TextRenderer::measure(text, scale);
+        let (width, height) = (width + 2.0 * padding, height + padding);
+        let (x, y) = match self.corner {
+            Corner::TopLeft => (content.x, content.y),
+            Corner::TopRight => (content.x + content.width - width, content.y),
+            Corner::BottomLeft => (content.x, content.y + content.height - height),
+            Corner::BottomRight => (content.x + content.width - width, content.y + content.height - height)
+        };
+        let background = Rect::new(x.round(), y.round(), width, height);
+        quad_renderer.draw_shape(d3d, windowspace, &Shape {
+            rect: background,
+            color: self.background.premultiplied(),
+            ..Default::default()
+        });
+        let transform = windowspace * Mat4::from_scale_rotation_translation(
+            vec3(scale, scale, 1.0),
+            Quat::IDENTITY,
+            vec3(background.x + padding, background.y + padding, 0.0));
+        text_renderer.draw(d3d, quad_renderer, transform, self.color.premultiplied(), text);
+    }
+
+}
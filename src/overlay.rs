@@ -0,0 +1,880 @@
+//! The tray app with the overlay window
+
+use std::ops::Add;
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
+use anyhow::Context;
+use error_tools::log::LogResultExt;
+use error_tools::tao::EventLoopExtRunResult;
+use glam::{Mat4, Quat, Vec2, vec2, vec3};
+use windows::Win32::Graphics::Gdi::HMONITOR;
+use tao::{event::*, event_loop::*, window::*};
+use tao::platform::windows::{WindowBuilderExtWindows};
+use windows::Win32::Graphics::Direct3D11::*;
+use crate::{cursor_tracker, image_io, input, redaction, snapshot, vsync_helper, CustomEvent};
+use crate::animation::Animator;
+use crate::chrome::caption_text;
+use crate::config::{Config, OverlayConfig};
+use crate::cursor_effects::Ripples;
+use crate::cursor_shape::CursorShape;
+use crate::dwell::{DwellFilter, Switch};
+use crate::zones::{resolve_key_triggers, resolve_zones, KeyTrigger, TriggerZone};
+use crate::trigger::{cycle, TriggerMode, Triggers};
+use crate::hotkeys::{HotkeyAction, HotkeyBindings, HotkeyThreadHandle, start_hotkey_thread};
+use crate::ipc::{Command, Response, start_ipc_server, Status};
+use crate::recording::Recording;
+use crate::web::WebServer;
+use crate::vnc::VncServer;
+use crate::remote::RemoteSender;
+use crate::frame_source::FrameSource;
+use crate::capture::Capture;
+use crate::frame_export::FrameExport;
+use crate::redaction::Redaction;
+use crate::cursor_tracker::output_relative;
+use crate::input::{InputEventKind, InputRecorder};
+use crate::osd::Diagnostics;
+use crate::directx::{Adapter, AdapterFactory, CachedFrame, CursorData, CursorSprite, Direct3D, QuadRenderer, Rect, Shape, TextRenderer};
+use crate::tray_helper::create_system_tray;
+use crate::utils::{com_initialized, make_blend_state};
+
+/// `command` is carried out once the event loop runs, like a control client would
+pub fn run(command: Option<Command>) -> anyhow::Result<bool> {
+
+    com_initialized();
+
+    let mut config = Config::load()?;
+
+    let mut event_loop = EventLoop::with_user_event();
+    let window = WindowBuilder::new()
+        .with_visible(false)
+        .with_title("Display Peek")
+        .with_drag_and_drop(false)
+        .with_decorations(false)
+        .with_always_on_top(true)
+        .with_skip_taskbar(true)
+        //.with_undecorated_shadow(true)
+        .with_no_redirection_bitmap(true)
+        .build(&event_loop)?;
+    window.set_ignore_cursor_events(true)?;
+    let system_tray = create_system_tray(&event_loop)?;
+    let tracker = cursor_tracker::set_hook(&event_loop)?;
+    let mut _config_watcher = Config::create_watcher(&event_loop.create_proxy(), &Config::path())?;
+    let _ipc_server = start_ipc_server(&event_loop);
+    if let Some(command) = command {
+        apply_command(&event_loop.create_proxy(), command);
+    }
+    let vsync_switcher = vsync_helper::start_vsync_thread(&event_loop, None);
+    let mut input_recorder = std::env::var_os("DISPLAY_PEEK_RECORD_INPUT")
+        .and_then(|path| InputRecorder::create(path).log_ok("Can not start input recording"));
+    if let Some(path) = std::env::var_os("DISPLAY_PEEK_REPLAY_INPUT") {
+        input::read_recording(path)
+            .map(|events| input::start_replay(&event_loop, events))
+            .log_ok("Can not replay input recording");
+    }
+
+    let adapter = AdapterFactory::new()?
+        .get_adapter_by_idx(0)
+        .context("Can not get default graphics adapter")?;
+
+    let mut d3d = Direct3D::new(&adapter, &window)?;
+    let mut quad_renderer = QuadRenderer::new(&d3d)?;
+    let text_renderer = TextRenderer::new(&d3d)?;
+
+    let mut dupl: Option<FrameSource> = None;
+    let mut cursor_sprite = CursorSprite::new(&d3d.device, 32, 32)?;
+    let mut frame_cache = CachedFrame::new();
+    let mut caption = String::new();
+    let mut recording: Option<Recording> = None;
+    //The overlay only duplicates its monitor while it is visible, the capture keeps running for the recording and the streams
+    let mut capture: Option<Capture> = None;
+    let mut capture_target: Option<(u32, HMONITOR)> = None;
+
+    let blend_state_color = make_blend_state(&d3d.device, D3D11_BLEND_ONE, D3D11_BLEND_INV_SRC_ALPHA)?;
+    //src * (1 - dst) + dst * (1 - src) is the xor of the pointer shape for every channel that is either 0 or 255
+    let blend_state_xor = make_blend_state(&d3d.device, D3D11_BLEND_INV_DEST_COLOR, D3D11_BLEND_INV_SRC_COLOR)?;
+
+    let event_proxy = event_loop.create_proxy();
+    let mut dwell = DwellFilter::new(config.dwell);
+    let mut triggers = Triggers::default();
+    let mut paused = false;
+    let mut cycle_position: Option<(HMONITOR, usize, usize)> = None;
+    let mut hotkeys = start_hotkeys(&event_proxy, &config);
+    let (mut zones, mut key_triggers, mut pointer_monitor) = restart_triggers(&adapter, &config, &mut dwell);
+    let mut web_server = start_web_server(&adapter, &config, &event_proxy);
+    let mut vnc_server = start_vnc_server(&config, None);
+    let mut remote_sender = start_remote_sender(&adapter, &config);
+    let mut frame_export = start_frame_export(&config);
+    let mut capture_monitor = peekable_monitors(&adapter, &config).first().copied();
+
+    let mut reload_timer: Option<Instant> = None;
+    //`None` while the main config is used
+    let mut profile: Option<String> = None;
+    let mut animator = Animator::default();
+    let mut current_overlay: Option<OverlayConfig> = None;
+    let mut translucent = true;
+    let mut diagnostics = Diagnostics::default();
+    let mut osd_visible = config.osd.enabled;
+    let mut ripples = Ripples::default();
+    //Pointer position from the input hook relative to the mirrored output; Preferred over the position from DXGI
+    let mut hook_cursor_pos: Option<Vec2> = None;
+
+    let mut last_flow = ControlFlow::Wait;
+    let result = event_loop.run_result(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Wait;
+        match event {
+            Event::MainEventsCleared => if animator.is_running() || ripples.is_running() {
+                window.request_redraw()
+            },
+            Event::RedrawRequested(_) => {
+                if animator.update(Instant::now()) {
+                    dupl = None;
+                    vsync_switcher.change_display(None);
+                    window.set_visible(false);
+                    frame_cache.invalidate();
+                }
+                if let Some(dupl) = dupl.as_ref() {
+                    if let Some( tex) = frame_cache.get_view() {
+                        unsafe {
+                            let window_size = window.inner_size();
+                            let windowspace = Mat4::orthographic_rh(
+                                0.0,
+                                window_size.width as f32,
+                                window_size.height as f32,
+                                0.0,
+                                -1.0,
+                                1.0);
+
+                            d3d.context.ClearRenderTargetView(d3d.render_target(), [0.0, 0.0, 0.0, 0.0].as_ptr());
+
+                            d3d.context.RSSetViewports(Some(&[D3D11_VIEWPORT {
+                                Width: window_size.width as f32,
+                                Height: window_size.height as f32,
+                                MaxDepth: 1.0,
+                                ..Default::default()
+                            }]));
+                            d3d.context.OMSetRenderTargets(Some(&[d3d.render_target().clone()]), None);
+
+                            quad_renderer.bind(&d3d);
+
+                            let margin = config.chrome.margin();
+                            let available_width = (window_size.width as f32 - 2.0 * margin).max(1.0);
+                            let available_height = (window_size.height as f32 - 2.0 * margin).max(1.0);
+                            let (display_width, display_height) = dupl.get_display_mode().get_flipped_size();
+                            let aspect = display_width as f32 / display_height as  f32;
+                            let width = available_width.min(available_height * aspect);
+                            let height = width / aspect;
+
+                            let animation = animator.frame();
+                            let (opacity, force_opaque) = current_overlay
+                                .map(|o| o.alpha_mode(translucent))
+                                .unwrap_or((1.0, false));
+                            quad_renderer.set_force_opaque(force_opaque);
+                            d3d.set_opacity(opacity * animation.opacity)
+                                .log_ok("Can not set overlay opacity");
+                            let content = Rect::new(
+                                0.5 * (window_size.width as f32 - width * animation.scale) + animation.offset.x * window_size.width as f32,
+                                0.5 * (window_size.height as f32 - height * animation.scale) + animation.offset.y * window_size.height as f32,
+                                width * animation.scale,
+                                height * animation.scale);
+                            let scale = content.height / display_height as f32;
+
+                            d3d.context.OMSetBlendState(&blend_state_color, None, u32::MAX);
+                            config.chrome.draw_background(&d3d, &quad_renderer, windowspace, content);
+                            quad_renderer.set_clip(&d3d, config.chrome.clip(content));
+
+                            let screenspace = windowspace * Mat4::from_scale_rotation_translation(
+                                vec3(scale, scale, 0.0),
+                                Quat::IDENTITY,
+                                vec3(content.x, content.y, 0.0)
+                            );
+
+                            let transform = screenspace * dupl.get_display_mode().get_frame_transform();
+                            d3d.context.OMSetBlendState(&blend_state_color, None, u32::MAX);
+                            quad_renderer.draw_scaled(&d3d, transform, tex);
+
+                            let now = Instant::now();
+                            let to_window = |p: Vec2| vec2(content.x, content.y) + p * scale;
+                            let cursor_pos = dupl
+                                .get_cursor_pos()
+                                .map(|pt| hook_cursor_pos.unwrap_or(vec2(pt.x as f32, pt.y as f32)));
+                            if let (Some(pt), true) = (cursor_pos, cursor_sprite.valid) {
+                                let clip = config.chrome.clip(content);
+                                let content_shape = clip.unwrap_or(Shape { rect: content, ..Default::default() });
+                                config.cursor.draw_background(&d3d, &quad_renderer, windowspace, content_shape, to_window(pt));
+                                quad_renderer.set_clip(&d3d, clip);
+
+                                let pointer_scale = config.cursor.pointer_scale(cursor_sprite.height, scale);
+                                let transform = screenspace * Mat4::from_scale_rotation_translation(
+                                    vec3(cursor_sprite.width as f32 * pointer_scale, cursor_sprite.height as f32 * pointer_scale, 0.0),
+                                    Quat::IDENTITY,
+                                    vec3(
+                                        pt.x - cursor_sprite.hotspot.0 as f32 * pointer_scale,
+                                        pt.y - cursor_sprite.hotspot.1 as f32 * pointer_scale,
+                                        0.0)
+                                );
+                                d3d.context.OMSetBlendState(&blend_state_color, None, u32::MAX);
+                                quad_renderer.draw(&d3d, transform, cursor_sprite.color_srv());
+                                if cursor_sprite.has_xor {
+                                    d3d.context.OMSetBlendState(&blend_state_xor, None, u32::MAX);
+                                    quad_renderer.draw(&d3d, transform, cursor_sprite.xor_srv());
+                                }
+
+                            }
+                            d3d.context.OMSetBlendState(&blend_state_color, None, u32::MAX);
+                            ripples.update(now, config.cursor.ripple_duration());
+                            config.cursor.draw_ripples(&d3d, &quad_renderer, windowspace, &ripples, now, to_window);
+                            quad_renderer.set_clip(&d3d, None);
+                            config.chrome.draw_foreground(&d3d, &quad_renderer, &text_renderer, windowspace, content, &caption);
+                            if osd_visible {
+                                diagnostics.display_mode = Some(dupl.get_display_mode());
+                                diagnostics.frame_age = dupl.get_frame_age();
+                                diagnostics.timeouts = dupl.get_timeout_count();
+                                diagnostics.cursor_type = cursor_sprite.valid.then_some(cursor_sprite.cursor_type);
+                                let text = diagnostics.text(Instant::now());
+                                config.osd.draw(&d3d, &quad_renderer, &text_renderer, windowspace, content, &text);
+                            }
+                            //TODO only swap dirty rects
+                            d3d.swap_chain.Present(1, 0)
+                                .ok()
+                                .map_err(|err| {
+                                    log::error!("Swapchain error: {}", err);
+                                    true
+                                })?;
+                            diagnostics.presented.record(Instant::now());
+                        }
+                    }
+                }
+            },
+            Event::UserEvent(CustomEvent::CursorMonitorSwitch(monitor)) => {
+                match adapter.get_display_by_handle(monitor) {
+                    None => log::warn!("Cannot find the correct display"),
+                    Some(display) => match display
+                        .name()
+                        .log_ok("Can not get monitor name")
+                        .and_then(|n| config.get_overlay_config(&n).map(|o| (n, o))) {
+                        None => match animator.is_visible() {
+                            true => animator.close(Instant::now(), config.animation.close),
+                            false => {
+                                dupl = None;
+                                vsync_switcher.change_display(None);
+                                window.set_visible(false);
+                                frame_cache.invalidate();
+                            }
+                        }
+                        Some((name, overlay_config)) => {
+                            let remote = config.remote_source(&name);
+                            let equals = dupl.as_ref().map(|d| d.get_current_output() == &display && d.remote_source() == remote);
+                            if !equals.unwrap_or(false) {
+                                dupl.take();
+                                match FrameSource::new(&d3d.device, display, remote) {
+                                    Ok(new_dupl) => {
+                                        vsync_switcher.change_display(new_dupl.get_current_output().clone());
+                                        let name = new_dupl.get_current_output()
+                                            .name()
+                                            .log_ok("Can not get monitor name")
+                                            .unwrap_or_default();
+                                        diagnostics.monitor = name;
+                                        ripples.clear();
+                                        hook_cursor_pos = None;
+                                        dupl = Some(new_dupl);
+                                    }
+                                    Err(err) => log::error!("Can not create frame source: {}", err)
+                                };
+                            }
+                            if let Some(dupl) = dupl.as_ref() {
+                                let position = cycle_position
+                                    .filter(|(m, _, _)| *m == monitor)
+                                    .map(|(_, index, count)| (index, count));
+                                caption = caption_text(&diagnostics.monitor, dupl.get_display_mode(), position);
+                            }
+                            animator.open(Instant::now(), config.animation.open);
+                            capture_monitor = Some(monitor);
+                            current_overlay = Some(overlay_config);
+                            quad_renderer.set_filter(&d3d, overlay_config.filter);
+                            window.set_outer_position(overlay_config.position);
+                            window.set_inner_size(overlay_config.size);
+                            window.set_visible(true);
+                        }
+                    }
+                }
+            },
+            Event::UserEvent(CustomEvent::HideOverlay) => match animator.is_visible() {
+                true => animator.close(Instant::now(), config.animation.close),
+                false => {
+                    dupl = None;
+                    vsync_switcher.change_display(None);
+                    window.set_visible(false);
+                    frame_cache.invalidate();
+                }
+            },
+            Event::UserEvent(CustomEvent::DismissOverlay) => {
+                if let Some(switch) = triggers.dismiss() {
+                    send_switch(&event_proxy, switch);
+                }
+            },
+            Event::UserEvent(CustomEvent::ShowOverlay) if !paused => {
+                let target = Some(pointer_monitor)
+                    .filter(|monitor| has_overlay(&adapter, &config, *monitor))
+                    .or_else(|| cycle(&peekable_monitors(&adapter, &config), None, true).map(|(_, m)| m));
+                if let Some(switch) = target.and_then(|monitor| triggers.open(monitor)) {
+                    send_switch(&event_proxy, switch);
+                }
+            },
+            Event::UserEvent(CustomEvent::PinOverlay) if !paused => {
+                if let Some(switch) = triggers.pin() {
+                    send_switch(&event_proxy, switch);
+                }
+            },
+            Event::UserEvent(CustomEvent::PeekMonitor(name, reply)) => {
+                let monitor = adapter
+                    .get_display_by_name(&name)
+                    .and_then(|display| display.hmonitor().ok());
+                let response = match monitor {
+                    _ if paused => Response::failure("Peeking is paused"),
+                    None => Response::failure(format!("Can not find monitor {}", name)),
+                    Some(monitor) if !has_overlay(&adapter, &config, monitor) => Response::failure(format!("Monitor {} is not configured", name)),
+                    Some(monitor) => {
+                        if let Some(switch) = triggers.open(monitor) {
+                            send_switch(&event_proxy, switch);
+                        }
+                        Response::success()
+                    }
+                };
+                reply.send(response)
+                    .log_ok("Control client is gone");
+            },
+            Event::UserEvent(CustomEvent::QueryStatus(reply)) => {
+                let now = Instant::now();
+                let visible = animator.is_visible();
+                let status = Status {
+                    monitor: visible.then(|| diagnostics.monitor.clone()),
+                    profile: profile.clone(),
+                    visible,
+                    paused,
+                    recording: recording.is_some(),
+                    capture_fps: diagnostics.captured.fps(now),
+                    present_fps: diagnostics.presented.fps(now),
+                };
+                reply.send(Response { status: Some(status), ..Response::success() })
+                    .log_ok("Control client is gone");
+            },
+            Event::UserEvent(CustomEvent::SwitchProfile(name, reply)) => {
+                //The profile is only checked here, the reload below applies it like an edit of the file
+                let switched = Config::profile_path(Some(&name))
+                    .and_then(|path| {
+                        Config::load_profile(Some(&name))?;
+                        Config::create_watcher(&event_proxy, &path)
+                    });
+                let response = match switched {
+                    Ok(watcher) => {
+                        log::info!("Switching to profile {}", name);
+                        _config_watcher = watcher;
+                        profile = Some(name).filter(|name| name != "default");
+                        let timer = reload_timer.insert(Instant::now());
+                        *control_flow = ControlFlow::WaitUntil(*timer);
+                        Response::success()
+                    }
+                    Err(err) => Response::failure(format!("Can not load profile {}: {:#}", name, err))
+                };
+                reply.send(response)
+                    .log_ok("Control client is gone");
+            },
+            Event::UserEvent(CustomEvent::ReloadConfig) => {
+                let timer = reload_timer.insert(Instant::now());
+                *control_flow = ControlFlow::WaitUntil(*timer);
+            },
+            Event::UserEvent(CustomEvent::Hotkey(action)) => match action {
+                HotkeyAction::Toggle => event_proxy
+                    .send_event(match triggers.shown() {
+                        Some(_) => CustomEvent::DismissOverlay,
+                        None => CustomEvent::ShowOverlay
+                    })
+                    .unwrap_or_else(|_| log::warn!("Can not send toggle event to eventloop")),
+                HotkeyAction::Pin => event_proxy
+                    .send_event(CustomEvent::PinOverlay)
+                    .unwrap_or_else(|_| log::warn!("Can not send pin event to eventloop")),
+                HotkeyAction::NextMonitor | HotkeyAction::PreviousMonitor if !paused => {
+                    let monitors = peekable_monitors(&adapter, &config);
+                    let forward = action == HotkeyAction::NextMonitor;
+                    if let Some((index, monitor)) = cycle(&monitors, triggers.shown(), forward) {
+                        cycle_position = Some((monitor, index, monitors.len()));
+                        if let Some(switch) = triggers.peek(monitor) {
+                            send_switch(&event_proxy, switch);
+                        }
+                    }
+                }
+                HotkeyAction::Pause => {
+                    paused = !paused;
+                    log::info!("Peeking {}", if paused { "paused" } else { "resumed" });
+                    match paused {
+                        true => if let Some(switch) = triggers.dismiss() {
+                            send_switch(&event_proxy, switch);
+                        }
+                        false => (zones, key_triggers, pointer_monitor) = restart_triggers(&adapter, &config, &mut dwell)
+                    }
+                }
+                HotkeyAction::ReloadConfig => event_proxy
+                    .send_event(CustomEvent::ReloadConfig)
+                    .unwrap_or_else(|_| log::warn!("Can not send reload event to eventloop")),
+                HotkeyAction::Snapshot => event_proxy
+                    .send_event(CustomEvent::TakeSnapshot)
+                    .unwrap_or_else(|_| log::warn!("Can not send snapshot event to eventloop")),
+                HotkeyAction::Record => event_proxy
+                    .send_event(CustomEvent::ToggleRecording)
+                    .unwrap_or_else(|_| log::warn!("Can not send recording event to eventloop")),
+                HotkeyAction::ToggleOsd => event_proxy
+                    .send_event(CustomEvent::ToggleOsd)
+                    .unwrap_or_else(|_| log::warn!("Can not send osd event to eventloop")),
+                _ => log::debug!("Ignoring {:?} while paused", action)
+            },
+            Event::UserEvent(CustomEvent::VBlank) => {
+                if let Some(dupl) = dupl.as_mut() {
+                    match dupl.try_acquire_next_frame() {
+                        Ok(result) => {
+                            if result.success {
+                                if result.frame_update {
+                                    diagnostics.captured.record(Instant::now());
+                                    if let Some(frame) = dupl.get_frame() {
+                                        frame_cache.update(&d3d.device, &d3d.context, frame);
+                                        let redactions = redaction::frame_areas(config.redactions(&diagnostics.monitor), dupl.get_display_mode());
+                                        frame_cache.redact(&d3d, &quad_renderer, &redactions)
+                                            .log_ok("Can not redact frame");
+                                    }
+                                }
+                                window.request_redraw()
+                            }
+                            if result.cursor_updated {
+                                let cursor_data = dupl.get_cursor_data().expect("The cursor should be available");
+                                cursor_sprite.update(&d3d.device, &d3d.context, cursor_data)
+                                    .log_ok("Can not update cursor");
+                            }
+                        },
+                        Err(err) => log::error!("error acquiring frame: {}", err)
+                    }
+                    if osd_visible {
+                        window.request_redraw();
+                    }
+                }
+            },
+            Event::UserEvent(CustomEvent::CaptureTick) => if let Some(capture) = capture.as_mut() {
+                match capture.acquire(&d3d, &quad_renderer, &config) {
+                    Ok(result) => {
+                        if result.success {
+                            if let Some(rec) = recording.as_mut() {
+                                rec.frame_changed();
+                            }
+                            if let Some(web) = web_server.as_mut() {
+                                web.frame_changed();
+                            }
+                            if let Some(export) = frame_export.as_mut() {
+                                export.frame_changed();
+                            }
+                        }
+                        if let Some(vnc) = vnc_server.as_mut() {
+                            let source = capture.source();
+                            if result.frame_update {
+                                let mode = source.get_display_mode();
+                                vnc.frame_changed(source
+                                    .get_move_rects()
+                                    .iter()
+                                    .map(|mv| mv.rotated(mode.width, mode.height, snapshot::quarter_turns(mode.orientation))));
+                            }
+                            if result.cursor_updated {
+                                vnc.cursor_changed(source.get_cursor_data().and_then(|data| CursorShape::decode(data).ok()));
+                            }
+                        }
+                    },
+                    Err(err) => log::debug!("Can not acquire frame to capture: {}", err)
+                }
+                let capture = &*capture;
+                if let Some(rec) = recording.as_mut() {
+                    let cursor = rec.config().cursor;
+                    if let Err(err) = rec.update(Instant::now(), || capture.read(&d3d, cursor)) {
+                        log::error!("Stopping recording: {}", err);
+                        if let Some(rec) = recording.take() {
+                            rec.stop()
+                                .log_ok("Can not finish recording");
+                        }
+                    }
+                }
+                if let Some(export) = frame_export.as_mut() {
+                    let cursor = export.config().cursor;
+                    export.update(Instant::now(), capture.source(), || capture.read(&d3d, cursor))
+                        .log_ok("Can not export frame");
+                }
+                if let Some(web) = web_server.as_mut() {
+                    let cursor = web.config().cursor;
+                    web.update(Instant::now(), capture.name(), || capture.read(&d3d, cursor))
+                        .log_ok("Can not stream frame");
+                }
+                if let Some(vnc) = vnc_server.as_mut() {
+                    let pointer = capture.source().get_cursor_pos().map(|pos| (pos.x, pos.y));
+                    vnc.update(Instant::now(), pointer, || capture.read(&d3d, false))
+                        .log_ok("Can not update VNC clients");
+                }
+            },
+            Event::UserEvent(event @ (CustomEvent::InputAvailable | CustomEvent::Input(_))) => {
+                let events = match event {
+                    CustomEvent::Input(event) => vec![event],
+                    _ => cursor_tracker::take_events()
+                };
+                for event in events {
+                    if let Some(recorder) = input_recorder.as_mut() {
+                        recorder.record(&event)
+                            .log_ok("Can not record input event");
+                    }
+                    match event.kind {
+                        InputEventKind::MonitorEnter(monitor) => {
+                            pointer_monitor = monitor;
+                            dwell.enter(Instant::now(), monitor, is_configured(&adapter, &config, monitor));
+                        }
+                        InputEventKind::Move { position, .. } => dwell
+                            .moved(Instant::now(), vec2(position.x as f32, position.y as f32)),
+                        InputEventKind::ZoneEnter(zone) => if let Some(zone) = zones.get(zone as usize) {
+                            dwell.enter_zone(Instant::now(), zone.target, zone.delays);
+                        }
+                        InputEventKind::ZoneLeave(_) => dwell
+                            .enter(Instant::now(), pointer_monitor, is_configured(&adapter, &config, pointer_monitor)),
+                        InputEventKind::Key { key, pressed } if !paused => {
+                            for trigger in key_triggers.iter().filter(|t| t.key == key) {
+                                if let Some(switch) = triggers.key(trigger.target, trigger.mode, pressed) {
+                                    send_switch(&event_proxy, switch);
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                    //The local pointer is not on a remote monitor
+                    let Some(dupl) = dupl.as_ref().filter(|d| d.is_local()) else { continue };
+                    let to_output = |position| dupl.get_current_output()
+                        .desktop_rect()
+                        .log_ok("Can not get desktop coordinates of the output")
+                        .and_then(|rect| output_relative(rect, position));
+                    match event.kind {
+                        InputEventKind::Move { position, .. } => {
+                            hook_cursor_pos = to_output(position);
+                            window.request_redraw();
+                        }
+                        InputEventKind::Button { pressed: true, position, .. } if config.cursor.ripples => {
+                            if let Some(position) = to_output(position) {
+                                ripples.push(Instant::now(), position);
+                                window.request_redraw();
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Event::UserEvent(CustomEvent::ToggleTranslucency) => {
+                translucent = !translucent;
+                log::debug!("Translucency {}", if translucent { "enabled" } else { "disabled" });
+                window.request_redraw();
+            }
+            Event::UserEvent(CustomEvent::ToggleOsd) => {
+                osd_visible = !osd_visible;
+                window.request_redraw();
+            }
+            Event::UserEvent(CustomEvent::ExportCursor) => {
+                match dupl.as_ref().and_then(|d| d.get_cursor_data()) {
+                    None => log::warn!("There is no cursor shape to export"),
+                    Some(data) => {
+                        CursorShape::decode(data)
+                            .and_then(|shape| {
+                                let path = image_io::output_path("cursor", "png");
+                                shape.save_png(&path)?;
+                                shape.save_cur(path.with_extension("cur"))?;
+                                log::info!("Exported cursor shape to {}", path.display());
+                                Ok(())
+                            })
+                            .log_ok("Can not export cursor shape");
+                    }
+                }
+            }
+            Event::UserEvent(CustomEvent::TakeSnapshot) => match dupl.as_ref() {
+                None => log::warn!("There is no monitor to take a snapshot of"),
+                Some(dupl) => {
+                    snapshot::take_snapshot(&d3d, &frame_cache, dupl, config.snapshot)
+                        .log_ok("Can not take snapshot");
+                }
+            },
+            Event::UserEvent(CustomEvent::StartRecording) => if recording.is_none() {
+                recording = Recording::start(config.recording)
+                    .log_ok("Can not start recording");
+            },
+            Event::UserEvent(CustomEvent::StopRecording) => if let Some(rec) = recording.take() {
+                rec.stop()
+                    .log_ok("Can not finish recording");
+            },
+            Event::UserEvent(CustomEvent::ToggleRecording) => event_proxy
+                .send_event(match recording.is_some() {
+                    true => CustomEvent::StopRecording,
+                    false => CustomEvent::StartRecording
+                })
+                .unwrap_or_else(|_| log::warn!("Can not send recording event to eventloop")),
+            Event::UserEvent(CustomEvent::QuitButton) => {
+                *control_flow = ControlFlow::Exit;
+            }
+            Event::UserEvent(CustomEvent::ConfigChange) => {
+                log::trace!("Config modified");
+                let timer = reload_timer.insert(Instant::now().add(Duration::from_secs_f32(0.25)));
+                *control_flow = ControlFlow::WaitUntil(*timer);
+            },
+            Event::NewEvents(_) => {
+                if let Some(timer) = reload_timer {
+                    if timer.checked_duration_since(Instant::now()).is_none() {
+                        log::debug!("Reloading config");
+                        reload_timer = None;
+                        match Config::load_profile(profile.as_deref()) {
+                            Ok(new_config) => {
+                                config = new_config;
+                                osd_visible = config.osd.enabled;
+                                //Redactions or the remote source of the captured monitor might have changed
+                                capture_target = None;
+                                capture_monitor = capture_monitor.or_else(|| peekable_monitors(&adapter, &config).first().copied());
+                                (zones, key_triggers, pointer_monitor) = restart_triggers(&adapter, &config, &mut dwell);
+                                drop(hotkeys.take());
+                                hotkeys = start_hotkeys(&event_proxy, &config);
+                                match web_server.as_ref() {
+                                    Some(web) if web.config() == &config.web => web.set_monitors(monitor_names(&adapter, &config)),
+                                    _ => {
+                                        drop(web_server.take());
+                                        web_server = start_web_server(&adapter, &config, &event_proxy);
+                                    }
+                                }
+                                if vnc_server.as_ref().map(VncServer::config) != Some(&config.vnc) {
+                                    drop(vnc_server.take());
+                                    vnc_server = start_vnc_server(&config, capture.as_ref().and_then(|c| c.source().get_cursor_data()));
+                                }
+                                let sender_redactions = remote_sender_redactions(&adapter, &config);
+                                if remote_sender.as_ref().map(|s| (s.config(), s.redactions())) != Some((&config.remote, sender_redactions.as_slice())) {
+                                    drop(remote_sender.take());
+                                    remote_sender = start_remote_sender(&adapter, &config);
+                                }
+                                if frame_export.as_ref().map(FrameExport::config) != Some(&config.export) {
+                                    drop(frame_export.take());
+                                    frame_export = start_frame_export(&config);
+                                }
+                            },
+                            Err(err) => {
+                                log::error!("Error loading config: {}", err);
+                            }
+                        }
+                    }
+                }
+            }
+            Event::WindowEvent { event: WindowEvent::Resized(size), .. } => {
+                d3d.resize(size.width, size.height)
+                    .log_ok("Can not resize resources");
+                log::trace!("Resized dx resources to {}/{}", size.width, size.height);
+                //window.set_undecorated_shadow(true);
+            }
+            Event::LoopDestroyed => {
+                window.set_visible(false);
+                if let Some(rec) = recording.take() {
+                    rec.stop()
+                        .log_ok("Can not finish recording");
+                }
+            }
+            _ => {}
+        }
+        let wanted = capture_fps(&config, recording.as_ref(), web_server.is_some(), vnc_server.is_some(), frame_export.is_some())
+            .zip(capture_monitor);
+        if wanted != capture_target {
+            capture_target = wanted;
+            drop(capture.take());
+            capture = wanted.and_then(|(fps, monitor)| start_capture(&adapter, &d3d, &config, monitor, fps, &event_proxy));
+        }
+        if let Some(switch) = dwell
+            .update(Instant::now())
+            .filter(|_| !paused)
+            .and_then(|switch| triggers.hover(switch, |monitor| trigger_mode(&adapter, &config, monitor))) {
+            send_switch(&event_proxy, switch);
+        }
+        if let Some(deadline) = dwell.deadline() {
+            match *control_flow {
+                ControlFlow::Wait => *control_flow = ControlFlow::WaitUntil(deadline),
+                ControlFlow::WaitUntil(timer) if deadline < timer => *control_flow = ControlFlow::WaitUntil(deadline),
+                _ => {}
+            }
+        }
+        if (animator.is_running() || ripples.is_running()) && !matches!(*control_flow, ControlFlow::ExitWithCode(_)) {
+            *control_flow = ControlFlow::Poll;
+        }
+        if *control_flow != last_flow {
+            last_flow = *control_flow;
+            log::trace!("switching to {:?}", last_flow);
+        }
+        Ok(())
+    });
+
+    drop(tracker);
+    system_tray.wait_for_end();
+    Ok(match result {
+        Err(true) => true,
+        _ => false
+    })
+}
+
+/// Whether the overlay should be shown while the pointer is on `monitor`
+fn is_configured(adapter: &Adapter, config: &Config, monitor: HMONITOR) -> bool {
+    adapter
+        .get_display_by_handle(monitor)
+        .and_then(|display| display.name().ok())
+        .is_some_and(|name| config.shows_on_hover(&name))
+}
+
+fn has_overlay(adapter: &Adapter, config: &Config, monitor: HMONITOR) -> bool {
+    adapter
+        .get_display_by_handle(monitor)
+        .and_then(|display| display.name().ok())
+        .is_some_and(|name| config.get_overlay_config(&name).is_some())
+}
+
+/// All monitors that have an overlay in the order of the adapter
+fn peekable_monitors(adapter: &Adapter, config: &Config) -> Vec<HMONITOR> {
+    adapter
+        .iter_displays()
+        .filter_map(|display| display.hmonitor().ok())
+        .filter(|monitor| has_overlay(adapter, config, *monitor))
+        .collect()
+}
+
+fn monitor_names(adapter: &Adapter, config: &Config) -> Vec<String> {
+    adapter
+        .iter_displays()
+        .filter_map(|display| display.name().ok())
+        .filter(|name| config.get_overlay_config(name).is_some())
+        .collect()
+}
+
+/// The highest frame rate anything that consumes captured frames needs, if there is something
+fn capture_fps(config: &Config, recording: Option<&Recording>, web: bool, vnc: bool, export: bool) -> Option<u32> {
+    [
+        recording.map(|rec| rec.config().fps),
+        web.then_some(config.web.fps),
+        vnc.then_some(config.vnc.fps),
+        export.then_some(config.export.fps)
+    ]
+        .into_iter()
+        .flatten()
+        .max()
+}
+
+fn start_capture(adapter: &Adapter, d3d: &Direct3D, config: &Config, monitor: HMONITOR, fps: u32, proxy: &EventLoopProxy<CustomEvent>) -> Option<Capture> {
+    let display = adapter.get_display_by_handle(monitor)?;
+    let remote = display
+        .name()
+        .ok()
+        .and_then(|name| config.remote_source(&name));
+    Capture::start(d3d, display, remote, fps, proxy.clone())
+        .log_ok("Can not capture the monitor")
+}
+
+fn start_web_server(adapter: &Adapter, config: &Config, proxy: &EventLoopProxy<CustomEvent>) -> Option<WebServer> {
+    if !config.web.enabled {
+        return None;
+    }
+    let proxy = proxy.clone();
+    let peek = move |monitor, reply| proxy
+        .send_event(CustomEvent::PeekMonitor(monitor, reply))
+        .is_ok();
+    let server = WebServer::start(config.web.clone(), peek)
+        .log_ok("Can not start web viewer")?;
+    server.set_monitors(monitor_names(adapter, config));
+    Some(server)
+}
+
+fn start_vnc_server(config: &Config, cursor: Option<&CursorData>) -> Option<VncServer> {
+    if !config.vnc.enabled {
+        return None;
+    }
+    let mut server = VncServer::start(config.vnc.clone())
+        .log_ok("Can not start VNC server")?;
+    server.cursor_changed(cursor.and_then(|data| CursorShape::decode(data).ok()));
+    Some(server)
+}
+
+/// The redactions of the monitor that the remote sender duplicates
+fn remote_sender_redactions(adapter: &Adapter, config: &Config) -> Vec<Redaction> {
+    config.remote.monitor
+        .clone()
+        .or_else(|| adapter
+            .get_display_by_idx(0)
+            .and_then(|display| display.name().ok()))
+        .map(|name| config.redactions(&name).to_vec())
+        .unwrap_or_default()
+}
+
+fn start_remote_sender(adapter: &Adapter, config: &Config) -> Option<RemoteSender> {
+    if !config.remote.serve {
+        return None;
+    }
+    RemoteSender::start(config.remote.clone(), remote_sender_redactions(adapter, config))
+        .log_ok("Can not start remote sender")
+}
+
+fn start_frame_export(config: &Config) -> Option<FrameExport> {
+    if !config.export.enabled {
+        return None;
+    }
+    FrameExport::start(config.export.clone())
+        .log_ok("Can not start frame export")
+}
+
+fn trigger_mode(adapter: &Adapter, config: &Config, monitor: HMONITOR) -> TriggerMode {
+    adapter
+        .get_display_by_handle(monitor)
+        .and_then(|display| display.name().ok())
+        .map(|name| config.trigger_mode(&name))
+        .unwrap_or_default()
+}
+
+fn send_switch(proxy: &EventLoopProxy<CustomEvent>, switch: Switch<HMONITOR>) {
+    proxy.send_event(match switch {
+        Switch::Show(monitor) => CustomEvent::CursorMonitorSwitch(monitor),
+        Switch::Hide => CustomEvent::HideOverlay
+    }).unwrap_or_else(|_| log::warn!("Can not send monitor switch event to eventloop"));
+}
+
+fn apply_command(proxy: &EventLoopProxy<CustomEvent>, command: Command) {
+    log::debug!("Carrying out {:?}", command);
+    let (reply, answer) = channel();
+    if proxy.send_event(command.into_event(reply)).is_err() {
+        log::warn!("Can not send the command to the event loop");
+        return;
+    }
+    // Commands without an answer drop the channel, which ends the thread
+    std::thread::spawn(move || if let Ok(Response { error: Some(error), .. }) = answer.recv() {
+        log::warn!("Can not carry out the command: {}", error);
+    });
+}
+
+fn start_hotkeys(proxy: &EventLoopProxy<CustomEvent>, config: &Config) -> Option<HotkeyThreadHandle> {
+    let proxy = proxy.clone();
+    start_hotkey_thread(HotkeyBindings::new(&config.hotkeys), move |action| proxy
+        .send_event(CustomEvent::Hotkey(action))
+        .is_ok())
+}
+
+/// Passes the trigger zones and keys to the cursor tracker and makes the overlay follow the monitor under the pointer again
+fn restart_triggers(adapter: &Adapter, config: &Config, dwell: &mut DwellFilter<HMONITOR>) -> (Vec<TriggerZone>, Vec<KeyTrigger>, HMONITOR) {
+    let zones = resolve_zones(adapter, config);
+    cursor_tracker::set_zones(zones
+        .iter()
+        .map(|zone| (zone.host, zone.shape))
+        .collect());
+    let keys = resolve_key_triggers(adapter, config);
+    cursor_tracker::set_watched_keys(keys
+        .iter()
+        .map(|trigger| trigger.key)
+        .collect())
+        .log_ok("Can not install the keyboard hook");
+    let monitor = cursor_tracker::get_current_monitor().unwrap_or_else(|| {
+        log::warn!("Can not get current monitor");
+        HMONITOR::default()
+    });
+    dwell.set_config(config.dwell);
+    dwell.reset();
+    dwell.enter(Instant::now(), monitor, is_configured(adapter, config, monitor));
+    (zones, keys, monitor)
+}
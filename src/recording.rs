@@ -0,0 +1,442 @@
+//! Records the mirrored monitor at a constant frame rate. Frames are encoded on a separate thread.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use serde::Deserialize;
+use crate::image_io::{self, Image};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RecordingFormat {
+    /// Uncompressed YUV 4:2:0 video
+    Y4m,
+    /// One png per captured frame and a csv file with their timestamps
+    PngSequence
+}
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct RecordingConfig {
+    pub format: RecordingFormat,
+    pub fps: u32,
+    pub cursor: bool
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            format: RecordingFormat::Y4m,
+            fps: 30,
+            cursor: true,
+        }
+    }
+}
+
+/// Frames that can wait for the encoder before new ones are dropped
+const QUEUE_LENGTH: usize = 4;
+
+/// Hands out frame slots at a fixed rate. Slots that passed without a new frame are filled by repeating the last one
+#[derive(Debug, Clone)]
+pub struct FramePacer {
+    interval: Duration,
+    start: Instant,
+    emitted: u64
+}
+
+impl FramePacer {
+
+    pub fn new(start: Instant, fps: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / fps.max(1),
+            start,
+            emitted: 0,
+        }
+    }
+
+    /// Number of frames that have to be written to catch up with `now`
+    pub fn due(&mut self, now: Instant) -> u64 {
+        let elapsed = now.saturating_duration_since(self.start);
+        let total = (elapsed.as_nanos() / self.interval.as_nanos()) as u64 + 1;
+        let due = total.saturating_sub(self.emitted);
+        self.emitted = self.emitted.max(total);
+        due
+    }
+
+    /// Presentation time of the next frame slot
+    pub fn elapsed(&self) -> Duration {
+        self.interval * self.emitted as u32
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+}
+
+/// Converts to BT.601 limited range YUV with chroma averaged over 2x2 blocks
+pub fn rgba_to_yuv420(image: &Image) -> Vec<u8> {
+    let (w, h) = (image.width as usize, image.height as usize);
+    let (cw, ch) = (w.div_ceil(2), h.div_ceil(2));
+    let pixel = |x: usize, y: usize| {
+        let i = 4 * (y.min(h - 1) * w + x.min(w - 1));
+        [image.rgba[i] as f32, image.rgba[i + 1] as f32, image.rgba[i + 2] as f32]
+    };
+    let mut yuv = Vec::with_capacity(w * h + 2 * cw * ch);
+    for y in 0..h {
+        for x in 0..w {
+            let [r, g, b] = pixel(x, y);
+            yuv.push((16.0 + (65.738 * r + 129.057 * g + 25.064 * b) / 256.0).round() as u8);
+        }
+    }
+    let chroma = |x: usize, y: usize| {
+        let [r, g, b] = [pixel(2 * x, 2 * y), pixel(2 * x + 1, 2 * y), pixel(2 * x, 2 * y + 1), pixel(2 * x + 1, 2 * y + 1)]
+            .into_iter()
+            .fold([0.0; 3], |acc, p| [acc[0] + p[0] / 4.0, acc[1] + p[1] / 4.0, acc[2] + p[2] / 4.0]);
+        let u = 128.0 + (-37.945 * r - 74.494 * g + 112.439 * b) / 256.0;
+        let v = 128.0 + (112.439 * r - 94.154 * g - 18.285 * b) / 256.0;
+        (u.round() as u8, v.round() as u8)
+    };
+    let planes: Vec<(u8, u8)> = (0..ch)
+        .flat_map(|y| (0..cw).map(move |x| (x, y)))
+        .map(|(x, y)| chroma(x, y))
+        .collect();
+    yuv.extend(planes.iter().map(|(u, _)| *u));
+    yuv.extend(planes.iter().map(|(_, v)| *v));
+    yuv
+}
+
+trait FrameSink: Send {
+    /// Writes `frame` `repeat` times, starting at `time` after the start of the recording.
+    /// Without a frame the last written one is repeated
+    fn write(&mut self, frame: Option<&Image>, repeat: u64, time: Duration) -> Result<()>;
+    fn finish(&mut self) -> Result<()>;
+}
+
+struct Y4mSink {
+    writer: BufWriter<File>,
+    fps: u32,
+    size: Option<(u32, u32)>,
+    last: Vec<u8>
+}
+
+impl FrameSink for Y4mSink {
+    fn write(&mut self, frame: Option<&Image>, repeat: u64, _: Duration) -> Result<()> {
+        if let Some(frame) = frame {
+            match self.size {
+                None => {
+                    writeln!(self.writer, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED", frame.width, frame.height, self.fps)?;
+                    self.size = Some((frame.width, frame.height));
+                }
+                Some(size) => ensure!(size == (frame.width, frame.height), "Y4M can not change the frame size mid stream"),
+            }
+            self.last = rgba_to_yuv420(frame);
+        }
+        ensure!(self.size.is_some(), "There is no frame to repeat");
+        for _ in 0..repeat {
+            self.writer.write_all(b"FRAME\n")?;
+            self.writer.write_all(&self.last)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+struct PngSequenceSink {
+    directory: PathBuf,
+    index: u32,
+    timestamps: BufWriter<File>
+}
+
+impl PngSequenceSink {
+    fn new(directory: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&directory)
+            .with_context(|| format!("Can not create {}", directory.display()))?;
+        let mut timestamps = BufWriter::new(File::create(directory.join("timestamps.csv"))?);
+        writeln!(timestamps, "file,milliseconds")?;
+        Ok(Self { directory, index: 0, timestamps })
+    }
+}
+
+impl FrameSink for PngSequenceSink {
+    //Repeated frames are not written again, the sidecar keeps the timing
+    fn write(&mut self, frame: Option<&Image>, _: u64, time: Duration) -> Result<()> {
+        let Some(frame) = frame else { return Ok(()) };
+        let name = format!("frame_{:06}.png", self.index);
+        frame.save_png(self.directory.join(&name))?;
+        writeln!(self.timestamps, "{},{}", name, time.as_millis())?;
+        self.index += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.timestamps.flush()?;
+        Ok(())
+    }
+}
+
+/// `image` is `None` if the last encoded frame is repeated
+struct QueuedFrame {
+    image: Option<Image>,
+    repeat: u64,
+    time: Duration
+}
+
+/// A running recording. If it is dropped without `stop` the encoder finishes in the background
+pub struct Recording {
+    config: RecordingConfig,
+    path: PathBuf,
+    pacer: FramePacer,
+    changed: bool,
+    /// Whether the encoder got a frame it can repeat
+    encoded: bool,
+    /// Slots that still have to repeat the last encoded frame because the queue was full
+    backlog: u64,
+    dropped: u64,
+    sender: Option<SyncSender<QueuedFrame>>,
+    thread: Option<JoinHandle<Result<()>>>
+}
+
+impl Recording {
+
+    pub fn start(config: RecordingConfig) -> Result<Self> {
+        let (path, sink): (PathBuf, Box<dyn FrameSink>) = match config.format {
+            RecordingFormat::Y4m => {
+                let path = image_io::output_path("recording", "y4m");
+                let file = File::create(&path)
+                    .with_context(|| format!("Can not create {}", path.display()))?;
+                (path, Box::new(Y4mSink { writer: BufWriter::new(file), fps: config.fps.max(1), size: None, last: Vec::new() }))
+            }
+            RecordingFormat::PngSequence => {
+                let path = image_io::output_path("recording", "png").with_extension("");
+                (path.clone(), Box::new(PngSequenceSink::new(path)?))
+            }
+        };
+        log::info!("Recording to {}", path.display());
+        Ok(Self::with_sink(config, path, sink, Instant::now()))
+    }
+
+    fn with_sink(config: RecordingConfig, path: PathBuf, mut sink: Box<dyn FrameSink>, start: Instant) -> Self {
+        let (sender, receiver) = sync_channel::<QueuedFrame>(QUEUE_LENGTH);
+        let thread = std::thread::spawn(move || {
+            for frame in receiver {
+                sink.write(frame.image.as_ref(), frame.repeat, frame.time)?;
+            }
+            sink.finish()
+        });
+        Self {
+            config,
+            path,
+            pacer: FramePacer::new(start, config.fps),
+            changed: true,
+            encoded: false,
+            backlog: 0,
+            dropped: 0,
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    pub fn config(&self) -> RecordingConfig {
+        self.config
+    }
+
+    /// Marks the captured frame as changed so the next due slot reads it back
+    pub fn frame_changed(&mut self) {
+        self.changed = true;
+    }
+
+    /// Queues `frame` without waiting for the encoder. Returns the frame if the queue is full
+    fn try_queue(&mut self, frame: QueuedFrame) -> Result<Option<QueuedFrame>> {
+        let sender = self.sender.as_ref().expect("The sender is only taken when stopping");
+        match sender.try_send(frame) {
+            Ok(()) => Ok(None),
+            Err(TrySendError::Full(frame)) => Ok(Some(frame)),
+            Err(TrySendError::Disconnected(_)) => bail!("The encoder thread stopped")
+        }
+    }
+
+    /// Writes all frame slots up to `now`. `capture` is only called if there is a slot and the frame changed.
+    /// Missed slots repeat the last encoded frame; a failed capture or a full queue also falls back to it.
+    /// Only fails if the encoder stopped
+    pub fn update(&mut self, now: Instant, capture: impl FnOnce() -> Result<Image>) -> Result<()> {
+        let time = self.pacer.elapsed();
+        let due = self.pacer.due(now);
+        if due == 0 {
+            return Ok(());
+        }
+        let image = match self.changed || !self.encoded {
+            true => capture()
+                .map_err(|err| log::debug!("Can not capture frame to record: {:#}", err))
+                .ok(),
+            false => None
+        };
+        if image.is_some() {
+            self.changed = false;
+        }
+        if !self.encoded && image.is_none() {
+            //There is nothing to repeat yet
+            return Ok(());
+        }
+        let repeat = match self.encoded {
+            true => self.backlog + due - image.is_some() as u64,
+            false => 0
+        };
+        self.backlog = 0;
+        if repeat > 0 {
+            if let Some(frame) = self.try_queue(QueuedFrame { image: None, repeat, time })? {
+                self.backlog = frame.repeat;
+            }
+        }
+        if let Some(image) = image {
+            let time = time + self.pacer.interval() * (due - 1) as u32;
+            let queued = self.backlog == 0
+                && self.try_queue(QueuedFrame { image: Some(image), repeat: 1, time })?.is_none();
+            match queued {
+                true => self.encoded = true,
+                false => {
+                    //The slot repeats the last encoded frame and the next slot captures again
+                    self.backlog += 1;
+                    self.dropped += 1;
+                    self.changed = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for the encoder to write everything and returns the path of the recording
+    pub fn stop(mut self) -> Result<PathBuf> {
+        let sender = self.sender.take().expect("The recording is only stopped once");
+        if self.backlog > 0 {
+            sender.send(QueuedFrame { image: None, repeat: self.backlog, time: self.pacer.elapsed() })
+                .map_err(|_| anyhow!("The encoder thread stopped"))?;
+        }
+        drop(sender);
+        self.thread
+            .take()
+            .expect("The encoder is only joined once")
+            .join()
+            .map_err(|_| anyhow!("The encoder thread panicked"))??;
+        if self.dropped > 0 {
+            log::warn!("Dropped {} frames because the encoder could not keep up", self.dropped);
+        }
+        log::info!("Finished recording {}", self.path.display());
+        Ok(self.path.clone())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use super::*;
+
+    /// (first byte of the frame or `None` for a repeat, repeat, milliseconds)
+    type Written = Arc<Mutex<Vec<(Option<u8>, u64, u128)>>>;
+
+    /// Remembers what was written. With a gate the first write waits until the gate is opened
+    struct TestSink {
+        written: Written,
+        entered: Option<Sender<()>>,
+        gate: Option<Receiver<()>>
+    }
+
+    impl FrameSink for TestSink {
+        fn write(&mut self, frame: Option<&Image>, repeat: u64, time: Duration) -> Result<()> {
+            if let (Some(entered), Some(gate)) = (self.entered.take(), self.gate.take()) {
+                entered.send(()).unwrap();
+                gate.recv().unwrap();
+            }
+            self.written.lock().unwrap().push((frame.map(|f| f.rgba[0]), repeat, time.as_millis()));
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn recording(start: Instant) -> (Recording, Written) {
+        let written = Written::default();
+        let sink = TestSink { written: written.clone(), entered: None, gate: None };
+        let config = RecordingConfig { fps: 4, ..Default::default() };
+        (Recording::with_sink(config, PathBuf::new(), Box::new(sink), start), written)
+    }
+
+    fn frame(value: u8) -> Result<Image> {
+        Ok(Image { width: 1, height: 1, rgba: vec![value; 4] })
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn catch_up_repeats_the_last_encoded_frame() {
+        let start = Instant::now();
+        let (mut rec, written) = recording(start);
+        rec.update(start, || frame(1)).unwrap();
+        rec.update(start + ms(100), || panic!("There is no due slot")).unwrap();
+        rec.frame_changed();
+        rec.update(start + ms(1000), || frame(2)).unwrap();
+        rec.update(start + ms(1250), || panic!("The frame did not change")).unwrap();
+        rec.stop().unwrap();
+        assert_eq!(*written.lock().unwrap(), vec![
+            (Some(1), 1, 0),
+            (None, 3, 250),
+            (Some(2), 1, 1000),
+            (None, 1, 1250)
+        ]);
+    }
+
+    #[test]
+    fn capture_errors_do_not_end_the_recording() {
+        let start = Instant::now();
+        let (mut rec, written) = recording(start);
+        rec.update(start, || Err(anyhow!("No frame yet"))).unwrap();
+        rec.update(start + ms(250), || frame(1)).unwrap();
+        rec.frame_changed();
+        rec.update(start + ms(500), || Err(anyhow!("Access lost"))).unwrap();
+        rec.update(start + ms(750), || frame(2)).unwrap();
+        rec.stop().unwrap();
+        assert_eq!(*written.lock().unwrap(), vec![
+            (Some(1), 1, 250),
+            (None, 1, 500),
+            (Some(2), 1, 750)
+        ]);
+    }
+
+    #[test]
+    fn full_queues_drop_frames_but_keep_the_timing() {
+        let start = Instant::now();
+        let written = Written::default();
+        let (entered, wait) = channel();
+        let (open, gate) = channel();
+        let sink = TestSink { written: written.clone(), entered: Some(entered), gate: Some(gate) };
+        let config = RecordingConfig { fps: 4, ..Default::default() };
+        let mut rec = Recording::with_sink(config, PathBuf::new(), Box::new(sink), start);
+        rec.update(start, || frame(0)).unwrap();
+        wait.recv().unwrap();
+        for i in 1..=QUEUE_LENGTH as u64 + 2 {
+            rec.frame_changed();
+            rec.update(start + ms(250 * i), || frame(i as u8)).unwrap();
+        }
+        assert_eq!(rec.dropped, 2);
+        open.send(()).unwrap();
+        rec.stop().unwrap();
+        let written = written.lock().unwrap();
+        let slots: u64 = written.iter().map(|(_, repeat, _)| repeat).sum();
+        assert_eq!(slots, QUEUE_LENGTH as u64 + 3);
+        assert_eq!(written.last(), Some(&(None, 2, 1750)));
+    }
+}
@@ -0,0 +1,322 @@
+//! Hides parts of a monitor before its frames reach the overlay or any capture output.
+//! The overlay path redacts the cached frame on the GPU, frames that do not go through the cache use `redact_image`.
+
+use serde::Deserialize;
+use crate::config::Color;
+use crate::directx::DisplayMode;
+use crate::image_io::{Area, Image};
+use crate::snapshot::quarter_turns;
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RedactionStyle {
+    Blur,
+    Pixelate,
+    #[default]
+    Fill
+}
+
+/// A rect in desktop pixels of the monitor, relative to its top-left corner
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub struct Redaction {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub style: RedactionStyle,
+    /// Blur radius or edge length of the pixelation blocks in pixels
+    #[serde(default = "default_size")]
+    pub size: u32,
+    /// Color of filled areas; The alpha channel is ignored so nothing shines through
+    #[serde(default = "default_color")]
+    pub color: Color
+}
+
+fn default_size() -> u32 {
+    16
+}
+
+fn default_color() -> Color {
+    Color::rgba(0, 0, 0, 255)
+}
+
+impl Redaction {
+
+    pub fn area(&self) -> Area {
+        Area { x: self.x, y: self.y, width: self.width, height: self.height }
+    }
+
+}
+
+/// Maps the redactions onto the unrotated frame that the duplication delivers for `mode`
+pub fn frame_areas(redactions: &[Redaction], mode: DisplayMode) -> Vec<(Area, Redaction)> {
+    let (width, height) = mode.get_flipped_size();
+    let desktop = Area { x: 0, y: 0, width, height };
+    let turns = (4 - quarter_turns(mode.orientation)) % 4;
+    redactions
+        .iter()
+        .filter_map(|r| r
+            .area()
+            .intersect(desktop)
+            .map(|area| (area.rotated(width, height, turns), *r)))
+        .collect()
+}
+
+/// Redacts an image in desktop orientation
+pub fn redact_image(image: &mut Image, redactions: &[Redaction]) {
+    for redaction in redactions {
+        let Some(area) = redaction.area().intersect(Area::of(image)) else { continue };
+        match redaction.style {
+            RedactionStyle::Fill => {
+                let Color { r, g, b, .. } = redaction.color;
+                for_each_pixel(image, area, |pixel| pixel.copy_from_slice(&[r, g, b, u8::MAX]));
+            }
+            RedactionStyle::Pixelate => {
+                let block = redaction.size.max(2);
+                for by in (area.y..area.y + area.height).step_by(block as usize) {
+                    for bx in (area.x..area.x + area.width).step_by(block as usize) {
+                        let cell = Area { x: bx, y: by, width: block, height: block }
+                            .intersect(area)
+                            .expect("The block starts inside of the area");
+                        let average = average(image, cell);
+                        for_each_pixel(image, cell, |pixel| pixel.copy_from_slice(&average));
+                    }
+                }
+            }
+            RedactionStyle::Blur => box_blur(image, area, redaction.size.max(1))
+        }
+    }
+}
+
+fn for_each_pixel(image: &mut Image, area: Area, mut f: impl FnMut(&mut [u8])) {
+    for y in area.y..area.y + area.height {
+        let start = 4 * (y as usize * image.width as usize + area.x as usize);
+        image.rgba[start..start + 4 * area.width as usize]
+            .chunks_exact_mut(4)
+            .for_each(&mut f);
+    }
+}
+
+fn average(image: &Image, area: Area) -> [u8; 4] {
+    let mut sum = [0u64; 4];
+    for y in area.y..area.y + area.height {
+        for pixel in image.row(area, y).chunks_exact(4) {
+            sum.iter_mut().zip(pixel).for_each(|(s, p)| *s += *p as u64);
+        }
+    }
+    let count = (area.width as u64 * area.height as u64).max(1);
+    sum.map(|s| (s / count) as u8)
+}
+
+/// Three passes of a separable box blur approximate a gaussian. Only pixels inside of the area are sampled
+fn box_blur(image: &mut Image, area: Area, radius: u32) {
+    let (w, h) = (area.width as usize, area.height as usize);
+    let mut pixels: Vec<[u32; 4]> = (area.y..area.y + area.height)
+        .flat_map(|y| image.row(area, y).chunks_exact(4).map(|p| [p[0], p[1], p[2], p[3]].map(u32::from)))
+        .collect();
+    let mut line = Vec::new();
+    for _ in 0..3 {
+        for y in 0..h {
+            line.clear();
+            line.extend((0..w).map(|x| pixels[y * w + x]));
+            blur_line(&line, radius as usize, |x, value| pixels[y * w + x] = value);
+        }
+        for x in 0..w {
+            line.clear();
+            line.extend((0..h).map(|y| pixels[y * w + x]));
+            blur_line(&line, radius as usize, |y, value| pixels[y * w + x] = value);
+        }
+    }
+    let mut blurred = pixels.iter();
+    for_each_pixel(image, area, |pixel| {
+        let value = blurred.next().expect("One value per pixel");
+        pixel.iter_mut().zip(value).for_each(|(p, v)| *p = *v as u8);
+    });
+}
+
+/// Averages every value with its `radius` neighbours on both sides, clamping at the ends of the line
+fn blur_line(line: &[[u32; 4]], radius: usize, mut set: impl FnMut(usize, [u32; 4])) {
+    let last = line.len() - 1;
+    let at = |i: isize| line[i.clamp(0, last as isize) as usize];
+    let mut sum = [0u32; 4];
+    for i in -(radius as isize)..=radius as isize {
+        sum.iter_mut().zip(at(i)).for_each(|(s, v)| *s += v);
+    }
+    let count = 2 * radius as u32 + 1;
+    for i in 0..line.len() {
+        set(i, sum.map(|s| s / count));
+        let (add, remove) = (at((i + radius + 1) as isize), at(i as isize - radius as isize));
+        sum.iter_mut().zip(add).zip(remove).for_each(|((s, a), r)| *s = *s + a - r);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::directx::DisplayOrientation;
+    use super::*;
+
+    fn image(width: u32, height: u32) -> Image {
+        let rgba = (0..4 * width * height)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        Image { width, height, rgba }
+    }
+
+    fn redaction(x: u32, y: u32, width: u32, height: u32, style: RedactionStyle, size: u32) -> Redaction {
+        Redaction { x, y, width, height, style, size, color: Color::rgba(10, 20, 30, 40) }
+    }
+
+    fn pixel(image: &Image, x: u32, y: u32) -> [u8; 4] {
+        let start = 4 * (y * image.width + x) as usize;
+        image.rgba[start..start + 4].try_into().unwrap()
+    }
+
+    /// Checks that only the pixels inside of `area` changed
+    fn assert_outside_unchanged(before: &Image, after: &Image, area: Area) {
+        for y in 0..before.height {
+            for x in 0..before.width {
+                if !area.contains(Area { x, y, width: 1, height: 1 }) {
+                    assert_eq!(pixel(before, x, y), pixel(after, x, y), "pixel {},{} outside of {:?}", x, y, area);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn frame_areas_match_the_desktop_in_every_orientation() {
+        let redactions = [
+            Redaction { color: Color::rgba(255, 0, 0, 255), ..redaction(2, 3, 10, 5, RedactionStyle::Fill, 16) },
+            Redaction { color: Color::rgba(0, 255, 0, 255), ..redaction(20, 0, 50, 4, RedactionStyle::Fill, 16) },
+            Redaction { color: Color::rgba(0, 0, 255, 255), ..redaction(0, 19, 7, 100, RedactionStyle::Fill, 16) },
+            redaction(100, 100, 5, 5, RedactionStyle::Fill, 16)
+        ];
+        for orientation in [DisplayOrientation::Landscape, DisplayOrientation::Portrait, DisplayOrientation::FlippedLandscape, DisplayOrientation::FlippedPortrait] {
+            let mode = DisplayMode { width: 40, height: 24, orientation, refresh_num: 60, refresh_den: 1, hdr: false };
+            let turns = quarter_turns(orientation);
+
+            let mut desktop = image(40, 24).rotated(turns);
+            assert_eq!((desktop.width, desktop.height), mode.get_flipped_size());
+            redact_image(&mut desktop, &redactions);
+
+            let mut frame = image(40, 24);
+            let areas = frame_areas(&redactions, mode);
+            assert_eq!(areas.len(), 3, "{:?}", orientation);
+            for (area, r) in areas {
+                assert!(Area::of(&frame).contains(area), "{:?} lies outside of the frame in {:?}", area, orientation);
+                redact_image(&mut frame, &[Redaction { x: area.x, y: area.y, width: area.width, height: area.height, ..r }]);
+            }
+            assert_eq!(frame.rotated(turns), desktop, "{:?}", orientation);
+        }
+    }
+
+    #[test]
+    fn fills_stop_at_the_edges() {
+        let before = image(10, 6);
+        let mut after = before.clone();
+        redact_image(&mut after, &[
+            redaction(7, 4, 10, 10, RedactionStyle::Fill, 0),
+            redaction(20, 20, 5, 5, RedactionStyle::Fill, 1),
+            redaction(3, 3, 0, 4, RedactionStyle::Fill, 16)
+        ]);
+        let area = Area { x: 7, y: 4, width: 3, height: 2 };
+        assert_outside_unchanged(&before, &after, area);
+        for y in 4..6 {
+            for x in 7..10 {
+                assert_eq!(pixel(&after, x, y), [10, 20, 30, 255], "the alpha of the color is ignored");
+            }
+        }
+    }
+
+    #[test]
+    fn pixelation_averages_the_blocks_cut_by_the_edges() {
+        let before = image(10, 7);
+        let mut after = before.clone();
+        redact_image(&mut after, &[redaction(1, 1, 20, 20, RedactionStyle::Pixelate, 4)]);
+        let area = Area { x: 1, y: 1, width: 9, height: 6 };
+        assert_outside_unchanged(&before, &after, area);
+        for (bx, width) in [(1, 4), (5, 4), (9, 1)] {
+            for (by, height) in [(1, 4), (5, 2)] {
+                let mut sum = [0u32; 4];
+                for y in by..by + height {
+                    for x in bx..bx + width {
+                        sum.iter_mut().zip(pixel(&before, x, y)).for_each(|(s, p)| *s += p as u32);
+                    }
+                }
+                let average = sum.map(|s| (s / (width * height)) as u8);
+                for y in by..by + height {
+                    for x in bx..bx + width {
+                        assert_eq!(pixel(&after, x, y), average, "block at {},{}", bx, by);
+                    }
+                }
+            }
+        }
+
+        //Blocks are at least two pixels wide
+        let pixelated = |size| {
+            let mut image = before.clone();
+            redact_image(&mut image, &[redaction(1, 1, 20, 20, RedactionStyle::Pixelate, size)]);
+            image
+        };
+        assert_ne!(pixelated(2), before);
+        assert_eq!(pixelated(0), pixelated(2));
+        assert_eq!(pixelated(1), pixelated(2));
+    }
+
+    #[test]
+    fn blur_only_samples_inside_of_the_area() {
+        //A uniform area stays uniform no matter what surrounds it
+        let mut before = image(8, 6);
+        let area = Area { x: 5, y: 3, width: 3, height: 3 };
+        for y in 3..6 {
+            for x in 5..8 {
+                let start = 4 * (y * 8 + x) as usize;
+                before.rgba[start..start + 4].copy_from_slice(&[200, 100, 50, 255]);
+            }
+        }
+        let mut after = before.clone();
+        redact_image(&mut after, &[redaction(5, 3, 100, 100, RedactionStyle::Blur, 16)]);
+        assert_eq!(after, before);
+
+        let before = image(8, 6);
+        let blurred = |area: Area, size| {
+            let mut image = before.clone();
+            redact_image(&mut image, &[redaction(area.x, area.y, area.width, area.height, RedactionStyle::Blur, size)]);
+            assert_outside_unchanged(&before, &image, area);
+            image
+        };
+        let after = blurred(area, 2);
+        assert_ne!(after, before);
+        for channel in 0..4 {
+            let values = |image: &Image| (3..6).flat_map(|y| (5..8).map(move |x| (x, y))).map(|(x, y)| pixel(image, x, y)[channel]).collect::<Vec<_>>();
+            let (min, max) = (*values(&before).iter().min().unwrap(), *values(&before).iter().max().unwrap());
+            assert!(values(&after).iter().all(|v| (min..=max).contains(v)), "channel {} leaves the range of the area", channel);
+        }
+
+        //The radius is at least one pixel, also on areas that are a single pixel wide
+        assert_eq!(blurred(area, 0), blurred(area, 1));
+        let column = Area { x: 7, y: 0, width: 1, height: 6 };
+        assert_ne!(blurred(column, 1), before);
+        let single = Area { x: 7, y: 5, width: 1, height: 1 };
+        assert_eq!(blurred(single, 0), before);
+    }
+
+    #[test]
+    fn tiny_images_are_redacted() {
+        for style in [RedactionStyle::Fill, RedactionStyle::Pixelate, RedactionStyle::Blur] {
+            for size in [0, 1] {
+                let mut empty = image(0, 0);
+                redact_image(&mut empty, &[redaction(0, 0, 5, 5, style, size)]);
+                assert!(empty.rgba.is_empty());
+
+                let mut single = image(1, 1);
+                redact_image(&mut single, &[redaction(0, 0, 5, 5, style, size)]);
+                let expected = match style {
+                    RedactionStyle::Fill => vec![10, 20, 30, 255],
+                    _ => image(1, 1).rgba
+                };
+                assert_eq!(single.rgba, expected, "{:?} with size {}", style, size);
+            }
+        }
+    }
+}
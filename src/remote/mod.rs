@@ -0,0 +1,58 @@
+//! Peeking at a monitor of another machine. The sender duplicates one of its monitors and streams the tiles
+//! that changed, xor-ed with the previous frame and zstd compressed, together with the pointer over TCP.
+//! The receiver turns them back into a texture that is rendered like a local duplication.
+
+mod protocol;
+mod transport;
+#[cfg(windows)]
+mod sender;
+#[cfg(windows)]
+mod receiver;
+
+use serde::Deserialize;
+
+#[cfg(windows)]
+pub use sender::RemoteSender;
+#[cfg(windows)]
+pub use receiver::RemoteFrames;
+
+/// Edge length of the tiles that are compared between frames
+const TILE: u32 = 64;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RemoteConfig {
+    /// Stream a monitor of this machine to other instances
+    pub serve: bool,
+    /// Interface and port to listen on
+    pub address: String,
+    /// The streamed monitor; Defaults to the first one
+    pub monitor: Option<String>,
+    /// Required unless the sender only listens on loopback
+    pub token: Option<String>,
+    pub fps: u32
+}
+
+impl Default for RemoteConfig {
+    fn default() -> Self {
+        Self {
+            serve: false,
+            address: String::from("127.0.0.1:7878"),
+            monitor: None,
+            token: None,
+            fps: 30,
+        }
+    }
+}
+
+/// Shows the frames of a remote sender instead of the local monitor
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RemoteSource {
+    /// `host:port` of the sender
+    pub address: String,
+    pub token: Option<String>
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,278 @@
+use std::io::{Read, Write};
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::directx::{CursorData, CursorType};
+use crate::image_io::{Area, Image};
+
+pub const VERSION: u32 = 1;
+
+const MAX_HEADER: usize = 64 * 1024;
+const MAX_PAYLOAD: usize = 256 * 1024 * 1024;
+/// Tiles address the frame with u16 coordinates and a whole frame has to fit into one payload
+const MAX_FRAME_SIDE: u32 = u16::MAX as u32;
+const MAX_FRAME_PIXELS: usize = MAX_PAYLOAD / 4;
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CursorKind {
+    Color,
+    Monochrome,
+    MaskedColor
+}
+
+impl From<CursorType> for CursorKind {
+    fn from(value: CursorType) -> Self {
+        match value {
+            CursorType::Color => CursorKind::Color,
+            CursorType::Monochrome => CursorKind::Monochrome,
+            CursorType::MaskedColor => CursorKind::MaskedColor
+        }
+    }
+}
+
+impl From<CursorKind> for CursorType {
+    fn from(value: CursorKind) -> Self {
+        match value {
+            CursorKind::Color => CursorType::Color,
+            CursorKind::Monochrome => CursorType::Monochrome,
+            CursorKind::MaskedColor => CursorType::MaskedColor
+        }
+    }
+}
+
+/// Every message is a json header that may be followed by a binary payload
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Header {
+    /// First message of the receiver
+    Hello { version: u32, token: Option<String> },
+    /// The size of the frames changed. The next tiles cover the whole frame
+    Mode { width: u32, height: u32, refresh_num: u32, refresh_den: u32 },
+    /// Payload: zstd compressed tiles, see `encode_tiles`
+    Tiles { count: u32 },
+    /// Position of the pointer hotspot in frame coordinates
+    Pointer { position: Option<(i32, i32)> },
+    /// Payload: the raw shape like DXGI reports it
+    Cursor { kind: CursorKind, width: u32, height: u32, pitch: u32, hotspot: (u32, u32) },
+    Error { message: String }
+}
+
+impl Header {
+
+    pub fn cursor(data: &CursorData) -> Self {
+        Header::Cursor {
+            kind: data.cursor_type.into(),
+            width: data.width,
+            height: data.height,
+            pitch: data.pitch,
+            hotspot: data.hotspot,
+        }
+    }
+
+}
+
+/// Writes `u32 header length, json header, u32 payload length, payload` with big endian lengths
+pub fn write_message(writer: &mut impl Write, header: &Header, payload: &[u8]) -> Result<()> {
+    let header = serde_json::to_vec(header)?;
+    let mut message = Vec::with_capacity(8 + header.len() + payload.len());
+    message.extend_from_slice(&(header.len() as u32).to_be_bytes());
+    message.extend_from_slice(&header);
+    message.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    message.extend_from_slice(payload);
+    writer.write_all(&message)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_block(reader: &mut impl Read, limit: usize) -> Result<Vec<u8>> {
+    let mut length = [0u8; 4];
+    reader.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    ensure!(length <= limit, "Message of {} bytes is too large", length);
+    let mut block = vec![0u8; length];
+    reader.read_exact(&mut block)?;
+    Ok(block)
+}
+
+pub fn read_message(reader: &mut impl Read) -> Result<(Header, Vec<u8>)> {
+    read_message_limited(reader, MAX_PAYLOAD)
+}
+
+/// Like `read_message`, but fails instead of reading a payload larger than `payload_limit`
+pub fn read_message_limited(reader: &mut impl Read, payload_limit: usize) -> Result<(Header, Vec<u8>)> {
+    let header = serde_json::from_slice(&read_block(reader, MAX_HEADER)?)?;
+    let payload = read_block(reader, payload_limit.min(MAX_PAYLOAD))?;
+    Ok((header, payload))
+}
+
+/// An empty frame for the size of a `Header::Mode`. Fails for sizes a sender can not send
+pub fn mode_frame(width: u32, height: u32) -> Result<Image> {
+    ensure!(width <= MAX_FRAME_SIDE && height <= MAX_FRAME_SIDE && width as usize * height as usize <= MAX_FRAME_PIXELS,
+        "Frames of {}x{} are too large", width, height);
+    Ok(Image { width, height, rgba: vec![0; 4 * width as usize * height as usize] })
+}
+
+/// Each tile is `x, y, width, height` as big endian u16 followed by its rgba pixels xor-ed with the previous frame,
+/// so unchanged pixels become zeros that compress well
+pub fn encode_tiles(previous: &Image, current: &Image, areas: &[Area]) -> Result<Vec<u8>> {
+    let mut tiles = Vec::new();
+    for area in areas {
+        for value in [area.x, area.y, area.width, area.height] {
+            tiles.extend_from_slice(&u16::try_from(value)?.to_be_bytes());
+        }
+        for y in area.y..area.y + area.height {
+            tiles.extend(previous
+                .row(*area, y)
+                .iter()
+                .zip(current.row(*area, y))
+                .map(|(a, b)| a ^ b));
+        }
+    }
+    Ok(zstd::encode_all(tiles.as_slice(), ZSTD_LEVEL)?)
+}
+
+/// Applies the tiles to `frame` and returns the areas they covered
+pub fn decode_tiles(frame: &mut Image, count: u32, payload: &[u8]) -> Result<Vec<Area>> {
+    //The tiles never cover more than the frame, so anything beyond that is not decompressed
+    let limit = 8 * count as u64 + frame.rgba.len() as u64;
+    let mut tiles = Vec::new();
+    zstd::stream::Decoder::new(payload)?
+        .take(limit + 1)
+        .read_to_end(&mut tiles)
+        .context("Can not decompress the tiles")?;
+    ensure!(tiles.len() as u64 <= limit, "The tiles are larger than the frame");
+    let mut rest = tiles.as_slice();
+    let bounds = Area::of(frame);
+    let mut areas = Vec::with_capacity((count as usize).min(tiles.len() / 8));
+    for _ in 0..count {
+        ensure!(rest.len() >= 8, "Tile header is truncated");
+        let value = |i: usize| u16::from_be_bytes([rest[2 * i], rest[2 * i + 1]]) as u32;
+        let area = Area { x: value(0), y: value(1), width: value(2), height: value(3) };
+        ensure!(bounds.contains(area), "Tile {:?} is outside of the frame", area);
+        let size = 4 * area.width as usize * area.height as usize;
+        ensure!(rest.len() >= 8 + size, "Tile data is truncated");
+        for (y, row) in (area.y..area.y + area.height).zip(rest[8..8 + size].chunks_exact(4 * area.width as usize)) {
+            let start = 4 * (y as usize * frame.width as usize + area.x as usize);
+            for (pixel, xor) in frame.rgba[start..start + row.len()].iter_mut().zip(row) {
+                *pixel ^= xor;
+            }
+        }
+        rest = &rest[8 + size..];
+        areas.push(area);
+    }
+    ensure!(rest.is_empty(), "Unexpected data after the tiles");
+    Ok(areas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, seed: u32) -> Image {
+        let rgba = (0..4 * width * height)
+            .map(|i| (i.wrapping_mul(2654435761).wrapping_add(seed) >> 13) as u8)
+            .collect();
+        Image { width, height, rgba }
+    }
+
+    /// Tiles in the wire format before compression
+    fn raw_tile(area: Area, fill: u8) -> Vec<u8> {
+        let mut tile: Vec<u8> = [area.x, area.y, area.width, area.height]
+            .iter()
+            .flat_map(|v| (*v as u16).to_be_bytes())
+            .collect();
+        tile.resize(tile.len() + 4 * (area.width * area.height) as usize, fill);
+        tile
+    }
+
+    fn compress(raw: &[u8]) -> Vec<u8> {
+        zstd::encode_all(raw, ZSTD_LEVEL).unwrap()
+    }
+
+    #[test]
+    fn tiles_round_trip() {
+        let previous = image(100, 70, 1);
+        let current = image(100, 70, 2);
+        let areas = previous.changed_areas(&current, Area::of(&current), 32);
+        assert!(!areas.is_empty());
+        let payload = encode_tiles(&previous, &current, &areas).unwrap();
+        let mut frame = Image { rgba: previous.rgba.clone(), ..previous };
+        assert_eq!(decode_tiles(&mut frame, areas.len() as u32, &payload).unwrap(), areas);
+        assert_eq!(frame.rgba, current.rgba);
+
+        //A frame from scratch after a mode change
+        let mut frame = mode_frame(100, 70).unwrap();
+        let payload = encode_tiles(&mode_frame(100, 70).unwrap(), &current, &[Area::of(&current)]).unwrap();
+        decode_tiles(&mut frame, 1, &payload).unwrap();
+        assert_eq!(frame.rgba, current.rgba);
+
+        let empty = encode_tiles(&frame, &frame, &[]).unwrap();
+        assert!(decode_tiles(&mut frame, 0, &empty).unwrap().is_empty());
+        assert_eq!(frame.rgba, current.rgba);
+    }
+
+    #[test]
+    fn malformed_tiles_are_rejected() {
+        let decode = |count: u32, raw: &[u8]| {
+            let mut frame = mode_frame(16, 8).unwrap();
+            decode_tiles(&mut frame, count, &compress(raw))
+        };
+        let inside = raw_tile(Area { x: 8, y: 0, width: 8, height: 8 }, 1);
+        assert!(decode(1, &inside).is_ok());
+        assert!(decode(2, &inside).is_err(), "missing tile");
+        assert!(decode(1, &inside[..5]).is_err(), "truncated header");
+        assert!(decode(1, &inside[..inside.len() - 1]).is_err(), "truncated pixels");
+        assert!(decode(1, &[inside.as_slice(), &[0]].concat()).is_err(), "trailing data");
+        assert!(decode(1, &raw_tile(Area { x: 9, y: 0, width: 8, height: 8 }, 1)).is_err(), "right of the frame");
+        assert!(decode(1, &raw_tile(Area { x: 0, y: 1, width: 8, height: 8 }, 1)).is_err(), "below the frame");
+        assert!(decode(1, &raw_tile(Area { x: 0xFFFF, y: 0xFFFF, width: 2, height: 2 }, 1)).is_err(), "far outside");
+        assert!(decode(u32::MAX, &inside).is_err(), "huge counts must not allocate");
+
+        let mut frame = mode_frame(16, 8).unwrap();
+        assert!(decode_tiles(&mut frame, 1, b"not zstd").is_err());
+        assert!(frame.rgba.iter().all(|b| *b == 0), "a rejected payload must not touch the frame");
+    }
+
+    #[test]
+    fn decompression_stops_at_the_frame_size() {
+        let mut frame = mode_frame(16, 8).unwrap();
+        //Tiny compressed, but far larger than anything that fits into the frame
+        let bomb = compress(&vec![0; 64 * 1024 * 1024]);
+        assert!(bomb.len() < 64 * 1024);
+        let error = decode_tiles(&mut frame, 1, &bomb).err().unwrap();
+        assert_eq!(error.to_string(), "The tiles are larger than the frame");
+    }
+
+    #[test]
+    fn messages_round_trip_within_their_limits() {
+        let mut wire = Vec::new();
+        let hello = Header::Hello { version: VERSION, token: Some(String::from("secret")) };
+        write_message(&mut wire, &hello, &[]).unwrap();
+        write_message(&mut wire, &Header::Tiles { count: 3 }, &[1, 2, 3]).unwrap();
+        let mut reader = wire.as_slice();
+        assert_eq!(read_message_limited(&mut reader, 0).unwrap(), (hello, Vec::new()));
+        assert_eq!(read_message(&mut reader).unwrap(), (Header::Tiles { count: 3 }, vec![1, 2, 3]));
+        assert!(read_message(&mut reader).is_err(), "end of the stream");
+
+        let mut wire = Vec::new();
+        write_message(&mut wire, &Header::Hello { version: VERSION, token: None }, &[0; 16]).unwrap();
+        let error = read_message_limited(&mut wire.as_slice(), 0).err().unwrap();
+        assert_eq!(error.to_string(), "Message of 16 bytes is too large");
+
+        //A length prefix is not trusted before it was checked
+        let mut wire = 2u32.to_be_bytes().to_vec();
+        wire.extend_from_slice(b"{}");
+        wire.extend_from_slice(&u32::MAX.to_be_bytes());
+        assert!(read_message(&mut wire.as_slice()).is_err());
+    }
+
+    #[test]
+    fn modes_are_limited() {
+        assert_eq!(mode_frame(3840, 2160).unwrap().rgba.len(), 4 * 3840 * 2160);
+        assert!(mode_frame(0, 0).unwrap().rgba.is_empty());
+        assert!(mode_frame(MAX_FRAME_SIDE + 1, 1).is_err());
+        assert!(mode_frame(1, u32::MAX).is_err());
+        assert!(mode_frame(MAX_FRAME_SIDE, MAX_FRAME_SIDE).is_err(), "the frame would not fit into a payload");
+    }
+}
@@ -0,0 +1,162 @@
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use windows::Win32::Foundation::POINT;
+use windows::Win32::Graphics::Direct3D11::*;
+use windows::Win32::Graphics::Dxgi::Common::*;
+use crate::directx::{AcquisitionResults, CursorData, Display, DisplayMode};
+use crate::remote::RemoteSource;
+use crate::remote::transport::{spawn_receiver, Connection, Update};
+use crate::utils::make_resource;
+
+/// Frames of a remote sender that take the place of a local duplication. Reconnects until it is dropped
+pub struct RemoteFrames {
+    device: ID3D11Device,
+    output: Display,
+    source: RemoteSource,
+    connection: Arc<Connection>,
+    updates: Receiver<Update>,
+    display_mode: DisplayMode,
+    frame: Option<ID3D11Texture2D>,
+    cursor_pos: Option<POINT>,
+    cursor_data: Option<CursorData>,
+    last_update: Option<Instant>,
+    timeouts: u64
+}
+
+impl RemoteFrames {
+
+    /// `output` is the local monitor that is shown with the remote content
+    pub fn new(device: &ID3D11Device, output: Display, source: RemoteSource) -> Self {
+        let connection = Arc::new(Connection::default());
+        let updates = spawn_receiver(source.clone(), connection.clone());
+        Self {
+            device: device.clone(),
+            output,
+            source,
+            connection,
+            updates,
+            display_mode: DisplayMode::default(),
+            frame: None,
+            cursor_pos: None,
+            cursor_data: None,
+            last_update: None,
+            timeouts: 0,
+        }
+    }
+
+    fn create_frame(&self, mode: &DisplayMode) -> Result<ID3D11Texture2D> {
+        make_resource(|ptr| unsafe {
+            self.device.CreateTexture2D(&D3D11_TEXTURE2D_DESC {
+                Width: mode.width,
+                Height: mode.height,
+                MipLevels: 1,
+                ArraySize: 1,
+                Format: DXGI_FORMAT_B8G8R8A8_UNORM,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Usage: D3D11_USAGE_DEFAULT,
+                BindFlags: D3D11_BIND_SHADER_RESOURCE,
+                CPUAccessFlags: Default::default(),
+                MiscFlags: Default::default(),
+            }, None, ptr)
+        })
+    }
+
+    /// Applies everything that arrived since the last call
+    pub fn try_acquire_next_frame(&mut self) -> Result<AcquisitionResults> {
+        let mut result = AcquisitionResults::default();
+        while let Ok(update) = self.updates.try_recv() {
+            match update {
+                Update::Mode(mode) => {
+                    self.frame = Some(self.create_frame(&mode)?);
+                    self.display_mode = mode;
+                }
+                Update::Tiles(tiles) => {
+                    let Some(frame) = self.frame.as_ref() else { continue };
+                    let context = unsafe {
+                        let mut context = None;
+                        self.device.GetImmediateContext(&mut context);
+                        context.ok_or_else(|| anyhow!("The device has no immediate context"))?
+                    };
+                    for (area, pixels) in &tiles {
+                        let bounds = D3D11_BOX {
+                            left: area.x,
+                            top: area.y,
+                            front: 0,
+                            right: area.x + area.width,
+                            bottom: area.y + area.height,
+                            back: 1,
+                        };
+                        unsafe {
+                            context.UpdateSubresource(frame, 0, Some(&bounds as *const _), pixels.as_ptr() as _, 4 * area.width, 0);
+                        }
+                    }
+                    self.last_update = Some(Instant::now());
+                    result.frame_update = true;
+                }
+                Update::Pointer(position) => self.cursor_pos = position.map(|(x, y)| POINT { x, y }),
+                Update::Cursor(data) => {
+                    self.cursor_data = Some(data);
+                    result.cursor_updated = true;
+                }
+                Update::Disconnected => {
+                    self.cursor_pos = None;
+                    self.last_update = None;
+                }
+            }
+            result.success = true;
+        }
+        if !result.success {
+            self.timeouts += 1;
+        }
+        result.success &= self.frame.is_some();
+        Ok(result)
+    }
+
+    pub fn get_frame(&self) -> Option<&ID3D11Texture2D> {
+        self.frame.as_ref()
+    }
+
+    /// Position of the pointer hotspot in frame coordinates
+    pub fn get_cursor_pos(&self) -> Option<POINT> {
+        self.cursor_pos
+    }
+
+    pub fn get_cursor_data(&self) -> Option<&CursorData> {
+        self.cursor_data.as_ref()
+    }
+
+    /// Number of times `try_acquire_next_frame` found nothing new
+    pub fn get_timeout_count(&self) -> u64 {
+        self.timeouts
+    }
+
+    /// Time since the last tiles arrived
+    pub fn get_frame_age(&self) -> Option<Duration> {
+        self.last_update.map(|time| time.elapsed())
+    }
+
+    /// Size of the remote frames, which are always sent upright
+    pub fn get_display_mode(&self) -> DisplayMode {
+        self.display_mode
+    }
+
+    pub fn get_current_output(&self) -> &Display {
+        &self.output
+    }
+
+    pub fn source(&self) -> &RemoteSource {
+        &self.source
+    }
+
+}
+
+impl Drop for RemoteFrames {
+    fn drop(&mut self) {
+        self.connection.close();
+    }
+}
@@ -0,0 +1,149 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, ensure, Context, Result};
+use crate::directx::{create_device, AdapterFactory, DesktopDuplication, FrameReader};
+use crate::image_io::{Area, Image};
+use crate::redaction::{redact_image, Redaction};
+use crate::remote::{RemoteConfig, TILE};
+use crate::remote::protocol::*;
+use crate::remote::transport::accept_receivers;
+use crate::snapshot::quarter_turns;
+
+/// The pointer is resent this often so that a vanished receiver is noticed while the monitor is idle
+const KEEPALIVE: Duration = Duration::from_secs(1);
+
+/// Streams the configured monitor until the receiver disconnects or the sender stops. The receiver already passed the hello
+fn serve_receiver(mut stream: TcpStream, config: &RemoteConfig, redactions: &[Redaction], stop: &AtomicBool) -> Result<()> {
+    stream.set_nodelay(true)?;
+
+    let adapter = AdapterFactory::new()?
+        .get_adapter_by_idx(0)
+        .context("Can not get default graphics adapter")?;
+    let display = match config.monitor.as_deref() {
+        None => adapter.get_display_by_idx(0),
+        Some(monitor) => adapter.get_display_by_name(monitor)
+    }.ok_or_else(|| anyhow!("Can not find monitor {}", config.monitor.as_deref().unwrap_or_default()));
+    let display = match display {
+        Ok(display) => display,
+        Err(err) => {
+            write_message(&mut stream, &Header::Error { message: err.to_string() }, &[])?;
+            return Err(err);
+        }
+    };
+    let (device, context) = create_device(&adapter)?;
+    let mut dupl = DesktopDuplication::new(&device, display)?;
+    let mut reader = FrameReader::default();
+    let mut previous: Option<Image> = None;
+    let mut pointer = None;
+    let mut last_pointer = Instant::now();
+    let tick = Duration::from_secs(1) / config.fps.max(1);
+    while !stop.load(Ordering::Acquire) {
+        let next_tick = Instant::now() + tick;
+        let result = match dupl.try_acquire_next_frame() {
+            Ok(result) => result,
+            Err(err) => {
+                log::debug!("Can not acquire frame to send: {}", err);
+                std::thread::sleep(tick);
+                continue;
+            }
+        };
+        if result.cursor_updated {
+            if let Some(data) = dupl.get_cursor_data() {
+                write_message(&mut stream, &Header::cursor(data), &data.data)?;
+            }
+        }
+        let position = dupl.get_cursor_pos().map(|pos| (pos.x, pos.y));
+        if position != pointer || last_pointer.elapsed() >= KEEPALIVE {
+            pointer = position;
+            last_pointer = Instant::now();
+            write_message(&mut stream, &Header::Pointer { position }, &[])?;
+        }
+        if let Some(frame) = dupl.get_frame().filter(|_| result.frame_update) {
+            let mode = dupl.get_display_mode();
+            let mut image = reader.read(&device, &context, frame)?
+                .rotated(quarter_turns(mode.orientation));
+            redact_image(&mut image, redactions);
+            let old = match previous.take() {
+                Some(old) if old.width == image.width && old.height == image.height => old,
+                _ => {
+                    write_message(&mut stream, &Header::Mode {
+                        width: image.width,
+                        height: image.height,
+                        refresh_num: mode.refresh_num,
+                        refresh_den: mode.refresh_den,
+                    }, &[])?;
+                    Image { rgba: vec![0; image.rgba.len()], ..image }
+                }
+            };
+            let areas = old.changed_areas(&image, Area::of(&image), TILE);
+            if !areas.is_empty() {
+                let tiles = encode_tiles(&old, &image, &areas)?;
+                write_message(&mut stream, &Header::Tiles { count: areas.len() as u32 }, &tiles)?;
+            }
+            previous = Some(image);
+        }
+        if let Some(remaining) = next_tick.checked_duration_since(Instant::now()) {
+            std::thread::sleep(remaining);
+        }
+    }
+    Ok(())
+}
+
+/// Serves one receiver at a time as every receiver needs its own duplication; Others are refused while one is connected.
+/// Dropping it stops the sender
+pub struct RemoteSender {
+    config: RemoteConfig,
+    redactions: Vec<Redaction>,
+    address: SocketAddr,
+    stop: Arc<AtomicBool>
+}
+
+impl RemoteSender {
+
+    /// `redactions` are applied to the frames of the sent monitor
+    pub fn start(config: RemoteConfig, redactions: Vec<Redaction>) -> Result<Self> {
+        let listener = TcpListener::bind(config.address.as_str())
+            .with_context(|| format!("Can not listen on {}", config.address))?;
+        let address = listener.local_addr()?;
+        ensure!(config.token.is_some() || address.ip().is_loopback(),
+            "Refusing to send frames on {} without a token", address);
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender_config, sender_redactions, sender_stop) = (config.clone(), redactions.clone(), stop.clone());
+        let serve = move |stream| serve_receiver(stream, &sender_config, &sender_redactions, &sender_stop);
+        let (token, accept_stop) = (config.token.clone(), stop.clone());
+        std::thread::spawn(move || accept_receivers(listener, token, accept_stop, serve));
+        log::info!("Sending frames on {}", address);
+        Ok(Self {
+            config,
+            redactions,
+            address,
+            stop,
+        })
+    }
+
+    pub fn config(&self) -> &RemoteConfig {
+        &self.config
+    }
+
+    pub fn redactions(&self) -> &[Redaction] {
+        &self.redactions
+    }
+
+}
+
+impl Drop for RemoteSender {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        // Wakes the accept loop up, which can not connect to the unspecified address itself
+        let mut address = self.address;
+        if address.ip().is_unspecified() {
+            address.set_ip(match address {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into()
+            });
+        }
+        let _ = TcpStream::connect_timeout(&address, Duration::from_millis(200));
+    }
+}
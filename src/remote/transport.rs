@@ -0,0 +1,371 @@
+//! The parts of the sender and the receiver that work without Direct3D: the hello, the single receiver slot of the sender
+//! and the receive loop that reconnects
+
+use std::io::BufReader;
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, bail, Result};
+use error_tools::log::LogResultExt;
+use crate::directx::{CursorData, DisplayMode, DisplayOrientation};
+use crate::image_io::{Area, Image};
+use crate::remote::{constant_time_eq, RemoteSource};
+use crate::remote::protocol::*;
+
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_BACKOFF: Duration = Duration::from_secs(8);
+
+/// Reads the hello of a new receiver and refuses the receiver if the version or the token is wrong
+fn check_hello(stream: &mut TcpStream, token: Option<&str>) -> Result<()> {
+    stream.set_read_timeout(Some(HELLO_TIMEOUT))?;
+    //The hello has no payload, so nothing larger is read before the token was checked
+    let refusal = match read_message_limited(&mut BufReader::new(&*stream), 0)? {
+        (Header::Hello { version, .. }, _) if version != VERSION => Some(format!("Unsupported protocol version {}", version)),
+        (Header::Hello { token: given, .. }, _) => match token {
+            Some(token) if !given.is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes())) => Some(String::from("Wrong token")),
+            _ => None
+        },
+        (other, _) => Some(format!("Expected hello instead of {:?}", other))
+    };
+    stream.set_read_timeout(None)?;
+    if let Some(message) = refusal {
+        refuse(stream, &message)?;
+        bail!("Refused receiver: {}", message);
+    }
+    Ok(())
+}
+
+fn refuse(stream: &mut TcpStream, message: &str) -> Result<()> {
+    stream.set_write_timeout(Some(HELLO_TIMEOUT))?;
+    write_message(stream, &Header::Error { message: message.to_string() }, &[])
+}
+
+/// Accepts receivers until `stop` is set. Only a receiver that passed the hello can take the slot;
+/// Others are refused while `serve` streams to the one that has it
+pub fn accept_receivers(listener: TcpListener, token: Option<String>, stop: Arc<AtomicBool>, serve: impl Fn(TcpStream) -> Result<()> + Clone + Send + 'static) {
+    let busy = Arc::new(AtomicBool::new(false));
+    for stream in listener.incoming() {
+        if stop.load(Ordering::Acquire) {
+            break;
+        }
+        let Some(mut stream) = stream.log_ok("Can not accept receiver") else { continue };
+        let (token, busy, serve) = (token.clone(), busy.clone(), serve.clone());
+        std::thread::spawn(move || {
+            let peer = stream.peer_addr().ok();
+            if let Err(err) = check_hello(&mut stream, token.as_deref()) {
+                log::info!("Receiver from {:?} failed the hello: {}", peer, err);
+                return;
+            }
+            if busy.swap(true, Ordering::AcqRel) {
+                log::info!("Refusing receiver from {:?} while another one is connected", peer);
+                refuse(&mut stream, "Another receiver is connected")
+                    .log_ok("Can not refuse receiver");
+                return;
+            }
+            log::info!("Receiver connected from {:?}", peer);
+            if let Err(err) = serve(stream) {
+                log::info!("Receiver disconnected: {}", err);
+            }
+            busy.store(false, Ordering::Release);
+        });
+    }
+    log::trace!("Stopping remote sender");
+}
+
+pub enum Update {
+    Mode(DisplayMode),
+    /// Changed areas with their pixels in bgra order
+    Tiles(Vec<(Area, Vec<u8>)>),
+    Pointer(Option<(i32, i32)>),
+    Cursor(CursorData),
+    Disconnected
+}
+
+/// The connection of a receiver. Closing it stops the receive loop
+#[derive(Default)]
+pub struct Connection {
+    stop: AtomicBool,
+    stream: Mutex<Option<TcpStream>>
+}
+
+impl Connection {
+    pub fn close(&self) {
+        self.stop.store(true, Ordering::Release);
+        if let Ok(Some(stream)) = self.stream.lock().as_deref() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+fn bgra_rows(frame: &Image, area: Area) -> Vec<u8> {
+    (area.y..area.y + area.height)
+        .flat_map(|y| frame.row(area, y).chunks_exact(4))
+        .flat_map(|p| [p[2], p[1], p[0], p[3]])
+        .collect()
+}
+
+/// Receives messages until the connection breaks. Returns early with `Ok` if the receiver is dropped
+fn receive(source: &RemoteSource, connection: &Connection, updates: &Sender<Update>, backoff: &mut Duration) -> Result<()> {
+    let address = source.address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow!("Can not resolve {}", source.address))?;
+    let mut stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+    stream.set_nodelay(true)?;
+    *connection.stream.lock().map_err(|_| anyhow!("Stream lock poisoned"))? = Some(stream.try_clone()?);
+    if connection.stop.load(Ordering::Acquire) {
+        return Ok(());
+    }
+    write_message(&mut stream, &Header::Hello { version: VERSION, token: source.token.clone() }, &[])?;
+    log::info!("Connected to remote monitor at {}", address);
+
+    let mut reader = BufReader::new(stream);
+    let mut frame = Image { width: 0, height: 0, rgba: Vec::new() };
+    loop {
+        let (header, payload) = read_message(&mut reader)?;
+        *backoff = Duration::from_secs(1);
+        let update = match header {
+            Header::Mode { width, height, refresh_num, refresh_den } => {
+                frame = mode_frame(width, height)?;
+                Update::Mode(DisplayMode {
+                    width,
+                    height,
+                    orientation: DisplayOrientation::Landscape,
+                    refresh_num,
+                    refresh_den,
+                    hdr: false,
+                })
+            }
+            Header::Tiles { count } => Update::Tiles(decode_tiles(&mut frame, count, &payload)?
+                .into_iter()
+                .map(|area| (area, bgra_rows(&frame, area)))
+                .collect()),
+            Header::Pointer { position } => Update::Pointer(position),
+            Header::Cursor { kind, width, height, pitch, hotspot } => Update::Cursor(CursorData {
+                cursor_type: kind.into(),
+                width,
+                height,
+                pitch,
+                hotspot,
+                data: payload,
+            }),
+            Header::Error { message } => bail!("The sender refused the connection: {}", message),
+            Header::Hello { .. } => bail!("Unexpected hello from the sender")
+        };
+        if updates.send(update).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Receives from `source` on a thread of its own and reconnects with a growing delay until the connection is closed.
+/// Every lost connection is reported as `Update::Disconnected`
+pub fn spawn_receiver(source: RemoteSource, connection: Arc<Connection>) -> Receiver<Update> {
+    let (tx, updates) = channel();
+    std::thread::spawn(move || {
+        let mut backoff = Duration::from_secs(1);
+        while !connection.stop.load(Ordering::Acquire) {
+            if let Err(err) = receive(&source, &connection, &tx, &mut backoff) {
+                log::warn!("Lost connection to {}: {}", source.address, err);
+            }
+            if tx.send(Update::Disconnected).is_err() {
+                break;
+            }
+            let retry = Instant::now() + backoff;
+            while Instant::now() < retry && !connection.stop.load(Ordering::Acquire) {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+        log::trace!("Stopped receiving from {}", source.address);
+    });
+    updates
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::SocketAddr;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc::RecvTimeoutError;
+    use super::*;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn image(width: u32, height: u32, seed: u32) -> Image {
+        let rgba = (0..4 * width * height)
+            .map(|i| (i.wrapping_mul(2654435761).wrapping_add(seed) >> 13) as u8)
+            .collect();
+        Image { width, height, rgba }
+    }
+
+    /// A sender that streams `frames[n]` to its `n`th receiver and then waits for it to go away,
+    /// or drops it right away if `hang_up` is set
+    struct TestSender {
+        address: SocketAddr,
+        stop: Arc<AtomicBool>,
+        served: Arc<AtomicUsize>
+    }
+
+    impl TestSender {
+        fn start(token: Option<&str>, frames: Vec<Image>, hang_up: bool) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            let stop = Arc::new(AtomicBool::new(false));
+            let served = Arc::new(AtomicUsize::new(0));
+            let counter = served.clone();
+            let serve = move |mut stream: TcpStream| {
+                let frame = &frames[counter.fetch_add(1, Ordering::AcqRel)];
+                write_message(&mut stream, &Header::Mode { width: frame.width, height: frame.height, refresh_num: 60, refresh_den: 1 }, &[])?;
+                let blank = mode_frame(frame.width, frame.height)?;
+                let tiles = encode_tiles(&blank, frame, &[Area::of(frame)])?;
+                write_message(&mut stream, &Header::Tiles { count: 1 }, &tiles)?;
+                write_message(&mut stream, &Header::Pointer { position: Some((3, 4)) }, &[])?;
+                if !hang_up {
+                    let _ = stream.read(&mut [0]);
+                }
+                Ok(())
+            };
+            std::thread::spawn({
+                let (token, stop) = (token.map(str::to_string), stop.clone());
+                move || accept_receivers(listener, token, stop, serve)
+            });
+            Self { address, stop, served }
+        }
+
+        fn source(&self, token: Option<&str>) -> RemoteSource {
+            RemoteSource { address: self.address.to_string(), token: token.map(str::to_string) }
+        }
+
+        /// Connects without the receive loop and returns the first answer to `hello`
+        fn answer(&self, hello: &Header) -> Header {
+            let mut stream = TcpStream::connect(self.address).unwrap();
+            stream.set_read_timeout(Some(WAIT)).unwrap();
+            write_message(&mut stream, hello, &[]).unwrap();
+            read_message(&mut stream).unwrap().0
+        }
+    }
+
+    impl Drop for TestSender {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Release);
+            let _ = TcpStream::connect(self.address);
+        }
+    }
+
+    fn hello(token: Option<&str>) -> Header {
+        Header::Hello { version: VERSION, token: token.map(str::to_string) }
+    }
+
+    /// Applies the updates up to the pointer that ends every frame of the test sender
+    fn next_frame(updates: &Receiver<Update>) -> Image {
+        received(updates).1
+    }
+
+    fn received(updates: &Receiver<Update>) -> (Option<DisplayMode>, Image) {
+        let mut last_mode = None;
+        let mut frame = Image { width: 0, height: 0, rgba: Vec::new() };
+        loop {
+            match updates.recv_timeout(WAIT).expect("the frame did not arrive") {
+                Update::Mode(mode) => {
+                    frame = mode_frame(mode.width, mode.height).unwrap();
+                    last_mode = Some(mode);
+                }
+                Update::Tiles(tiles) => for (area, bgra) in tiles {
+                    for (i, pixel) in bgra.chunks_exact(4).enumerate() {
+                        let (x, y) = (area.x + i as u32 % area.width, area.y + i as u32 / area.width);
+                        let start = 4 * (y * frame.width + x) as usize;
+                        frame.rgba[start..start + 4].copy_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                    }
+                },
+                Update::Pointer(position) => {
+                    assert_eq!(position, Some((3, 4)));
+                    return (last_mode, frame);
+                }
+                Update::Cursor(_) => panic!("The test sender sends no cursor"),
+                Update::Disconnected => panic!("Disconnected before the frame arrived")
+            }
+        }
+    }
+
+    #[test]
+    fn wrong_tokens_and_versions_are_refused() {
+        let sender = TestSender::start(Some("secret"), vec![image(8, 8, 1)], false);
+        let refusal = |message: &str| Header::Error { message: message.to_string() };
+        assert_eq!(sender.answer(&hello(None)), refusal("Wrong token"));
+        assert_eq!(sender.answer(&hello(Some("secreT"))), refusal("Wrong token"));
+        assert_eq!(sender.answer(&Header::Hello { version: VERSION + 1, token: Some(String::from("secret")) }), refusal("Unsupported protocol version 2"));
+        assert_eq!(sender.answer(&Header::Pointer { position: None }), refusal("Expected hello instead of Pointer { position: None }"));
+
+        let connection = Arc::new(Connection::default());
+        let updates = spawn_receiver(sender.source(Some("wrong")), connection.clone());
+        assert!(matches!(updates.recv_timeout(WAIT), Ok(Update::Disconnected)));
+        connection.close();
+        assert_eq!(sender.served.load(Ordering::Acquire), 0);
+    }
+
+    #[test]
+    fn frames_arrive_at_the_receiver() {
+        let frame = image(150, 70, 1);
+        let sender = TestSender::start(Some("secret"), vec![frame.clone()], false);
+        let connection = Arc::new(Connection::default());
+        let updates = spawn_receiver(sender.source(Some("secret")), connection.clone());
+        let (mode, received) = received(&updates);
+        let mode = mode.expect("The mode has to come before the tiles");
+        assert_eq!((mode.width, mode.height, mode.refresh_num, mode.refresh_den), (150, 70, 60, 1));
+        assert_eq!(received, frame);
+        connection.close();
+
+        let connection = Arc::new(Connection::default());
+        let sender = TestSender::start(None, vec![frame.clone()], false);
+        let updates = spawn_receiver(sender.source(None), connection.clone());
+        assert_eq!(next_frame(&updates), frame);
+        connection.close();
+    }
+
+    #[test]
+    fn receivers_reconnect_after_the_sender_hangs_up() {
+        let (first, second) = (image(32, 16, 1), image(16, 32, 2));
+        let sender = TestSender::start(None, vec![first.clone(), second.clone()], true);
+        let connection = Arc::new(Connection::default());
+        let updates = spawn_receiver(sender.source(None), connection.clone());
+        assert_eq!(next_frame(&updates), first);
+        assert!(matches!(updates.recv_timeout(WAIT), Ok(Update::Disconnected)));
+        assert_eq!(next_frame(&updates), second);
+        connection.close();
+        assert!(matches!(updates.recv_timeout(WAIT), Ok(Update::Disconnected) | Err(RecvTimeoutError::Disconnected)));
+    }
+
+    #[test]
+    fn only_one_receiver_is_served() {
+        let sender = TestSender::start(Some("secret"), vec![image(8, 8, 1), image(8, 8, 2)], false);
+        //A connection that never says hello does not take the slot
+        let _silent = TcpStream::connect(sender.address).unwrap();
+        let connection = Arc::new(Connection::default());
+        let updates = spawn_receiver(sender.source(Some("secret")), connection.clone());
+        assert_eq!(next_frame(&updates), image(8, 8, 1));
+
+        assert_eq!(sender.answer(&hello(Some("secret"))), Header::Error { message: String::from("Another receiver is connected") });
+        assert_eq!(sender.answer(&hello(Some("wrong"))), Header::Error { message: String::from("Wrong token") });
+
+        //The slot is free again once the receiver is gone
+        connection.close();
+        drop(updates);
+        let deadline = Instant::now() + WAIT;
+        let mut stream = loop {
+            let mut stream = TcpStream::connect(sender.address).unwrap();
+            stream.set_read_timeout(Some(WAIT)).unwrap();
+            write_message(&mut stream, &hello(Some("secret")), &[]).unwrap();
+            match read_message(&mut stream).unwrap().0 {
+                Header::Mode { .. } => break stream,
+                _ if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+                other => panic!("The slot was not freed: {:?}", other)
+            }
+        };
+        assert!(matches!(read_message(&mut stream).unwrap().0, Header::Tiles { count: 1 }));
+        assert_eq!(sender.served.load(Ordering::Acquire), 2);
+    }
+}
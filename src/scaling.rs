@@ -0,0 +1,200 @@
+use std::f32::consts::PI;
+use serde::Deserialize;
+
+/// Number of kernel samples uploaded to the gpu
+pub const LUT_SIZE: usize = 256;
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScalingFilter {
+    /// Trilinear sampling of the mip chain
+    #[default]
+    Linear,
+    /// Catmull-Rom spline
+    Bicubic,
+    /// Three lobed lanczos window
+    Lanczos
+}
+
+impl ScalingFilter {
+
+    pub fn radius(self) -> f32 {
+        match self {
+            ScalingFilter::Linear => 1.0,
+            ScalingFilter::Bicubic => 2.0,
+            ScalingFilter::Lanczos => 3.0
+        }
+    }
+
+    pub fn weight(self, x: f32) -> f32 {
+        let x = x.abs();
+        if x >= self.radius() {
+            return 0.0;
+        }
+        match self {
+            ScalingFilter::Linear => 1.0 - x,
+            ScalingFilter::Bicubic => catmull_rom(x),
+            ScalingFilter::Lanczos => sinc(x) * sinc(x / 3.0)
+        }
+    }
+
+    /// Samples the kernel at `LUT_SIZE` evenly spaced points between `0` and `radius`
+    pub fn lookup_table(self) -> [f32; LUT_SIZE] {
+        let mut lut = [0.0; LUT_SIZE];
+        let step = self.radius() / (LUT_SIZE - 1) as f32;
+        for (i, w) in lut.iter_mut().enumerate() {
+            *w = self.weight(i as f32 * step);
+        }
+        lut
+    }
+
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * PI;
+        x.sin() / x
+    }
+}
+
+fn catmull_rom(x: f32) -> f32 {
+    const A: f32 = -0.5;
+    if x <= 1.0 {
+        ((A + 2.0) * x - (A + 3.0)) * x * x + 1.0
+    } else {
+        ((A * x - 5.0 * A) * x + 8.0 * A) * x - 4.0 * A
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [ScalingFilter; 3] = [ScalingFilter::Linear, ScalingFilter::Bicubic, ScalingFilter::Lanczos];
+
+    #[test]
+    fn kernels_are_symmetric() {
+        for filter in FILTERS {
+            for i in 0..=400 {
+                let x = i as f32 * 0.01;
+                assert_eq!(filter.weight(x), filter.weight(-x), "{:?} at {}", filter, x);
+            }
+        }
+    }
+
+    #[test]
+    fn kernels_interpolate() {
+        for filter in FILTERS {
+            assert_eq!(filter.weight(0.0), 1.0, "{:?}", filter);
+            for k in 1..=4 {
+                assert!(filter.weight(k as f32).abs() < 1e-6, "{:?} at {}", filter, k);
+            }
+        }
+    }
+
+    #[test]
+    fn kernels_are_normalized() {
+        // Linear and Catmull-Rom are a partition of unity; Lanczos only comes close
+        for (filter, tolerance) in [(ScalingFilter::Linear, 1e-5), (ScalingFilter::Bicubic, 1e-5), (ScalingFilter::Lanczos, 1e-2)] {
+            for i in 0..100 {
+                let phase = i as f32 / 100.0;
+                let sum: f32 = (-4..=4)
+                    .map(|k| filter.weight(phase + k as f32))
+                    .sum();
+                assert!((sum - 1.0).abs() < tolerance, "{:?} sums to {} at phase {}", filter, sum, phase);
+            }
+        }
+    }
+
+    /// The kernel like `kernel_weight` in the shader reads it: linearly interpolated between the table entries
+    fn lut_weight(lut: &[f32; LUT_SIZE], radius: f32, x: f32) -> f32 {
+        let t = x.abs() / radius * (LUT_SIZE - 1) as f32;
+        if t >= (LUT_SIZE - 1) as f32 {
+            return 0.0;
+        }
+        let i = t as usize;
+        lut[i] + (lut[i + 1] - lut[i]) * t.fract()
+    }
+
+    /// A single channel image in row major order
+    struct Plane {
+        width: usize,
+        height: usize,
+        values: Vec<f32>
+    }
+
+    impl Plane {
+        fn synthetic(width: usize, height: usize) -> Self {
+            let values = (0..height)
+                .flat_map(|y| (0..width).map(move |x| {
+                    let wave = (x as f32 * 0.05).sin() * (y as f32 * 0.07).cos();
+                    let checker = ((x / 7 + y / 5) % 2) as f32;
+                    0.5 + 0.25 * wave + 0.25 * checker - 0.125
+                }))
+                .collect();
+            Self { width, height, values }
+        }
+
+        fn transposed(&self) -> Self {
+            let values = (0..self.width)
+                .flat_map(|x| (0..self.height).map(move |y| self.values[y * self.width + x]))
+                .collect();
+            Self { width: self.height, height: self.width, values }
+        }
+    }
+
+    /// Resamples the rows to `width` the way the shader does along one axis: The kernel is stretched when shrinking,
+    /// samples outside of the image are clamped to the edge and the weights are normalized
+    fn resample_rows(plane: &Plane, width: usize, radius: f32, weight: impl Fn(f32) -> f32) -> Plane {
+        let scale = plane.width as f32 / width as f32;
+        let stretch = scale.max(1.0);
+        let taps = (radius * stretch).ceil() as i64;
+        let mut values = Vec::with_capacity(width * plane.height);
+        for row in plane.values.chunks_exact(plane.width) {
+            for i in 0..width {
+                let pos = (i as f32 + 0.5) * scale - 0.5;
+                let center = pos.floor();
+                let (mut sum, mut weight_sum) = (0.0, 0.0);
+                for k in 1 - taps..=taps {
+                    let w = weight((pos - center - k as f32) / stretch);
+                    let x = (center as i64 + k).clamp(0, plane.width as i64 - 1) as usize;
+                    sum += w * row[x];
+                    weight_sum += w;
+                }
+                values.push(sum / weight_sum);
+            }
+        }
+        Plane { width, height: plane.height, values }
+    }
+
+    fn resample(plane: &Plane, width: usize, height: usize, radius: f32, weight: impl Fn(f32) -> f32) -> Plane {
+        let rows = resample_rows(plane, width, radius, &weight);
+        resample_rows(&rows.transposed(), height, radius, &weight).transposed()
+    }
+
+    #[test]
+    fn lookup_table_resampling_matches_the_reference() {
+        let uhd = Plane::synthetic(3840, 2160);
+        let small = Plane::synthetic(320, 180);
+        for filter in FILTERS {
+            let lut = filter.lookup_table();
+            assert_eq!(lut[0], 1.0);
+            assert_eq!(lut[LUT_SIZE - 1], 0.0);
+            for (source, width, height) in [(&uhd, 1280, 720), (&small, 1280, 720), (&small, 1000, 333)] {
+                let reference = resample(source, width, height, filter.radius(), |x| filter.weight(x));
+                let gpu = resample(source, width, height, filter.radius(), |x| lut_weight(&lut, filter.radius(), x));
+                let error = reference.values
+                    .iter()
+                    .zip(&gpu.values)
+                    .map(|(a, b)| (a - b).abs())
+                    .fold(0.0, f32::max);
+                //Less than a tenth of an 8 bit step
+                assert!(error < 0.1 / 255.0, "{:?} {}x{} -> {}x{} is off by {}",
+                    filter, source.width, source.height, width, height, error);
+            }
+        }
+    }
+
+}
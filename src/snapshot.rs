@@ -0,0 +1,206 @@
+#[cfg(windows)]
+use anyhow::{bail, Context};
+use anyhow::Result;
+use serde::Deserialize;
+#[cfg(windows)]
+use windows::Win32::Foundation::{HANDLE, HWND};
+#[cfg(windows)]
+use windows::Win32::System::DataExchange::{CloseClipboard, EmptyClipboard, OpenClipboard, SetClipboardData};
+#[cfg(windows)]
+use windows::Win32::System::Memory::{GlobalAlloc, GlobalFree, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
+use crate::cursor_shape::CursorShape;
+use crate::directx::{CursorData, DisplayOrientation};
+#[cfg(windows)]
+use crate::directx::{CachedFrame, FrameReader, Direct3D};
+#[cfg(windows)]
+use crate::frame_source::FrameSource;
+#[cfg(windows)]
+use crate::image_io;
+use crate::image_io::Image;
+use crate::redaction::{redact_image, Redaction};
+
+#[cfg(windows)]
+const CF_DIB: u32 = 8;
+
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default)]
+pub struct SnapshotConfig {
+    /// Draw the pointer into the image
+    pub cursor: bool,
+    /// Copy the image to the clipboard instead of saving it to the pictures folder
+    pub clipboard: bool
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            cursor: true,
+            clipboard: false,
+        }
+    }
+}
+
+/// Number of clockwise quarter turns that bring a duplicated frame into the orientation of the desktop
+pub fn quarter_turns(orientation: DisplayOrientation) -> u32 {
+    match orientation {
+        DisplayOrientation::Landscape => 0,
+        DisplayOrientation::Portrait => 1,
+        DisplayOrientation::FlippedLandscape => 2,
+        DisplayOrientation::FlippedPortrait => 3
+    }
+}
+
+/// Turns a frame read back from the duplication into the image the monitor shows:
+/// Rotated into desktop orientation, redacted and with the pointer's hotspot drawn at its position
+pub fn compose(frame: Image, orientation: DisplayOrientation, redactions: &[Redaction], cursor: Option<((i32, i32), &CursorData)>) -> Result<Image> {
+    let mut image = frame.rotated(quarter_turns(orientation));
+    redact_image(&mut image, redactions);
+    if let Some(((x, y), data)) = cursor {
+        CursorShape::decode(data)?
+            .composite_at_hotspot(&mut image.rgba, image.width, image.height, x, y);
+    }
+    Ok(image)
+}
+
+/// Reads the cached frame back into an image the way the monitor shows it. The cached frame is already redacted
+#[cfg(windows)]
+pub fn capture(reader: &mut FrameReader, d3d: &Direct3D, frame: &CachedFrame, dupl: &FrameSource, with_cursor: bool) -> Result<Image> {
+    let texture = frame
+        .get_texture()
+        .context("There is no frame to capture")?;
+    let cursor = match with_cursor {
+        true => dupl.get_cursor_pos().zip(dupl.get_cursor_data()).map(|(pos, data)| ((pos.x, pos.y), data)),
+        false => None
+    };
+    compose(reader.read(&d3d.device, &d3d.context, texture)?, dupl.get_display_mode().orientation, &[], cursor)
+}
+
+#[cfg(windows)]
+pub fn copy_to_clipboard(image: &Image) -> Result<()> {
+    let dib = image.to_dib();
+    unsafe {
+        if !OpenClipboard(HWND(0)).as_bool() {
+            bail!("Can not open the clipboard");
+        }
+        let result = (|| {
+            EmptyClipboard();
+            let memory = GlobalAlloc(GMEM_MOVEABLE, dib.len());
+            let ptr = GlobalLock(memory) as *mut u8;
+            if ptr.is_null() {
+                GlobalFree(memory);
+                bail!("Can not allocate memory for the clipboard");
+            }
+            std::ptr::copy_nonoverlapping(dib.as_ptr(), ptr, dib.len());
+            GlobalUnlock(memory);
+            //The clipboard owns the memory once this succeeds
+            if let Err(err) = SetClipboardData(CF_DIB, HANDLE(memory)) {
+                GlobalFree(memory);
+                return Err(err.into());
+            }
+            Ok(())
+        })();
+        CloseClipboard();
+        result
+    }
+}
+
+/// Captures the peeked monitor and saves it as a timestamped png or puts it on the clipboard
+#[cfg(windows)]
+pub fn take_snapshot(d3d: &Direct3D, frame: &CachedFrame, dupl: &FrameSource, config: SnapshotConfig) -> Result<()> {
+    let image = capture(&mut FrameReader::default(), d3d, frame, dupl, config.cursor)?;
+    match config.clipboard {
+        true => {
+            copy_to_clipboard(&image)?;
+            log::info!("Copied a {}x{} snapshot to the clipboard", image.width, image.height);
+        }
+        false => {
+            let path = image_io::output_path("snapshot", "png");
+            image.save_png(&path)?;
+            log::info!("Saved snapshot to {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Color;
+    use crate::directx::CursorType;
+    use crate::image_io::{encode_png, PixelFormat};
+    use crate::redaction::RedactionStyle;
+    use super::*;
+
+    fn source(x: u8, y: u8) -> [u8; 4] {
+        [10 * x + 1, 10 * y + 2, 50, 255]
+    }
+
+    /// A 3x2 bgra frame with four bytes of padding after every row, like a mapped staging texture
+    fn readback() -> Image {
+        let mut data = Vec::new();
+        for y in 0..2 {
+            for x in 0..3 {
+                let [r, g, b, _] = source(x, y);
+                data.extend_from_slice(&[b, g, r, 0]);
+            }
+            data.extend_from_slice(&[0xAA; 4]);
+        }
+        Image::from_rows(PixelFormat::Bgra8, 3, 2, 16, &data).unwrap()
+    }
+
+    fn decode_png(png: &[u8]) -> Image {
+        let mut reader = png::Decoder::new(png).read_info().unwrap();
+        let mut rgba = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut rgba).unwrap();
+        rgba.truncate(info.buffer_size());
+        Image { width: info.width, height: info.height, rgba }
+    }
+
+    #[test]
+    fn snapshots_match_the_desktop() {
+        let redaction = Redaction {
+            x: 0,
+            y: 2,
+            width: 1,
+            height: 1,
+            style: RedactionStyle::Fill,
+            size: 16,
+            color: Color::rgba(255, 0, 0, 128),
+        };
+        let cursor = CursorData {
+            cursor_type: CursorType::Color,
+            width: 1,
+            height: 1,
+            pitch: 4,
+            hotspot: (0, 0),
+            data: vec![255, 0, 0, 255],
+        };
+        let image = compose(readback(), DisplayOrientation::Portrait, &[redaction], Some(((1, 2), &cursor))).unwrap();
+        let mut png = Vec::new();
+        encode_png(&mut png, image.width, image.height, &image.rgba).unwrap();
+        let decoded = decode_png(&png);
+
+        //A clockwise quarter turn puts the bottom-left of the frame at the top-left of the desktop
+        let expected: Vec<[u8; 4]> = vec![
+            source(0, 1), source(0, 0),
+            source(1, 1), source(1, 0),
+            [255, 0, 0, 255], [0, 0, 255, 255]
+        ];
+        assert_eq!((decoded.width, decoded.height), (2, 3));
+        assert_eq!(decoded.rgba, expected.concat());
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn landscape_frames_are_not_rotated() {
+        let image = compose(readback(), DisplayOrientation::Landscape, &[], None).unwrap();
+        assert_eq!(image, readback());
+        let flipped = compose(readback(), DisplayOrientation::FlippedLandscape, &[], None).unwrap();
+        assert_eq!(&flipped.rgba[..4], &source(2, 1));
+    }
+
+    #[test]
+    fn invalid_pointers_fail_the_snapshot() {
+        let cursor = CursorData { cursor_type: CursorType::Color, width: 2, height: 2, pitch: 8, hotspot: (0, 0), data: vec![0; 4] };
+        assert!(compose(readback(), DisplayOrientation::Landscape, &[], Some(((0, 0), &cursor))).is_err());
+    }
+}
@@ -31,6 +31,12 @@ pub fn create_system_tray(event_loop: &EventLoop<CustomEvent>) -> Result<TrayHan
     let _version_item = tray_menu.add_item(MenuItemAttributes::new(concat!("Display Peek (version ", env!("CARGO_PKG_VERSION"), ")"))
         .with_enabled(false));
     let config_item = tray_menu.add_item(MenuItemAttributes::new("Open Config"));
+    let translucency_item = tray_menu.add_item(MenuItemAttributes::new("Toggle Translucency"));
+    let osd_item = tray_menu.add_item(MenuItemAttributes::new("Toggle Diagnostics"));
+    let dismiss_item = tray_menu.add_item(MenuItemAttributes::new("Dismiss Overlay"));
+    let snapshot_item = tray_menu.add_item(MenuItemAttributes::new("Take Snapshot"));
+    let recording_item = tray_menu.add_item(MenuItemAttributes::new("Start/Stop Recording"));
+    let export_cursor_item = tray_menu.add_item(MenuItemAttributes::new("Export Cursor Shape"));
     let mut auto_start_item = tray_menu.add_item(MenuItemAttributes::new("Run at Startup")
         .with_selected(auto_start));
     let quit_item = tray_menu.add_item(MenuItemAttributes::new("Quit"));
@@ -63,6 +69,30 @@ pub fn create_system_tray(event_loop: &EventLoop<CustomEvent>) -> Result<TrayHan
                             show_message_box("Error", format!("Can not open editor\n{}", err));
                         }
                     }
+                    if menu_id == translucency_item.clone().id() {
+                        proxy.send_event(CustomEvent::ToggleTranslucency)
+                            .log_ok("Main event loop seems to be gone");
+                    }
+                    if menu_id == osd_item.clone().id() {
+                        proxy.send_event(CustomEvent::ToggleOsd)
+                            .log_ok("Main event loop seems to be gone");
+                    }
+                    if menu_id == dismiss_item.clone().id() {
+                        proxy.send_event(CustomEvent::DismissOverlay)
+                            .log_ok("Main event loop seems to be gone");
+                    }
+                    if menu_id == snapshot_item.clone().id() {
+                        proxy.send_event(CustomEvent::TakeSnapshot)
+                            .log_ok("Main event loop seems to be gone");
+                    }
+                    if menu_id == recording_item.clone().id() {
+                        proxy.send_event(CustomEvent::ToggleRecording)
+                            .log_ok("Main event loop seems to be gone");
+                    }
+                    if menu_id == export_cursor_item.clone().id() {
+                        proxy.send_event(CustomEvent::ExportCursor)
+                            .log_ok("Main event loop seems to be gone");
+                    }
                     if menu_id == auto_start_item.clone().id() {
                         if auto_start {
                             autostart::disable()
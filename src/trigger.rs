@@ -0,0 +1,233 @@
+use serde::Deserialize;
+use crate::dwell::Switch;
+
+/// How the overlay for a monitor is shown and hidden
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TriggerMode {
+    /// Visible while the pointer is on the monitor or in one of its zones
+    #[default]
+    Hover,
+    /// The key toggles the overlay
+    Toggle,
+    /// Visible while the key is held down
+    Hold,
+    /// Shown like `Hover` but stays after the pointer left until the key is pressed or it is dismissed
+    Pin
+}
+
+impl TriggerMode {
+    /// Whether the pointer alone can show the monitor
+    pub fn uses_hover(self) -> bool {
+        matches!(self, TriggerMode::Hover | TriggerMode::Pin)
+    }
+}
+
+/// Combines the hover decisions with key presses into the final visibility of the overlay.
+/// `M` identifies a monitor and nothing in here talks to the system.
+#[derive(Debug, Clone)]
+pub struct Triggers<M> {
+    shown: Option<(M, TriggerMode)>,
+    /// The monitor the hover logic currently wants to show
+    hovered: Option<(M, TriggerMode)>
+}
+
+impl<M> Default for Triggers<M> {
+    fn default() -> Self {
+        Self {
+            shown: None,
+            hovered: None,
+        }
+    }
+}
+
+impl<M: Copy + PartialEq> Triggers<M> {
+
+    /// Handles a decision of the hover logic. `mode` looks up the trigger mode of a monitor
+    pub fn hover(&mut self, switch: Switch<M>, mode: impl FnOnce(M) -> TriggerMode) -> Option<Switch<M>> {
+        match switch {
+            Switch::Show(monitor) => {
+                let mode = mode(monitor);
+                if !mode.uses_hover() {
+                    return None;
+                }
+                self.hovered = Some((monitor, mode));
+                self.show(monitor, mode)
+            }
+            Switch::Hide => {
+                self.hovered = None;
+                match self.shown {
+                    Some((_, TriggerMode::Hover)) => self.hide(),
+                    _ => None
+                }
+            }
+        }
+    }
+
+    /// Handles the key of a monitor going down or up
+    pub fn key(&mut self, monitor: M, mode: TriggerMode, pressed: bool) -> Option<Switch<M>> {
+        let shown = self.shown.is_some_and(|(m, _)| m == monitor);
+        match (mode, pressed) {
+            (TriggerMode::Toggle | TriggerMode::Pin, true) if shown => self.release(),
+            (TriggerMode::Toggle | TriggerMode::Pin | TriggerMode::Hold, true) => self.show(monitor, mode),
+            (TriggerMode::Hold, false) if shown => self.release(),
+            _ => None
+        }
+    }
+
+    /// Shows `monitor` until it is dismissed
+    pub fn open(&mut self, monitor: M) -> Option<Switch<M>> {
+        self.show(monitor, TriggerMode::Toggle)
+    }
+
+    /// Shows `monitor` until it is unpinned or dismissed
+    pub fn peek(&mut self, monitor: M) -> Option<Switch<M>> {
+        self.show(monitor, TriggerMode::Pin)
+    }
+
+    pub fn shown(&self) -> Option<M> {
+        self.shown.map(|(monitor, _)| monitor)
+    }
+
+    /// Keeps the shown monitor visible after the pointer left or releases it again
+    pub fn pin(&mut self) -> Option<Switch<M>> {
+        match self.shown.as_mut()? {
+            (_, TriggerMode::Pin) => self.release(),
+            (_, mode) => {
+                *mode = TriggerMode::Pin;
+                None
+            }
+        }
+    }
+
+    /// Hides the overlay regardless of how it was shown
+    pub fn dismiss(&mut self) -> Option<Switch<M>> {
+        self.hovered = None;
+        self.hide()
+    }
+
+    fn show(&mut self, monitor: M, mode: TriggerMode) -> Option<Switch<M>> {
+        let already_shown = self.shown.is_some_and(|(m, _)| m == monitor);
+        self.shown = Some((monitor, mode));
+        (!already_shown).then_some(Switch::Show(monitor))
+    }
+
+    fn hide(&mut self) -> Option<Switch<M>> {
+        self.shown.take().map(|_| Switch::Hide)
+    }
+
+    /// Hides the overlay or falls back to the monitor under the pointer
+    fn release(&mut self) -> Option<Switch<M>> {
+        match self.hovered {
+            Some((monitor, TriggerMode::Hover)) => {
+                self.shown = None;
+                self.show(monitor, TriggerMode::Hover)
+            }
+            _ => self.hide()
+        }
+    }
+
+}
+
+/// Picks the monitor after (or before) `current`, wrapping around at both ends.
+/// Starts at the first (or last) monitor if `current` is not in the list
+pub fn cycle<M: Copy + PartialEq>(monitors: &[M], current: Option<M>, forward: bool) -> Option<(usize, M)> {
+    let len = monitors.len();
+    let index = match current.and_then(|current| monitors.iter().position(|m| *m == current)) {
+        Some(i) if forward => (i + 1) % len,
+        Some(i) => (i + len - 1) % len,
+        None if forward => 0,
+        None => len.checked_sub(1)?
+    };
+    monitors.get(index).map(|m| (index, *m))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modes(monitor: u32) -> TriggerMode {
+        match monitor {
+            1 => TriggerMode::Hover,
+            2 => TriggerMode::Toggle,
+            3 => TriggerMode::Hold,
+            _ => TriggerMode::Pin
+        }
+    }
+
+    fn hover(triggers: &mut Triggers<u32>, switch: Switch<u32>) -> Option<Switch<u32>> {
+        triggers.hover(switch, modes)
+    }
+
+    #[test]
+    fn hover_follows_the_pointer() {
+        let mut triggers = Triggers::default();
+        assert_eq!(hover(&mut triggers, Switch::Show(1)), Some(Switch::Show(1)));
+        assert_eq!(hover(&mut triggers, Switch::Show(1)), None);
+        assert_eq!(hover(&mut triggers, Switch::Hide), Some(Switch::Hide));
+        assert_eq!(triggers.shown(), None);
+    }
+
+    #[test]
+    fn key_only_monitors_ignore_the_pointer() {
+        let mut triggers = Triggers::default();
+        assert_eq!(hover(&mut triggers, Switch::Show(2)), None);
+        assert_eq!(hover(&mut triggers, Switch::Show(3)), None);
+        assert_eq!(triggers.shown(), None);
+    }
+
+    #[test]
+    fn toggle_shows_and_hides() {
+        let mut triggers = Triggers::default();
+        assert_eq!(triggers.key(2, TriggerMode::Toggle, true), Some(Switch::Show(2)));
+        assert_eq!(triggers.key(2, TriggerMode::Toggle, false), None);
+        assert_eq!(hover(&mut triggers, Switch::Hide), None);
+        assert_eq!(triggers.key(2, TriggerMode::Toggle, true), Some(Switch::Hide));
+    }
+
+    #[test]
+    fn hold_falls_back_to_the_hovered_monitor() {
+        let mut triggers = Triggers::default();
+        assert_eq!(hover(&mut triggers, Switch::Show(1)), Some(Switch::Show(1)));
+        assert_eq!(triggers.key(3, TriggerMode::Hold, true), Some(Switch::Show(3)));
+        assert_eq!(triggers.key(3, TriggerMode::Hold, true), None);
+        assert_eq!(triggers.key(3, TriggerMode::Hold, false), Some(Switch::Show(1)));
+        assert_eq!(triggers.key(3, TriggerMode::Hold, false), None);
+    }
+
+    #[test]
+    fn pinned_monitors_stay_after_the_pointer_left() {
+        let mut triggers = Triggers::default();
+        assert_eq!(hover(&mut triggers, Switch::Show(4)), Some(Switch::Show(4)));
+        assert_eq!(hover(&mut triggers, Switch::Hide), None);
+        assert_eq!(triggers.shown(), Some(4));
+        assert_eq!(triggers.key(4, TriggerMode::Pin, true), Some(Switch::Hide));
+
+        assert_eq!(hover(&mut triggers, Switch::Show(1)), Some(Switch::Show(1)));
+        assert_eq!(triggers.pin(), None);
+        assert_eq!(hover(&mut triggers, Switch::Hide), None);
+        assert_eq!(triggers.pin(), Some(Switch::Hide));
+        assert_eq!(triggers.pin(), None);
+    }
+
+    #[test]
+    fn dismiss_hides_everything() {
+        let mut triggers = Triggers::default();
+        assert_eq!(triggers.peek(2), Some(Switch::Show(2)));
+        assert_eq!(triggers.open(2), None);
+        assert_eq!(triggers.dismiss(), Some(Switch::Hide));
+        assert_eq!(triggers.dismiss(), None);
+    }
+
+    #[test]
+    fn cycle_wraps_around() {
+        let monitors = [10, 20, 30];
+        assert_eq!(cycle(&monitors, None, true), Some((0, 10)));
+        assert_eq!(cycle(&monitors, None, false), Some((2, 30)));
+        assert_eq!(cycle(&monitors, Some(30), true), Some((0, 10)));
+        assert_eq!(cycle(&monitors, Some(10), false), Some((2, 30)));
+        assert_eq!(cycle(&monitors, Some(99), true), Some((0, 10)));
+        assert_eq!(cycle::<u32>(&[], None, false), None);
+        assert_eq!(cycle::<u32>(&[], None, true), None);
+    }
+}
@@ -5,6 +5,7 @@ use error_tools::SomeOptionExt;
 use windows::Win32::Foundation::{FALSE, TRUE};
 use windows::Win32::Graphics::Direct3D11::*;
 use windows::Win32::UI::WindowsAndMessaging::{MB_ICONERROR, MB_OK, MessageBoxW};
+use windows::Win32::System::Console::{ATTACH_PARENT_PROCESS, AttachConsole};
 use windows::Win32::System::Com::{COINIT_MULTITHREADED, CoInitializeEx, CoUninitialize};
 use windows::Win32::UI::HiDpi::{DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2, SetProcessDpiAwarenessContext};
 
@@ -92,38 +93,7 @@ pub fn show_message_box<T1: Into<HSTRING>, T2: Into<HSTRING>>(title: T1, msg: T2
     }
 }
 
-pub struct U8Iter {
-    value: u8,
-    size: u32
+/// Lets a command line invocation print to the terminal it was started from, as the app is built for the windows subsystem
+pub fn attach_console() {
+    unsafe { AttachConsole(ATTACH_PARENT_PROCESS) };
 }
-
-impl U8Iter {
-    pub fn new(value: u8) -> Self {
-        Self {
-            value,
-            size: u8::BITS,
-        }
-    }
-}
-
-impl Iterator for U8Iter {
-    type Item = bool;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.size > 0 {
-            let result = self.value & 0x80 != 0x0;
-            self.size -= 1;
-            self.value <<= 1;
-            Some(result)
-        } else {
-            None
-        }
-    }
-
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        (self.size as usize, Some(self.size as usize))
-    }
-}
-
-impl ExactSizeIterator for U8Iter {}
-
@@ -0,0 +1,165 @@
+use std::io::Write;
+use anyhow::Result;
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use crate::cursor_shape::CursorShape;
+use crate::directx::MoveRect;
+use crate::image_io::{Area, Image};
+use crate::vnc::protocol::PixelFormat;
+
+/// Edge length of the tiles that are compared between frames and of ZRLE tiles
+const TILE: u32 = 64;
+
+fn rgb(image: &Image, x: u32, y: u32) -> [u8; 3] {
+    let i = 4 * (y as usize * image.width as usize + x as usize);
+    [image.rgba[i], image.rgba[i + 1], image.rgba[i + 2]]
+}
+
+/// Tiles of `within` that differ between the images
+pub fn changed_areas(old: &Image, new: &Image, within: Area) -> Vec<Area> {
+    old.changed_areas(new, within, TILE)
+}
+
+/// Copies the moved pixels like a client does when it receives a CopyRect
+pub fn apply_move(image: &mut Image, mv: &MoveRect) {
+    let bounds = Area::of(image);
+    let (width, height) = mv.size;
+    let source = Area { x: mv.source.0, y: mv.source.1, width, height };
+    let destination = Area { x: mv.destination.0, y: mv.destination.1, width, height };
+    if !bounds.contains(source) || !bounds.contains(destination) {
+        return;
+    }
+    let rows: Vec<Vec<u8>> = (source.y..source.y + height)
+        .map(|y| image.row(source, y).to_vec())
+        .collect();
+    for (y, pixels) in (destination.y..destination.y + height).zip(rows) {
+        let start = 4 * (y as usize * image.width as usize + destination.x as usize);
+        image.rgba[start..start + pixels.len()].copy_from_slice(&pixels);
+    }
+}
+
+/// Copies the pixels of `area` from `source` like a client does when it receives them in a rect
+pub fn apply_rect(image: &mut Image, source: &Image, area: Area) {
+    for y in area.y..area.y + area.height {
+        let start = 4 * (y as usize * image.width as usize + area.x as usize);
+        image.rgba[start..start + 4 * area.width as usize].copy_from_slice(source.row(area, y));
+    }
+}
+
+pub fn write_raw(image: &Image, area: Area, format: &PixelFormat, out: &mut Vec<u8>) {
+    for y in area.y..area.y + area.height {
+        for x in area.x..area.x + area.width {
+            format.put(format.pixel(rgb(image, x, y)), out);
+        }
+    }
+}
+
+/// Writes the pixels and the transparency mask of the Cursor pseudo-encoding
+pub fn write_cursor(shape: &CursorShape, format: &PixelFormat, out: &mut Vec<u8>) {
+    let preview = shape.preview();
+    for pixel in preview.chunks_exact(4) {
+        format.put(format.pixel([pixel[0], pixel[1], pixel[2]]), out);
+    }
+    for row in preview.chunks_exact(4 * shape.width as usize) {
+        let mut mask = vec![0u8; (shape.width as usize).div_ceil(8)];
+        for (x, pixel) in row.chunks_exact(4).enumerate() {
+            if pixel[3] >= 128 {
+                mask[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        out.extend_from_slice(&mask);
+    }
+}
+
+/// ZRLE keeps one zlib stream for the whole connection
+pub struct ZrleEncoder {
+    zlib: ZlibEncoder<Vec<u8>>,
+    tile: Vec<u8>
+}
+
+impl Default for ZrleEncoder {
+    fn default() -> Self {
+        Self {
+            zlib: ZlibEncoder::new(Vec::new(), Compression::fast()),
+            tile: Vec::new(),
+        }
+    }
+}
+
+impl ZrleEncoder {
+
+    pub fn write(&mut self, image: &Image, area: Area, format: &PixelFormat, out: &mut Vec<u8>) -> Result<()> {
+        self.tile.clear();
+        let mut pixels = Vec::with_capacity((TILE * TILE) as usize);
+        for ty in (area.y..area.y + area.height).step_by(TILE as usize) {
+            for tx in (area.x..area.x + area.width).step_by(TILE as usize) {
+                let width = TILE.min(area.x + area.width - tx);
+                let height = TILE.min(area.y + area.height - ty);
+                pixels.clear();
+                pixels.extend((ty..ty + height)
+                    .flat_map(|y| (tx..tx + width).map(move |x| (x, y)))
+                    .map(|(x, y)| format.pixel(rgb(image, x, y))));
+                encode_tile(&pixels, width as usize, format, &mut self.tile);
+            }
+        }
+        self.zlib.write_all(&self.tile)?;
+        //Flushing ends the zlib data of this rect on a byte boundary without resetting the stream
+        self.zlib.flush()?;
+        let data = std::mem::take(self.zlib.get_mut());
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(&data);
+        Ok(())
+    }
+
+}
+
+/// Uses a solid color, a packed palette of up to 16 colors or raw pixels
+fn encode_tile(pixels: &[u32], width: usize, format: &PixelFormat, out: &mut Vec<u8>) {
+    let mut palette: Vec<u32> = Vec::with_capacity(17);
+    for pixel in pixels {
+        if !palette.contains(pixel) {
+            palette.push(*pixel);
+            if palette.len() > 16 {
+                break;
+            }
+        }
+    }
+    match palette.len() {
+        1 => {
+            out.push(1);
+            format.put_cpixel(palette[0], out);
+        }
+        colors @ 2..=16 => {
+            out.push(colors as u8);
+            for color in &palette {
+                format.put_cpixel(*color, out);
+            }
+            let bits = match colors {
+                2 => 1,
+                3 | 4 => 2,
+                _ => 4
+            };
+            for row in pixels.chunks(width) {
+                let (mut byte, mut used) = (0u8, 0);
+                for pixel in row {
+                    let index = palette.iter().position(|c| c == pixel).unwrap_or_default() as u8;
+                    byte = (byte << bits) | index;
+                    used += bits;
+                    if used == 8 {
+                        out.push(byte);
+                        (byte, used) = (0, 0);
+                    }
+                }
+                if used > 0 {
+                    out.push(byte << (8 - used));
+                }
+            }
+        }
+        _ => {
+            out.push(0);
+            for pixel in pixels {
+                format.put_cpixel(*pixel, out);
+            }
+        }
+    }
+}
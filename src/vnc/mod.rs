@@ -0,0 +1,831 @@
+//! View-only VNC (RFB 3.8) server for the peeked monitor. Clients get Raw or ZRLE rects of the tiles that changed,
+//! CopyRects for the moves reported by the duplication and the pointer as Cursor/PointerPos pseudo-encodings
+//! if they support both; Otherwise the pointer is drawn into the frame.
+
+mod encoding;
+mod protocol;
+
+use std::io::{BufReader, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use serde::Deserialize;
+use error_tools::log::LogResultExt;
+use crate::cursor_shape::CursorShape;
+use crate::directx::MoveRect;
+use crate::image_io::{Area, Image};
+use encoding::{apply_move, apply_rect, changed_areas, write_cursor, write_raw, ZrleEncoder};
+use protocol::*;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct VncConfig {
+    pub enabled: bool,
+    /// Interface and port to listen on
+    pub address: String,
+    /// Required unless the server only listens on loopback. Only the first eight characters are used
+    pub password: Option<String>,
+    pub fps: u32,
+    /// Desktop name shown by the clients
+    pub name: String
+}
+
+impl Default for VncConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: String::from("127.0.0.1:5900"),
+            password: None,
+            fps: 30,
+            name: String::from("Display Peek"),
+        }
+    }
+}
+
+/// The most recent frame and pointer state. Every frame has a new sequence number
+#[derive(Default)]
+struct FrameState {
+    sequence: u64,
+    image: Option<Arc<Image>>,
+    /// Moves that turn the previous frame into this one
+    moves: Vec<MoveRect>,
+    cursor_sequence: u64,
+    cursor: Option<Arc<CursorShape>>,
+    pointer: Option<(i32, i32)>
+}
+
+#[derive(Default)]
+struct Shared {
+    stop: AtomicBool,
+    clients: AtomicUsize,
+    state: Mutex<FrameState>
+}
+
+impl Shared {
+    fn state(&self) -> Result<MutexGuard<'_, FrameState>> {
+        self.state.lock().map_err(|_| anyhow!("Frame lock poisoned"))
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct Encodings {
+    zrle: bool,
+    copy_rect: bool,
+    cursor: bool,
+    pointer_pos: bool,
+    desktop_size: bool
+}
+
+impl Encodings {
+    fn new(encodings: &[i32]) -> Self {
+        Self {
+            zrle: encodings.contains(&ENCODING_ZRLE),
+            copy_rect: encodings.contains(&ENCODING_COPY_RECT),
+            cursor: encodings.contains(&ENCODING_CURSOR),
+            pointer_pos: encodings.contains(&ENCODING_POINTER_POS),
+            desktop_size: encodings.contains(&ENCODING_DESKTOP_SIZE),
+        }
+    }
+
+    /// A client without pointer positions would draw the cursor at its own mouse position
+    fn remote_cursor(self) -> bool {
+        self.cursor && self.pointer_pos
+    }
+}
+
+/// What the client currently shows
+struct Sent {
+    sequence: u64,
+    /// With the pointer drawn in unless the client draws it
+    image: Arc<Image>,
+    /// Whether all of `image` is frame `sequence`. Requests for a part of the screen leave the rest as it was
+    complete: bool
+}
+
+struct Session {
+    stream: TcpStream,
+    format: PixelFormat,
+    encodings: Encodings,
+    zrle: ZrleEncoder,
+    size: (u32, u32),
+    sent: Option<Sent>,
+    sent_cursor: u64,
+    sent_pointer: Option<(i32, i32)>,
+    request: Option<(bool, Area)>
+}
+
+impl Session {
+
+    fn handle(&mut self, message: ClientMessage) {
+        match message {
+            ClientMessage::SetPixelFormat(format) => self.format = format,
+            ClientMessage::SetEncodings(encodings) => {
+                self.encodings = Encodings::new(&encodings);
+                //The pointer has to move in or out of the frame
+                self.sent = None;
+                self.sent_cursor = 0;
+            }
+            ClientMessage::UpdateRequest { incremental, area } => self.request = Some(match self.request {
+                Some((pending, _)) => (pending && incremental, Area { x: 0, y: 0, width: self.size.0, height: self.size.1 }),
+                None => (incremental, area)
+            }),
+            ClientMessage::Ignored => {}
+        }
+    }
+
+    /// Answers the pending update request once there is something new to show
+    fn update(&mut self, shared: &Shared) -> Result<()> {
+        let Some((incremental, requested)) = self.request else { return Ok(()) };
+        let (sequence, image, moves, cursor_sequence, cursor, pointer) = {
+            let state = shared.state()?;
+            let Some(image) = state.image.clone() else { return Ok(()) };
+            (state.sequence, image, state.moves.clone(), state.cursor_sequence, state.cursor.clone(), state.pointer)
+        };
+        let mut rects = 0u16;
+        let mut out = vec![0, 0, 0, 0];
+
+        let resized = (image.width, image.height) != self.size;
+        if resized {
+            ensure!(self.encodings.desktop_size, "The client can not follow the monitor to {}x{}", image.width, image.height);
+            self.size = (image.width, image.height);
+            self.sent = None;
+            rect_header(&mut out, Area { x: 0, y: 0, width: image.width, height: image.height }, ENCODING_DESKTOP_SIZE);
+            rects += 1;
+        }
+
+        let remote_cursor = self.encodings.remote_cursor();
+        let pointer_moved = pointer != self.sent_pointer;
+        let frame = match (remote_cursor, pointer, cursor.as_ref()) {
+            (false, Some((x, y)), Some(shape)) => {
+                let mut composited = Image::clone(&image);
+                shape.composite_at_hotspot(&mut composited.rgba, composited.width, composited.height, x, y);
+                Arc::new(composited)
+            }
+            _ => image
+        };
+        let bounds = Area::of(&frame);
+        let requested = if resized { bounds } else { requested };
+        let whole = requested.contains(bounds);
+        let unchanged = self.sent
+            .as_ref()
+            .is_some_and(|sent| incremental && sent.complete && sent.sequence == sequence && (remote_cursor || !pointer_moved));
+        //The client side image after the copies, if it is needed to know what the client shows afterwards
+        let (copies, areas, shown) = match self.sent.as_ref() {
+            _ if unchanged => (Vec::new(), Vec::new(), None),
+            Some(sent) if incremental => {
+                let copies = match self.encodings.copy_rect && sent.sequence + 1 == sequence && whole {
+                    true => moves,
+                    false => Vec::new()
+                };
+                let mut base = Image::clone(&sent.image);
+                for mv in &copies {
+                    apply_move(&mut base, mv);
+                }
+                let areas = requested
+                    .intersect(bounds)
+                    .map(|within| changed_areas(&base, &frame, within))
+                    .unwrap_or_default();
+                (copies, areas, Some(base))
+            }
+            sent => {
+                let base = sent
+                    .filter(|_| !whole)
+                    .map(|sent| Image::clone(&sent.image));
+                (Vec::new(), requested.intersect(bounds).into_iter().collect(), base)
+            }
+        };
+
+        for mv in &copies {
+            let (width, height) = mv.size;
+            rect_header(&mut out, Area { x: mv.destination.0, y: mv.destination.1, width, height }, ENCODING_COPY_RECT);
+            out.extend_from_slice(&(mv.source.0 as u16).to_be_bytes());
+            out.extend_from_slice(&(mv.source.1 as u16).to_be_bytes());
+            rects += 1;
+        }
+        for area in &areas {
+            match self.encodings.zrle {
+                true => {
+                    rect_header(&mut out, *area, ENCODING_ZRLE);
+                    self.zrle.write(&frame, *area, &self.format, &mut out)?;
+                }
+                false => {
+                    rect_header(&mut out, *area, ENCODING_RAW);
+                    write_raw(&frame, *area, &self.format, &mut out);
+                }
+            }
+            rects += 1;
+        }
+        if remote_cursor && cursor_sequence != self.sent_cursor {
+            match cursor.as_ref() {
+                Some(shape) => {
+                    let hotspot = Area { x: shape.hotspot.0, y: shape.hotspot.1, width: shape.width, height: shape.height };
+                    rect_header(&mut out, hotspot, ENCODING_CURSOR);
+                    write_cursor(shape, &self.format, &mut out);
+                }
+                None => rect_header(&mut out, Area { x: 0, y: 0, width: 0, height: 0 }, ENCODING_CURSOR)
+            }
+            self.sent_cursor = cursor_sequence;
+            rects += 1;
+        }
+        if remote_cursor && pointer_moved {
+            if let Some((x, y)) = pointer {
+                rect_header(&mut out, Area { x: x.clamp(0, u16::MAX as i32) as u32, y: y.clamp(0, u16::MAX as i32) as u32, width: 0, height: 0 }, ENCODING_POINTER_POS);
+                rects += 1;
+            }
+        }
+        self.sent_pointer = pointer;
+        if !unchanged {
+            self.sent = match shown {
+                _ if whole => Some(Sent { sequence, image: frame, complete: true }),
+                Some(mut shown) => {
+                    for area in &areas {
+                        apply_rect(&mut shown, &frame, *area);
+                    }
+                    Some(Sent { sequence, image: Arc::new(shown), complete: false })
+                }
+                //Nothing is known about the parts that were not requested
+                None => None
+            };
+        }
+        if rects == 0 && incremental {
+            return Ok(());
+        }
+        out[2..4].copy_from_slice(&rects.to_be_bytes());
+        self.stream.write_all(&out)?;
+        self.request = None;
+        Ok(())
+    }
+
+}
+
+/// Waits until the main loop captured a frame for the new client
+fn wait_for_frame(shared: &Shared) -> Result<(u32, u32)> {
+    let deadline = Instant::now() + FIRST_FRAME_TIMEOUT;
+    loop {
+        if let Some(image) = shared.state()?.image.as_ref() {
+            return Ok((image.width, image.height));
+        }
+        if Instant::now() > deadline {
+            bail!("No monitor is being peeked");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn spawn_reader(stream: TcpStream) -> Receiver<ClientMessage> {
+    let (tx, rx) = channel();
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(stream);
+        loop {
+            match ClientMessage::read(&mut reader) {
+                Ok(message) => if tx.send(message).is_err() {
+                    break;
+                }
+                Err(err) => {
+                    log::debug!("VNC client disconnected: {}", err);
+                    break;
+                }
+            }
+        }
+    });
+    rx
+}
+
+fn serve_client(mut stream: TcpStream, shared: &Shared, config: &VncConfig) -> Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    let size = wait_for_frame(shared);
+    let refusal = size.as_ref().err().map(ToString::to_string);
+    handshake(&mut stream, config.password.as_deref(), refusal.as_deref())?;
+    let size = size?;
+    stream.write_all(&server_init(size.0, size.1, &config.name))?;
+    stream.set_read_timeout(None)?;
+
+    let messages = spawn_reader(stream.try_clone()?);
+    let mut session = Session {
+        stream,
+        format: PixelFormat::SERVER,
+        encodings: Encodings::default(),
+        zrle: ZrleEncoder::default(),
+        size,
+        sent: None,
+        sent_cursor: 0,
+        sent_pointer: None,
+        request: None,
+    };
+    let tick = Duration::from_secs(1) / config.fps.max(1);
+    while !shared.stop.load(Ordering::Acquire) {
+        match messages.recv_timeout(tick) {
+            Ok(message) => {
+                session.handle(message);
+                //Handles everything the client sent before answering
+                while let Ok(message) = messages.try_recv() {
+                    session.handle(message);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break
+        }
+        session.update(shared)?;
+    }
+    Ok(())
+}
+
+/// A running VNC server. Dropping it stops the server
+pub struct VncServer {
+    config: VncConfig,
+    address: SocketAddr,
+    shared: Arc<Shared>,
+    next_frame: Instant,
+    changes: u32,
+    moves: Vec<MoveRect>
+}
+
+impl VncServer {
+
+    pub fn start(config: VncConfig) -> Result<Self> {
+        let listener = TcpListener::bind(config.address.as_str())
+            .with_context(|| format!("Can not listen on {}", config.address))?;
+        let address = listener.local_addr()?;
+        ensure!(config.password.is_some() || address.ip().is_loopback(),
+            "Refusing to serve {} without a password", address);
+        let shared = Arc::new(Shared::default());
+        let server = shared.clone();
+        let client_config = config.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if server.stop.load(Ordering::Acquire) {
+                    break;
+                }
+                let Some(stream) = stream.log_ok("Can not accept VNC client") else { continue };
+                let (shared, config) = (server.clone(), client_config.clone());
+                std::thread::spawn(move || {
+                    log::debug!("VNC client connected from {:?}", stream.peer_addr().ok());
+                    shared.clients.fetch_add(1, Ordering::AcqRel);
+                    if let Err(err) = serve_client(stream, &shared, &config) {
+                        log::debug!("VNC session ended: {}", err);
+                    }
+                    shared.clients.fetch_sub(1, Ordering::AcqRel);
+                });
+            }
+            log::trace!("Stopping VNC server");
+        });
+        log::info!("Serving VNC on {}", address);
+        Ok(Self {
+            config,
+            address,
+            shared,
+            next_frame: Instant::now(),
+            changes: 1,
+            moves: Vec::new(),
+        })
+    }
+
+    pub fn config(&self) -> &VncConfig {
+        &self.config
+    }
+
+    /// Notes a new frame. `moves` have to be rotated like the captured image
+    pub fn frame_changed(&mut self, moves: impl IntoIterator<Item = MoveRect>) {
+        self.changes += 1;
+        self.moves.clear();
+        self.moves.extend(moves);
+    }
+
+    pub fn cursor_changed(&mut self, cursor: Option<CursorShape>) {
+        if let Ok(mut state) = self.shared.state() {
+            state.cursor_sequence += 1;
+            state.cursor = cursor.map(Arc::new);
+        }
+    }
+
+    /// Captures a new frame without the pointer if a client is connected, the frame changed and the frame rate allows it
+    pub fn update(&mut self, now: Instant, pointer: Option<(i32, i32)>, capture: impl FnOnce() -> Result<Image>) -> Result<()> {
+        let idle = self.shared.clients.load(Ordering::Acquire) == 0;
+        let wanted = {
+            let mut state = self.shared.state()?;
+            state.pointer = pointer;
+            if idle {
+                //A new client has to get a fresh frame
+                state.image = None;
+            }
+            !idle && (self.changes > 0 || state.image.is_none()) && now >= self.next_frame
+        };
+        if !wanted {
+            return Ok(());
+        }
+        let image = Arc::new(capture()?);
+        let mut state = self.shared.state()?;
+        state.image = Some(image);
+        state.sequence += 1;
+        //Moves are only valid relative to the previous capture if nothing else changed in between
+        state.moves = match self.changes {
+            1 => std::mem::take(&mut self.moves),
+            _ => Vec::new()
+        };
+        self.changes = 0;
+        self.next_frame = now + Duration::from_secs(1) / self.config.fps.max(1);
+        Ok(())
+    }
+
+}
+
+impl Drop for VncServer {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        // Wakes the accept loop up, which can not connect to the unspecified address itself
+        let mut address = self.address;
+        if address.ip().is_unspecified() {
+            address.set_ip(match address {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into()
+            });
+        }
+        let _ = TcpStream::connect_timeout(&address, Duration::from_millis(200));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use flate2::{Decompress, FlushDecompress};
+    use crate::directx::{CursorData, CursorType};
+    use super::*;
+
+    /// 16 bit RGB565, which clients ask for on slow connections
+    const RGB565: PixelFormat = PixelFormat {
+        bits_per_pixel: 16,
+        depth: 16,
+        big_endian: false,
+        true_colour: true,
+        max: [31, 63, 31],
+        shift: [11, 5, 0],
+    };
+
+    /// The decoding half of a client, written from the RFB spec instead of the encoder
+    struct Client {
+        stream: TcpStream,
+        format: PixelFormat,
+        zlib: Decompress,
+        screen: Image,
+        /// The shape with its hotspot, the colors of its pixels and the transparency mask
+        cursor: Option<(Area, Vec<[u8; 3]>, Vec<u8>)>,
+        pointer: Option<(u32, u32)>
+    }
+
+    impl Client {
+
+        fn bytes(&mut self, count: usize) -> Vec<u8> {
+            let mut bytes = vec![0; count];
+            self.stream.read_exact(&mut bytes).unwrap();
+            bytes
+        }
+
+        fn value(&self, bytes: &[u8]) -> u32 {
+            match self.format.big_endian {
+                false => bytes.iter().rev().fold(0, |value, b| value << 8 | *b as u32),
+                true => bytes.iter().fold(0, |value, b| value << 8 | *b as u32)
+            }
+        }
+
+        fn rgb(&self, pixel: u32) -> [u8; 3] {
+            std::array::from_fn(|i| {
+                let max = self.format.max[i] as u32;
+                (((pixel >> self.format.shift[i]) & max) * 255 + max / 2).checked_div(max).unwrap_or(0) as u8
+            })
+        }
+
+        fn set(&mut self, x: u32, y: u32, rgb: [u8; 3]) {
+            let i = 4 * (y as usize * self.screen.width as usize + x as usize);
+            self.screen.rgba[i..i + 4].copy_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+        }
+
+        fn pixel_size(&self) -> usize {
+            self.format.bits_per_pixel as usize / 8
+        }
+
+        /// 32 bit pixels with a depth of up to 24 bits lose their unused byte; The test formats keep their colors in the low bytes
+        fn cpixel_size(&self) -> usize {
+            match self.format.bits_per_pixel == 32 && self.format.depth <= 24 {
+                true => 3,
+                false => self.pixel_size()
+            }
+        }
+
+        /// Reads one FramebufferUpdate and returns the encodings of its rects
+        fn read_update(&mut self) -> Vec<i32> {
+            let header = self.bytes(4);
+            assert_eq!(header[0], 0, "not a FramebufferUpdate");
+            let count = u16::from_be_bytes([header[2], header[3]]);
+            let mut encodings = Vec::new();
+            for _ in 0..count {
+                let rect = self.bytes(12);
+                let field = |i: usize| u16::from_be_bytes([rect[2 * i], rect[2 * i + 1]]) as u32;
+                let area = Area { x: field(0), y: field(1), width: field(2), height: field(3) };
+                let encoding = i32::from_be_bytes([rect[8], rect[9], rect[10], rect[11]]);
+                match encoding {
+                    ENCODING_RAW => {
+                        let data = self.bytes(self.pixel_size() * (area.width * area.height) as usize);
+                        let pixels: Vec<u32> = data.chunks_exact(self.pixel_size()).map(|p| self.value(p)).collect();
+                        self.fill(area, &pixels);
+                    }
+                    ENCODING_COPY_RECT => {
+                        let source = self.bytes(4);
+                        let (sx, sy) = (u16::from_be_bytes([source[0], source[1]]) as u32, u16::from_be_bytes([source[2], source[3]]) as u32);
+                        let old = self.screen.clone();
+                        for y in 0..area.height {
+                            for x in 0..area.width {
+                                let i = 4 * ((sy + y) as usize * old.width as usize + (sx + x) as usize);
+                                self.set(area.x + x, area.y + y, [old.rgba[i], old.rgba[i + 1], old.rgba[i + 2]]);
+                            }
+                        }
+                    }
+                    ENCODING_ZRLE => self.read_zrle(area),
+                    ENCODING_CURSOR => {
+                        let data = self.bytes(self.pixel_size() * (area.width * area.height) as usize);
+                        let colors = data.chunks_exact(self.pixel_size()).map(|p| self.rgb(self.value(p))).collect();
+                        let mask = self.bytes(area.width.div_ceil(8) as usize * area.height as usize);
+                        self.cursor = Some((area, colors, mask));
+                    }
+                    ENCODING_POINTER_POS => self.pointer = Some((area.x, area.y)),
+                    other => panic!("Unexpected encoding {}", other)
+                }
+                encodings.push(encoding);
+            }
+            encodings
+        }
+
+        /// Writes pixels in rows across `area`
+        fn fill(&mut self, area: Area, pixels: &[u32]) {
+            assert_eq!(pixels.len(), (area.width * area.height) as usize);
+            for (i, pixel) in pixels.iter().enumerate() {
+                let (x, y) = (i as u32 % area.width, i as u32 / area.width);
+                self.set(area.x + x, area.y + y, self.rgb(*pixel));
+            }
+        }
+
+        fn read_zrle(&mut self, area: Area) {
+            let length = u32::from_be_bytes(self.bytes(4).try_into().unwrap()) as usize;
+            let compressed = self.bytes(length);
+            //One zlib stream spans all rects of the connection
+            let mut data = Vec::with_capacity(64 * 1024);
+            let mut input = compressed.as_slice();
+            loop {
+                let consumed = self.zlib.total_in();
+                self.zlib.decompress_vec(input, &mut data, FlushDecompress::Sync).unwrap();
+                input = &input[(self.zlib.total_in() - consumed) as usize..];
+                if input.is_empty() && data.len() < data.capacity() {
+                    break;
+                }
+                data.reserve(64 * 1024);
+            }
+
+            let mut data = data.as_slice();
+            let cpixel_size = self.cpixel_size();
+            let mut take = |count: usize| {
+                let (bytes, rest) = data.split_at(count);
+                data = rest;
+                bytes.to_vec()
+            };
+            for ty in (area.y..area.y + area.height).step_by(64) {
+                for tx in (area.x..area.x + area.width).step_by(64) {
+                    let tile = Area { x: tx, y: ty, width: 64.min(area.x + area.width - tx), height: 64.min(area.y + area.height - ty) };
+                    let count = (tile.width * tile.height) as usize;
+                    let subencoding = take(1)[0];
+                    let pixels: Vec<u32> = match subencoding {
+                        0 => take(count * cpixel_size).chunks_exact(cpixel_size).map(|p| self.value(p)).collect(),
+                        1 => vec![self.value(&take(cpixel_size)); count],
+                        colors @ 2..=16 => {
+                            let palette: Vec<u32> = take(colors as usize * cpixel_size).chunks_exact(cpixel_size).map(|p| self.value(p)).collect();
+                            let bits = match colors {
+                                2 => 1,
+                                3 | 4 => 2,
+                                _ => 4
+                            };
+                            let row_size = (tile.width as usize * bits).div_ceil(8);
+                            let packed = take(row_size * tile.height as usize);
+                            packed
+                                .chunks_exact(row_size)
+                                .flat_map(|row| (0..tile.width as usize).map(move |x| {
+                                    let bit = x * bits;
+                                    (row[bit / 8] >> (8 - bits - bit % 8)) & ((1 << bits) - 1)
+                                }))
+                                .map(|index| palette[index as usize])
+                                .collect()
+                        }
+                        other => panic!("Unexpected ZRLE subencoding {}", other)
+                    };
+                    self.fill(tile, &pixels);
+                }
+            }
+            assert!(data.is_empty(), "{} bytes are left over", data.len());
+        }
+
+        /// Compares every pixel with `frame`, allowing for the precision of the pixel format
+        fn assert_shows(&self, frame: &Image, tolerance: u8) {
+            assert_eq!((self.screen.width, self.screen.height), (frame.width, frame.height));
+            for (i, (shown, expected)) in self.screen.rgba.chunks_exact(4).zip(frame.rgba.chunks_exact(4)).enumerate() {
+                let close = (0..3).all(|c| shown[c].abs_diff(expected[c]) <= tolerance);
+                assert!(close, "pixel {}x{} is {:?} instead of {:?}", i as u32 % frame.width, i as u32 / frame.width, shown, expected);
+            }
+        }
+
+    }
+
+    fn connect(format: PixelFormat, encodings: &[i32], width: u32, height: u32) -> (Session, Client) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let session = Session {
+            stream: listener.accept().unwrap().0,
+            format,
+            encodings: Encodings::new(encodings),
+            zrle: ZrleEncoder::default(),
+            size: (width, height),
+            sent: None,
+            sent_cursor: 0,
+            sent_pointer: None,
+            request: None,
+        };
+        let client = Client {
+            stream,
+            format,
+            zlib: Decompress::new(true),
+            screen: Image { width, height, rgba: vec![0; 4 * (width * height) as usize] },
+            cursor: None,
+            pointer: None,
+        };
+        (session, client)
+    }
+
+    /// Makes `image` the next captured frame
+    fn capture(shared: &Shared, image: &Image, moves: Vec<MoveRect>) {
+        let mut state = shared.state().unwrap();
+        state.sequence += 1;
+        state.image = Some(Arc::new(image.clone()));
+        state.moves = moves;
+    }
+
+    fn request(session: &mut Session, shared: &Shared, incremental: bool, area: Area) {
+        session.handle(ClientMessage::UpdateRequest { incremental, area });
+        session.update(shared).unwrap();
+    }
+
+    /// Tiles with one, two, three, a dozen and many colors; The tiles at the right and bottom edge are cut off
+    fn test_image(width: u32, height: u32, seed: u32) -> Image {
+        let rgba = (0..height)
+            .flat_map(|y| (0..width).map(move |x| {
+                let [r, g, b] = match (x / 64 + y / 64 * 3 + seed) % 5 {
+                    0 => [seed * 40 % 256, 90, 200],
+                    1 => [255 * ((x + y) % 2); 3],
+                    2 => [[255, 0, 0], [0, 255, 0], [0, 0, 255]][(x % 3) as usize],
+                    3 => [(x + y) % 12 * 20, 255 - (x + y) % 12 * 20, 128],
+                    _ => [(x * 7 + y * 13 + seed) % 256, (x * x + y) % 256, (x ^ y) % 256]
+                };
+                [r as u8, g as u8, b as u8, 255]
+            }))
+            .flatten()
+            .collect();
+        Image { width, height, rgba }
+    }
+
+    #[test]
+    fn raw_updates_show_the_frame() {
+        let shared = Shared::default();
+        let frame = test_image(200, 150, 0);
+        capture(&shared, &frame, Vec::new());
+        let (mut session, mut client) = connect(PixelFormat::SERVER, &[], 200, 150);
+        request(&mut session, &shared, false, Area::of(&frame));
+        assert_eq!(client.read_update(), vec![ENCODING_RAW]);
+        client.assert_shows(&frame, 0);
+
+        //Nothing changed, so the request waits for the next frame
+        request(&mut session, &shared, true, Area::of(&frame));
+        assert!(session.request.is_some());
+        let next = test_image(200, 150, 1);
+        capture(&shared, &next, Vec::new());
+        session.update(&shared).unwrap();
+        assert!(client.read_update().iter().all(|encoding| *encoding == ENCODING_RAW));
+        client.assert_shows(&next, 0);
+    }
+
+    #[test]
+    fn zrle_updates_show_the_frame() {
+        for (format, tolerance) in [(RGB565, 5), (PixelFormat::SERVER, 0)] {
+            let shared = Shared::default();
+            let frame = test_image(200, 150, 0);
+            capture(&shared, &frame, Vec::new());
+            let (mut session, mut client) = connect(format, &[ENCODING_ZRLE], 200, 150);
+            request(&mut session, &shared, false, Area::of(&frame));
+            assert_eq!(client.read_update(), vec![ENCODING_ZRLE]);
+            client.assert_shows(&frame, tolerance);
+
+            //The later rects continue the zlib stream
+            let next = test_image(200, 150, 3);
+            capture(&shared, &next, Vec::new());
+            request(&mut session, &shared, true, Area::of(&frame));
+            assert!(client.read_update().iter().all(|encoding| *encoding == ENCODING_ZRLE));
+            client.assert_shows(&next, tolerance);
+        }
+    }
+
+    #[test]
+    fn copy_rects_move_what_the_client_shows() {
+        let shared = Shared::default();
+        let frame = test_image(200, 150, 0);
+        capture(&shared, &frame, Vec::new());
+        let (mut session, mut client) = connect(PixelFormat::SERVER, &[ENCODING_COPY_RECT], 200, 150);
+        request(&mut session, &shared, false, Area::of(&frame));
+        client.read_update();
+
+        let mv = MoveRect { source: (10, 20), destination: (90, 60), size: (100, 70) };
+        let mut moved = frame.clone();
+        for y in 0..70 {
+            let source = 4 * ((20 + y) * 200 + 10);
+            let destination = 4 * ((60 + y) * 200 + 90);
+            moved.rgba[destination..destination + 400].copy_from_slice(&frame.rgba[source..source + 400]);
+        }
+        capture(&shared, &moved, vec![mv]);
+        request(&mut session, &shared, true, Area::of(&frame));
+        assert_eq!(client.read_update(), vec![ENCODING_COPY_RECT]);
+        client.assert_shows(&moved, 0);
+    }
+
+    #[test]
+    fn partial_requests_leave_the_rest_pending() {
+        let shared = Shared::default();
+        let (first, second) = (test_image(200, 150, 0), test_image(200, 150, 2));
+        let left = Area { x: 0, y: 0, width: 100, height: 150 };
+        capture(&shared, &first, Vec::new());
+        let (mut session, mut client) = connect(PixelFormat::SERVER, &[], 200, 150);
+        request(&mut session, &shared, false, Area::of(&first));
+        client.read_update();
+
+        capture(&shared, &second, Vec::new());
+        request(&mut session, &shared, true, left);
+        client.read_update();
+        let mut expected = first.clone();
+        apply_rect(&mut expected, &second, left);
+        client.assert_shows(&expected, 0);
+        //The right half still shows the first frame
+        request(&mut session, &shared, true, Area::of(&first));
+        client.read_update();
+        client.assert_shows(&second, 0);
+
+        //A client that only ever asked for a part has nothing to compare the rest with
+        let (mut session, mut client) = connect(PixelFormat::SERVER, &[], 200, 150);
+        request(&mut session, &shared, false, left);
+        client.read_update();
+        request(&mut session, &shared, true, Area::of(&first));
+        client.read_update();
+        client.assert_shows(&second, 0);
+    }
+
+    #[test]
+    fn cursors_are_sent_as_pseudo_encodings() {
+        let shape = CursorShape::decode(&CursorData {
+            cursor_type: CursorType::Color,
+            width: 3,
+            height: 2,
+            pitch: 12,
+            hotspot: (1, 1),
+            data: vec![
+                0, 0, 255, 255,  0, 255, 0, 255,  0, 0, 0, 0,
+                255, 0, 0, 255,  10, 20, 30, 255,  0, 0, 0, 0
+            ],
+        }).unwrap();
+        let shared = Shared::default();
+        let frame = test_image(200, 150, 0);
+        capture(&shared, &frame, Vec::new());
+        {
+            let mut state = shared.state().unwrap();
+            state.cursor = Some(Arc::new(shape.clone()));
+            state.cursor_sequence = 1;
+            state.pointer = Some((30, 40));
+        }
+
+        let (mut session, mut client) = connect(PixelFormat::SERVER, &[ENCODING_CURSOR, ENCODING_POINTER_POS], 200, 150);
+        request(&mut session, &shared, false, Area::of(&frame));
+        assert_eq!(client.read_update(), vec![ENCODING_RAW, ENCODING_CURSOR, ENCODING_POINTER_POS]);
+        client.assert_shows(&frame, 0);
+        assert_eq!(client.pointer, Some((30, 40)));
+        let (hotspot, colors, mask) = client.cursor.unwrap();
+        assert_eq!(hotspot, Area { x: 1, y: 1, width: 3, height: 2 });
+        assert_eq!(colors, vec![[255, 0, 0], [0, 255, 0], [0, 0, 0], [0, 0, 255], [30, 20, 10], [0, 0, 0]]);
+        assert_eq!(mask, vec![0b1100_0000, 0b1100_0000]);
+
+        //Without pointer positions the pointer is drawn into the frame
+        let (mut session, mut client) = connect(PixelFormat::SERVER, &[ENCODING_CURSOR], 200, 150);
+        request(&mut session, &shared, false, Area::of(&frame));
+        assert_eq!(client.read_update(), vec![ENCODING_RAW]);
+        let mut composited = frame.clone();
+        shape.composite_at_hotspot(&mut composited.rgba, 200, 150, 30, 40);
+        client.assert_shows(&composited, 0);
+    }
+}
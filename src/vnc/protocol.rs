@@ -0,0 +1,453 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{bail, ensure, Result};
+use des::Des;
+use des::cipher::{BlockEncrypt, KeyInit};
+use des::cipher::generic_array::GenericArray;
+use crate::image_io::Area;
+
+pub const ENCODING_RAW: i32 = 0;
+pub const ENCODING_COPY_RECT: i32 = 1;
+pub const ENCODING_ZRLE: i32 = 16;
+pub const ENCODING_CURSOR: i32 = -239;
+pub const ENCODING_DESKTOP_SIZE: i32 = -223;
+pub const ENCODING_POINTER_POS: i32 = -232;
+
+const SECURITY_NONE: u8 = 1;
+const SECURITY_VNC: u8 = 2;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PixelFormat {
+    pub bits_per_pixel: u8,
+    pub depth: u8,
+    pub big_endian: bool,
+    pub true_colour: bool,
+    pub max: [u16; 3],
+    pub shift: [u8; 3]
+}
+
+impl PixelFormat {
+
+    /// 32 bit little endian BGRX
+    pub const SERVER: Self = Self {
+        bits_per_pixel: 32,
+        depth: 24,
+        big_endian: false,
+        true_colour: true,
+        max: [255; 3],
+        shift: [16, 8, 0],
+    };
+
+    fn read(bytes: [u8; 16]) -> Result<Self> {
+        let u16_at = |i: usize| u16::from_be_bytes([bytes[i], bytes[i + 1]]);
+        let format = Self {
+            bits_per_pixel: bytes[0],
+            depth: bytes[1],
+            big_endian: bytes[2] != 0,
+            true_colour: bytes[3] != 0,
+            max: [u16_at(4), u16_at(6), u16_at(8)],
+            shift: [bytes[10], bytes[11], bytes[12]],
+        };
+        ensure!(format.true_colour, "Colour maps are not supported");
+        ensure!(matches!(format.bits_per_pixel, 8 | 16 | 32), "Unsupported pixel size of {} bits", format.bits_per_pixel);
+        ensure!(format.shift.iter().all(|s| *s < 32), "Invalid color shift");
+        Ok(format)
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.bits_per_pixel, self.depth, self.big_endian as u8, self.true_colour as u8]);
+        for max in self.max {
+            out.extend_from_slice(&max.to_be_bytes());
+        }
+        out.extend_from_slice(&self.shift);
+        out.extend_from_slice(&[0; 3]);
+    }
+
+    pub fn pixel(&self, rgb: [u8; 3]) -> u32 {
+        rgb.iter()
+            .zip(self.max.iter().zip(self.shift))
+            .fold(0, |pixel, (c, (max, shift))| pixel | ((*c as u32 * *max as u32 + 127) / 255) << shift)
+    }
+
+    pub fn put(&self, pixel: u32, out: &mut Vec<u8>) {
+        match (self.bits_per_pixel, self.big_endian) {
+            (8, _) => out.push(pixel as u8),
+            (16, false) => out.extend_from_slice(&(pixel as u16).to_le_bytes()),
+            (16, true) => out.extend_from_slice(&(pixel as u16).to_be_bytes()),
+            (_, false) => out.extend_from_slice(&pixel.to_le_bytes()),
+            (_, true) => out.extend_from_slice(&pixel.to_be_bytes())
+        }
+    }
+
+    /// ZRLE drops the unused byte of 32 bit pixels whose colors fit into three bytes
+    pub fn put_cpixel(&self, pixel: u32, out: &mut Vec<u8>) {
+        let channels = || self.max.iter().zip(self.shift).map(|(max, shift)| (*max as u64) << shift);
+        let compact = self.true_colour && self.bits_per_pixel == 32 && self.depth <= 24;
+        let fits_low = compact && channels().all(|c| c < 1 << 24);
+        let fits_high = compact && channels().all(|c| c & 0xFF == 0 && c < 1 << 32);
+        let bytes = match self.big_endian {
+            false => pixel.to_le_bytes(),
+            true => pixel.to_be_bytes()
+        };
+        match (fits_low, fits_high, self.big_endian) {
+            (true, _, false) | (false, true, true) => out.extend_from_slice(&bytes[..3]),
+            (true, _, true) | (false, true, false) => out.extend_from_slice(&bytes[1..]),
+            _ => self.put(pixel, out)
+        }
+    }
+
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ClientMessage {
+    SetPixelFormat(PixelFormat),
+    SetEncodings(Vec<i32>),
+    UpdateRequest { incremental: bool, area: Area },
+    /// Input and clipboard messages of a view-only session
+    Ignored
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u16(reader: &mut impl Read) -> Result<u16> {
+    Ok(u16::from_be_bytes(read_array(reader)?))
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    Ok(u32::from_be_bytes(read_array(reader)?))
+}
+
+impl ClientMessage {
+
+    pub fn read(reader: &mut impl Read) -> Result<Self> {
+        let [kind] = read_array(reader)?;
+        Ok(match kind {
+            0 => {
+                read_array::<3>(reader)?;
+                ClientMessage::SetPixelFormat(PixelFormat::read(read_array(reader)?)?)
+            }
+            2 => {
+                read_array::<1>(reader)?;
+                let count = read_u16(reader)?;
+                let encodings = (0..count)
+                    .map(|_| Ok(i32::from_be_bytes(read_array(reader)?)))
+                    .collect::<Result<_>>()?;
+                ClientMessage::SetEncodings(encodings)
+            }
+            3 => {
+                let [incremental] = read_array(reader)?;
+                let [x, y, width, height] = [read_u16(reader)?, read_u16(reader)?, read_u16(reader)?, read_u16(reader)?];
+                ClientMessage::UpdateRequest {
+                    incremental: incremental != 0,
+                    area: Area { x: x as u32, y: y as u32, width: width as u32, height: height as u32 },
+                }
+            }
+            4 => {
+                read_array::<7>(reader)?;
+                ClientMessage::Ignored
+            }
+            5 => {
+                read_array::<5>(reader)?;
+                ClientMessage::Ignored
+            }
+            6 => {
+                read_array::<3>(reader)?;
+                let length = read_u32(reader)?;
+                std::io::copy(&mut reader.take(length as u64), &mut std::io::sink())?;
+                ClientMessage::Ignored
+            }
+            other => bail!("Unknown client message {}", other)
+        })
+    }
+
+}
+
+fn write_string(out: &mut Vec<u8>, text: &str) {
+    out.extend_from_slice(&(text.len() as u32).to_be_bytes());
+    out.extend_from_slice(text.as_bytes());
+}
+
+/// Random bytes from the OS seeded hasher keys of the standard library
+fn challenge() -> [u8; 16] {
+    let state = RandomState::new();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut bytes = [0u8; 16];
+    for (i, chunk) in bytes.chunks_exact_mut(8).enumerate() {
+        let mut hasher = state.build_hasher();
+        hasher.write_usize(i);
+        hasher.write_u128(nanos);
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    bytes
+}
+
+/// VNC authentication encrypts the challenge with DES, using the first eight bytes of the password with reversed bits as key
+pub fn encrypt_challenge(password: &str, challenge: [u8; 16]) -> [u8; 16] {
+    let mut key = [0u8; 8];
+    for (k, b) in key.iter_mut().zip(password.bytes()) {
+        *k = b.reverse_bits();
+    }
+    let cipher = Des::new(GenericArray::from_slice(&key));
+    let mut response = challenge;
+    for block in response.chunks_exact_mut(8) {
+        cipher.encrypt_block(GenericArray::from_mut_slice(block));
+    }
+    response
+}
+
+fn refuse(stream: &mut (impl Read + Write), reason: &str) -> Result<()> {
+    let mut out = vec![0];
+    write_string(&mut out, reason);
+    stream.write_all(&out)?;
+    bail!("Refused client: {}", reason)
+}
+
+/// Negotiates RFB 3.8 up to the client init message. A `refusal` is sent to the client instead of the security types
+pub fn handshake(stream: &mut (impl Read + Write), password: Option<&str>, refusal: Option<&str>) -> Result<()> {
+    stream.write_all(b"RFB 003.008\n")?;
+    let version: [u8; 12] = read_array(stream)?;
+    let minor = std::str::from_utf8(&version)
+        .ok()
+        .filter(|v| v.starts_with("RFB 003.") && v.ends_with('\n'))
+        .and_then(|v| v[8..11].parse::<u32>().ok());
+    match minor {
+        Some(minor) if minor >= 8 => {}
+        _ => return refuse(stream, "Only RFB 3.8 is supported")
+    }
+    if let Some(reason) = refusal {
+        return refuse(stream, reason);
+    }
+    let security = if password.is_some() { SECURITY_VNC } else { SECURITY_NONE };
+    stream.write_all(&[1, security])?;
+    let [chosen] = read_array(stream)?;
+    ensure!(chosen == security, "The client chose the unsupported security type {}", chosen);
+    if let Some(password) = password {
+        let challenge = challenge();
+        stream.write_all(&challenge)?;
+        let response: [u8; 16] = read_array(stream)?;
+        if response != encrypt_challenge(password, challenge) {
+            let mut out = 1u32.to_be_bytes().to_vec();
+            write_string(&mut out, "Wrong password");
+            stream.write_all(&out)?;
+            bail!("A client sent a wrong password");
+        }
+    }
+    stream.write_all(&0u32.to_be_bytes())?;
+    //The shared flag does not matter as every session is view-only
+    read_array::<1>(stream)?;
+    Ok(())
+}
+
+pub fn server_init(width: u32, height: u32, name: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&(width as u16).to_be_bytes());
+    out.extend_from_slice(&(height as u16).to_be_bytes());
+    PixelFormat::SERVER.write(&mut out);
+    write_string(&mut out, name);
+    out
+}
+
+pub fn rect_header(out: &mut Vec<u8>, area: Area, encoding: i32) {
+    for value in [area.x, area.y, area.width, area.height] {
+        out.extend_from_slice(&(value as u16).to_be_bytes());
+    }
+    out.extend_from_slice(&encoding.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use super::*;
+
+    /// An in-memory connection. Whenever the server runs out of input, `client` gets everything the server wrote
+    /// since it was last called and returns its answer; An empty answer closes the connection
+    struct Loopback<F: FnMut(&[u8]) -> Vec<u8>> {
+        client: F,
+        input: VecDeque<u8>,
+        output: Vec<u8>
+    }
+
+    impl<F: FnMut(&[u8]) -> Vec<u8>> Read for Loopback<F> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.input.is_empty() {
+                self.input.extend((self.client)(&self.output));
+                self.output.clear();
+            }
+            self.input.read(buf)
+        }
+    }
+
+    impl<F: FnMut(&[u8]) -> Vec<u8>> Write for Loopback<F> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn loopback<F: FnMut(&[u8]) -> Vec<u8>>(client: F) -> Loopback<F> {
+        Loopback { client, input: VecDeque::new(), output: Vec::new() }
+    }
+
+    fn reason(bytes: &[u8]) -> &str {
+        let length = u32::from_be_bytes(bytes[..4].try_into().unwrap()) as usize;
+        std::str::from_utf8(&bytes[4..4 + length]).unwrap()
+    }
+
+    fn client_messages() -> Vec<u8> {
+        let mut out = vec![0, 0, 0, 0];
+        PixelFormat { bits_per_pixel: 16, depth: 16, big_endian: true, true_colour: true, max: [31, 63, 31], shift: [11, 5, 0] }
+            .write(&mut out);
+        out.extend_from_slice(&[2, 0, 0, 3]);
+        for encoding in [ENCODING_ZRLE, ENCODING_RAW, ENCODING_CURSOR] {
+            out.extend_from_slice(&encoding.to_be_bytes());
+        }
+        out.extend_from_slice(&[3, 1, 0, 10, 0, 20, 0, 30, 0, 40]);
+        //A key event, a pointer event and a clipboard update
+        out.extend_from_slice(&[4, 1, 0, 0, 0, 0, 0, 0x61]);
+        out.extend_from_slice(&[5, 0, 0, 1, 0, 2]);
+        out.extend_from_slice(&[6, 0, 0, 0, 0, 0, 0, 5]);
+        out.extend_from_slice(b"hello");
+        out
+    }
+
+    #[test]
+    fn password_sessions_reach_the_client_messages() {
+        let mut step = 0;
+        let mut stream = loopback(|server: &[u8]| {
+            step += 1;
+            match step {
+                1 => {
+                    assert_eq!(server, b"RFB 003.008\n");
+                    b"RFB 003.008\n".to_vec()
+                }
+                2 => {
+                    assert_eq!(server, [1, SECURITY_VNC]);
+                    vec![SECURITY_VNC]
+                }
+                3 => encrypt_challenge("secret", server.try_into().unwrap()).to_vec(),
+                4 => {
+                    assert_eq!(server, 0u32.to_be_bytes());
+                    let mut answer = vec![1];
+                    answer.extend(client_messages());
+                    answer
+                }
+                _ => Vec::new()
+            }
+        });
+        handshake(&mut stream, Some("secret"), None).unwrap();
+        let messages = (0..6)
+            .map(|_| ClientMessage::read(&mut stream).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, vec![
+            ClientMessage::SetPixelFormat(PixelFormat { bits_per_pixel: 16, depth: 16, big_endian: true, true_colour: true, max: [31, 63, 31], shift: [11, 5, 0] }),
+            ClientMessage::SetEncodings(vec![ENCODING_ZRLE, ENCODING_RAW, ENCODING_CURSOR]),
+            ClientMessage::UpdateRequest { incremental: true, area: Area { x: 10, y: 20, width: 30, height: 40 } },
+            ClientMessage::Ignored,
+            ClientMessage::Ignored,
+            ClientMessage::Ignored
+        ]);
+        assert!(ClientMessage::read(&mut stream).is_err(), "the client closed the connection");
+    }
+
+    #[test]
+    fn sessions_without_password_skip_the_challenge() {
+        let mut step = 0;
+        let mut stream = loopback(|server: &[u8]| {
+            step += 1;
+            match step {
+                1 => b"RFB 003.008\n".to_vec(),
+                2 => {
+                    assert_eq!(server, [1, SECURITY_NONE]);
+                    vec![SECURITY_NONE]
+                }
+                3 => {
+                    assert_eq!(server, 0u32.to_be_bytes());
+                    vec![0]
+                }
+                _ => Vec::new()
+            }
+        });
+        handshake(&mut stream, None, None).unwrap();
+        assert_eq!(step, 3);
+    }
+
+    #[test]
+    fn wrong_passwords_are_refused() {
+        let mut step = 0;
+        let mut refusal = Vec::new();
+        let mut stream = loopback(|server: &[u8]| {
+            step += 1;
+            match step {
+                1 => b"RFB 003.008\n".to_vec(),
+                2 => vec![SECURITY_VNC],
+                3 => encrypt_challenge("guess", server.try_into().unwrap()).to_vec(),
+                _ => {
+                    refusal = server.to_vec();
+                    Vec::new()
+                }
+            }
+        });
+        assert!(handshake(&mut stream, Some("secret"), None).is_err());
+        let written = stream.output;
+        assert_eq!(written[..4], 1u32.to_be_bytes());
+        assert_eq!(reason(&written[4..]), "Wrong password");
+        assert!(refusal.is_empty(), "the server must not read after refusing");
+    }
+
+    #[test]
+    fn old_versions_wrong_security_types_and_refusals_end_the_handshake() {
+        let mut stream = loopback(|_: &[u8]| b"RFB 003.003\n".to_vec());
+        assert!(handshake(&mut stream, None, None).is_err());
+        assert_eq!(stream.output[0], 0);
+        assert_eq!(reason(&stream.output[1..]), "Only RFB 3.8 is supported");
+
+        let mut stream = loopback(|_: &[u8]| b"RFB 003.008\n".to_vec());
+        assert!(handshake(&mut stream, None, Some("Too many clients")).is_err());
+        assert_eq!(stream.output[0], 0);
+        assert_eq!(reason(&stream.output[1..]), "Too many clients");
+
+        let mut step = 0;
+        let mut stream = loopback(|_: &[u8]| {
+            step += 1;
+            match step {
+                1 => b"RFB 003.008\n".to_vec(),
+                2 => vec![SECURITY_NONE],
+                _ => Vec::new()
+            }
+        });
+        assert!(handshake(&mut stream, Some("secret"), None).is_err(), "the client must not skip the password");
+    }
+
+    #[test]
+    fn broken_client_messages_are_rejected() {
+        let read = |bytes: &[u8]| ClientMessage::read(&mut &bytes[..]);
+        assert!(read(&[7]).is_err(), "unknown message");
+        assert!(read(&[2, 0, 0, 2, 0, 0, 0, 0]).is_err(), "truncated encodings");
+        assert!(read(&[3, 0, 0, 0]).is_err(), "truncated update request");
+
+        let format = |edit: fn(&mut PixelFormat)| {
+            let mut pixel_format = PixelFormat::SERVER;
+            edit(&mut pixel_format);
+            let mut out = vec![0, 0, 0, 0];
+            pixel_format.write(&mut out);
+            out
+        };
+        assert_eq!(read(&format(|_| {})).unwrap(), ClientMessage::SetPixelFormat(PixelFormat::SERVER));
+        assert!(read(&format(|f| f.true_colour = false)).is_err(), "colour maps");
+        assert!(read(&format(|f| f.bits_per_pixel = 24)).is_err(), "24 bit pixels");
+        assert!(read(&format(|f| f.shift[1] = 32)).is_err(), "shift past the pixel");
+    }
+}
@@ -0,0 +1,550 @@
+//! Opt-in HTTP viewer for the mirrored monitor. `/` serves a page with the live mirror, `/stream` pushes jpeg frames
+//! as `multipart/x-mixed-replace` (MJPEG), `/monitors` lists the peekable monitors and `/peek?monitor=...` shows one.
+//! If a token is configured every request has to carry it as `?token=...` or as `Authorization: Bearer ...`.
+
+use std::io::{BufRead, BufReader, Take, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Sender, SyncSender, TrySendError};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use error_tools::log::LogResultExt;
+use crate::image_io::Image;
+use crate::ipc::Response;
+
+const VIEWER_PAGE: &str = include_str!("../resources/viewer.html");
+const BOUNDARY: &str = "frame";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Request line and headers together; Nothing the viewer sends comes close
+const MAX_REQUEST: u64 = 8 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct WebConfig {
+    pub enabled: bool,
+    /// Interface and port to listen on
+    pub address: String,
+    /// Required unless the server only listens on loopback
+    pub token: Option<String>,
+    pub fps: u32,
+    /// Jpeg quality from 1 to 100
+    pub quality: u8,
+    pub cursor: bool
+}
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: String::from("127.0.0.1:8765"),
+            token: None,
+            fps: 10,
+            quality: 75,
+            cursor: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct MonitorList {
+    monitors: Vec<String>,
+    /// Monitor of the last streamed frame
+    current: Option<String>
+}
+
+#[derive(Default)]
+struct Shared {
+    stop: AtomicBool,
+    viewers: AtomicUsize,
+    /// Set when a viewer connects so it gets a frame even if the monitor is idle
+    refresh: AtomicBool,
+    frame: Mutex<(u64, Option<Arc<Vec<u8>>>)>,
+    new_frame: Condvar,
+    monitors: Mutex<MonitorList>
+}
+
+/// Counts a viewer as long as it is alive
+struct ViewerGuard<'a>(&'a Shared);
+
+impl<'a> ViewerGuard<'a> {
+    fn new(shared: &'a Shared) -> Self {
+        shared.viewers.fetch_add(1, Ordering::AcqRel);
+        shared.refresh.store(true, Ordering::Release);
+        Self(shared)
+    }
+}
+
+impl Drop for ViewerGuard<'_> {
+    fn drop(&mut self) {
+        self.0.viewers.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    bearer: Option<String>
+}
+
+impl Request {
+
+    /// Reads the request line and the headers, but never more than `MAX_REQUEST` bytes
+    fn read(reader: impl BufRead) -> Result<Self> {
+        let mut reader = reader.take(MAX_REQUEST);
+        let mut line = String::new();
+        read_line(&mut reader, &mut line)?;
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            bail!("Malformed request line");
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let query = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                Ok((percent_decode(key)?, percent_decode(value)?))
+            })
+            .collect::<Result<_>>()?;
+        let mut bearer = None;
+        let mut header = String::new();
+        loop {
+            read_line(&mut reader, &mut header)?;
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("authorization") {
+                    bearer = value.trim().strip_prefix("Bearer ").map(str::to_string);
+                }
+            }
+        }
+        Ok(Self { method: method.to_string(), path: path.to_string(), query, bearer })
+    }
+
+    fn param(&self, key: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn authorized(&self, token: Option<&str>) -> bool {
+        match token {
+            None => true,
+            Some(token) => self.param("token").or(self.bearer.as_deref())
+                .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+        }
+    }
+
+}
+
+/// Replaces `line` with the next line. Fails if the connection closes or the request gets too large before the line ends
+fn read_line(reader: &mut Take<impl BufRead>, line: &mut String) -> Result<()> {
+    line.clear();
+    reader.read_line(line)?;
+    ensure!(line.ends_with('\n'), match reader.limit() {
+        0 => "The request is too large",
+        _ => "Connection closed during the request"
+    });
+    Ok(())
+}
+
+fn percent_decode(text: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut iter = text.bytes();
+    while let Some(byte) = iter.next() {
+        bytes.push(match byte {
+            b'+' => b' ',
+            b'%' => {
+                let hex = [iter.next(), iter.next()];
+                let [Some(hi), Some(lo)] = hex else { bail!("Truncated percent escape") };
+                //from_str_radix would also take a sign
+                ensure!(hi.is_ascii_hexdigit() && lo.is_ascii_hexdigit(), "Invalid percent escape");
+                u8::from_str_radix(std::str::from_utf8(&[hi, lo])?, 16)?
+            }
+            other => other
+        });
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) -> Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+           status, content_type, body.len())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn respond_json(stream: &mut TcpStream, status: &str, body: &impl Serialize) -> Result<()> {
+    respond(stream, status, "application/json", &serde_json::to_vec(body)?)
+}
+
+fn stream_frames(stream: &mut TcpStream, shared: &Shared) -> Result<()> {
+    let _viewer = ViewerGuard::new(shared);
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary={}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n", BOUNDARY)?;
+    let mut sent = 0;
+    loop {
+        let jpeg = {
+            let mut frame = shared.frame.lock().map_err(|_| anyhow!("Frame lock poisoned"))?;
+            while frame.0 == sent || frame.1.is_none() {
+                if shared.stop.load(Ordering::Acquire) {
+                    return Ok(());
+                }
+                frame = shared.new_frame
+                    .wait_timeout(frame, Duration::from_secs(1))
+                    .map_err(|_| anyhow!("Frame lock poisoned"))?
+                    .0;
+            }
+            sent = frame.0;
+            frame.1.clone().expect("Checked by the loop condition")
+        };
+        write!(stream, "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n", BOUNDARY, jpeg.len())?;
+        stream.write_all(&jpeg)?;
+        stream.write_all(b"\r\n")?;
+        stream.flush()?;
+    }
+}
+
+/// `peek` asks the event loop to show a monitor and returns `false` if the event loop is gone
+fn handle_connection(mut stream: TcpStream, shared: &Shared, token: Option<&str>, peek: &impl Fn(String, Sender<Response>) -> bool) -> Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let request = Request::read(BufReader::new(stream.try_clone()?))?;
+    log::trace!("{} {}", request.method, request.path);
+    if !request.authorized(token) {
+        return respond(&mut stream, "401 Unauthorized", "text/plain", b"Missing or wrong token");
+    }
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") => respond(&mut stream, "200 OK", "text/html; charset=utf-8", VIEWER_PAGE.as_bytes()),
+        ("GET", "/stream") => stream_frames(&mut stream, shared),
+        ("GET", "/monitors") => {
+            let monitors = shared.monitors.lock().map_err(|_| anyhow!("Monitor lock poisoned"))?.clone();
+            respond_json(&mut stream, "200 OK", &monitors)
+        }
+        ("POST", "/peek") => {
+            let Some(monitor) = request.param("monitor") else {
+                return respond_json(&mut stream, "400 Bad Request", &Response::failure("Missing monitor"));
+            };
+            let (tx, rx) = channel();
+            let response = match peek(monitor.to_string(), tx) {
+                true => rx.recv_timeout(REPLY_TIMEOUT).context("The event loop did not answer"),
+                false => Err(anyhow!("The event loop is gone"))
+            }.unwrap_or_else(|err| Response::failure(format!("{:#}", err)));
+            let status = if response.ok { "200 OK" } else { "400 Bad Request" };
+            respond_json(&mut stream, status, &response)
+        }
+        (_, "/" | "/stream" | "/monitors" | "/peek") => respond(&mut stream, "405 Method Not Allowed", "text/plain", b"Method not allowed"),
+        _ => respond(&mut stream, "404 Not Found", "text/plain", b"Not found")
+    }
+}
+
+/// A running web viewer. Dropping it stops the server
+pub struct WebServer {
+    config: WebConfig,
+    address: SocketAddr,
+    shared: Arc<Shared>,
+    encoder: SyncSender<(Image, String)>,
+    next_frame: Instant,
+    changed: bool
+}
+
+impl WebServer {
+
+    pub fn start(config: WebConfig, peek: impl Fn(String, Sender<Response>) -> bool + Clone + Send + 'static) -> Result<Self> {
+        let listener = TcpListener::bind(config.address.as_str())
+            .with_context(|| format!("Can not listen on {}", config.address))?;
+        let address = listener.local_addr()?;
+        ensure!(config.token.is_some() || address.ip().is_loopback(),
+            "Refusing to serve {} without a token", address);
+        let shared = Arc::new(Shared::default());
+
+        let (encoder, frames) = sync_channel::<(Image, String)>(1);
+        let quality = config.quality.clamp(1, 100);
+        let encoded = shared.clone();
+        std::thread::spawn(move || {
+            for (image, monitor) in frames {
+                let Some(jpeg) = image.to_jpeg(quality).log_ok("Can not encode frame") else { continue };
+                //The monitor goes first, so viewers that got the frame also find it in `/monitors`
+                if let Ok(mut monitors) = encoded.monitors.lock() {
+                    monitors.current = Some(monitor);
+                }
+                if let Ok(mut frame) = encoded.frame.lock() {
+                    *frame = (frame.0 + 1, Some(Arc::new(jpeg)));
+                }
+                encoded.new_frame.notify_all();
+            }
+        });
+
+        let token = config.token.clone();
+        let server = shared.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if server.stop.load(Ordering::Acquire) {
+                    break;
+                }
+                let Some(stream) = stream.log_ok("Can not accept web viewer") else { continue };
+                let (shared, token, peek) = (server.clone(), token.clone(), peek.clone());
+                std::thread::spawn(move || {
+                    if let Err(err) = handle_connection(stream, &shared, token.as_deref(), &peek) {
+                        log::debug!("Web viewer disconnected: {}", err);
+                    }
+                });
+            }
+            log::trace!("Stopping web viewer");
+        });
+        log::info!("Serving the web viewer on http://{}/", address);
+        Ok(Self {
+            config,
+            address,
+            shared,
+            encoder,
+            next_frame: Instant::now(),
+            changed: true,
+        })
+    }
+
+    pub fn config(&self) -> &WebConfig {
+        &self.config
+    }
+
+    pub fn set_monitors(&self, monitors: Vec<String>) {
+        if let Ok(mut list) = self.shared.monitors.lock() {
+            list.monitors = monitors;
+        }
+    }
+
+    pub fn frame_changed(&mut self) {
+        self.changed = true;
+    }
+
+    /// Hands a new frame of `monitor` to the encoder if someone is watching, the frame changed and the frame rate allows it.
+    /// Frames are dropped while the encoder is still busy
+    pub fn update(&mut self, now: Instant, monitor: &str, capture: impl FnOnce() -> Result<Image>) -> Result<()> {
+        let changed = self.changed || self.shared.refresh.swap(false, Ordering::AcqRel);
+        if self.shared.viewers.load(Ordering::Acquire) == 0 || !changed || now < self.next_frame {
+            self.changed = changed;
+            return Ok(());
+        }
+        self.changed = false;
+        self.next_frame = now + Duration::from_secs(1) / self.config.fps.max(1);
+        match self.encoder.try_send((capture()?, monitor.to_string())) {
+            Ok(()) | Err(TrySendError::Full(_)) => Ok(()),
+            Err(TrySendError::Disconnected(_)) => bail!("The jpeg encoder stopped")
+        }
+    }
+
+}
+
+impl Drop for WebServer {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        self.shared.new_frame.notify_all();
+        // Wakes the accept loop up, which can not connect to the unspecified address itself
+        let mut address = self.address;
+        if address.ip().is_unspecified() {
+            address.set_ip(match address {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into()
+            });
+        }
+        let _ = TcpStream::connect_timeout(&address, Duration::from_millis(200));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(request: &str) -> Result<Request> {
+        Request::read(request.as_bytes())
+    }
+
+    #[test]
+    fn requests_are_parsed() {
+        let request = parse("POST /peek?monitor=Left%20screen&token=a+b&flag HTTP/1.1\r\nHost: pc\r\nauthorization:  Bearer secret \r\n\r\nbody").unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/peek");
+        assert_eq!(request.param("monitor"), Some("Left screen"));
+        assert_eq!(request.param("token"), Some("a b"));
+        assert_eq!(request.param("flag"), Some(""));
+        assert_eq!(request.param("missing"), None);
+        assert_eq!(request.bearer.as_deref(), Some("secret"));
+
+        let request = parse("GET / HTTP/1.1\n\n").unwrap();
+        assert_eq!((request.method.as_str(), request.path.as_str()), ("GET", "/"));
+        assert!(request.query.is_empty());
+        assert!(request.bearer.is_none());
+    }
+
+    #[test]
+    fn broken_requests_are_rejected() {
+        for request in ["", "\r\n\r\n", "GET\r\n\r\n", "GET / HTTP/1.1\r\nHost: pc\r\n", "GET / HTTP/1.1", "GET /?a=%4 HTTP/1.1\r\n\r\n"] {
+            assert!(parse(request).is_err(), "{:?}", request);
+        }
+    }
+
+    #[test]
+    fn requests_are_capped() {
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_REQUEST as usize));
+        let error = parse(&long_line).err().unwrap();
+        assert_eq!(error.to_string(), "The request is too large");
+
+        let header = format!("X-Filler: {}\r\n", "a".repeat(100));
+        let many_headers = format!("GET / HTTP/1.1\r\n{}\r\n", header.repeat(MAX_REQUEST as usize / header.len() + 1));
+        let error = parse(&many_headers).err().unwrap();
+        assert_eq!(error.to_string(), "The request is too large");
+
+        let headers = format!("GET / HTTP/1.1\r\n{}\r\n", header.repeat(MAX_REQUEST as usize / header.len() - 1));
+        assert!(parse(&headers).is_ok());
+    }
+
+    #[test]
+    fn percent_escapes_are_decoded() {
+        assert_eq!(percent_decode("plain").unwrap(), "plain");
+        assert_eq!(percent_decode("a%20b+c").unwrap(), "a b c");
+        assert_eq!(percent_decode("%2B%2b%25").unwrap(), "++%");
+        assert_eq!(percent_decode("%C3%A4").unwrap(), "ä");
+        assert_eq!(percent_decode("").unwrap(), "");
+        for broken in ["%", "%2", "%zz", "%+1", "%ff", "%C3"] {
+            assert!(percent_decode(broken).is_err(), "{:?}", broken);
+        }
+    }
+
+    #[test]
+    fn tokens_are_checked() {
+        let request = |query: &str, bearer: Option<&str>| Request {
+            method: String::from("GET"),
+            path: String::from("/"),
+            query: match query {
+                "" => Vec::new(),
+                token => vec![(String::from("token"), token.to_string())]
+            },
+            bearer: bearer.map(str::to_string),
+        };
+        assert!(request("", None).authorized(None));
+        assert!(request("anything", None).authorized(None));
+        assert!(request("secret", None).authorized(Some("secret")));
+        assert!(request("", Some("secret")).authorized(Some("secret")));
+        assert!(!request("", None).authorized(Some("secret")));
+        assert!(!request("Secret", None).authorized(Some("secret")));
+        assert!(!request("secret2", None).authorized(Some("secret")));
+        assert!(!request("", Some("secre")).authorized(Some("secret")));
+        //The query takes precedence over the header
+        assert!(!request("wrong", Some("secret")).authorized(Some("secret")));
+    }
+
+    /// Starts a server on a free loopback port. Peeks are answered like the event loop would and reported on the returned channel
+    fn start(token: Option<&str>) -> (WebServer, std::sync::mpsc::Receiver<String>) {
+        let config = WebConfig {
+            enabled: true,
+            address: String::from("127.0.0.1:0"),
+            token: token.map(str::to_string),
+            fps: 1000,
+            ..Default::default()
+        };
+        let (peeks, peeked) = channel();
+        let server = WebServer::start(config, move |monitor, reply| {
+            let response = match monitor.as_str() {
+                "DISPLAY1" => Response::success(),
+                other => Response::failure(format!("Can not find monitor {}", other))
+            };
+            peeks.send(monitor).is_ok() && reply.send(response).is_ok()
+        }).unwrap();
+        (server, peeked)
+    }
+
+    fn connect(server: &WebServer, request: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(server.address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        write!(stream, "{}\r\nHost: localhost\r\n\r\n", request).unwrap();
+        stream
+    }
+
+    /// Sends a request and returns the status line and the body
+    fn fetch(server: &WebServer, request: &str) -> (String, String) {
+        let mut response = String::new();
+        std::io::Read::read_to_string(&mut connect(server, request), &mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    #[test]
+    fn requests_without_the_token_are_refused() {
+        let (server, _) = start(Some("secret"));
+        for request in ["GET / HTTP/1.1", "GET /?token=wrong HTTP/1.1", "GET /monitors?token=Secret HTTP/1.1", "GET /stream HTTP/1.1"] {
+            assert_eq!(fetch(&server, request).0, "HTTP/1.1 401 Unauthorized", "{}", request);
+        }
+        assert_eq!(fetch(&server, "GET /monitors HTTP/1.1\r\nAuthorization: Bearer wrong").0, "HTTP/1.1 401 Unauthorized");
+        assert_eq!(fetch(&server, "GET /monitors HTTP/1.1\r\nAuthorization: Bearer secret").0, "HTTP/1.1 200 OK");
+        let (status, body) = fetch(&server, "GET /?token=secret HTTP/1.1");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body, VIEWER_PAGE);
+    }
+
+    #[test]
+    fn monitors_are_listed() {
+        let (server, _) = start(None);
+        assert_eq!(fetch(&server, "GET /monitors HTTP/1.1").1, r#"{"monitors":[],"current":null}"#);
+        server.set_monitors(vec![String::from("DISPLAY1"), String::from("DISPLAY2")]);
+        let (status, body) = fetch(&server, "GET /monitors HTTP/1.1");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(body, r#"{"monitors":["DISPLAY1","DISPLAY2"],"current":null}"#);
+    }
+
+    #[test]
+    fn frames_are_streamed() {
+        let (mut server, _) = start(None);
+        let mut stream = BufReader::new(connect(&server, "GET /stream HTTP/1.1"));
+        let mut read_line = || {
+            let mut line = String::new();
+            stream.read_line(&mut line).unwrap();
+            line
+        };
+        assert_eq!(read_line(), "HTTP/1.1 200 OK\r\n");
+        let headers: Vec<String> = std::iter::repeat_with(&mut read_line)
+            .take_while(|line| line != "\r\n")
+            .collect();
+        assert!(headers.contains(&format!("Content-Type: multipart/x-mixed-replace; boundary={}\r\n", BOUNDARY)), "{:?}", headers);
+
+        //The viewer is counted before the headers are sent
+        let image = Image { width: 16, height: 8, rgba: [200, 30, 30, 255].repeat(16 * 8) };
+        server.update(Instant::now(), "DISPLAY1", || Ok(image.clone())).unwrap();
+        assert_eq!(read_line(), format!("--{}\r\n", BOUNDARY));
+        assert_eq!(read_line(), "Content-Type: image/jpeg\r\n");
+        let length: usize = read_line()
+            .strip_prefix("Content-Length: ")
+            .and_then(|length| length.trim_end().parse().ok())
+            .unwrap();
+        assert_eq!(read_line(), "\r\n");
+        let mut jpeg = vec![0; length + 2];
+        std::io::Read::read_exact(&mut stream, &mut jpeg).unwrap();
+        assert_eq!(&jpeg[..2], &[0xFF, 0xD8]);
+        assert_eq!(&jpeg[length - 2..], &[0xFF, 0xD9, b'\r', b'\n']);
+        assert!(fetch(&server, "GET /monitors HTTP/1.1").1.ends_with(r#""current":"DISPLAY1"}"#));
+    }
+
+    #[test]
+    fn peeks_round_trip() {
+        let (server, peeked) = start(None);
+        assert_eq!(fetch(&server, "POST /peek?monitor=DISPLAY1 HTTP/1.1"), (String::from("HTTP/1.1 200 OK"), String::from(r#"{"ok":true}"#)));
+        assert_eq!(fetch(&server, "POST /peek?monitor=DISPLAY9 HTTP/1.1"),
+            (String::from("HTTP/1.1 400 Bad Request"), String::from(r#"{"ok":false,"error":"Can not find monitor DISPLAY9"}"#)));
+        assert_eq!(fetch(&server, "POST /peek HTTP/1.1").0, "HTTP/1.1 400 Bad Request");
+        assert_eq!(peeked.try_iter().collect::<Vec<_>>(), vec![String::from("DISPLAY1"), String::from("DISPLAY9")]);
+
+        assert_eq!(fetch(&server, "GET /peek?monitor=DISPLAY1 HTTP/1.1").0, "HTTP/1.1 405 Method Not Allowed");
+        assert_eq!(fetch(&server, "POST /monitors HTTP/1.1").0, "HTTP/1.1 405 Method Not Allowed");
+        assert_eq!(fetch(&server, "GET /missing HTTP/1.1").0, "HTTP/1.1 404 Not Found");
+        assert!(peeked.try_recv().is_err(), "only POST /peek may peek");
+    }
+}
@@ -0,0 +1,232 @@
+use serde::Deserialize;
+#[cfg(windows)]
+use windows::Win32::Foundation::{POINT, RECT};
+#[cfg(windows)]
+use windows::Win32::Graphics::Gdi::HMONITOR;
+use crate::animation::Edge;
+#[cfg(windows)]
+use crate::config::Config;
+#[cfg(windows)]
+use crate::directx::Adapter;
+#[cfg(windows)]
+use crate::dwell::Delays;
+#[cfg(windows)]
+use crate::keys::Key;
+use crate::osd::Corner;
+#[cfg(windows)]
+use crate::trigger::TriggerMode;
+
+fn default_zone_size() -> i32 {
+    8
+}
+
+/// Region of a monitor in pixels
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ZoneShape {
+    /// Relative to the top-left corner of the monitor
+    Rect { x: i32, y: i32, width: i32, height: i32 },
+    /// A strip of `size` pixels along one edge
+    Edge {
+        edge: Edge,
+        #[serde(default = "default_zone_size")]
+        size: i32
+    },
+    /// A `size` x `size` square in one corner
+    Corner {
+        corner: Corner,
+        #[serde(default = "default_zone_size")]
+        size: i32
+    }
+}
+
+#[cfg(windows)]
+impl ZoneShape {
+
+    /// Checks whether `pt` lies in the zone of the monitor covering `monitor` in virtual desktop coordinates
+    pub fn contains(&self, monitor: RECT, pt: POINT) -> bool {
+        let (x, y) = (pt.x - monitor.left, pt.y - monitor.top);
+        let (width, height) = (monitor.right - monitor.left, monitor.bottom - monitor.top);
+        let near = |value: i32, extent: i32, size: i32, far: bool| match far {
+            true => value >= extent - size,
+            false => value < size
+        };
+        match *self {
+            ZoneShape::Rect { x: left, y: top, width: w, height: h } =>
+                x >= left && x < left + w && y >= top && y < top + h,
+            ZoneShape::Edge { edge, size } => match edge {
+                Edge::Left => near(x, width, size, false),
+                Edge::Right => near(x, width, size, true),
+                Edge::Top => near(y, height, size, false),
+                Edge::Bottom => near(y, height, size, true)
+            },
+            ZoneShape::Corner { corner, size } => {
+                let (right, bottom) = match corner {
+                    Corner::TopLeft => (false, false),
+                    Corner::TopRight => (true, false),
+                    Corner::BottomLeft => (false, true),
+                    Corner::BottomRight => (true, true)
+                };
+                near(x, width, size, right) && near(y, height, size, bottom)
+            }
+        }
+    }
+
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ZoneConfig {
+    #[serde(flatten)]
+    pub shape: ZoneShape,
+    /// Name of the monitor the zone is placed on; Defaults to the monitor that is shown
+    pub on: Option<String>,
+    /// Seconds the pointer has to stay in the zone
+    #[serde(default)]
+    pub enter_delay: f32,
+    /// Seconds the pointer has to stay away from the zone before the overlay is hidden
+    #[serde(default)]
+    pub leave_delay: f32
+}
+
+/// A zone with all monitor names resolved
+#[cfg(windows)]
+#[derive(Debug, Copy, Clone)]
+pub struct TriggerZone {
+    /// Monitor the zone is placed on
+    pub host: HMONITOR,
+    /// Monitor that is shown
+    pub target: HMONITOR,
+    pub shape: ZoneShape,
+    pub delays: Delays
+}
+
+/// A key that shows a monitor
+#[cfg(windows)]
+#[derive(Debug, Copy, Clone)]
+pub struct KeyTrigger {
+    pub key: Key,
+    pub target: HMONITOR,
+    pub mode: TriggerMode
+}
+
+#[cfg(windows)]
+fn monitor_finder(adapter: &Adapter) -> impl Fn(&str) -> Option<HMONITOR> {
+    let monitors: Vec<(String, HMONITOR)> = adapter
+        .iter_displays()
+        .filter_map(|display| Some((display.name().ok()?, display.hmonitor().ok()?)))
+        .collect();
+    move |name: &str| {
+        let monitor = monitors
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, monitor)| *monitor);
+        if monitor.is_none() {
+            log::warn!("Can not find monitor {} for trigger", name);
+        }
+        monitor
+    }
+}
+
+/// Resolves the keys of all monitors that use a key based trigger mode
+#[cfg(windows)]
+pub fn resolve_key_triggers(adapter: &Adapter, config: &Config) -> Vec<KeyTrigger> {
+    let find = monitor_finder(adapter);
+    config.monitors
+        .iter()
+        .filter(|monitor| monitor.trigger != TriggerMode::Hover)
+        .filter_map(|monitor| {
+            if monitor.key.is_none() && !monitor.trigger.uses_hover() {
+                log::warn!("Monitor {} uses the {:?} trigger but has no key", monitor.name, monitor.trigger);
+            }
+            Some(KeyTrigger {
+                key: monitor.key?,
+                target: find(&monitor.name)?,
+                mode: monitor.trigger,
+            })
+        })
+        .collect()
+}
+
+/// Resolves the zones of all monitors. Zones referring to unknown monitors are skipped
+#[cfg(windows)]
+pub fn resolve_zones(adapter: &Adapter, config: &Config) -> Vec<TriggerZone> {
+    let find = monitor_finder(adapter);
+    config.monitors
+        .iter()
+        .flat_map(|monitor| monitor.zones
+            .iter()
+            .filter_map(|zone| Some(TriggerZone {
+                host: find(zone.on.as_deref().unwrap_or(&monitor.name))?,
+                target: find(&monitor.name)?,
+                shape: zone.shape,
+                delays: Delays::from_secs(zone.enter_delay, zone.leave_delay),
+            })))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Zones {
+        zones: Vec<ZoneConfig>
+    }
+
+    #[test]
+    fn zones_parse() {
+        let Zones { zones } = toml::from_str(r#"
+            zones = [
+                { kind = "edge", edge = "top", size = 4, enter_delay = 0.2 },
+                { kind = "corner", corner = "bottom-right", on = 'DISPLAY1', leave_delay = 0.5 },
+                { kind = "rect", x = -4, y = 10, width = 20, height = 30 },
+            ]
+        "#).unwrap();
+        assert_eq!(zones[0].shape, ZoneShape::Edge { edge: Edge::Top, size: 4 });
+        assert_eq!(zones[0].enter_delay, 0.2);
+        assert_eq!(zones[1].shape, ZoneShape::Corner { corner: Corner::BottomRight, size: 8 });
+        assert_eq!(zones[1].on.as_deref(), Some("DISPLAY1"));
+        assert_eq!(zones[2].shape, ZoneShape::Rect { x: -4, y: 10, width: 20, height: 30 });
+        assert!(toml::from_str::<Zones>(r#"zones = [{ kind = "circle" }]"#).is_err());
+    }
+
+    #[cfg(windows)]
+    const MONITOR: RECT = RECT { left: -1920, top: -200, right: 0, bottom: 880 };
+
+    #[cfg(windows)]
+    fn hits(shape: ZoneShape, points: &[(i32, i32)]) -> Vec<bool> {
+        points
+            .iter()
+            .map(|&(x, y)| shape.contains(MONITOR, POINT { x: MONITOR.left + x, y: MONITOR.top + y }))
+            .collect()
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn edges_hit_the_outermost_pixels() {
+        let points = [(0, 500), (3, 500), (4, 500), (1919, 500), (1916, 500), (1915, 500)];
+        assert_eq!(hits(ZoneShape::Edge { edge: Edge::Left, size: 4 }, &points), [true, true, false, false, false, false]);
+        assert_eq!(hits(ZoneShape::Edge { edge: Edge::Right, size: 4 }, &points), [false, false, false, true, true, false]);
+        let points = [(500, 0), (500, 3), (500, 4), (500, 1079), (500, 1076), (500, 1075)];
+        assert_eq!(hits(ZoneShape::Edge { edge: Edge::Top, size: 4 }, &points), [true, true, false, false, false, false]);
+        assert_eq!(hits(ZoneShape::Edge { edge: Edge::Bottom, size: 4 }, &points), [false, false, false, true, true, false]);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn corners_need_both_edges() {
+        let shape = ZoneShape::Corner { corner: Corner::BottomRight, size: 16 };
+        let points = [(1919, 1079), (1904, 1064), (1903, 1079), (1919, 1063), (0, 0), (1919, 0)];
+        assert_eq!(hits(shape, &points), [true, true, false, false, false, false]);
+        let shape = ZoneShape::Corner { corner: Corner::TopLeft, size: 16 };
+        assert_eq!(hits(shape, &[(0, 0), (15, 15), (16, 0), (0, 16)]), [true, true, false, false]);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn rects_are_relative_to_the_monitor() {
+        let shape = ZoneShape::Rect { x: 100, y: 50, width: 10, height: 20 };
+        let points = [(100, 50), (109, 69), (110, 50), (100, 70), (99, 50), (100, 49)];
+        assert_eq!(hits(shape, &points), [true, true, false, false, false, false]);
+    }
+}